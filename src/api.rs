@@ -0,0 +1,1276 @@
+use crate::bitcoind_client::BitcoindClient;
+use crate::bootstrap;
+use crate::cli;
+use crate::disk;
+use crate::encryption::Unlocker;
+use crate::hex_utils;
+use crate::lnurl;
+use crate::persist::NodePersister;
+use crate::rgs::{RgsClient, RgsServer};
+use crate::router::{ConstrainedRouter, RouteConstraints};
+use crate::signer::NodeKeys;
+use crate::wallet::{AddressType, OnchainWallet};
+use crate::{
+	time_now_secs, ChainMonitor, ChannelManager, ExternalFundingStorage, ForwardingHistoryStorage,
+	NetworkGraph, PaymentInfoStorage, PeerConnectionsStorage, PeerManager, ProbeStatsStorage,
+	Scorer, WithdrawLink, WithdrawLinkStorage, BOOTSTRAP_PEERS, WITHDRAW_LINKS_FNAME,
+};
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Path, Query, State};
+use axum::http::header::AUTHORIZATION;
+use axum::http::{Request, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use bitcoin::network::constants::Network;
+use bitcoin::secp256k1::PublicKey;
+use lightning::chain::keysinterface::EntropySource;
+use lightning::ln::channelmanager::Retry;
+use lightning::ln::msgs::NetAddress;
+use lightning::ln::{PaymentHash, PaymentPreimage};
+use lightning::routing::scoring::ProbabilisticScoringParameters;
+use lightning::util::events::{Event, PaymentPurpose};
+use lightning::util::persist::KVStorePersister;
+use lightning_invoice::Invoice;
+use rand::{thread_rng, Rng};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs;
+use std::io::{self, Write};
+use std::net::SocketAddr;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, oneshot};
+
+/// Everything the HTTP handlers need to serve requests, mirroring the arguments of
+/// `cli::poll_for_user_input`.
+pub(crate) struct ApiState {
+	pub(crate) peer_manager: Arc<PeerManager>,
+	pub(crate) channel_manager: Arc<ChannelManager>,
+	pub(crate) chain_monitor: Arc<ChainMonitor>,
+	pub(crate) bitcoind_client: Arc<BitcoindClient>,
+	pub(crate) wallet: Arc<OnchainWallet>,
+	pub(crate) keys_manager: Arc<NodeKeys>,
+	pub(crate) network_graph: Arc<NetworkGraph>,
+	pub(crate) scorer: Arc<Mutex<Scorer>>,
+	pub(crate) router: Arc<ConstrainedRouter>,
+	pub(crate) probe_stats: ProbeStatsStorage,
+	pub(crate) external_fundings: ExternalFundingStorage,
+	pub(crate) inbound_payments: PaymentInfoStorage,
+	pub(crate) outbound_payments: PaymentInfoStorage,
+	pub(crate) forwarding_history: ForwardingHistoryStorage,
+	pub(crate) peer_connections: PeerConnectionsStorage,
+	pub(crate) proxy: Option<SocketAddr>,
+	pub(crate) dns_seeds: Vec<String>,
+	pub(crate) rgs_client: Option<Arc<RgsClient>>,
+	pub(crate) rgs_server: Option<Arc<RgsServer>>,
+	pub(crate) scoring_params: ProbabilisticScoringParameters,
+	pub(crate) persister: Arc<NodePersister>,
+	pub(crate) ldk_data_dir: String,
+	pub(crate) network: Network,
+	pub(crate) announced_listen_addr: Vec<NetAddress>,
+	pub(crate) payment_retry: Retry,
+	/// Where wallets reach `serve_public`, if it's running.
+	pub(crate) public_url: Option<String>,
+	pub(crate) withdraw_links: WithdrawLinkStorage,
+	pub(crate) logger: Arc<disk::FilesystemLogger>,
+	pub(crate) events: broadcast::Sender<Value>,
+	pub(crate) shutdown: mpsc::Sender<bool>,
+}
+
+type ApiResult = Result<Json<Value>, (StatusCode, Json<Value>)>;
+
+/// Holds the token API clients have to send as `Authorization: Bearer <token>`.
+const API_TOKEN_FNAME: &str = "api_token";
+
+/// Reads the API token from `ldk_data_dir`, creating a random one only we can read on first use.
+pub(crate) fn read_or_create_api_token(ldk_data_dir: &str) -> Result<String, String> {
+	let path = format!("{}/{}", ldk_data_dir, API_TOKEN_FNAME);
+	match fs::read_to_string(&path) {
+		Ok(token) if !token.trim().is_empty() => return Ok(token.trim().to_string()),
+		Ok(_) => return Err(format!("{} is empty", path)),
+		Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+		Err(e) => return Err(format!("failed to read {}: {}", path, e)),
+	}
+	let mut token = [0; 32];
+	thread_rng().fill_bytes(&mut token);
+	let token = hex_utils::hex_str(&token);
+	let mut options = fs::OpenOptions::new();
+	options.write(true).create_new(true);
+	#[cfg(unix)]
+	options.mode(0o600);
+	options
+		.open(&path)
+		.and_then(|mut f| {
+			f.write_all(token.as_bytes())?;
+			f.sync_all()
+		})
+		.map_err(|e| format!("failed to write {}: {}", path, e))?;
+	Ok(token)
+}
+
+/// Rejects requests that don't carry the API token.
+async fn require_token<B>(
+	State(token): State<Arc<String>>, request: Request<B>, next: Next<B>,
+) -> Response {
+	let given = request
+		.headers()
+		.get(AUTHORIZATION)
+		.and_then(|value| value.to_str().ok())
+		.and_then(|value| value.strip_prefix("Bearer "));
+	match given {
+		Some(given) if tokens_match(given.as_bytes(), token.as_bytes()) => next.run(request).await,
+		_ => {
+			let msg =
+				format!("missing or wrong bearer token, see {} in the data dir", API_TOKEN_FNAME);
+			(StatusCode::UNAUTHORIZED, Json(json!({ "error": msg }))).into_response()
+		}
+	}
+}
+
+/// Compares in constant time, so the token can't be guessed from response times.
+fn tokens_match(a: &[u8], b: &[u8]) -> bool {
+	a.len() == b.len() && a.iter().zip(b.iter()).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn bad_request(msg: &str) -> (StatusCode, Json<Value>) {
+	(StatusCode::BAD_REQUEST, Json(json!({ "error": msg })))
+}
+
+fn failed(msg: &str) -> (StatusCode, Json<Value>) {
+	(StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "error": msg })))
+}
+
+fn success() -> ApiResult {
+	Ok(Json(json!({ "success": true })))
+}
+
+fn str_param<'a>(body: &'a Value, name: &str) -> Result<&'a str, (StatusCode, Json<Value>)> {
+	body[name].as_str().ok_or_else(|| bad_request(&format!("missing string field `{}`", name)))
+}
+
+fn u64_param(body: &Value, name: &str) -> Result<u64, (StatusCode, Json<Value>)> {
+	body[name].as_u64().ok_or_else(|| bad_request(&format!("missing integer field `{}`", name)))
+}
+
+fn pubkey_param(body: &Value, name: &str) -> Result<PublicKey, (StatusCode, Json<Value>)> {
+	hex_utils::to_compressed_pubkey(str_param(body, name)?)
+		.ok_or_else(|| bad_request(&format!("couldn't parse `{}`", name)))
+}
+
+fn channel_id_param(body: &Value) -> Result<[u8; 32], (StatusCode, Json<Value>)> {
+	match hex_utils::to_vec(str_param(body, "channel_id")?) {
+		Some(channel_id_vec) if channel_id_vec.len() == 32 => {
+			let mut channel_id = [0; 32];
+			channel_id.copy_from_slice(&channel_id_vec);
+			Ok(channel_id)
+		}
+		_ => Err(bad_request("couldn't parse `channel_id`")),
+	}
+}
+
+async fn node_info(State(state): State<Arc<ApiState>>) -> Json<Value> {
+	Json(cli::node_info_json(
+		&state.channel_manager,
+		&state.peer_manager,
+		&state.announced_listen_addr,
+	))
+}
+
+async fn estimate_fees(State(state): State<Arc<ApiState>>) -> Json<Value> {
+	Json(cli::fees_json(&state.bitcoind_client))
+}
+
+async fn list_unconfirmed(State(state): State<Arc<ApiState>>) -> Json<Value> {
+	Json(cli::unconfirmed_json(&state.bitcoind_client))
+}
+
+async fn backup_status(State(state): State<Arc<ApiState>>) -> Json<Value> {
+	Json(cli::backup_status_json(&state.persister))
+}
+
+async fn verify_state(State(state): State<Arc<ApiState>>) -> Json<Value> {
+	Json(cli::verify_state_json(
+		&state.persister,
+		&state.channel_manager,
+		&state.chain_monitor,
+		&state.keys_manager,
+		&state.bitcoind_client,
+		&state.network_graph,
+		&state.scorer,
+		&state.logger,
+	))
+}
+
+async fn chain_status(State(state): State<Arc<ApiState>>) -> Json<Value> {
+	Json(cli::chain_status_json(&state.channel_manager, &state.bitcoind_client).await)
+}
+
+/// Takes an optional `type`, `p2wpkh` (the default) or `p2tr`.
+async fn new_address(State(state): State<Arc<ApiState>>, body: Option<Json<Value>>) -> ApiResult {
+	let address_type = match body.as_ref().and_then(|Json(body)| body.get("type")) {
+		Some(address_type) => address_type
+			.as_str()
+			.ok_or_else(|| bad_request("couldn't parse `type`"))?
+			.parse::<AddressType>()
+			.map_err(|e| bad_request(&e))?,
+		None => AddressType::P2wpkh,
+	};
+	let address = state.wallet.get_new_address(address_type).await.map_err(|e| failed(&e))?;
+	Ok(Json(json!({ "address": address.to_string() })))
+}
+
+async fn onchain_balance(State(state): State<Arc<ApiState>>) -> ApiResult {
+	Ok(Json(cli::onchain_balance_json(&state.wallet).await.map_err(|e| failed(&e))?))
+}
+
+async fn list_channels(State(state): State<Arc<ApiState>>) -> Json<Value> {
+	Json(cli::channels_json(&state.channel_manager, &state.network_graph))
+}
+
+async fn list_peers(State(state): State<Arc<ApiState>>) -> Json<Value> {
+	Json(cli::peers_json(&state.peer_manager, &state.channel_manager, &state.peer_connections))
+}
+
+/// Takes the same filters as the CLI command as query parameters, e.g.
+/// `/listpayments?incoming&status=succeeded&limit=10`.
+async fn list_payments(
+	State(state): State<Arc<ApiState>>, Query(params): Query<HashMap<String, String>>,
+) -> ApiResult {
+	let args =
+		params
+			.iter()
+			.map(|(name, value)| {
+				if value.is_empty() {
+					format!("--{}", name)
+				} else {
+					format!("--{}={}", name, value)
+				}
+			})
+			.collect::<Vec<_>>();
+	let filter = cli::PaymentFilter::from_args(args.iter().map(|arg| arg.as_str()))
+		.map_err(|e| bad_request(&e))?;
+	Ok(Json(cli::payments_json(&state.inbound_payments, &state.outbound_payments, &filter)))
+}
+
+/// Takes the same filters as the CLI command as query parameters, e.g.
+/// `/listforwards?since=1672531200&limit=10`.
+async fn list_forwards(
+	State(state): State<Arc<ApiState>>, Query(params): Query<HashMap<String, String>>,
+) -> ApiResult {
+	let args =
+		params.iter().map(|(name, value)| format!("--{}={}", name, value)).collect::<Vec<_>>();
+	let filter = cli::ForwardFilter::from_args(args.iter().map(|arg| arg.as_str()))
+		.map_err(|e| bad_request(&e))?;
+	Ok(Json(cli::forwards_json(&state.forwarding_history, &filter)))
+}
+
+/// `/queryroutes/<dest_pubkey>?amount_msat=<amt>`
+async fn query_routes(
+	State(state): State<Arc<ApiState>>, Path(dest_pubkey): Path<String>,
+	Query(params): Query<HashMap<String, String>>,
+) -> ApiResult {
+	let dest_pubkey = hex_utils::to_compressed_pubkey(&dest_pubkey)
+		.ok_or_else(|| bad_request("couldn't parse destination pubkey"))?;
+	let amt_msat = params
+		.get("amount_msat")
+		.and_then(|amt| amt.parse().ok())
+		.ok_or_else(|| bad_request("missing integer parameter `amount_msat`"))?;
+	cli::route_json(
+		dest_pubkey,
+		amt_msat,
+		&state.channel_manager,
+		&state.network_graph,
+		&state.scorer,
+		&*state.keys_manager,
+		Arc::clone(&state.logger),
+	)
+	.map(Json)
+	.map_err(|e| failed(&format!("failed to find a route: {}", e)))
+}
+
+async fn probe(State(state): State<Arc<ApiState>>, Json(body): Json<Value>) -> ApiResult {
+	let dest_pubkey = pubkey_param(&body, "dest_pubkey")?;
+	let amt_msat = u64_param(&body, "amount_msat")?;
+	let num_sent = cli::send_probes(
+		dest_pubkey,
+		amt_msat,
+		&state.channel_manager,
+		&state.network_graph,
+		&state.scorer,
+		&*state.keys_manager,
+		Arc::clone(&state.logger),
+	)
+	.map_err(|e| failed(&e))?;
+	Ok(Json(json!({ "num_probes_sent": num_sent })))
+}
+
+async fn probe_stats(State(state): State<Arc<ApiState>>) -> Json<Value> {
+	Json(cli::probe_stats_json(&state.probe_stats, &state.scorer))
+}
+
+async fn scorer_info(State(state): State<Arc<ApiState>>) -> Json<Value> {
+	Json(cli::scorer_info_json(&state.scorer, &state.network_graph, &state.scoring_params))
+}
+
+async fn reset_scorer(State(state): State<Arc<ApiState>>) -> ApiResult {
+	cli::reset_scorer(
+		&state.scorer,
+		&state.network_graph,
+		&state.logger,
+		&state.scoring_params,
+		&state.persister,
+	)
+	.map_err(|e| failed(&e))?;
+	success()
+}
+
+async fn connect_peer(State(state): State<Arc<ApiState>>, Json(body): Json<Value>) -> ApiResult {
+	let peer = str_param(&body, "peer")?;
+	let (pubkey, peer_addr) = cli::parse_peer_info(peer.to_string())
+		.map_err(|_| bad_request("`peer` should be formatted as `pubkey@host:port`"))?;
+	cli::connect_peer_if_necessary(pubkey, peer_addr, Arc::clone(&state.peer_manager), state.proxy)
+		.await
+		.map_err(|_| failed("failed to connect to peer"))?;
+	let peer_data_path = format!("{}/channel_peer_data", state.ldk_data_dir);
+	let _ = disk::persist_channel_peer(std::path::Path::new(&peer_data_path), peer);
+	success()
+}
+
+async fn bootstrap(State(state): State<Arc<ApiState>>, body: Option<Json<Value>>) -> ApiResult {
+	let num_peers = match body.as_ref().and_then(|Json(body)| body.get("num_peers")) {
+		Some(num_peers) => {
+			num_peers.as_u64().ok_or_else(|| bad_request("couldn't parse `num_peers`"))? as usize
+		}
+		None => BOOTSTRAP_PEERS,
+	};
+	let peer_data_path = format!("{}/channel_peer_data", state.ldk_data_dir);
+	let peers = bootstrap::bootstrap(
+		&state.dns_seeds,
+		num_peers,
+		Arc::clone(&state.peer_manager),
+		state.proxy,
+		&peer_data_path,
+	)
+	.await
+	.map_err(|e| failed(&e))?;
+	Ok(Json(cli::bootstrap_json(&peers)))
+}
+
+async fn set_gossip_sync(State(state): State<Arc<ApiState>>, Json(body): Json<Value>) -> ApiResult {
+	let rgs = match str_param(&body, "mode")? {
+		"rgs" => true,
+		"p2p" => false,
+		_ => return Err(bad_request("`mode` should be `rgs` or `p2p`")),
+	};
+	cli::set_gossip_sync(rgs, state.rgs_client.as_ref()).map_err(|e| bad_request(&e))?;
+	success()
+}
+
+/// A Rapid Gossip Sync snapshot with the changes to our graph since `last_sync`, for other nodes
+/// to sync from.
+async fn rgs_snapshot(
+	State(state): State<Arc<ApiState>>, Path(last_sync): Path<u32>,
+) -> Result<Vec<u8>, (StatusCode, Json<Value>)> {
+	let rgs_server = state.rgs_server.as_ref().ok_or_else(|| {
+		(StatusCode::NOT_FOUND, Json(json!({ "error": "RGS snapshots aren't served" })))
+	})?;
+	rgs_server.snapshot(last_sync).ok_or_else(|| {
+		let msg = "the first RGS snapshots are still being generated";
+		(StatusCode::SERVICE_UNAVAILABLE, Json(json!({ "error": msg })))
+	})
+}
+
+async fn disconnect_peer(State(state): State<Arc<ApiState>>, Json(body): Json<Value>) -> ApiResult {
+	let peer_pubkey = pubkey_param(&body, "peer_pubkey")?;
+	let no_reconnect = body["no_reconnect"].as_bool().unwrap_or(false);
+	if no_reconnect {
+		cli::set_reconnect(peer_pubkey, false, &state.peer_connections, &state.ldk_data_dir)
+			.map_err(|_| failed("failed to persist reconnection settings"))?;
+	}
+	cli::do_disconnect_peer(
+		peer_pubkey,
+		no_reconnect,
+		Arc::clone(&state.peer_manager),
+		Arc::clone(&state.channel_manager),
+	)
+	.map_err(|_| failed("failed to disconnect from peer"))?;
+	success()
+}
+
+async fn set_reconnect(State(state): State<Arc<ApiState>>, Json(body): Json<Value>) -> ApiResult {
+	let peer_pubkey = pubkey_param(&body, "peer_pubkey")?;
+	let reconnect = body["reconnect"]
+		.as_bool()
+		.ok_or_else(|| bad_request("missing boolean field `reconnect`"))?;
+	cli::set_reconnect(peer_pubkey, reconnect, &state.peer_connections, &state.ldk_data_dir)
+		.map_err(|_| failed("failed to persist reconnection settings"))?;
+	success()
+}
+
+async fn open_channel(State(state): State<Arc<ApiState>>, Json(body): Json<Value>) -> ApiResult {
+	let peer_pubkey_and_ip_addr = str_param(&body, "peer")?;
+	let (pubkey, peer_addr) = cli::parse_peer_info(peer_pubkey_and_ip_addr.to_string())
+		.map_err(|_| bad_request("`peer` should be formatted as `pubkey@host:port`"))?;
+	let chan_amt_sat = u64_param(&body, "amount_sat")?;
+	let u32_param = |name: &str| match body.get(name) {
+		Some(value) => value
+			.as_u64()
+			.filter(|value| *value <= u32::MAX as u64)
+			.map(|value| Some(value as u32))
+			.ok_or_else(|| bad_request(&format!("couldn't parse `{}`", name))),
+		None => Ok(None),
+	};
+	let options = cli::OpenChannelOptions {
+		announced_channel: body["public"].as_bool().unwrap_or(false),
+		push_msat: body["push_msat"].as_u64().unwrap_or(0),
+		htlc_minimum_msat: body["min_htlc_msat"].as_u64(),
+		cltv_expiry_delta: match u32_param("cltv_expiry_delta")? {
+			Some(delta) if delta > u16::MAX as u32 => {
+				return Err(bad_request("`cltv_expiry_delta` is too large"))
+			}
+			delta => delta.map(|delta| delta as u16),
+		},
+		forwarding_fee_base_msat: u32_param("fee_base_msat")?,
+		forwarding_fee_proportional_millionths: u32_param("fee_proportional_millionths")?,
+		external_funding: body["psbt"].as_bool().unwrap_or(false),
+	};
+
+	cli::connect_peer_if_necessary(pubkey, peer_addr, Arc::clone(&state.peer_manager), state.proxy)
+		.await
+		.map_err(|_| failed("failed to connect to peer"))?;
+	let temporary_channel_id = cli::open_channel(
+		pubkey,
+		chan_amt_sat,
+		&options,
+		Arc::clone(&state.channel_manager),
+		&state.external_fundings,
+	)
+	.map_err(|_| failed("failed to open channel"))?;
+
+	let peer_data_path = format!("{}/channel_peer_data", state.ldk_data_dir);
+	let _ =
+		disk::persist_channel_peer(std::path::Path::new(&peer_data_path), peer_pubkey_and_ip_addr);
+	if !options.external_funding {
+		return success();
+	}
+	let funding = cli::await_external_funding(
+		temporary_channel_id,
+		&state.channel_manager,
+		&state.external_fundings,
+	)
+	.await
+	.map_err(|e| failed(&e))?;
+	Ok(Json(cli::external_funding_json(temporary_channel_id, &funding, state.network)))
+}
+
+async fn fund_channel(State(state): State<Arc<ApiState>>, Json(body): Json<Value>) -> ApiResult {
+	let temporary_channel_id = match hex_utils::to_vec(str_param(&body, "temporary_channel_id")?) {
+		Some(id_vec) if id_vec.len() == 32 => {
+			let mut id = [0; 32];
+			id.copy_from_slice(&id_vec);
+			id
+		}
+		_ => return Err(bad_request("couldn't parse `temporary_channel_id`")),
+	};
+	let funding = str_param(&body, "funding")?;
+	let txid = cli::fund_channel(
+		temporary_channel_id,
+		funding,
+		&state.channel_manager,
+		&state.external_fundings,
+	)
+	.map_err(|e| bad_request(&e))?;
+	Ok(Json(json!({ "funding_txid": txid.to_string() })))
+}
+
+async fn close_channel(State(state): State<Arc<ApiState>>, Json(body): Json<Value>) -> ApiResult {
+	let channel_id = channel_id_param(&body)?;
+	let peer_pubkey = pubkey_param(&body, "peer_pubkey")?;
+	let target_sat_per_vbyte = match body.get("sat_per_vbyte") {
+		Some(feerate) => Some(
+			feerate
+				.as_u64()
+				.filter(|feerate| *feerate <= u32::MAX as u64)
+				.ok_or_else(|| bad_request("couldn't parse `sat_per_vbyte`"))? as u32,
+		),
+		None => None,
+	};
+	let res = if body["force"].as_bool().unwrap_or(false) {
+		if target_sat_per_vbyte.is_some() {
+			return Err(bad_request("`sat_per_vbyte` can't be set for a force-close"));
+		}
+		cli::force_close_channel(channel_id, peer_pubkey, Arc::clone(&state.channel_manager))
+	} else {
+		cli::close_channel(
+			channel_id,
+			peer_pubkey,
+			target_sat_per_vbyte,
+			Arc::clone(&state.channel_manager),
+		)
+	};
+	res.map_err(|_| failed("failed to close channel"))?;
+	success()
+}
+
+async fn abandon_channel(State(state): State<Arc<ApiState>>, Json(body): Json<Value>) -> ApiResult {
+	let channel_id = channel_id_param(&body)?;
+	let peer_pubkey = pubkey_param(&body, "peer_pubkey")?;
+	cli::abandon_channel(channel_id, peer_pubkey, Arc::clone(&state.channel_manager))
+		.map_err(|_| failed("failed to abandon channel"))?;
+	success()
+}
+
+/// Takes either a `channel_id` or a `peer_pubkey` to update all our channels with that peer.
+async fn update_channel_policy(
+	State(state): State<Arc<ApiState>>, Json(body): Json<Value>,
+) -> ApiResult {
+	let target = if body.get("channel_id").is_some() {
+		cli::PolicyTarget::Channel(channel_id_param(&body)?)
+	} else {
+		cli::PolicyTarget::Peer(pubkey_param(&body, "peer_pubkey")?)
+	};
+	let u32_param = |name: &str| match body.get(name) {
+		Some(value) => value
+			.as_u64()
+			.filter(|value| *value <= u32::MAX as u64)
+			.map(|value| Some(value as u32))
+			.ok_or_else(|| bad_request(&format!("couldn't parse `{}`", name))),
+		None => Ok(None),
+	};
+	let update = cli::ChannelPolicyUpdate {
+		forwarding_fee_base_msat: u32_param("fee_base_msat")?,
+		forwarding_fee_proportional_millionths: u32_param("fee_proportional_millionths")?,
+		cltv_expiry_delta: match u32_param("cltv_expiry_delta")? {
+			Some(delta) if delta > u16::MAX as u32 => {
+				return Err(bad_request("`cltv_expiry_delta` is too large"))
+			}
+			delta => delta.map(|delta| delta as u16),
+		},
+	};
+	let num_updated = cli::update_channel_policy(&target, &update, &state.channel_manager)
+		.map_err(|_| failed("failed to update channel policy"))?;
+	Ok(Json(json!({ "num_updated": num_updated })))
+}
+
+/// Takes the optional `max_attempts` or `retry_timeout_secs` fields to override the node's default
+/// retry strategy.
+fn retry_param(body: &Value, default: Retry) -> Result<Retry, (StatusCode, Json<Value>)> {
+	if body.get("max_attempts").is_some() {
+		let attempts = u64_param(body, "max_attempts")?;
+		return Ok(Retry::Attempts(attempts as usize));
+	}
+	if body.get("retry_timeout_secs").is_some() {
+		let secs = u64_param(body, "retry_timeout_secs")?;
+		return Ok(Retry::Timeout(Duration::from_secs(secs)));
+	}
+	Ok(default)
+}
+
+/// Takes the optional `max_fee_sat`, `max_fee_percent`, `max_hops`, `avoid_nodes` and
+/// `first_hop_channels` fields constraining the route of a payment.
+fn route_constraints_param(body: &Value) -> Result<RouteConstraints, (StatusCode, Json<Value>)> {
+	let mut route = RouteConstraints::default();
+	if body.get("max_fee_sat").is_some() {
+		route.max_fee_msat = Some(u64_param(body, "max_fee_sat")?.saturating_mul(1000));
+	}
+	if let Some(percent) = body.get("max_fee_percent") {
+		match percent.as_f64() {
+			Some(percent) if percent >= 0.0 => route.max_fee_percent = Some(percent),
+			_ => return Err(bad_request("max_fee_percent must be a non-negative number")),
+		}
+	}
+	if body.get("max_hops").is_some() {
+		match u64_param(body, "max_hops")? {
+			0 => return Err(bad_request("max_hops must be at least 1")),
+			hops => route.max_hops = Some(hops as usize),
+		}
+	}
+	for node in body["avoid_nodes"].as_array().into_iter().flatten() {
+		match node.as_str().and_then(hex_utils::to_compressed_pubkey) {
+			Some(pubkey) => route.avoid_nodes.push(pubkey),
+			None => return Err(bad_request("couldn't parse `avoid_nodes`")),
+		}
+	}
+	for channel in body["first_hop_channels"].as_array().into_iter().flatten() {
+		match channel.as_str().and_then(hex_utils::to_vec) {
+			Some(channel_id_vec) if channel_id_vec.len() == 32 => {
+				let mut channel_id = [0; 32];
+				channel_id.copy_from_slice(&channel_id_vec);
+				route.first_hop_channels.push(channel_id);
+			}
+			_ => return Err(bad_request("couldn't parse `first_hop_channels`")),
+		}
+	}
+	Ok(route)
+}
+
+/// Takes the retry and route fields plus the optional `max_parts` or `no_mpp` fields taken by
+/// `/sendpayment`.
+fn payment_options_param(
+	body: &Value, default_retry: Retry,
+) -> Result<cli::PaymentOptions, (StatusCode, Json<Value>)> {
+	let retry = retry_param(body, default_retry)?;
+	let max_parts = match body.get("max_parts") {
+		Some(_) => Some(
+			u8::try_from(u64_param(body, "max_parts")?)
+				.map_err(|_| bad_request("max_parts must be between 1 and 255"))?,
+		),
+		None => None,
+	};
+	let no_mpp = body.get("no_mpp").and_then(Value::as_bool).unwrap_or(false);
+	let route = route_constraints_param(body)?;
+	cli::PaymentOptions::new(retry, max_parts, no_mpp, route).map_err(|e| bad_request(&e))
+}
+
+async fn send_payment(State(state): State<Arc<ApiState>>, Json(body): Json<Value>) -> ApiResult {
+	let invoice = Invoice::from_str(str_param(&body, "invoice")?)
+		.map_err(|_| bad_request("invalid invoice"))?;
+	let options = payment_options_param(&body, state.payment_retry)?;
+	cli::send_payment(
+		&state.channel_manager,
+		&state.router,
+		&invoice,
+		options,
+		Arc::clone(&state.outbound_payments),
+		Arc::clone(&state.persister),
+	)
+	.map_err(|_| failed("failed to send payment"))?;
+	success()
+}
+
+async fn pay_lnurl_url(state: &ApiState, url: Result<String, String>, body: &Value) -> ApiResult {
+	let amt_msat = u64_param(body, "amount_msat")?;
+	let options = payment_options_param(body, state.payment_retry)?;
+	let url = url.map_err(|e| bad_request(&e))?;
+	let invoice = lnurl::fetch_invoice(&url, amt_msat).await.map_err(|e| failed(&e))?;
+	cli::send_payment(
+		&state.channel_manager,
+		&state.router,
+		&invoice,
+		options,
+		Arc::clone(&state.outbound_payments),
+		Arc::clone(&state.persister),
+	)
+	.map_err(|_| failed("failed to send payment"))?;
+	Ok(Json(json!({ "payment_hash": hex_utils::hex_str(&invoice.payment_hash()[..]) })))
+}
+
+async fn pay_address(State(state): State<Arc<ApiState>>, Json(body): Json<Value>) -> ApiResult {
+	let url = lnurl::address_to_url(str_param(&body, "address")?);
+	pay_lnurl_url(&state, url, &body).await
+}
+
+async fn pay_lnurl(State(state): State<Arc<ApiState>>, Json(body): Json<Value>) -> ApiResult {
+	let url = lnurl::decode_lnurl(str_param(&body, "lnurl")?);
+	pay_lnurl_url(&state, url, &body).await
+}
+
+async fn keysend(State(state): State<Arc<ApiState>>, Json(body): Json<Value>) -> ApiResult {
+	let dest_pubkey = pubkey_param(&body, "dest_pubkey")?;
+	let amt_msat = u64_param(&body, "amount_msat")?;
+	let retry = retry_param(&body, state.payment_retry)?;
+	cli::keysend(
+		&state.channel_manager,
+		dest_pubkey,
+		amt_msat,
+		retry,
+		&*state.keys_manager,
+		Arc::clone(&state.outbound_payments),
+		Arc::clone(&state.persister),
+	)
+	.map_err(|_| failed("failed to send payment"))?;
+	success()
+}
+
+/// Takes the optional `description`, `description_hash`, `fallback_address` and `route_hints`
+/// (a list of channel IDs) fields.
+fn invoice_options_param(
+	body: &Value, network: Network,
+) -> Result<cli::InvoiceOptions, (StatusCode, Json<Value>)> {
+	let mut options = cli::InvoiceOptions::default();
+	if body.get("description").is_some() {
+		options.description = str_param(body, "description")?.to_string();
+	}
+	if body.get("description_hash").is_some() {
+		options.description_hash = Some(hash_param(body, "description_hash")?);
+	}
+	if body.get("fallback_address").is_some() {
+		options.fallback_address = Some(
+			cli::parse_fallback_address(str_param(body, "fallback_address")?, network)
+				.map_err(|e| bad_request(&e))?,
+		);
+	}
+	if let Some(route_hints) = body.get("route_hints") {
+		let channel_ids = route_hints
+			.as_array()
+			.ok_or_else(|| bad_request("`route_hints` must be a list of channel IDs"))?;
+		let mut channels = Vec::new();
+		for channel_id in channel_ids {
+			match channel_id.as_str().and_then(hex_utils::to_vec) {
+				Some(id) if id.len() == 32 => {
+					let mut channel_id = [0; 32];
+					channel_id.copy_from_slice(&id);
+					channels.push(channel_id);
+				}
+				_ => return Err(bad_request("`route_hints` must be a list of channel IDs")),
+			}
+		}
+		options.route_hint_channels = Some(channels);
+	}
+	Ok(options)
+}
+
+async fn get_invoice(State(state): State<Arc<ApiState>>, Json(body): Json<Value>) -> ApiResult {
+	let amt_msat = u64_param(&body, "amount_msat")?;
+	let expiry_secs = u64_param(&body, "expiry_secs")?;
+	if expiry_secs > u32::MAX as u64 {
+		return Err(bad_request("`expiry_secs` is too large"));
+	}
+	let options = invoice_options_param(&body, state.network)?;
+	let invoice = cli::get_invoice(
+		amt_msat,
+		None,
+		options,
+		Arc::clone(&state.inbound_payments),
+		Arc::clone(&state.persister),
+		&state.channel_manager,
+		Arc::clone(&state.keys_manager),
+		state.network,
+		expiry_secs as u32,
+	)
+	.map_err(|_| failed("failed to create invoice"))?;
+	Ok(Json(json!({ "invoice": invoice.to_string() })))
+}
+
+async fn phantom_route_hints(State(state): State<Arc<ApiState>>) -> Json<Value> {
+	Json(json!({ "route_hints": cli::phantom_route_hints_hex(&state.channel_manager) }))
+}
+
+async fn get_phantom_invoice(
+	State(state): State<Arc<ApiState>>, Json(body): Json<Value>,
+) -> ApiResult {
+	let amt_msat = u64_param(&body, "amount_msat")?;
+	let expiry_secs = u64_param(&body, "expiry_secs")?;
+	if expiry_secs > u32::MAX as u64 {
+		return Err(bad_request("`expiry_secs` is too large"));
+	}
+	let description = body["description"].as_str().unwrap_or("ldk-tutorial-node").to_string();
+	let mut route_hints = Vec::new();
+	if let Some(hints) = body.get("route_hints") {
+		let hints = hints
+			.as_array()
+			.and_then(|hints| hints.iter().map(|hint| hint.as_str()).collect::<Option<Vec<_>>>())
+			.ok_or_else(|| bad_request("`route_hints` must be an array of strings"))?;
+		if !hints.is_empty() {
+			route_hints =
+				cli::parse_phantom_route_hints(&hints.join(",")).map_err(|e| bad_request(&e))?;
+		}
+	}
+	let invoice = cli::get_phantom_invoice(
+		amt_msat,
+		description,
+		expiry_secs as u32,
+		route_hints,
+		Arc::clone(&state.inbound_payments),
+		Arc::clone(&state.persister),
+		&state.channel_manager,
+		Arc::clone(&state.keys_manager),
+		Arc::clone(&state.logger),
+		state.network,
+	)
+	.map_err(|e| failed(&e))?;
+	Ok(Json(json!({ "invoice": invoice.to_string() })))
+}
+
+fn hash_param(body: &Value, name: &str) -> Result<[u8; 32], (StatusCode, Json<Value>)> {
+	match hex_utils::to_vec(str_param(body, name)?) {
+		Some(hash_vec) if hash_vec.len() == 32 => {
+			let mut hash = [0; 32];
+			hash.copy_from_slice(&hash_vec);
+			Ok(hash)
+		}
+		_ => Err(bad_request(&format!("couldn't parse `{}`", name))),
+	}
+}
+
+async fn add_hold_invoice(
+	State(state): State<Arc<ApiState>>, Json(body): Json<Value>,
+) -> ApiResult {
+	let payment_hash = PaymentHash(hash_param(&body, "payment_hash")?);
+	let amt_msat = u64_param(&body, "amount_msat")?;
+	let expiry_secs = u64_param(&body, "expiry_secs")?;
+	if expiry_secs > u32::MAX as u64 {
+		return Err(bad_request("`expiry_secs` is too large"));
+	}
+	let options = invoice_options_param(&body, state.network)?;
+	let invoice = cli::get_invoice(
+		amt_msat,
+		Some(payment_hash),
+		options,
+		Arc::clone(&state.inbound_payments),
+		Arc::clone(&state.persister),
+		&state.channel_manager,
+		Arc::clone(&state.keys_manager),
+		state.network,
+		expiry_secs as u32,
+	)
+	.map_err(|_| failed("failed to create invoice"))?;
+	Ok(Json(json!({ "invoice": invoice.to_string() })))
+}
+
+async fn list_invoices(State(state): State<Arc<ApiState>>) -> ApiResult {
+	Ok(Json(cli::invoices_json(&state.inbound_payments)))
+}
+
+async fn settle_invoice(State(state): State<Arc<ApiState>>, Json(body): Json<Value>) -> ApiResult {
+	let payment_preimage = PaymentPreimage(hash_param(&body, "payment_preimage")?);
+	cli::settle_invoice(
+		payment_preimage,
+		Arc::clone(&state.inbound_payments),
+		Arc::clone(&state.persister),
+		&state.channel_manager,
+	)
+	.map_err(|_| failed("failed to settle invoice"))?;
+	success()
+}
+
+async fn cancel_invoice(State(state): State<Arc<ApiState>>, Json(body): Json<Value>) -> ApiResult {
+	let payment_hash = PaymentHash(hash_param(&body, "payment_hash")?);
+	cli::cancel_invoice(
+		payment_hash,
+		Arc::clone(&state.inbound_payments),
+		Arc::clone(&state.persister),
+		&state.channel_manager,
+	)
+	.map_err(|_| failed("failed to cancel invoice"))?;
+	success()
+}
+
+async fn get_node_info(
+	State(state): State<Arc<ApiState>>, Path(node_pubkey): Path<String>,
+) -> ApiResult {
+	let node_pubkey = hex_utils::to_compressed_pubkey(&node_pubkey)
+		.ok_or_else(|| bad_request("couldn't parse node pubkey"))?;
+	cli::node_graph_json(&node_pubkey, &state.network_graph)
+		.map(Json)
+		.ok_or_else(|| (StatusCode::NOT_FOUND, Json(json!({ "error": "unknown node" }))))
+}
+
+async fn describe_graph(State(state): State<Arc<ApiState>>) -> Json<Value> {
+	Json(cli::graph_json(&state.network_graph))
+}
+
+/// Takes a graph in the format `/describegraph` returns.
+async fn import_graph(State(state): State<Arc<ApiState>>, Json(body): Json<Value>) -> ApiResult {
+	let (num_channels, num_updates) = cli::import_graph(&state.network_graph, &body, state.network)
+		.map_err(|e| bad_request(&e))?;
+	Ok(Json(json!({ "num_channels": num_channels, "num_updates": num_updates })))
+}
+
+async fn graph_stats(State(state): State<Arc<ApiState>>) -> Json<Value> {
+	Json(cli::graph_stats_json(&state.network_graph, &state.channel_manager.get_our_node_id()))
+}
+
+/// Issues a single-use LNURL-withdraw link paying out between `min_amount_msat` (0 by default) and
+/// `max_amount_msat` to whoever redeems it before it expires.
+async fn create_withdraw_link(
+	State(state): State<Arc<ApiState>>, Json(body): Json<Value>,
+) -> ApiResult {
+	let max_withdrawable_msat = u64_param(&body, "max_amount_msat")?;
+	let min_withdrawable_msat = match body.get("min_amount_msat") {
+		Some(_) => u64_param(&body, "min_amount_msat")?,
+		None => 0,
+	};
+	if min_withdrawable_msat > max_withdrawable_msat {
+		return Err(bad_request("`min_amount_msat` can't be larger than `max_amount_msat`"));
+	}
+	let expires_at = time_now_secs()
+		.checked_add(u64_param(&body, "expiry_secs")?)
+		.ok_or_else(|| bad_request("`expiry_secs` is too large"))?;
+	let description = match body.get("description") {
+		Some(_) => str_param(&body, "description")?.to_string(),
+		None => String::new(),
+	};
+	let public_url = state.public_url.as_ref().ok_or_else(|| {
+		bad_request(
+			"withdraw links are redeemed through --http-public-listen-addr, which isn't set",
+		)
+	})?;
+
+	let k1 = state.keys_manager.get_secure_random_bytes();
+	let mut links = state.withdraw_links.lock().unwrap();
+	links.insert(
+		k1,
+		WithdrawLink {
+			min_withdrawable_msat,
+			max_withdrawable_msat,
+			description,
+			expires_at,
+			redeemed: false,
+		},
+	);
+	if let Err(e) = state.persister.persist(WITHDRAW_LINKS_FNAME, &*links) {
+		links.remove(&k1);
+		return Err(failed(&format!("failed to persist the withdraw link: {}", e)));
+	}
+
+	let url = format!("{}/lnurlw/{}", public_url, hex_utils::hex_str(&k1));
+	Ok(Json(json!({
+		"k1": hex_utils::hex_str(&k1),
+		"url": url,
+		"lnurl": lnurl::encode_lnurl(&url),
+		"expires_at": expires_at,
+	})))
+}
+
+/// LNURL endpoints report errors in the body rather than through the status code.
+fn lnurl_error(reason: &str) -> Json<Value> {
+	Json(json!({ "status": "ERROR", "reason": reason }))
+}
+
+/// Our own failures still get a 500, so they can be told apart from the redeemer's mistakes.
+fn lnurl_failure(reason: &str) -> (StatusCode, Json<Value>) {
+	(StatusCode::INTERNAL_SERVER_ERROR, lnurl_error(reason))
+}
+
+fn k1_param(k1: &str) -> Option<[u8; 32]> {
+	match hex_utils::to_vec(k1) {
+		Some(k1_vec) if k1_vec.len() == 32 => {
+			let mut k1 = [0; 32];
+			k1.copy_from_slice(&k1_vec);
+			Some(k1)
+		}
+		_ => None,
+	}
+}
+
+/// The first step of LNURL-withdraw (LUD-03): describes what the link can be redeemed for.
+async fn withdraw_request(
+	State(state): State<Arc<ApiState>>, Path(k1_hex): Path<String>,
+) -> Json<Value> {
+	// Only `serve_public` serves this, which only runs with a public URL.
+	let public_url = match state.public_url.as_ref() {
+		Some(public_url) => public_url,
+		None => return lnurl_error("withdraw links aren't served"),
+	};
+	let links = state.withdraw_links.lock().unwrap();
+	let link = match k1_param(&k1_hex).and_then(|k1| links.get(&k1)) {
+		Some(link) => link,
+		None => return lnurl_error("unknown withdraw link"),
+	};
+	if link.redeemed {
+		return lnurl_error("withdraw link was already redeemed");
+	}
+	if time_now_secs() >= link.expires_at {
+		return lnurl_error("withdraw link has expired");
+	}
+	Json(json!({
+		"tag": "withdrawRequest",
+		"callback": format!("{}/lnurlw/{}/callback", public_url, k1_hex),
+		"k1": k1_hex,
+		"defaultDescription": link.description,
+		"minWithdrawable": link.min_withdrawable_msat,
+		"maxWithdrawable": link.max_withdrawable_msat,
+	}))
+}
+
+/// The second step of LNURL-withdraw: pays the invoice `pr` supplied by the redeemer, marking the
+/// link as redeemed. If the payment can't be initiated, the link can be redeemed again.
+async fn withdraw_callback(
+	State(state): State<Arc<ApiState>>, Path(k1_hex): Path<String>,
+	Query(params): Query<HashMap<String, String>>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+	if params.get("k1") != Some(&k1_hex) {
+		return Ok(lnurl_error("k1 doesn't match the withdraw link"));
+	}
+	let k1 = match k1_param(&k1_hex) {
+		Some(k1) => k1,
+		None => return Ok(lnurl_error("unknown withdraw link")),
+	};
+	let invoice = match params.get("pr").map(|pr| Invoice::from_str(pr)) {
+		Some(Ok(invoice)) => invoice,
+		_ => return Ok(lnurl_error("missing or invalid invoice")),
+	};
+	let options =
+		cli::PaymentOptions::new(state.payment_retry, None, false, RouteConstraints::default())
+			.map_err(|e| lnurl_failure(&e))?;
+	{
+		let mut links = state.withdraw_links.lock().unwrap();
+		let link = match links.get_mut(&k1) {
+			Some(link) => link,
+			None => return Ok(lnurl_error("unknown withdraw link")),
+		};
+		if link.redeemed {
+			return Ok(lnurl_error("withdraw link was already redeemed"));
+		}
+		if time_now_secs() >= link.expires_at {
+			return Ok(lnurl_error("withdraw link has expired"));
+		}
+		match invoice.amount_milli_satoshis() {
+			Some(amt) if amt >= link.min_withdrawable_msat && amt <= link.max_withdrawable_msat => {
+			}
+			_ => return Ok(lnurl_error("invoice amount is outside of the withdraw link's limits")),
+		}
+		link.redeemed = true;
+		// The link must be recorded as redeemed before paying, or a restart could pay it twice.
+		if let Err(e) = state.persister.persist(WITHDRAW_LINKS_FNAME, &*links) {
+			links.get_mut(&k1).unwrap().redeemed = false;
+			println!("ERROR: failed to persist {}: {}", WITHDRAW_LINKS_FNAME, e);
+			return Err(lnurl_failure("failed to record the withdrawal"));
+		}
+	}
+
+	let res = cli::send_payment(
+		&state.channel_manager,
+		&state.router,
+		&invoice,
+		options,
+		Arc::clone(&state.outbound_payments),
+		Arc::clone(&state.persister),
+	);
+	if res.is_err() {
+		let mut links = state.withdraw_links.lock().unwrap();
+		if let Some(link) = links.get_mut(&k1) {
+			link.redeemed = false;
+		}
+		// Worst case the link stays redeemed on disk, and can't be redeemed again after a restart.
+		if let Err(e) = state.persister.persist(WITHDRAW_LINKS_FNAME, &*links) {
+			println!("ERROR: failed to persist {}: {}", WITHDRAW_LINKS_FNAME, e);
+		}
+		return Ok(lnurl_error("failed to pay invoice"));
+	}
+	Ok(Json(json!({ "status": "OK" })))
+}
+
+async fn decode_invoice(Json(body): Json<Value>) -> ApiResult {
+	let invoice = Invoice::from_str(str_param(&body, "invoice")?)
+		.map_err(|_| bad_request("invalid invoice"))?;
+	Ok(Json(cli::invoice_json(&invoice)))
+}
+
+async fn sign_message(State(state): State<Arc<ApiState>>, Json(body): Json<Value>) -> ApiResult {
+	let message = str_param(&body, "message")?;
+	let signature = state
+		.keys_manager
+		.sign_message(message.as_bytes())
+		.map_err(|()| failed("failed to sign message"))?;
+	Ok(Json(json!({ "signature": signature })))
+}
+
+async fn verify_message(State(state): State<Arc<ApiState>>, Json(body): Json<Value>) -> ApiResult {
+	let message = str_param(&body, "message")?;
+	let signature = str_param(&body, "signature")?;
+	let res = cli::verify_message_json(message.as_bytes(), signature, &state.network_graph)
+		.map_err(|_| bad_request("invalid signature"))?;
+	Ok(Json(res))
+}
+
+async fn events(ws: WebSocketUpgrade, State(state): State<Arc<ApiState>>) -> Response {
+	let receiver = state.events.subscribe();
+	ws.on_upgrade(move |socket| stream_events(socket, receiver))
+}
+
+async fn stream_events(mut socket: WebSocket, mut receiver: broadcast::Receiver<Value>) {
+	loop {
+		match receiver.recv().await {
+			Ok(event) => {
+				if socket.send(Message::Text(event.to_string())).await.is_err() {
+					// The client went away.
+					return;
+				}
+			}
+			// A slow client simply misses the events it couldn't keep up with.
+			Err(broadcast::error::RecvError::Lagged(_)) => {}
+			Err(broadcast::error::RecvError::Closed) => return,
+		}
+	}
+}
+
+/// Converts the events that are interesting to API clients into the JSON sent over `/ws/events`.
+pub(crate) fn event_json(event: &Event) -> Option<Value> {
+	match event {
+		Event::PaymentClaimed { payment_hash, purpose, amount_msat, .. } => Some(json!({
+			"type": "payment_received",
+			"payment_hash": hex_utils::hex_str(&payment_hash.0),
+			"amount_msat": amount_msat,
+			"spontaneous": matches!(purpose, PaymentPurpose::SpontaneousPayment(_)),
+		})),
+		Event::PaymentSent { payment_hash, fee_paid_msat, .. } => Some(json!({
+			"type": "payment_sent",
+			"payment_hash": hex_utils::hex_str(&payment_hash.0),
+			"fee_paid_msat": fee_paid_msat,
+		})),
+		Event::PaymentFailed { payment_hash, .. } => Some(json!({
+			"type": "payment_failed",
+			"payment_hash": hex_utils::hex_str(&payment_hash.0),
+		})),
+		Event::PaymentForwarded { prev_channel_id, next_channel_id, fee_earned_msat, .. } => {
+			Some(json!({
+				"type": "payment_forwarded",
+				"prev_channel_id": prev_channel_id.map(|id| hex_utils::hex_str(&id)),
+				"next_channel_id": next_channel_id.map(|id| hex_utils::hex_str(&id)),
+				"fee_earned_msat": fee_earned_msat,
+			}))
+		}
+		Event::ChannelReady { channel_id, counterparty_node_id, .. } => Some(json!({
+			"type": "channel_ready",
+			"channel_id": hex_utils::hex_str(channel_id),
+			"peer_pubkey": hex_utils::hex_str(&counterparty_node_id.serialize()),
+		})),
+		Event::ChannelClosed { channel_id, reason, .. } => Some(json!({
+			"type": "channel_closed",
+			"channel_id": hex_utils::hex_str(channel_id),
+			"reason": format!("{:?}", reason),
+		})),
+		_ => None,
+	}
+}
+
+/// Shuts the node down and exits the process once it's done, after responding.
+async fn stop(State(state): State<Arc<ApiState>>) -> ApiResult {
+	// A full channel means a shutdown is already in progress.
+	let _ = state.shutdown.try_send(true);
+	success()
+}
+
+/// Serves the REST API and the `/ws/events` websocket on `addr` until the process exits. All but
+/// the routes other nodes and wallets fetch require `token`.
+pub(crate) async fn serve(addr: SocketAddr, state: Arc<ApiState>, token: String) {
+	let app = Router::new()
+		.route("/nodeinfo", get(node_info))
+		.route("/estimatefees", get(estimate_fees))
+		.route("/listunconfirmed", get(list_unconfirmed))
+		.route("/backupstatus", get(backup_status))
+		.route("/verifystate", get(verify_state))
+		.route("/chainstatus", get(chain_status))
+		.route("/newaddress", post(new_address))
+		.route("/onchainbalance", get(onchain_balance))
+		.route("/listchannels", get(list_channels))
+		.route("/listpeers", get(list_peers))
+		.route("/listpayments", get(list_payments))
+		.route("/listforwards", get(list_forwards))
+		.route("/getnodeinfo/:node_pubkey", get(get_node_info))
+		.route("/describegraph", get(describe_graph))
+		.route("/importgraph", post(import_graph))
+		.route("/graphstats", get(graph_stats))
+		.route("/queryroutes/:dest_pubkey", get(query_routes))
+		.route("/probe", post(probe))
+		.route("/probestats", get(probe_stats))
+		.route("/scorerinfo", get(scorer_info))
+		.route("/resetscorer", post(reset_scorer))
+		.route("/connectpeer", post(connect_peer))
+		.route("/bootstrap", post(bootstrap))
+		.route("/disconnectpeer", post(disconnect_peer))
+		.route("/setreconnect", post(set_reconnect))
+		.route("/setgossipsync", post(set_gossip_sync))
+		.route("/openchannel", post(open_channel))
+		.route("/fundchannel", post(fund_channel))
+		.route("/closechannel", post(close_channel))
+		.route("/abandonchannel", post(abandon_channel))
+		.route("/updatechannelpolicy", post(update_channel_policy))
+		.route("/sendpayment", post(send_payment))
+		.route("/payaddress", post(pay_address))
+		.route("/paylnurl", post(pay_lnurl))
+		.route("/keysend", post(keysend))
+		.route("/getinvoice", post(get_invoice))
+		.route("/addholdinvoice", post(add_hold_invoice))
+		.route("/getphantominvoice", post(get_phantom_invoice))
+		.route("/phantomroutehints", get(phantom_route_hints))
+		.route("/listinvoices", get(list_invoices))
+		.route("/settleinvoice", post(settle_invoice))
+		.route("/cancelinvoice", post(cancel_invoice))
+		.route("/createwithdrawlink", post(create_withdraw_link))
+		.route("/decodeinvoice", post(decode_invoice))
+		.route("/signmessage", post(sign_message))
+		.route("/verifymessage", post(verify_message))
+		.route("/stop", post(stop))
+		.route("/ws/events", get(events))
+		.route_layer(middleware::from_fn_with_state(Arc::new(token), require_token))
+		.with_state(state);
+
+	let server = match axum::Server::try_bind(&addr) {
+		Ok(server) => server,
+		Err(e) => {
+			println!("ERROR: failed to bind HTTP API to {}: {}", addr, e);
+			return;
+		}
+	};
+	if let Err(e) = server.serve(app.into_make_service()).await {
+		println!("ERROR: HTTP API server stopped: {}", e);
+	}
+}
+
+/// Serves the routes other nodes and wallets fetch on `addr`, the RGS snapshots and LNURL-withdraw
+/// ones, apart from the rest of the API so only they have to be exposed.
+pub(crate) async fn serve_public(addr: SocketAddr, state: Arc<ApiState>) {
+	let app = Router::new()
+		.route("/rgs/snapshot/:last_sync", get(rgs_snapshot))
+		.route("/lnurlw/:k1", get(withdraw_request))
+		.route("/lnurlw/:k1/callback", get(withdraw_callback))
+		.with_state(state);
+
+	let server = match axum::Server::try_bind(&addr) {
+		Ok(server) => server,
+		Err(e) => {
+			println!("ERROR: failed to bind the public HTTP API to {}: {}", addr, e);
+			return;
+		}
+	};
+	if let Err(e) = server.serve(app.into_make_service()).await {
+		println!("ERROR: public HTTP API server stopped: {}", e);
+	}
+}
+
+#[derive(Clone)]
+struct LockedState {
+	unlocker: Unlocker,
+	/// Whether the CLI is also waiting for `unlock`, on a line that has to be entered to let go.
+	cli_waiting: bool,
+}
+
+async fn unlock(State(state): State<LockedState>, Json(body): Json<Value>) -> ApiResult {
+	let passphrase = str_param(&body, "passphrase")?.to_string();
+	state.unlocker.unlock(passphrase).await.map_err(|e| bad_request(&e))?;
+	if state.cli_waiting {
+		println!("\nEVENT: the node was unlocked through the HTTP API, press Enter to continue");
+	}
+	success()
+}
+
+async fn locked() -> (StatusCode, Json<Value>) {
+	let msg = "the node is locked, unlock it through POST /unlock first";
+	(StatusCode::SERVICE_UNAVAILABLE, Json(json!({ "error": msg })))
+}
+
+/// Serves only `POST /unlock`, which requires `token`, on `addr` while the node is locked, until
+/// `stop` fires once it's unlocked and the full API is about to take over.
+pub(crate) async fn serve_locked(
+	addr: SocketAddr, unlocker: Unlocker, cli_waiting: bool, token: String,
+	stop: oneshot::Receiver<()>,
+) {
+	let app = Router::new()
+		.route("/unlock", post(unlock))
+		.route_layer(middleware::from_fn_with_state(Arc::new(token), require_token))
+		.fallback(locked)
+		.with_state(LockedState { unlocker, cli_waiting });
+
+	let server = match axum::Server::try_bind(&addr) {
+		Ok(server) => server,
+		Err(e) => {
+			println!("ERROR: failed to bind HTTP API to {}: {}", addr, e);
+			return;
+		}
+	};
+	let stop = async {
+		let _ = stop.await;
+	};
+	if let Err(e) = server.serve(app.into_make_service()).with_graceful_shutdown(stop).await {
+		println!("ERROR: HTTP API server stopped: {}", e);
+	}
+}
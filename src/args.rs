@@ -1,19 +1,661 @@
+use crate::backup::BackupTarget;
+use crate::bitcoind_client::{
+	BitcoindRpcInfo, FeeBlend, FeeConfig, FeeSource, Target, DEFAULT_MEMPOOL_SPACE_URL,
+	DEFAULT_WHATTHEFEE_URL,
+};
+use crate::bootstrap;
 use crate::cli::LdkUserInfo;
+use crate::encryption::EncryptionSetup;
+use crate::mnemonic::MnemonicSetup;
+use crate::persist::{StorageBackend, DEFAULT_VSS_STORE_ID};
+use crate::snapshot::SnapshotConfig;
+use crate::tor::{TorConfig, DEFAULT_TOR_SOCKS_ADDR};
+use crate::wallet::AddressType;
+use crate::ProbeConfig;
 use bitcoin::network::constants::Network;
+use bitcoin::secp256k1::PublicKey;
+use lightning::ln::channelmanager::Retry;
 use lightning::ln::msgs::NetAddress;
+use lightning::routing::scoring::ProbabilisticScoringParameters;
 use std::collections::HashMap;
 use std::env;
 use std::fs;
-use std::net::IpAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::time::Duration;
+
+/// What `cargo run migrate-storage` was asked to do.
+pub(crate) struct MigrateStorageArgs {
+	pub(crate) ldk_storage_dir_path: String,
+	pub(crate) from: StorageBackend,
+	pub(crate) to: StorageBackend,
+}
+
+/// The backend `--storage=<name>` names, for the developer commands that take the node's state
+/// offline.
+fn parse_storage_backend(
+	name: &str, postgres_url: Option<&str>, vss_url: Option<&str>, vss_store_id: Option<&str>,
+) -> Result<StorageBackend, ()> {
+	match name {
+		"files" => Ok(StorageBackend::Filesystem),
+		"sqlite" => Ok(StorageBackend::Sqlite),
+		"postgres" => match postgres_url {
+			Some(url) => Ok(StorageBackend::Postgres(url.to_string())),
+			None => {
+				println!("ERROR: postgres storage requires --postgres-url=<connection string>");
+				Err(())
+			}
+		},
+		"vss" => match vss_url {
+			Some(url) => Ok(StorageBackend::Vss {
+				url: url.to_string(),
+				store_id: vss_store_id.unwrap_or(DEFAULT_VSS_STORE_ID).to_string(),
+			}),
+			None => {
+				println!("ERROR: vss storage requires --vss-url=<url>");
+				Err(())
+			}
+		},
+		_ => {
+			println!("ERROR: unknown storage backend {}", name);
+			Err(())
+		}
+	}
+}
+
+/// Sets the scorer parameter `--scorer=<name>:<value>` names, half-lives being given in seconds.
+fn set_scoring_param(
+	params: &mut ProbabilisticScoringParameters, name: &str, value: u64,
+) -> Result<(), ()> {
+	match name {
+		"base_penalty_msat" => params.base_penalty_msat = value,
+		"base_penalty_amount_multiplier_msat" => params.base_penalty_amount_multiplier_msat = value,
+		"liquidity_penalty_multiplier_msat" => params.liquidity_penalty_multiplier_msat = value,
+		"liquidity_penalty_amount_multiplier_msat" => {
+			params.liquidity_penalty_amount_multiplier_msat = value
+		}
+		"historical_liquidity_penalty_multiplier_msat" => {
+			params.historical_liquidity_penalty_multiplier_msat = value
+		}
+		"historical_liquidity_penalty_amount_multiplier_msat" => {
+			params.historical_liquidity_penalty_amount_multiplier_msat = value
+		}
+		"anti_probing_penalty_msat" => params.anti_probing_penalty_msat = value,
+		"liquidity_offset_half_life_secs" => {
+			params.liquidity_offset_half_life = Duration::from_secs(value)
+		}
+		"historical_no_updates_half_life_secs" => {
+			params.historical_no_updates_half_life = Duration::from_secs(value)
+		}
+		_ => {
+			println!("ERROR: unknown scorer parameter {}", name);
+			return Err(());
+		}
+	}
+	Ok(())
+}
+
+pub(crate) fn parse_migrate_storage_args() -> Result<MigrateStorageArgs, ()> {
+	let usage = "`cargo run migrate-storage ldk_storage_directory_path --from=<files|sqlite|postgres|vss> --to=<files|sqlite|postgres|vss> [--postgres-url=<url>] [--vss-url=<url> [--vss-store-id=<id>]]`";
+	let (flags, args): (Vec<String>, Vec<String>) =
+		env::args().skip(2).partition(|arg| arg.starts_with("--"));
+	let mut from = None;
+	let mut to = None;
+	let mut postgres_url = None;
+	let mut vss_url = None;
+	let mut vss_store_id = None;
+	for flag in flags.iter() {
+		let mut flag_parts = flag.splitn(2, '=');
+		match (flag_parts.next(), flag_parts.next()) {
+			(Some("--from"), Some(name)) => from = Some(name.to_string()),
+			(Some("--to"), Some(name)) => to = Some(name.to_string()),
+			(Some("--postgres-url"), Some(url)) => postgres_url = Some(url.to_string()),
+			(Some("--vss-url"), Some(url)) => vss_url = Some(url.trim_end_matches('/').to_string()),
+			(Some("--vss-store-id"), Some(id)) => vss_store_id = Some(id.to_string()),
+			_ => {
+				println!("ERROR: unknown option {}", flag);
+				return Err(());
+			}
+		}
+	}
+	let (from, to) = match (from, to, args.len()) {
+		(Some(from), Some(to), 1) => (from, to),
+		_ => {
+			println!(
+				"ERROR: migrate-storage requires a storage directory and two backends: {}",
+				usage
+			);
+			return Err(());
+		}
+	};
+	let backend = |name: &str| {
+		parse_storage_backend(
+			name,
+			postgres_url.as_deref(),
+			vss_url.as_deref(),
+			vss_store_id.as_deref(),
+		)
+	};
+	let (from, to) = (backend(&from)?, backend(&to)?);
+	if from == to {
+		println!("ERROR: --from and --to must be different backends");
+		return Err(());
+	}
+	Ok(MigrateStorageArgs { ldk_storage_dir_path: args[0].clone(), from, to })
+}
+
+/// What `cargo run restore-snapshot` was asked to do.
+pub(crate) struct RestoreSnapshotArgs {
+	pub(crate) ldk_storage_dir_path: String,
+	pub(crate) timestamp: u64,
+	pub(crate) snapshot_dir: PathBuf,
+	pub(crate) storage: StorageBackend,
+}
+
+pub(crate) fn parse_restore_snapshot_args() -> Result<RestoreSnapshotArgs, ()> {
+	let usage = "`cargo run restore-snapshot ldk_storage_directory_path <timestamp> --snapshot-dir=<path> [--storage=<files|sqlite|postgres|vss>] [--postgres-url=<url>] [--vss-url=<url> [--vss-store-id=<id>]]`";
+	let (flags, args): (Vec<String>, Vec<String>) =
+		env::args().skip(2).partition(|arg| arg.starts_with("--"));
+	let mut snapshot_dir = None;
+	let mut storage = "files".to_string();
+	let mut postgres_url = None;
+	let mut vss_url = None;
+	let mut vss_store_id = None;
+	for flag in flags.iter() {
+		let mut flag_parts = flag.splitn(2, '=');
+		match (flag_parts.next(), flag_parts.next()) {
+			(Some("--snapshot-dir"), Some(path)) => snapshot_dir = Some(PathBuf::from(path)),
+			(Some("--storage"), Some(name)) => storage = name.to_string(),
+			(Some("--postgres-url"), Some(url)) => postgres_url = Some(url.to_string()),
+			(Some("--vss-url"), Some(url)) => vss_url = Some(url.trim_end_matches('/').to_string()),
+			(Some("--vss-store-id"), Some(id)) => vss_store_id = Some(id.to_string()),
+			_ => {
+				println!("ERROR: unknown option {}", flag);
+				return Err(());
+			}
+		}
+	}
+	let (snapshot_dir, timestamp) = match (snapshot_dir, args.get(1).map(|ts| ts.parse::<u64>())) {
+		(Some(dir), Some(Ok(timestamp))) if args.len() == 2 => (dir, timestamp),
+		_ => {
+			println!(
+				"ERROR: restore-snapshot requires a storage directory, a snapshot timestamp and the snapshot directory: {}",
+				usage
+			);
+			return Err(());
+		}
+	};
+	let storage = parse_storage_backend(
+		&storage,
+		postgres_url.as_deref(),
+		vss_url.as_deref(),
+		vss_store_id.as_deref(),
+	)?;
+	Ok(RestoreSnapshotArgs {
+		ldk_storage_dir_path: args[0].clone(),
+		timestamp,
+		snapshot_dir,
+		storage,
+	})
+}
+
+/// What `cargo run signer-daemon` was asked to do.
+#[cfg(unix)]
+pub(crate) struct SignerDaemonArgs {
+	pub(crate) signer_dir: PathBuf,
+	pub(crate) socket_path: PathBuf,
+	pub(crate) mnemonic: Option<MnemonicSetup>,
+}
+
+#[cfg(unix)]
+pub(crate) fn parse_signer_daemon_args() -> Result<SignerDaemonArgs, ()> {
+	let usage =
+		"`cargo run signer-daemon signer_directory_path <socket_path> [--mnemonic=<new|import>]`";
+	let (flags, args): (Vec<String>, Vec<String>) =
+		env::args().skip(2).partition(|arg| arg.starts_with("--"));
+	let mut mnemonic = None;
+	for flag in flags.iter() {
+		match flag.as_str() {
+			"--mnemonic=new" => mnemonic = Some(MnemonicSetup::New),
+			"--mnemonic=import" => mnemonic = Some(MnemonicSetup::Import),
+			_ => {
+				println!("ERROR: unknown option {}", flag);
+				return Err(());
+			}
+		}
+	}
+	if args.len() != 2 {
+		println!(
+			"ERROR: signer-daemon requires a signer directory and the socket to listen on: {}",
+			usage
+		);
+		return Err(());
+	}
+	Ok(SignerDaemonArgs {
+		signer_dir: PathBuf::from(&args[0]),
+		socket_path: PathBuf::from(&args[1]),
+		mnemonic,
+	})
+}
 
 pub(crate) fn parse_startup_args() -> Result<LdkUserInfo, ()> {
-	if env::args().len() < 3 {
-		println!("ldk-tutorial-node requires at least 2 arguments: `cargo run [<bitcoind-rpc-username>:<bitcoind-rpc-password>@]<bitcoind-rpc-host>:<bitcoind-rpc-port> ldk_storage_directory_path [<ldk-incoming-peer-listening-port>] [bitcoin-network] [announced-node-name announced-listen-addr*]`");
+	parse_args(env::args().collect())
+}
+
+fn parse_args(mut args: Vec<String>) -> Result<LdkUserInfo, ()> {
+	// Everything after a `--` separator is a command to run once instead of starting the
+	// interactive prompt, e.g. `cargo run <rpc-info> <storage-dir> -- listchannels`.
+	let oneshot_command = match args.iter().position(|arg| arg == "--") {
+		Some(idx) => {
+			let command = args.split_off(idx)[1..].join(" ");
+			if command.is_empty() {
+				println!("ERROR: no command provided after `--`");
+				return Err(());
+			}
+			Some(command)
+		}
+		None => None,
+	};
+
+	// Optional `--name=value` settings may be given anywhere among the positional arguments.
+	let (flags, args): (Vec<String>, Vec<String>) =
+		args.into_iter().partition(|arg| arg.starts_with("--"));
+	let mut http_listen_addr = None;
+	let mut http_public_listen_addr = None;
+	let mut http_public_url = None;
+	let mut probe_interval = None;
+	let mut probe_config = ProbeConfig::default();
+	let mut accept_keysend = true;
+	let mut zmq_hashblock_endpoint = None;
+	let mut block_fallback_esplora_url = None;
+	let mut bitcoind_backups = Vec::new();
+	let mut bitcoind_rpc_cookie = None;
+	let mut fee_config = FeeConfig::default();
+	let mut storage = StorageBackend::Filesystem;
+	let mut postgres_url = None;
+	let mut vss_url = None;
+	let mut vss_store_id = None;
+	let mut encryption = EncryptionSetup::Unlock;
+	let mut passphrase_file = None;
+	let mut locked = false;
+	let mut tor_control_addr = None;
+	let mut tor_control_password = None;
+	let mut tor_socks_addr = None;
+	let mut tor_only = false;
+	let mut proxy = None;
+	let mut dns_seeds = Vec::new();
+	let mut rgs_url = None;
+	let mut rgs_serve = false;
+	let mut scoring_params = ProbabilisticScoringParameters::default();
+	let mut scorer_persist_interval = None;
+	let mut backup = None;
+	let mut recover_backup = None;
+	let mut mnemonic = None;
+	let mut remote_signer = None;
+	let mut phantom_secret = None;
+	let mut create_phantom_secret = false;
+	let mut sweep_address_type = AddressType::P2wpkh;
+	let mut snapshot_dir = None;
+	let mut snapshot_config = SnapshotConfig::default();
+	let mut payment_retry = Retry::Timeout(Duration::from_secs(10));
+	for flag in flags.iter() {
+		let mut flag_parts = flag.splitn(2, '=');
+		match (flag_parts.next(), flag_parts.next()) {
+			(Some("--http-listen-addr"), Some(addr)) => match SocketAddr::from_str(addr) {
+				Ok(addr) => http_listen_addr = Some(addr),
+				Err(_) => {
+					println!("ERROR: couldn't parse --http-listen-addr into a socket address");
+					return Err(());
+				}
+			},
+			(Some("--http-public-listen-addr"), Some(addr)) => match SocketAddr::from_str(addr) {
+				Ok(addr) => http_public_listen_addr = Some(addr),
+				Err(_) => {
+					println!(
+						"ERROR: couldn't parse --http-public-listen-addr into a socket address"
+					);
+					return Err(());
+				}
+			},
+			(Some("--http-public-url"), Some(url)) => {
+				http_public_url = Some(url.trim_end_matches('/').to_string())
+			}
+			(Some("--probe-interval-secs"), Some(secs)) => match secs.parse::<u64>() {
+				Ok(secs) if secs > 0 => probe_interval = Some(Duration::from_secs(secs)),
+				_ => {
+					println!("ERROR: --probe-interval-secs must be a positive number of seconds");
+					return Err(());
+				}
+			},
+			(Some("--probe-amount-msat"), Some(amt)) => match amt.parse::<u64>() {
+				Ok(amt) if amt > 0 => probe_config.amount_msat = amt,
+				_ => {
+					println!("ERROR: --probe-amount-msat must be a positive number of msats");
+					return Err(());
+				}
+			},
+			(Some("--probe-target"), Some(pubkey)) => match PublicKey::from_str(pubkey) {
+				Ok(pubkey) => probe_config.targets.push(pubkey),
+				Err(_) => {
+					println!("ERROR: couldn't parse --probe-target into a pubkey");
+					return Err(());
+				}
+			},
+			(Some("--probe-num-targets"), Some(n)) => match n.parse::<usize>() {
+				Ok(n) if n > 0 => probe_config.num_targets = n,
+				_ => {
+					println!("ERROR: --probe-num-targets must be a positive number");
+					return Err(());
+				}
+			},
+			(Some("--payment-max-attempts"), Some(attempts)) => match attempts.parse() {
+				Ok(attempts) => payment_retry = Retry::Attempts(attempts),
+				Err(_) => {
+					println!("ERROR: --payment-max-attempts must be a number");
+					return Err(());
+				}
+			},
+			(Some("--payment-retry-timeout-secs"), Some(secs)) => match secs.parse() {
+				Ok(secs) => payment_retry = Retry::Timeout(Duration::from_secs(secs)),
+				Err(_) => {
+					println!("ERROR: --payment-retry-timeout-secs must be a number");
+					return Err(());
+				}
+			},
+			(Some("--reject-keysend"), None) => accept_keysend = false,
+			(Some("--zmq-hashblock"), Some(endpoint)) => {
+				zmq_hashblock_endpoint = Some(endpoint.to_string())
+			}
+			(Some("--bitcoind-backup"), Some(rpc_info)) => bitcoind_backups.push(rpc_info),
+			(Some("--bitcoind-rpc-cookie"), Some(path)) => {
+				bitcoind_rpc_cookie = Some(PathBuf::from(path))
+			}
+			(Some("--fee-source"), Some(source)) => {
+				let mut source_parts = source.splitn(2, ':');
+				let source = match (source_parts.next(), source_parts.next()) {
+					(Some("mempool"), url) => FeeSource::MempoolSpace(
+						url.unwrap_or(DEFAULT_MEMPOOL_SPACE_URL).trim_end_matches('/').to_string(),
+					),
+					(Some("whatthefee"), url) => {
+						FeeSource::WhatTheFee(url.unwrap_or(DEFAULT_WHATTHEFEE_URL).to_string())
+					}
+					_ => {
+						println!(
+							"ERROR: --fee-source must be `mempool[:<url>]` or `whatthefee[:<url>]`"
+						);
+						return Err(());
+					}
+				};
+				fee_config.sources.push(source);
+			}
+			(Some("--fee-blend"), Some("max")) => fee_config.blend = FeeBlend::Max,
+			(Some("--fee-blend"), Some("median")) => fee_config.blend = FeeBlend::Median,
+			(Some("--fee-max-sat-per-vb"), Some(max)) => match max.parse::<u32>() {
+				Ok(max) if max > 0 => fee_config.max_sat_per_vb = max,
+				_ => {
+					println!("ERROR: --fee-max-sat-per-vb must be a positive number");
+					return Err(());
+				}
+			},
+			(Some(flag @ "--fee-target"), Some(setting))
+			| (Some(flag @ "--fee-override"), Some(setting)) => {
+				let mut setting_parts = setting.splitn(2, ':');
+				let (target, value) = match (
+					setting_parts.next().map(Target::from_str),
+					setting_parts.next().map(str::parse::<u32>),
+				) {
+					(Some(Ok(target)), Some(Ok(value))) if value > 0 => (target, value),
+					(Some(Err(e)), _) => {
+						println!("ERROR: {}", e);
+						return Err(());
+					}
+					_ => {
+						println!("ERROR: {} must be given as <target>:<positive number>", flag);
+						return Err(());
+					}
+				};
+				let settings = fee_config.targets.get_mut(&target).unwrap();
+				if flag == "--fee-target" {
+					settings.conf_blocks = std::cmp::min(value, u16::MAX as u32) as u16;
+				} else {
+					settings.override_sat_per_kw = Some(value.saturating_mul(250));
+				}
+			}
+			(Some("--scorer"), Some(setting)) => {
+				let mut setting_parts = setting.splitn(2, ':');
+				match (setting_parts.next(), setting_parts.next().map(str::parse::<u64>)) {
+					(Some(name), Some(Ok(value))) => {
+						set_scoring_param(&mut scoring_params, name, value)?
+					}
+					_ => {
+						println!("ERROR: --scorer must be given as <parameter>:<number>");
+						return Err(());
+					}
+				}
+			}
+			(Some("--scorer-persist-interval-secs"), Some(secs)) => match secs.parse::<u64>() {
+				Ok(secs) if secs > 0 => scorer_persist_interval = Some(Duration::from_secs(secs)),
+				_ => {
+					println!(
+						"ERROR: --scorer-persist-interval-secs must be a positive number of seconds"
+					);
+					return Err(());
+				}
+			},
+			(Some("--storage"), Some("files")) => storage = StorageBackend::Filesystem,
+			(Some("--storage"), Some("sqlite")) => storage = StorageBackend::Sqlite,
+			(Some("--storage"), Some("postgres")) => {
+				storage = StorageBackend::Postgres(String::new())
+			}
+			(Some("--postgres-url"), Some(url)) => postgres_url = Some(url.to_string()),
+			(Some("--storage"), Some("vss")) => {
+				storage = StorageBackend::Vss { url: String::new(), store_id: String::new() }
+			}
+			(Some("--vss-url"), Some(url)) => vss_url = Some(url.trim_end_matches('/').to_string()),
+			(Some("--vss-store-id"), Some(id)) => vss_store_id = Some(id.to_string()),
+			(Some("--encrypt"), None) => encryption = EncryptionSetup::New,
+			(Some("--encrypt-existing"), None) => encryption = EncryptionSetup::Existing,
+			(Some("--passphrase-file"), Some(path)) => passphrase_file = Some(PathBuf::from(path)),
+			(Some("--locked"), None) => locked = true,
+			(Some(flag @ "--tor-control"), Some(addr))
+			| (Some(flag @ "--tor-socks"), Some(addr)) => match SocketAddr::from_str(addr) {
+				Ok(addr) if flag == "--tor-control" => tor_control_addr = Some(addr),
+				Ok(addr) => tor_socks_addr = Some(addr),
+				Err(_) => {
+					println!("ERROR: couldn't parse {} into a socket address", flag);
+					return Err(());
+				}
+			},
+			(Some("--tor-control-password"), Some(password)) => {
+				tor_control_password = Some(password.to_string())
+			}
+			(Some("--tor-only"), None) => tor_only = true,
+			(Some("--proxy"), Some(addr)) => match SocketAddr::from_str(addr) {
+				Ok(addr) => proxy = Some(addr),
+				Err(_) => {
+					println!("ERROR: couldn't parse --proxy into a socket address");
+					return Err(());
+				}
+			},
+			(Some("--dns-seed"), Some(seed)) => dns_seeds.push(seed.to_string()),
+			(Some("--rgs-url"), Some(url)) => rgs_url = Some(url.to_string()),
+			(Some("--rgs-serve"), None) => rgs_serve = true,
+			(Some("--mnemonic"), Some("new")) => mnemonic = Some(MnemonicSetup::New),
+			(Some("--mnemonic"), Some("import")) => mnemonic = Some(MnemonicSetup::Import),
+			(Some("--remote-signer"), Some(path)) => remote_signer = Some(PathBuf::from(path)),
+			(Some("--phantom-secret"), Some(path)) => phantom_secret = Some(PathBuf::from(path)),
+			(Some("--create-phantom-secret"), None) => create_phantom_secret = true,
+			(Some("--sweep-address-type"), Some(address_type)) => {
+				match AddressType::from_str(address_type) {
+					Ok(address_type) => sweep_address_type = address_type,
+					Err(e) => {
+						println!("ERROR: invalid --sweep-address-type: {}", e);
+						return Err(());
+					}
+				}
+			}
+			(Some("--recover"), Some(path)) => recover_backup = Some(PathBuf::from(path)),
+			(Some("--snapshot-dir"), Some(path)) => snapshot_dir = Some(PathBuf::from(path)),
+			(Some("--snapshot-interval-hours"), Some(hours)) => match hours.parse::<u64>() {
+				Ok(hours) if hours > 0 => {
+					snapshot_config.interval = Duration::from_secs(hours * 60 * 60)
+				}
+				_ => {
+					println!("ERROR: --snapshot-interval-hours must be a positive number of hours");
+					return Err(());
+				}
+			},
+			(Some("--snapshot-keep-daily"), Some(n)) => match n.parse() {
+				Ok(n) => snapshot_config.keep_daily = n,
+				Err(_) => {
+					println!("ERROR: --snapshot-keep-daily must be a number");
+					return Err(());
+				}
+			},
+			(Some("--snapshot-keep-weekly"), Some(n)) => match n.parse() {
+				Ok(n) => snapshot_config.keep_weekly = n,
+				Err(_) => {
+					println!("ERROR: --snapshot-keep-weekly must be a number");
+					return Err(());
+				}
+			},
+			(Some("--backup"), Some(target)) => match BackupTarget::from_str(target) {
+				Ok(target) => backup = Some(target),
+				Err(e) => {
+					println!("ERROR: invalid --backup: {}", e);
+					return Err(());
+				}
+			},
+			(Some("--block-fallback-esplora-url"), Some(url)) => {
+				block_fallback_esplora_url = Some(url.trim_end_matches('/').to_string())
+			}
+			_ => {
+				println!("ERROR: unknown option {}", flag);
+				return Err(());
+			}
+		}
+	}
+
+	match (&mut storage, postgres_url) {
+		(StorageBackend::Postgres(url), Some(postgres_url)) => *url = postgres_url,
+		(StorageBackend::Postgres(_), None) => {
+			println!("ERROR: --storage=postgres requires --postgres-url=<connection string>");
+			return Err(());
+		}
+		(_, Some(_)) => {
+			println!("ERROR: --postgres-url is only used with --storage=postgres");
+			return Err(());
+		}
+		(_, None) => {}
+	}
+	match (&mut storage, vss_url) {
+		(StorageBackend::Vss { url, store_id }, Some(vss_url)) => {
+			*url = vss_url;
+			*store_id = vss_store_id.unwrap_or_else(|| DEFAULT_VSS_STORE_ID.to_string());
+		}
+		(StorageBackend::Vss { .. }, None) => {
+			println!("ERROR: --storage=vss requires --vss-url=<url>");
+			return Err(());
+		}
+		(_, Some(_)) => {
+			println!("ERROR: --vss-url is only used with --storage=vss");
+			return Err(());
+		}
+		(_, None) => {}
+	}
+	let snapshots = match snapshot_dir {
+		Some(dir) => Some(SnapshotConfig { dir, ..snapshot_config }),
+		None if snapshot_config != SnapshotConfig::default() => {
+			println!("ERROR: the --snapshot-* options are only used with --snapshot-dir=<path>");
+			return Err(());
+		}
+		None => None,
+	};
+	let probing = match probe_interval {
+		Some(interval) => {
+			if !probe_config.targets.is_empty()
+				&& probe_config.num_targets != ProbeConfig::default().num_targets
+			{
+				println!("ERROR: --probe-num-targets picks the nodes to probe when no --probe-target is given, they can't be combined");
+				return Err(());
+			}
+			Some(ProbeConfig { interval, ..probe_config })
+		}
+		None if probe_config != ProbeConfig::default() => {
+			println!(
+				"ERROR: the --probe-* options are only used with --probe-interval-secs=<secs>"
+			);
+			return Err(());
+		}
+		None => None,
+	};
+
+	if cfg!(not(unix)) && remote_signer.is_some() {
+		println!("ERROR: --remote-signer connects to the signer daemon over a Unix socket, which this platform lacks");
+		return Err(());
+	}
+	if remote_signer.is_some() && mnemonic.is_some() {
+		println!("ERROR: with --remote-signer, the keys seed is set up by the signer daemon, pass --mnemonic to it instead");
+		return Err(());
+	}
+	if remote_signer.is_some() && phantom_secret.is_some() {
+		println!("ERROR: nodes using a remote signer can't be part of a phantom node cluster");
+		return Err(());
+	}
+	if create_phantom_secret && phantom_secret.is_none() {
+		println!("ERROR: --create-phantom-secret requires --phantom-secret");
+		return Err(());
+	}
+	if locked && (passphrase_file.is_some() || encryption != EncryptionSetup::Unlock) {
+		println!("ERROR: --locked waits for the passphrase of already encrypted state to be given through `unlock`, it can't be combined with --passphrase-file, --encrypt or --encrypt-existing");
+		return Err(());
+	}
+	let tor = match tor_control_addr {
+		Some(control_addr) => Some(TorConfig {
+			control_addr,
+			control_password: tor_control_password,
+			socks_addr: tor_socks_addr
+				.unwrap_or_else(|| SocketAddr::from_str(DEFAULT_TOR_SOCKS_ADDR).unwrap()),
+			only: tor_only,
+		}),
+		None if tor_control_password.is_some() || tor_socks_addr.is_some() || tor_only => {
+			println!("ERROR: the --tor-* options are only used with --tor-control=<host:port>");
+			return Err(());
+		}
+		None => None,
+	};
+	if let Some(tor) = tor.as_ref().filter(|tor| tor.only) {
+		if proxy.is_some() {
+			println!(
+				"ERROR: with --tor-only, peers are connected to through --tor-socks, not --proxy"
+			);
+			return Err(());
+		}
+		proxy = Some(tor.socks_addr);
+	}
+	if http_public_listen_addr.is_some() && http_listen_addr.is_none() {
+		println!("ERROR: --http-public-listen-addr serves the public routes of the HTTP API, it requires --http-listen-addr");
+		return Err(());
+	}
+	if http_public_url.is_some() && http_public_listen_addr.is_none() {
+		println!("ERROR: --http-public-url is where --http-public-listen-addr is reachable, it requires it");
 		return Err(());
 	}
-	let bitcoind_rpc_info = env::args().skip(1).next().unwrap();
+	if rgs_serve && http_public_listen_addr.is_none() {
+		println!("ERROR: RGS snapshots are served on the public listener of the HTTP API, --rgs-serve requires --http-public-listen-addr");
+		return Err(());
+	}
+	if locked && oneshot_command.is_some() && http_listen_addr.is_none() {
+		println!("ERROR: a locked node running a one-shot command can only be unlocked through the HTTP API, which requires --http-listen-addr");
+		return Err(());
+	}
+
+	if args.len() < 3 {
+		println!("ldk-tutorial-node requires at least 2 arguments: `cargo run [<bitcoind-rpc-username>:<bitcoind-rpc-password>@]<bitcoind-rpc-host>:<bitcoind-rpc-port> ldk_storage_directory_path [<ldk-incoming-peer-listening-port>] [bitcoin-network] [announced-node-name announced-listen-addr*] [--http-listen-addr=<host:port> [--http-public-listen-addr=<host:port> [--http-public-url=<url>] [--rgs-serve]]] [--probe-interval-secs=<secs> [--probe-amount-msat=<amt>] [--probe-target=<pubkey>]* [--probe-num-targets=<n>]] [--scorer=<parameter>:<value>]* [--scorer-persist-interval-secs=<secs>] [--payment-max-attempts=<n>|--payment-retry-timeout-secs=<secs>] [--reject-keysend] [--zmq-hashblock=<tcp://host:port>] [--tor-control=<host:port> [--tor-control-password=<password>] [--tor-socks=<host:port>] [--tor-only]] [--proxy=<host:port>] [--dns-seed=<domain>]* [--rgs-url=<url>] [--block-fallback-esplora-url=<url>] [--storage=<files|sqlite|postgres|vss> [--postgres-url=<url>] [--vss-url=<url> [--vss-store-id=<id>]]] [--encrypt|--encrypt-existing] [--passphrase-file=<path>|--locked] [--mnemonic=<new|import>|--remote-signer=<socket_path>] [--phantom-secret=<path> [--create-phantom-secret]] [--sweep-address-type=<p2wpkh|p2tr>] [--backup=<dir:<path>|s3://<bucket>[/<prefix>]>] [--recover=<static_channel_backup>] [--snapshot-dir=<path> [--snapshot-interval-hours=<n>] [--snapshot-keep-daily=<n>] [--snapshot-keep-weekly=<n>]] [--bitcoind-rpc-cookie=<path>] [--fee-source=<mempool|whatthefee>[:<url>]]* [--fee-blend=<max|median>] [--fee-max-sat-per-vb=<n>] [--fee-target=<target>:<blocks>]* [--fee-override=<target>:<sat_per_vbyte>]* [--bitcoind-backup=[<user>:<password>@]<host>:<port>]* [-- <command> [<args>]]`");
+		return Err(());
+	}
+	let bitcoind_rpc_info = args[1].clone();
 	let bitcoind_rpc_info_parts: Vec<&str> = bitcoind_rpc_info.rsplitn(2, "@").collect();
 
 	// Parse rpc auth after getting network for default .cookie location
@@ -25,10 +667,10 @@ pub(crate) fn parse_startup_args() -> Result<LdkUserInfo, ()> {
 	let bitcoind_rpc_host = bitcoind_rpc_path[0].to_string();
 	let bitcoind_rpc_port = bitcoind_rpc_path[1].parse::<u16>().unwrap();
 
-	let ldk_storage_dir_path = env::args().skip(2).next().unwrap();
+	let ldk_storage_dir_path = args[2].clone();
 
 	let mut ldk_peer_port_set = true;
-	let ldk_peer_listening_port: u16 = match env::args().skip(3).next().map(|p| p.parse()) {
+	let ldk_peer_listening_port: u16 = match args.get(3).map(|p| p.parse()) {
 		Some(Ok(p)) => p,
 		Some(Err(_)) => {
 			ldk_peer_port_set = false;
@@ -44,7 +686,7 @@ pub(crate) fn parse_startup_args() -> Result<LdkUserInfo, ()> {
 		true => 4,
 		false => 3,
 	};
-	let network: Network = match env::args().skip(arg_idx).next().as_ref().map(String::as_str) {
+	let network: Network = match args.get(arg_idx).map(String::as_str) {
 		Some("testnet") => Network::Testnet,
 		Some("regtest") => Network::Regtest,
 		Some("signet") => Network::Signet,
@@ -54,15 +696,37 @@ pub(crate) fn parse_startup_args() -> Result<LdkUserInfo, ()> {
 		None => Network::Testnet,
 	};
 
+	// Credentials read from a cookie file are re-read from it if bitcoind rotates them.
+	let mut bitcoind_rpc_cookie_path = None;
 	let (bitcoind_rpc_username, bitcoind_rpc_password) = if bitcoind_rpc_info_parts.len() == 1 {
-		get_rpc_auth_from_env_vars()
-			.or(get_rpc_auth_from_env_file(None))
-			.or(get_rpc_auth_from_cookie(None, Some(network), None))
-			.or({
+		let auth = match bitcoind_rpc_cookie {
+			Some(cookie_path) => {
+				let auth = read_rpc_auth_from_cookie(&cookie_path).or_else(|()| {
+					println!(
+						"ERROR: unable to read bitcoind RPC cookie file {}",
+						cookie_path.display()
+					);
+					Err(())
+				})?;
+				bitcoind_rpc_cookie_path = Some(cookie_path);
+				Ok(auth)
+			}
+			None => {
+				get_rpc_auth_from_env_vars().or(get_rpc_auth_from_env_file(None)).or_else(|()| {
+					let auth = get_rpc_auth_from_cookie(None, Some(network), None)?;
+					bitcoind_rpc_cookie_path = Some(get_cookie_path(None, Some(network), None)?);
+					Ok(auth)
+				})
+			}
+		};
+		match auth {
+			Ok(auth) => auth,
+			Err(()) => {
 				println!("ERROR: unable to get bitcoind RPC username and password");
 				print_rpc_auth_help();
-				Err(())
-			})?
+				return Err(());
+			}
+		}
 	} else if bitcoind_rpc_info_parts.len() == 2 {
 		parse_rpc_auth(bitcoind_rpc_info_parts[1])?
 	} else {
@@ -70,7 +734,30 @@ pub(crate) fn parse_startup_args() -> Result<LdkUserInfo, ()> {
 		return Err(());
 	};
 
-	let ldk_announced_node_name = match env::args().skip(arg_idx + 1).next().as_ref() {
+	// Backup endpoints use the primary's credentials unless they're given their own.
+	let mut bitcoind_backup_rpcs = Vec::new();
+	for rpc_info in bitcoind_backups {
+		let rpc_info_parts: Vec<&str> = rpc_info.rsplitn(2, '@').collect();
+		let (user, password) = match rpc_info_parts.get(1) {
+			Some(rpc_auth) => parse_rpc_auth(rpc_auth)?,
+			None => (bitcoind_rpc_username.clone(), bitcoind_rpc_password.clone()),
+		};
+		let mut rpc_path = rpc_info_parts[0].rsplitn(2, ':');
+		match (rpc_path.next().map(str::parse::<u16>), rpc_path.next()) {
+			(Some(Ok(port)), Some(host)) => bitcoind_backup_rpcs.push(BitcoindRpcInfo {
+				host: host.to_string(),
+				port,
+				user,
+				password,
+			}),
+			_ => {
+				println!("ERROR: bad bitcoind RPC path provided in --bitcoind-backup");
+				return Err(());
+			}
+		}
+	}
+
+	let ldk_announced_node_name = match args.get(arg_idx + 1) {
 		Some(s) => {
 			if s.len() > 32 {
 				panic!("Node Alias can not be longer than 32 bytes");
@@ -85,7 +772,7 @@ pub(crate) fn parse_startup_args() -> Result<LdkUserInfo, ()> {
 
 	let mut ldk_announced_listen_addr = Vec::new();
 	loop {
-		match env::args().skip(arg_idx + 1).next().as_ref() {
+		match args.get(arg_idx + 1) {
 			Some(s) => match IpAddr::from_str(s) {
 				Ok(IpAddr::V4(a)) => {
 					ldk_announced_listen_addr
@@ -102,17 +789,55 @@ pub(crate) fn parse_startup_args() -> Result<LdkUserInfo, ()> {
 			None => break,
 		}
 	}
+	if dns_seeds.is_empty() {
+		dns_seeds = bootstrap::default_dns_seeds(network);
+	}
+	if tor.as_ref().map_or(false, |tor| tor.only) && !ldk_announced_listen_addr.is_empty() {
+		println!("ERROR: with --tor-only, the node is only reachable through its onion service, so it can't announce IP addresses");
+		return Err(());
+	}
 
 	Ok(LdkUserInfo {
 		bitcoind_rpc_username,
 		bitcoind_rpc_password,
 		bitcoind_rpc_host,
 		bitcoind_rpc_port,
+		bitcoind_rpc_cookie_path,
+		bitcoind_backup_rpcs,
 		ldk_storage_dir_path,
+		storage,
+		encryption,
+		passphrase_file,
+		locked,
+		tor,
+		proxy,
+		dns_seeds,
+		rgs_url,
+		rgs_serve,
+		scoring_params,
+		scorer_persist_interval,
+		backup,
+		recover_backup,
+		snapshots,
+		mnemonic,
+		remote_signer,
+		phantom_secret,
+		create_phantom_secret,
+		sweep_address_type,
 		ldk_peer_listening_port,
 		ldk_announced_listen_addr,
 		ldk_announced_node_name,
 		network,
+		oneshot_command,
+		http_listen_addr,
+		http_public_listen_addr,
+		http_public_url,
+		zmq_hashblock_endpoint,
+		block_fallback_esplora_url,
+		fee_config,
+		accept_keysend,
+		probing,
+		payment_retry,
 	})
 }
 
@@ -143,7 +868,8 @@ fn print_rpc_auth_help() {
 		<bitcoind-rpc-username>:<bitcoind-rpc-password>@<bitcoind-rpc-host>:<bitcoind-rpc-port>"
 	);
 	println!("2. Provide <bitcoind-rpc-username>:<bitcoind-rpc-password> in a .cookie file in the default \
-		bitcoind data directory (automatically created by bitcoind on startup): `{}`", data_dir);
+		bitcoind data directory (automatically created by bitcoind on startup): `{}`, or point \
+		--bitcoind-rpc-cookie=<path> at it if bitcoind uses another data directory", data_dir);
 	println!(
 		"3. Set the {} and {} environment variables",
 		BITCOIND_RPC_USER_KEY, BITCOIND_RPC_PASSWORD_KEY
@@ -190,8 +916,12 @@ fn get_rpc_auth_from_cookie(
 	data_dir: Option<(&str, bool)>, network: Option<Network>, cookie_file_name: Option<&str>,
 ) -> Result<(String, String), ()> {
 	let cookie_path = get_cookie_path(data_dir, network, cookie_file_name)?;
+	read_rpc_auth_from_cookie(&cookie_path)
+}
+
+pub(crate) fn read_rpc_auth_from_cookie(cookie_path: &Path) -> Result<(String, String), ()> {
 	let cookie_contents = fs::read_to_string(cookie_path).or(Err(()))?;
-	parse_rpc_auth(&cookie_contents)
+	parse_rpc_auth(cookie_contents.trim_end())
 }
 
 fn get_rpc_auth_from_env_vars() -> Result<(String, String), ()> {
@@ -369,3 +1099,283 @@ mod rpc_auth_tests {
 		assert_eq!(password, EXPECTED_PASSWORD);
 	}
 }
+
+#[cfg(test)]
+mod startup_args_tests {
+	use super::*;
+
+	const RPC_INFO: &str = "testuser:testpassword@127.0.0.1:18443";
+	const PUBKEY: &str = "02eec7245d6b7d2ccb30380bfbe2a3648cd7a942653f5aa340edcea1f283686619";
+
+	/// Parses the given flags after the positional arguments of a regtest node.
+	fn parse(flags: &[&str]) -> Result<LdkUserInfo, ()> {
+		let mut args = vec!["ldk-tutorial-node", RPC_INFO, "/tmp/ldk", "9735", "regtest"];
+		args.extend_from_slice(flags);
+		parse_args(args.into_iter().map(String::from).collect())
+	}
+
+	#[test]
+	fn test_parse_args_positional() {
+		let args = parse(&[]).unwrap();
+		assert_eq!(args.bitcoind_rpc_username, "testuser");
+		assert_eq!(args.bitcoind_rpc_password, "testpassword");
+		assert_eq!(args.bitcoind_rpc_host, "127.0.0.1");
+		assert_eq!(args.bitcoind_rpc_port, 18443);
+		assert_eq!(args.ldk_storage_dir_path, "/tmp/ldk");
+		assert_eq!(args.ldk_peer_listening_port, 9735);
+		assert_eq!(args.network, Network::Regtest);
+		assert!(args.oneshot_command.is_none());
+
+		let args = vec!["ldk-tutorial-node".to_string(), RPC_INFO.to_string()];
+		assert!(parse_args(args).is_err());
+	}
+
+	#[test]
+	fn test_parse_args_http_listen_addr() {
+		assert!(parse(&[]).unwrap().http_listen_addr.is_none());
+		let args = parse(&["--http-listen-addr=127.0.0.1:3000"]).unwrap();
+		assert_eq!(args.http_listen_addr, Some(SocketAddr::from_str("127.0.0.1:3000").unwrap()));
+
+		assert!(parse(&["--http-listen-addr=localhost"]).is_err());
+		assert!(parse(&["--http-listen-addr"]).is_err());
+		assert!(parse(&["--unknown-flag"]).is_err());
+	}
+
+	#[test]
+	fn test_parse_args_flags_among_positional_args() {
+		let args = parse_args(
+			[
+				"ldk-tutorial-node",
+				"--http-listen-addr=127.0.0.1:3000",
+				RPC_INFO,
+				"/tmp/ldk",
+				"9735",
+				"regtest",
+				"--",
+				"listchannels",
+				"--json",
+			]
+			.iter()
+			.map(|arg| arg.to_string())
+			.collect(),
+		)
+		.unwrap();
+		assert_eq!(args.ldk_storage_dir_path, "/tmp/ldk");
+		assert_eq!(args.ldk_peer_listening_port, 9735);
+		assert_eq!(args.network, Network::Regtest);
+		assert!(args.http_listen_addr.is_some());
+		// Flags after `--` belong to the command.
+		assert_eq!(args.oneshot_command.as_deref(), Some("listchannels --json"));
+	}
+
+	#[test]
+	fn test_parse_args_probing() {
+		assert!(parse(&[]).unwrap().probing.is_none());
+		let target = format!("--probe-target={}", PUBKEY);
+		let args =
+			parse(&["--probe-interval-secs=60", "--probe-amount-msat=1000", target.as_str()])
+				.unwrap();
+		let probing = args.probing.unwrap();
+		assert_eq!(probing.interval, Duration::from_secs(60));
+		assert_eq!(probing.amount_msat, 1000);
+		assert_eq!(probing.targets, vec![PublicKey::from_str(PUBKEY).unwrap()]);
+
+		let args = parse(&["--probe-interval-secs=60", "--probe-num-targets=5"]).unwrap();
+		let probing = args.probing.unwrap();
+		assert_eq!(probing.amount_msat, ProbeConfig::default().amount_msat);
+		assert!(probing.targets.is_empty());
+		assert_eq!(probing.num_targets, 5);
+
+		let invalid_flags: &[&[&str]] = &[
+			&["--probe-interval-secs=0"],
+			&["--probe-interval-secs=soon"],
+			&["--probe-interval-secs=60", "--probe-amount-msat=0"],
+			&["--probe-interval-secs=60", "--probe-target=02eec7"],
+			&["--probe-interval-secs=60", "--probe-num-targets=0"],
+			// Options only used along with another one.
+			&["--probe-amount-msat=1000"],
+			&[target.as_str()],
+			&["--probe-interval-secs=60", target.as_str(), "--probe-num-targets=5"],
+		];
+		for flags in invalid_flags {
+			assert!(parse(flags).is_err(), "{:?} was accepted", flags);
+		}
+	}
+
+	#[test]
+	fn test_parse_args_payment_retry() {
+		let args = parse(&[]).unwrap();
+		assert!(matches!(args.payment_retry, Retry::Timeout(t) if t == Duration::from_secs(10)));
+		let args = parse(&["--payment-max-attempts=3"]).unwrap();
+		assert!(matches!(args.payment_retry, Retry::Attempts(3)));
+		let args = parse(&["--payment-retry-timeout-secs=30"]).unwrap();
+		assert!(matches!(args.payment_retry, Retry::Timeout(t) if t == Duration::from_secs(30)));
+
+		assert!(parse(&["--payment-max-attempts=many"]).is_err());
+		assert!(parse(&["--payment-retry-timeout-secs=-1"]).is_err());
+	}
+
+	#[test]
+	fn test_parse_args_http_public_listener() {
+		let args = parse(&[
+			"--http-listen-addr=127.0.0.1:3000",
+			"--http-public-listen-addr=0.0.0.0:3001",
+			"--http-public-url=https://example.com/",
+		])
+		.unwrap();
+		assert_eq!(
+			args.http_public_listen_addr,
+			Some(SocketAddr::from_str("0.0.0.0:3001").unwrap())
+		);
+		assert_eq!(args.http_public_url.as_deref(), Some("https://example.com"));
+
+		let invalid_flags: &[&[&str]] = &[
+			&["--http-listen-addr=127.0.0.1:3000", "--http-public-listen-addr=3001"],
+			// Options only used along with another one.
+			&["--http-public-listen-addr=0.0.0.0:3001"],
+			&["--http-listen-addr=127.0.0.1:3000", "--http-public-url=https://example.com"],
+		];
+		for flags in invalid_flags {
+			assert!(parse(flags).is_err(), "{:?} was accepted", flags);
+		}
+	}
+
+	#[test]
+	fn test_parse_args_reject_keysend() {
+		assert!(parse(&[]).unwrap().accept_keysend);
+		assert!(!parse(&["--reject-keysend"]).unwrap().accept_keysend);
+		assert!(parse(&["--reject-keysend=true"]).is_err());
+	}
+
+	#[test]
+	fn test_parse_args_postgres() {
+		let args =
+			parse(&["--storage=postgres", "--postgres-url=postgres://localhost/ldk"]).unwrap();
+		assert!(
+			matches!(args.storage, StorageBackend::Postgres(ref url) if url == "postgres://localhost/ldk")
+		);
+
+		assert!(parse(&["--storage=postgres"]).is_err());
+		assert!(parse(&["--postgres-url=postgres://localhost/ldk"]).is_err());
+		assert!(parse(&["--storage=sqlite", "--postgres-url=postgres://localhost/ldk"]).is_err());
+	}
+
+	#[test]
+	fn test_parse_args_snapshots() {
+		assert!(parse(&[]).unwrap().snapshots.is_none());
+		let args = parse(&["--snapshot-dir=/tmp/snapshots", "--snapshot-keep-daily=2"]).unwrap();
+		let snapshots = args.snapshots.unwrap();
+		assert_eq!(snapshots.dir, PathBuf::from("/tmp/snapshots"));
+		assert_eq!(snapshots.interval, SnapshotConfig::default().interval);
+		assert_eq!(snapshots.keep_daily, 2);
+		assert_eq!(snapshots.keep_weekly, SnapshotConfig::default().keep_weekly);
+		let args = parse(&[
+			"--snapshot-dir=/tmp/snapshots",
+			"--snapshot-interval-hours=6",
+			"--snapshot-keep-weekly=0",
+		])
+		.unwrap();
+		let snapshots = args.snapshots.unwrap();
+		assert_eq!(snapshots.interval, Duration::from_secs(6 * 60 * 60));
+		assert_eq!(snapshots.keep_weekly, 0);
+
+		let invalid_flags: &[&[&str]] = &[
+			&["--snapshot-dir=/tmp/snapshots", "--snapshot-interval-hours=0"],
+			&["--snapshot-dir=/tmp/snapshots", "--snapshot-keep-daily=-1"],
+			// Options only used along with another one.
+			&["--snapshot-keep-daily=2"],
+			&["--snapshot-interval-hours=6"],
+		];
+		for flags in invalid_flags {
+			assert!(parse(flags).is_err(), "{:?} was accepted", flags);
+		}
+	}
+
+	#[test]
+	#[cfg(unix)]
+	fn test_parse_args_remote_signer() {
+		assert!(parse(&[]).unwrap().remote_signer.is_none());
+		let args = parse(&["--remote-signer=/tmp/signer.sock"]).unwrap();
+		assert_eq!(args.remote_signer, Some(PathBuf::from("/tmp/signer.sock")));
+
+		let invalid_flags: &[&[&str]] = &[
+			&["--remote-signer=/tmp/signer.sock", "--mnemonic=new"],
+			&["--remote-signer=/tmp/signer.sock", "--phantom-secret=/tmp/phantom_secret"],
+		];
+		for flags in invalid_flags {
+			assert!(parse(flags).is_err(), "{:?} was accepted", flags);
+		}
+	}
+
+	#[test]
+	fn test_parse_args_phantom_secret() {
+		let args = parse(&[]).unwrap();
+		assert!(args.phantom_secret.is_none());
+		assert!(!args.create_phantom_secret);
+		let args =
+			parse(&["--phantom-secret=/tmp/phantom_secret", "--create-phantom-secret"]).unwrap();
+		assert_eq!(args.phantom_secret, Some(PathBuf::from("/tmp/phantom_secret")));
+		assert!(args.create_phantom_secret);
+
+		// Only used along with --phantom-secret.
+		assert!(parse(&["--create-phantom-secret"]).is_err());
+	}
+
+	#[test]
+	fn test_parse_args_sweep_address_type() {
+		assert!(parse(&[]).unwrap().sweep_address_type == AddressType::P2wpkh);
+		let args = parse(&["--sweep-address-type=p2tr"]).unwrap();
+		assert!(args.sweep_address_type == AddressType::P2tr);
+
+		assert!(parse(&["--sweep-address-type=p2pkh"]).is_err());
+		assert!(parse(&["--sweep-address-type"]).is_err());
+	}
+
+	#[test]
+	fn test_parse_args_tor() {
+		assert!(parse(&[]).unwrap().tor.is_none());
+		let args = parse(&["--tor-control=127.0.0.1:9051"]).unwrap();
+		let tor = args.tor.unwrap();
+		assert_eq!(tor.control_addr, SocketAddr::from_str("127.0.0.1:9051").unwrap());
+		assert_eq!(tor.socks_addr, SocketAddr::from_str(DEFAULT_TOR_SOCKS_ADDR).unwrap());
+		assert!(!tor.only);
+		assert!(args.proxy.is_none());
+
+		// Tor-only nodes connect to peers through Tor's SOCKS port.
+		let args =
+			parse(&["--tor-control=127.0.0.1:9051", "--tor-socks=127.0.0.1:9150", "--tor-only"])
+				.unwrap();
+		assert!(args.tor.unwrap().only);
+		assert_eq!(args.proxy, Some(SocketAddr::from_str("127.0.0.1:9150").unwrap()));
+
+		let invalid_flags: &[&[&str]] = &[
+			&["--tor-control=127.0.0.1:9051", "--tor-only", "--proxy=127.0.0.1:1080"],
+			// Options only used along with another one.
+			&["--tor-only"],
+			&["--tor-socks=127.0.0.1:9150"],
+		];
+		for flags in invalid_flags {
+			assert!(parse(flags).is_err(), "{:?} was accepted", flags);
+		}
+	}
+
+	#[test]
+	fn test_parse_args_rgs() {
+		let args = parse(&[]).unwrap();
+		assert!(args.rgs_url.is_none());
+		assert!(!args.rgs_serve);
+		let args = parse(&[
+			"--rgs-url=https://rapidsync.lightningdevkit.org/snapshot",
+			"--http-listen-addr=127.0.0.1:3000",
+			"--http-public-listen-addr=0.0.0.0:3001",
+			"--rgs-serve",
+		])
+		.unwrap();
+		assert_eq!(args.rgs_url.as_deref(), Some("https://rapidsync.lightningdevkit.org/snapshot"));
+		assert!(args.rgs_serve);
+
+		// Snapshots are served on the public listener.
+		assert!(parse(&["--rgs-serve"]).is_err());
+		assert!(parse(&["--http-listen-addr=127.0.0.1:3000", "--rgs-serve"]).is_err());
+	}
+}
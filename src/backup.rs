@@ -0,0 +1,481 @@
+use crate::time_now_secs;
+use bitcoin::hashes::sha256;
+use bitcoin::hashes::Hash;
+use s3::bucket::Bucket;
+use s3::creds::Credentials;
+use s3::region::Region;
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+
+/// How long to wait before retrying writes that failed to replicate.
+const RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Where the node's state is mirrored to.
+#[derive(Clone)]
+pub(crate) enum BackupTarget {
+	/// Another directory, e.g. on a different disk or a network mount.
+	Dir(PathBuf),
+	/// An S3 (compatible) bucket, with the prefix objects are stored under.
+	S3 { bucket: String, prefix: String },
+}
+
+impl FromStr for BackupTarget {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		if let Some(path) = s.strip_prefix("dir:") {
+			if path.is_empty() {
+				return Err("the backup directory can't be empty".to_string());
+			}
+			return Ok(BackupTarget::Dir(PathBuf::from(path)));
+		}
+		if let Some(location) = s.strip_prefix("s3://") {
+			let mut parts = location.splitn(2, '/');
+			let bucket = parts.next().unwrap_or("").to_string();
+			if bucket.is_empty() {
+				return Err("the backup bucket can't be empty".to_string());
+			}
+			let prefix = parts.next().unwrap_or("").trim_end_matches('/').to_string();
+			return Ok(BackupTarget::S3 { bucket, prefix });
+		}
+		Err("expected dir:<path> or s3://<bucket>[/<prefix>]".to_string())
+	}
+}
+
+impl fmt::Display for BackupTarget {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			BackupTarget::Dir(path) => write!(f, "dir:{}", path.display()),
+			BackupTarget::S3 { bucket, prefix } if prefix.is_empty() => {
+				write!(f, "s3://{}", bucket)
+			}
+			BackupTarget::S3 { bucket, prefix } => write!(f, "s3://{}/{}", bucket, prefix),
+		}
+	}
+}
+
+enum Sink {
+	Dir(PathBuf),
+	S3 { bucket: Bucket, prefix: String },
+}
+
+impl Sink {
+	fn new(target: &BackupTarget) -> Result<Self, String> {
+		match target {
+			BackupTarget::Dir(path) => {
+				fs::create_dir_all(path)
+					.map_err(|e| format!("failed to create {}: {}", path.display(), e))?;
+				Ok(Sink::Dir(path.clone()))
+			}
+			BackupTarget::S3 { bucket, prefix } => {
+				// Configured like the AWS tools, so S3-compatible stores work too.
+				let region_name =
+					env::var("AWS_REGION").unwrap_or_else(|_| "us-east-1".to_string());
+				let region = match env::var("AWS_ENDPOINT_URL") {
+					Ok(endpoint) => Region::Custom { region: region_name, endpoint },
+					Err(_) => {
+						region_name.parse().map_err(|e| format!("invalid AWS_REGION: {}", e))?
+					}
+				};
+				let credentials = Credentials::default()
+					.map_err(|e| format!("failed to load S3 credentials: {}", e))?;
+				let bucket = Bucket::new(bucket, region, credentials)
+					.map_err(|e| format!("invalid S3 bucket: {}", e))?
+					.with_path_style();
+				Ok(Sink::S3 { bucket, prefix: prefix.clone() })
+			}
+		}
+	}
+
+	async fn write(&self, key: &str, value: &[u8]) -> Result<(), String> {
+		match self {
+			Sink::Dir(dir) => {
+				let path = dir.join(key);
+				let parent = path.parent().unwrap();
+				fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+				let tmp_path = path.with_extension("tmp");
+				let mut f = fs::File::create(&tmp_path).map_err(|e| e.to_string())?;
+				f.write_all(value).map_err(|e| e.to_string())?;
+				f.sync_all().map_err(|e| e.to_string())?;
+				fs::rename(&tmp_path, &path).map_err(|e| e.to_string())?;
+				fs::File::open(parent).and_then(|dir| dir.sync_all()).map_err(|e| e.to_string())
+			}
+			Sink::S3 { bucket, prefix } => {
+				let response = bucket
+					.put_object(s3_path(prefix, key), value)
+					.await
+					.map_err(|e| e.to_string())?;
+				match response.status_code() {
+					200 => Ok(()),
+					status => Err(format!("S3 returned HTTP {}", status)),
+				}
+			}
+		}
+	}
+
+	async fn read(&self, key: &str) -> Result<Vec<u8>, String> {
+		match self {
+			Sink::Dir(dir) => fs::read(dir.join(key)).map_err(|e| e.to_string()),
+			Sink::S3 { bucket, prefix } => {
+				let response =
+					bucket.get_object(s3_path(prefix, key)).await.map_err(|e| e.to_string())?;
+				match response.status_code() {
+					200 => Ok(response.bytes().to_vec()),
+					status => Err(format!("S3 returned HTTP {}", status)),
+				}
+			}
+		}
+	}
+
+	async fn remove(&self, key: &str) -> Result<(), String> {
+		match self {
+			Sink::Dir(dir) => match fs::remove_file(dir.join(key)) {
+				Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.to_string()),
+				_ => Ok(()),
+			},
+			Sink::S3 { bucket, prefix } => {
+				let response =
+					bucket.delete_object(s3_path(prefix, key)).await.map_err(|e| e.to_string())?;
+				match response.status_code() {
+					200 | 204 => Ok(()),
+					status => Err(format!("S3 returned HTTP {}", status)),
+				}
+			}
+		}
+	}
+
+	/// Writes `value` and reads it back, failing unless its checksum matches.
+	async fn write_verified(&self, key: &str, value: &[u8]) -> Result<(), ReplicationError> {
+		self.write(key, value).await.map_err(ReplicationError::Write)?;
+		let written = self.read(key).await.map_err(ReplicationError::Write)?;
+		if sha256::Hash::hash(&written) != sha256::Hash::hash(value) {
+			return Err(ReplicationError::Verification);
+		}
+		Ok(())
+	}
+}
+
+fn s3_path(prefix: &str, key: &str) -> String {
+	if prefix.is_empty() {
+		key.to_string()
+	} else {
+		format!("{}/{}", prefix, key)
+	}
+}
+
+enum ReplicationError {
+	Write(String),
+	Verification,
+}
+
+struct PendingWrite {
+	/// `None` if the key was removed.
+	value: Option<Vec<u8>>,
+	/// When the oldest write to this key that hasn't been replicated yet happened.
+	since: u64,
+}
+
+#[derive(Default)]
+struct ReplicationState {
+	/// The latest value of every key that's yet to be replicated, or removed from the backup.
+	pending: HashMap<String, PendingWrite>,
+	replicated_writes: u64,
+	last_replicated_at: Option<u64>,
+	verification_failures: u64,
+	last_error: Option<String>,
+}
+
+impl ReplicationState {
+	fn enqueue(&mut self, key: &str, value: Option<Vec<u8>>) {
+		let since = self.pending.get(key).map_or_else(time_now_secs, |pending| pending.since);
+		self.pending.insert(key.to_string(), PendingWrite { value, since });
+	}
+}
+
+/// A snapshot of how far behind the backup is.
+pub(crate) struct BackupStatus {
+	pub(crate) target: BackupTarget,
+	pub(crate) pending_writes: usize,
+	/// How long the oldest write that's yet to be replicated has been waiting.
+	pub(crate) lag_secs: u64,
+	pub(crate) replicated_writes: u64,
+	pub(crate) last_replicated_at: Option<u64>,
+	pub(crate) verification_failures: u64,
+	pub(crate) last_error: Option<String>,
+}
+
+/// Mirrors every write of the node's state to a backup target in the background, verifying each
+/// one by reading it back. Only the latest value of a key is kept while it waits, and monitors are
+/// always replicated before the `ChannelManager`, as restoring a manager newer than its monitors
+/// could lose funds. Removals wait for every write, so a journaled monitor update is only removed
+/// from the backup once the monitor it was folded into is there.
+pub(crate) struct Replicator {
+	target: BackupTarget,
+	state: Arc<Mutex<ReplicationState>>,
+	notify: Arc<Notify>,
+}
+
+impl Replicator {
+	pub(crate) fn start(target: BackupTarget) -> Result<Self, String> {
+		let sink = Sink::new(&target)?;
+		let state = Arc::new(Mutex::new(ReplicationState::default()));
+		let notify = Arc::new(Notify::new());
+		tokio::spawn(replicate(sink, Arc::clone(&state), Arc::clone(&notify)));
+		Ok(Self { target, state, notify })
+	}
+
+	pub(crate) fn enqueue(&self, key: &str, value: Vec<u8>) {
+		self.enqueue_change(key, Some(value));
+	}
+
+	pub(crate) fn enqueue_removal(&self, key: &str) {
+		self.enqueue_change(key, None);
+	}
+
+	fn enqueue_change(&self, key: &str, value: Option<Vec<u8>>) {
+		self.state.lock().unwrap().enqueue(key, value);
+		self.notify.notify_one();
+	}
+
+	pub(crate) fn status(&self) -> BackupStatus {
+		let state = self.state.lock().unwrap();
+		let oldest = state.pending.values().map(|pending| pending.since).min();
+		BackupStatus {
+			target: self.target.clone(),
+			pending_writes: state.pending.len(),
+			lag_secs: oldest.map_or(0, |since| time_now_secs().saturating_sub(since)),
+			replicated_writes: state.replicated_writes,
+			last_replicated_at: state.last_replicated_at,
+			verification_failures: state.verification_failures,
+			last_error: state.last_error.clone(),
+		}
+	}
+}
+
+async fn replicate(sink: Sink, state: Arc<Mutex<ReplicationState>>, notify: Arc<Notify>) {
+	loop {
+		let _ = tokio::time::timeout(RETRY_INTERVAL, notify.notified()).await;
+		replicate_pending(&sink, &state).await;
+	}
+}
+
+/// Replicates the writes pending, stopping at the first that fails so it's retried on the next
+/// call.
+async fn replicate_pending(sink: &Sink, state: &Mutex<ReplicationState>) {
+	let mut batch = state
+		.lock()
+		.unwrap()
+		.pending
+		.iter()
+		.map(|(key, pending)| (key.clone(), pending.value.clone()))
+		.collect::<Vec<_>>();
+	batch.sort_by_key(|(key, value)| (value.is_none(), key == "manager"));
+	for (key, value) in batch {
+		let result = match &value {
+			Some(value) => sink.write_verified(&key, value).await,
+			None => sink.remove(&key).await.map_err(ReplicationError::Write),
+		};
+		let mut state = state.lock().unwrap();
+		match result {
+			Ok(()) => {
+				// Unless it was written again meanwhile.
+				if state.pending.get(&key).map_or(false, |pending| pending.value == value) {
+					state.pending.remove(&key);
+				}
+				state.replicated_writes += 1;
+				state.last_replicated_at = Some(time_now_secs());
+				state.last_error = None;
+			}
+			Err(e) => {
+				let error = match e {
+					ReplicationError::Write(e) => format!("failed to replicate {}: {}", key, e),
+					ReplicationError::Verification => {
+						state.verification_failures += 1;
+						format!("replicated {} doesn't match its checksum", key)
+					}
+				};
+				if state.last_error.as_ref() != Some(&error) {
+					println!("ERROR: backup: {}", error);
+				}
+				state.last_error = Some(error);
+				// Keep the manager from getting ahead of monitors that failed, and journaled
+				// updates from being removed before the monitors they were folded into.
+				break;
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod backup_tests {
+	use super::*;
+	use std::path::Path;
+	use std::time::SystemTime;
+
+	fn test_dir(name: &str) -> PathBuf {
+		let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap();
+		std::env::temp_dir().join(format!("ldk-sample-{}-{}", name, now.as_nanos()))
+	}
+
+	fn dir_sink(name: &str) -> (PathBuf, Sink) {
+		let dir = test_dir(name);
+		let sink = Sink::new(&BackupTarget::Dir(dir.clone())).unwrap();
+		(dir, sink)
+	}
+
+	/// Makes writes to `key` fail, as a directory can't be renamed over with a file.
+	fn block_key(dir: &Path, key: &str) {
+		fs::create_dir_all(dir.join(key).join("blocked")).unwrap();
+	}
+
+	fn unblock_key(dir: &Path, key: &str) {
+		fs::remove_dir_all(dir.join(key)).unwrap();
+	}
+
+	#[test]
+	fn test_backup_target_from_str() {
+		match BackupTarget::from_str("dir:/mnt/backup").unwrap() {
+			BackupTarget::Dir(path) => assert_eq!(path, PathBuf::from("/mnt/backup")),
+			BackupTarget::S3 { .. } => panic!("expected a dir target"),
+		}
+		match BackupTarget::from_str("s3://ldk-backups").unwrap() {
+			BackupTarget::S3 { bucket, prefix } => {
+				assert_eq!(bucket, "ldk-backups");
+				assert_eq!(prefix, "");
+			}
+			BackupTarget::Dir(_) => panic!("expected an S3 target"),
+		}
+		match BackupTarget::from_str("s3://ldk-backups/nodes/alice/").unwrap() {
+			BackupTarget::S3 { bucket, prefix } => {
+				assert_eq!(bucket, "ldk-backups");
+				assert_eq!(prefix, "nodes/alice");
+			}
+			BackupTarget::Dir(_) => panic!("expected an S3 target"),
+		}
+		for target in ["dir:/mnt/backup", "s3://ldk-backups", "s3://ldk-backups/nodes/alice"] {
+			assert_eq!(BackupTarget::from_str(target).unwrap().to_string(), target);
+		}
+	}
+
+	#[test]
+	fn test_backup_target_from_str_invalid() {
+		for target in ["", "dir:", "s3://", "s3:///prefix", "/mnt/backup", "sftp://host/backup"] {
+			assert!(BackupTarget::from_str(target).is_err(), "{}", target);
+		}
+	}
+
+	#[tokio::test]
+	async fn test_replicate_pending_to_dir() {
+		let (dir, sink) = dir_sink("backup-dir");
+		let state = Mutex::new(ReplicationState::default());
+		fs::write(dir.join("stale"), b"stale").unwrap();
+		{
+			let mut state = state.lock().unwrap();
+			state.enqueue("manager", Some(b"manager".to_vec()));
+			state.enqueue("monitors/a_0", Some(b"monitor".to_vec()));
+			state.enqueue("stale", None);
+		}
+
+		replicate_pending(&sink, &state).await;
+
+		assert_eq!(fs::read(dir.join("manager")).unwrap(), b"manager");
+		assert_eq!(fs::read(dir.join("monitors/a_0")).unwrap(), b"monitor");
+		assert!(!dir.join("stale").exists());
+		let state = state.lock().unwrap();
+		assert!(state.pending.is_empty());
+		assert_eq!(state.replicated_writes, 3);
+		assert!(state.last_replicated_at.is_some());
+		assert!(state.last_error.is_none());
+	}
+
+	#[tokio::test]
+	async fn test_replicate_pending_holds_back_manager() {
+		let (dir, sink) = dir_sink("backup-order");
+		let state = Mutex::new(ReplicationState::default());
+		block_key(&dir, "monitors/a_0");
+		{
+			let mut state = state.lock().unwrap();
+			state.enqueue("manager", Some(b"manager".to_vec()));
+			state.enqueue("monitors/a_0", Some(b"monitor".to_vec()));
+		}
+
+		// The monitor fails, so the manager mustn't be replicated ahead of it.
+		replicate_pending(&sink, &state).await;
+		assert!(!dir.join("manager").exists());
+		{
+			let state = state.lock().unwrap();
+			assert_eq!(state.pending.len(), 2);
+			assert_eq!(state.replicated_writes, 0);
+			assert!(state.last_error.as_ref().unwrap().contains("monitors/a_0"));
+		}
+
+		// Both go through once the monitor can be written.
+		unblock_key(&dir, "monitors/a_0");
+		replicate_pending(&sink, &state).await;
+		assert_eq!(fs::read(dir.join("monitors/a_0")).unwrap(), b"monitor");
+		assert_eq!(fs::read(dir.join("manager")).unwrap(), b"manager");
+		let state = state.lock().unwrap();
+		assert!(state.pending.is_empty());
+		assert_eq!(state.replicated_writes, 2);
+		assert!(state.last_error.is_none());
+	}
+
+	#[tokio::test]
+	async fn test_replicate_pending_holds_back_removals() {
+		let (dir, sink) = dir_sink("backup-removal");
+		let state = Mutex::new(ReplicationState::default());
+		fs::create_dir_all(dir.join("monitor_updates")).unwrap();
+		fs::write(dir.join("monitor_updates/a_0_1"), b"update").unwrap();
+		block_key(&dir, "monitors/a_0");
+		{
+			let mut state = state.lock().unwrap();
+			state.enqueue("monitors/a_0", Some(b"monitor".to_vec()));
+			state.enqueue("monitor_updates/a_0_1", None);
+		}
+
+		// The update stays in the backup until the monitor it was folded into is written.
+		replicate_pending(&sink, &state).await;
+		assert!(dir.join("monitor_updates/a_0_1").exists());
+		assert_eq!(state.lock().unwrap().pending.len(), 2);
+
+		unblock_key(&dir, "monitors/a_0");
+		replicate_pending(&sink, &state).await;
+		assert_eq!(fs::read(dir.join("monitors/a_0")).unwrap(), b"monitor");
+		assert!(!dir.join("monitor_updates/a_0_1").exists());
+		assert!(state.lock().unwrap().pending.is_empty());
+	}
+
+	#[tokio::test]
+	async fn test_replicate_pending_retries_latest_value() {
+		let (dir, sink) = dir_sink("backup-retry");
+		let state = Mutex::new(ReplicationState::default());
+		block_key(&dir, "monitors/a_0");
+		state.lock().unwrap().enqueue("monitors/a_0", Some(b"first".to_vec()));
+		replicate_pending(&sink, &state).await;
+		let since = state.lock().unwrap().pending["monitors/a_0"].since;
+
+		// Writing the key again while it waits keeps only the latest value, but not how long
+		// it's been waiting.
+		state.lock().unwrap().enqueue("monitors/a_0", Some(b"second".to_vec()));
+		{
+			let state = state.lock().unwrap();
+			assert_eq!(state.pending.len(), 1);
+			assert_eq!(state.pending["monitors/a_0"].since, since);
+		}
+
+		unblock_key(&dir, "monitors/a_0");
+		replicate_pending(&sink, &state).await;
+		assert_eq!(fs::read(dir.join("monitors/a_0")).unwrap(), b"second");
+		let state = state.lock().unwrap();
+		assert!(state.pending.is_empty());
+		assert_eq!(state.replicated_writes, 1);
+	}
+}
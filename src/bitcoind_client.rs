@@ -1,181 +1,805 @@
-use crate::convert::{BlockchainInfo, FeeResponse, FundedTx, NewAddress, RawTx, SignedTx};
+use crate::args;
+use crate::convert::{
+	Balances, BlockchainInfo, FeeResponse, FundedTx, MempoolEntry, NewAddress, RawTx, SignedTx,
+	TxOutSetScan, TxOutStatus,
+};
 use crate::disk::FilesystemLogger;
+use crate::time_now_secs;
 use base64;
-use bitcoin::blockdata::transaction::Transaction;
+use bitcoin::blockdata::block::Block;
+use bitcoin::blockdata::script::Script;
+use bitcoin::blockdata::transaction::{OutPoint, Transaction, TxOut};
 use bitcoin::consensus::encode;
 use bitcoin::hash_types::{BlockHash, Txid};
+use bitcoin::hashes::hex::ToHex;
 use bitcoin::util::address::Address;
 use lightning::chain::chaininterface::{BroadcasterInterface, ConfirmationTarget, FeeEstimator};
-use lightning::log_error;
 use lightning::routing::utxo::{UtxoLookup, UtxoResult};
 use lightning::util::logger::Logger;
-use lightning_block_sync::http::HttpEndpoint;
+use lightning::{log_error, log_info};
+use lightning_block_sync::http::{HttpEndpoint, JsonResponse};
 use lightning_block_sync::rpc::RpcClient;
-use lightning_block_sync::{AsyncBlockSourceResult, BlockData, BlockHeaderData, BlockSource};
+use lightning_block_sync::{
+	AsyncBlockSourceResult, BlockData, BlockHeaderData, BlockSource, BlockSourceError,
+	BlockSourceErrorKind,
+};
 use serde_json;
 use std::collections::HashMap;
+use std::convert::{TryFrom, TryInto};
+use std::path::PathBuf;
 use std::str::FromStr;
-use std::sync::atomic::{AtomicU32, Ordering};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 use std::time::Duration;
+use tokio::sync::mpsc;
+use zeromq::{Socket, SocketRecv, SubSocket};
 
 pub struct BitcoindClient {
-	bitcoind_rpc_client: Arc<RpcClient>,
-	host: String,
-	port: u16,
-	rpc_user: String,
-	rpc_password: String,
+	endpoints: Arc<RpcEndpoints>,
 	fees: Arc<HashMap<Target, AtomicU32>>,
+	fee_targets: Arc<Mutex<HashMap<Target, FeeTargetSettings>>>,
+	/// Transactions we broadcast that haven't confirmed yet, oldest first.
+	unconfirmed_txs: Arc<Mutex<Vec<UnconfirmedTx>>>,
 	handle: tokio::runtime::Handle,
 	logger: Arc<FilesystemLogger>,
+	/// An Esplora instance to fetch blocks a pruned bitcoind no longer has from.
+	block_fallback_url: Option<String>,
 }
 
-#[derive(Clone, Eq, Hash, PartialEq)]
+#[derive(Clone, Copy, Eq, Hash, PartialEq)]
 pub enum Target {
 	Background,
 	Normal,
 	HighPriority,
 }
 
+impl Target {
+	pub const ALL: [Target; 3] = [Target::Background, Target::Normal, Target::HighPriority];
+
+	pub fn name(&self) -> &'static str {
+		match self {
+			Target::Background => "background",
+			Target::Normal => "normal",
+			Target::HighPriority => "high_priority",
+		}
+	}
+
+	fn estimate_mode(&self) -> &'static str {
+		match self {
+			Target::Background | Target::Normal => "ECONOMICAL",
+			Target::HighPriority => "CONSERVATIVE",
+		}
+	}
+
+	/// The feerate used until we get an estimate, or when no source has one for us.
+	fn default_feerate(&self) -> u32 {
+		match self {
+			Target::Background => MIN_FEERATE,
+			Target::Normal => 2000,
+			Target::HighPriority => 5000,
+		}
+	}
+}
+
+impl FromStr for Target {
+	type Err = String;
+	fn from_str(s: &str) -> Result<Self, String> {
+		Target::ALL.iter().find(|target| target.name() == s).copied().ok_or_else(|| {
+			"fee target must be one of background, normal or high_priority".to_string()
+		})
+	}
+}
+
+/// How the feerate for one of the targets is picked.
+#[derive(Clone, Copy)]
+pub struct FeeTargetSettings {
+	/// The number of blocks estimates are asked for.
+	pub conf_blocks: u16,
+	/// A static feerate in sat/KW to use instead of any estimate.
+	pub override_sat_per_kw: Option<u32>,
+}
+
 impl BlockSource for BitcoindClient {
 	fn get_header<'a>(
 		&'a self, header_hash: &'a BlockHash, height_hint: Option<u32>,
 	) -> AsyncBlockSourceResult<'a, BlockHeaderData> {
-		Box::pin(async move { self.bitcoind_rpc_client.get_header(header_hash, height_hint).await })
+		Box::pin(async move {
+			let mut last_err = None;
+			for endpoint in self.endpoints.by_preference() {
+				match endpoint.client().get_header(header_hash, height_hint).await {
+					Ok(header) => return Ok(header),
+					Err(e) => {
+						endpoint.record_block_source_error(&e, &self.logger);
+						last_err = Some(e);
+					}
+				}
+			}
+			Err(last_err.unwrap())
+		})
 	}
 
 	fn get_block<'a>(
 		&'a self, header_hash: &'a BlockHash,
 	) -> AsyncBlockSourceResult<'a, BlockData> {
-		Box::pin(async move { self.bitcoind_rpc_client.get_block(header_hash).await })
+		Box::pin(async move {
+			let mut last_err = None;
+			for endpoint in self.endpoints.by_preference() {
+				match endpoint.client().get_block(header_hash).await {
+					Ok(block) => return Ok(block),
+					Err(e) => {
+						endpoint.record_block_source_error(&e, &self.logger);
+						last_err = Some(e);
+					}
+				}
+			}
+			let e = last_err.unwrap();
+			// A pruned bitcoind doesn't have old blocks anymore, which we may still need to catch
+			// up after being offline for a while.
+			match &self.block_fallback_url {
+				Some(url) => match fetch_esplora_block(url, header_hash).await {
+					Ok(block) => {
+						log_info!(
+							self.logger,
+							"Fetched block {} missing from bitcoind from {}",
+							header_hash,
+							url
+						);
+						Ok(BlockData::FullBlock(block))
+					}
+					Err(fallback_err) => {
+						log_error!(
+							self.logger,
+							"Failed to fetch block {} missing from bitcoind from {}: {}",
+							header_hash,
+							url,
+							fallback_err
+						);
+						Err(e)
+					}
+				},
+				None => Err(e),
+			}
+		})
 	}
 
 	fn get_best_block<'a>(&'a self) -> AsyncBlockSourceResult<(BlockHash, Option<u32>)> {
-		Box::pin(async move { self.bitcoind_rpc_client.get_best_block().await })
+		Box::pin(async move {
+			let mut last_err = None;
+			for endpoint in self.endpoints.by_preference() {
+				match endpoint.client().get_best_block().await {
+					Ok(best_block) => return Ok(best_block),
+					Err(e) => {
+						endpoint.record_block_source_error(&e, &self.logger);
+						last_err = Some(e);
+					}
+				}
+			}
+			Err(last_err.unwrap())
+		})
+	}
+}
+
+/// A bitcoind RPC endpoint and the credentials to use with it.
+#[derive(Clone)]
+pub struct BitcoindRpcInfo {
+	pub host: String,
+	pub port: u16,
+	pub user: String,
+	pub password: String,
+}
+
+impl BitcoindRpcInfo {
+	fn new_client(&self) -> std::io::Result<RpcClient> {
+		let http_endpoint = HttpEndpoint::for_host(self.host.clone()).with_port(self.port);
+		let rpc_credentials = base64::encode(format!("{}:{}", self.user, self.password));
+		RpcClient::new(&rpc_credentials, http_endpoint)
+	}
+}
+
+struct RpcEndpoint {
+	name: String,
+	rpc_info: Mutex<BitcoindRpcInfo>,
+	client: RwLock<Arc<RpcClient>>,
+	/// The cookie file `rpc_info`'s credentials were read from, if any. bitcoind writes a new one
+	/// each time it starts, so we re-read it when calls start failing.
+	cookie_path: Option<PathBuf>,
+	/// Whether the endpoint answered the last time we talked to it.
+	healthy: AtomicBool,
+}
+
+impl RpcEndpoint {
+	fn new(rpc_info: BitcoindRpcInfo, cookie_path: Option<PathBuf>) -> std::io::Result<Self> {
+		Ok(Self {
+			name: format!("{}:{}", rpc_info.host, rpc_info.port),
+			client: RwLock::new(Arc::new(rpc_info.new_client()?)),
+			rpc_info: Mutex::new(rpc_info),
+			cookie_path,
+			healthy: AtomicBool::new(true),
+		})
+	}
+
+	fn client(&self) -> Arc<RpcClient> {
+		Arc::clone(&self.client.read().unwrap())
+	}
+
+	/// Picks up new credentials if bitcoind rotated its cookie file, returning whether they changed.
+	fn reload_cookie(&self, logger: &FilesystemLogger) -> bool {
+		let cookie_path = match &self.cookie_path {
+			Some(cookie_path) => cookie_path,
+			None => return false,
+		};
+		let (user, password) = match args::read_rpc_auth_from_cookie(cookie_path) {
+			Ok(auth) => auth,
+			// bitcoind removes the file while it's shut down.
+			Err(()) => return false,
+		};
+		let mut rpc_info = self.rpc_info.lock().unwrap();
+		if rpc_info.user == user && rpc_info.password == password {
+			return false;
+		}
+		let new_info = BitcoindRpcInfo { user, password, ..rpc_info.clone() };
+		match new_info.new_client() {
+			Ok(client) => {
+				*self.client.write().unwrap() = Arc::new(client);
+				*rpc_info = new_info;
+				log_info!(logger, "Reloaded rotated RPC cookie for bitcoind at {}", self.name);
+				true
+			}
+			Err(_) => false,
+		}
+	}
+
+	/// Calls `method`, retrying once if the call failed because bitcoind rotated its cookie.
+	async fn call_method<T>(
+		&self, method: &str, params: &[serde_json::Value], logger: &FilesystemLogger,
+	) -> std::io::Result<T>
+	where
+		JsonResponse: TryFrom<Vec<u8>, Error = std::io::Error> + TryInto<T, Error = std::io::Error>,
+	{
+		match self.client().call_method::<T>(method, params).await {
+			Err(_) if self.reload_cookie(logger) => {
+				self.client().call_method::<T>(method, params).await
+			}
+			res => res,
+		}
+	}
+
+	fn set_healthy(&self, healthy: bool, logger: &FilesystemLogger) {
+		if self.healthy.swap(healthy, Ordering::AcqRel) != healthy {
+			if healthy {
+				log_info!(logger, "bitcoind at {} is reachable again", self.name);
+			} else {
+				log_error!(logger, "bitcoind at {} is unreachable, failing over", self.name);
+			}
+		}
+	}
+
+	fn record_block_source_error(&self, e: &BlockSourceError, logger: &FilesystemLogger) {
+		if e.kind() == BlockSourceErrorKind::Transient {
+			self.set_healthy(false, logger);
+		}
+		// The next poll will use the new credentials, if any.
+		self.reload_cookie(logger);
+	}
+}
+
+/// The bitcoind endpoints we fetch chain data and fee estimates from and broadcast through,
+/// primary first. Wallet calls always go to the primary, since the others don't have its wallet.
+struct RpcEndpoints {
+	endpoints: Vec<RpcEndpoint>,
+}
+
+/// How often endpoints are checked, so we go back to the primary once it recovers.
+const RPC_HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+impl RpcEndpoints {
+	/// The endpoints that were last healthy first, then the others as a last resort, each in the
+	/// order they were configured in.
+	fn by_preference(&self) -> Vec<&RpcEndpoint> {
+		let (mut endpoints, unhealthy): (Vec<_>, Vec<_>) =
+			self.endpoints.iter().partition(|endpoint| endpoint.healthy.load(Ordering::Acquire));
+		endpoints.extend(unhealthy);
+		endpoints
+	}
+
+	/// The endpoint given on the command line, whose wallet we use.
+	fn primary(&self) -> &RpcEndpoint {
+		&self.endpoints[0]
+	}
+
+	/// Calls `method` on the preferred endpoint, failing over to the next one if it can't be
+	/// reached. Errors returned by bitcoind itself are returned as-is.
+	async fn call_method<T>(
+		&self, method: &str, params: &[serde_json::Value], logger: &FilesystemLogger,
+	) -> std::io::Result<T>
+	where
+		JsonResponse: TryFrom<Vec<u8>, Error = std::io::Error> + TryInto<T, Error = std::io::Error>,
+	{
+		let mut last_err = None;
+		for endpoint in self.by_preference() {
+			match endpoint.call_method::<T>(method, params, logger).await {
+				Ok(res) => {
+					endpoint.set_healthy(true, logger);
+					return Ok(res);
+				}
+				// RPC and HTTP errors come back as `Other`, anything else is a connection issue.
+				Err(e) if e.kind() == std::io::ErrorKind::Other => return Err(e),
+				Err(e) => {
+					endpoint.set_healthy(false, logger);
+					last_err = Some(e);
+				}
+			}
+		}
+		Err(last_err.unwrap())
+	}
+
+	fn start_health_checks(
+		endpoints: Arc<RpcEndpoints>, handle: &tokio::runtime::Handle,
+		logger: Arc<FilesystemLogger>,
+	) {
+		if endpoints.endpoints.len() < 2 {
+			return;
+		}
+		handle.spawn(async move {
+			loop {
+				tokio::time::sleep(RPC_HEALTH_CHECK_INTERVAL).await;
+				for endpoint in endpoints.endpoints.iter() {
+					let res = endpoint
+						.call_method::<BlockchainInfo>("getblockchaininfo", &vec![], &logger)
+						.await;
+					endpoint.set_healthy(res.is_ok(), &logger);
+				}
+			}
+		});
+	}
+}
+
+/// Fetches a block from the Esplora instance at `url`, checking it's the one we asked for since
+/// the instance isn't trusted.
+async fn fetch_esplora_block(url: &str, header_hash: &BlockHash) -> Result<Block, String> {
+	let block_url = format!("{}/block/{}/raw", url, header_hash);
+	let response = reqwest::get(&block_url).await.map_err(|e| e.to_string())?;
+	if !response.status().is_success() {
+		return Err(format!("{} returned {}", block_url, response.status()));
+	}
+	let bytes = response.bytes().await.map_err(|e| e.to_string())?;
+	let block: Block = encode::deserialize(&bytes).map_err(|e| e.to_string())?;
+	if block.block_hash() != *header_hash || !block.check_merkle_root() {
+		return Err(format!("{} returned an invalid block", block_url));
+	}
+	Ok(block)
+}
+
+/// An HTTP API to fetch fee estimates from alongside bitcoind's `estimatesmartfee`, which can be
+/// way off on a freshly synced or pruned bitcoind that hasn't seen many blocks get mined.
+#[derive(Clone)]
+pub enum FeeSource {
+	/// A mempool.space instance's `/api/v1/fees/recommended` endpoint, given its base URL.
+	MempoolSpace(String),
+	/// whatthefee.io's `data.json`, given its full URL.
+	WhatTheFee(String),
+}
+
+pub const DEFAULT_MEMPOOL_SPACE_URL: &str = "https://mempool.space";
+pub const DEFAULT_WHATTHEFEE_URL: &str = "https://whatthefee.io/data.json";
+
+/// The confirmation probability we pick whatthefee.io's estimates for.
+const WHATTHEFEE_CONFIDENCE: f64 = 0.8;
+const FEE_SOURCE_TIMEOUT: Duration = Duration::from_secs(10);
+
+impl std::fmt::Display for FeeSource {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self {
+			FeeSource::MempoolSpace(url) | FeeSource::WhatTheFee(url) => write!(f, "{}", url),
+		}
+	}
+}
+
+impl FeeSource {
+	/// Fetches the source's current estimates, as (confirmation target in blocks, sat/KW) pairs
+	/// sorted by target.
+	async fn fetch(&self, client: &reqwest::Client) -> Result<Vec<(u16, u32)>, String> {
+		match self {
+			FeeSource::MempoolSpace(url) => {
+				let url = format!("{}/api/v1/fees/recommended", url);
+				let fees = get_fee_json(client, &url).await?;
+				let mut estimates = Vec::new();
+				for (blocks, key) in
+					[(1, "fastestFee"), (3, "halfHourFee"), (6, "hourFee"), (144, "economyFee")]
+						.iter()
+				{
+					let sat_per_vb =
+						fees[key].as_f64().ok_or_else(|| format!("{} returned no {}", url, key))?;
+					estimates.push((*blocks, sat_per_vb_to_sat_per_kw(sat_per_vb)));
+				}
+				Ok(estimates)
+			}
+			FeeSource::WhatTheFee(url) => {
+				let fees = get_fee_json(client, url).await?;
+				parse_whatthefee_estimates(&fees)
+					.ok_or_else(|| format!("{} returned malformed fee data", url))
+			}
+		}
+	}
+}
+
+/// Parses whatthefee.io's fee data, whose rows are confirmation targets, columns are the
+/// probability to confirm within the target, and values are 100 * ln(sat/vB). Returns `None` if
+/// it's malformed.
+fn parse_whatthefee_estimates(fees: &serde_json::Value) -> Option<Vec<(u16, u32)>> {
+	let column = fees["columns"]
+		.as_array()?
+		.iter()
+		.map(|c| c.as_str().and_then(|c| c.parse::<f64>().ok()).filter(|c| c.is_finite()))
+		.collect::<Option<Vec<f64>>>()?
+		.iter()
+		.enumerate()
+		.min_by(|(_, a), (_, b)| {
+			(*a - WHATTHEFEE_CONFIDENCE).abs().total_cmp(&(*b - WHATTHEFEE_CONFIDENCE).abs())
+		})?
+		.0;
+	let index = fees["index"].as_array()?;
+	let data = fees["data"].as_array()?;
+	if index.len() != data.len() {
+		return None;
+	}
+	let mut estimates = Vec::new();
+	for (blocks, row) in index.iter().zip(data.iter()) {
+		let blocks = u16::try_from(blocks.as_u64()?).ok()?;
+		let value = row[column].as_f64()?;
+		estimates.push((blocks, sat_per_vb_to_sat_per_kw((value / 100.0).exp())));
+	}
+	estimates.sort_by_key(|(blocks, _)| *blocks);
+	if estimates.is_empty() {
+		return None;
+	}
+	Some(estimates)
+}
+
+async fn get_fee_json(client: &reqwest::Client, url: &str) -> Result<serde_json::Value, String> {
+	let response = client.get(url).send().await.map_err(|e| e.to_string())?;
+	if !response.status().is_success() {
+		return Err(format!("{} returned {}", url, response.status()));
+	}
+	response.json().await.map_err(|e| e.to_string())
+}
+
+fn sat_per_vb_to_sat_per_kw(sat_per_vb: f64) -> u32 {
+	// 1000 weight units are 250 virtual bytes.
+	(sat_per_vb * 250.0).round() as u32
+}
+
+/// The estimate for `conf_target` blocks out of a source's estimates, i.e. the one for the
+/// closest target that's at least as far out, or the furthest one.
+fn estimate_for_target(estimates: &[(u16, u32)], conf_target: u16) -> u32 {
+	estimates
+		.iter()
+		.find(|(blocks, _)| *blocks >= conf_target)
+		.unwrap_or(estimates.last().unwrap())
+		.1
+}
+
+/// How estimates from several sources are combined into the one we use.
+#[derive(Clone, Copy)]
+pub enum FeeBlend {
+	/// The highest estimate, so a single lowballing source can't get our transactions stuck.
+	Max,
+	/// The median estimate, so a single outlier can't skew the result either way.
+	Median,
+}
+
+impl FeeBlend {
+	fn blend(&self, estimates: &mut Vec<u32>) -> Option<u32> {
+		if estimates.is_empty() {
+			return None;
+		}
+		estimates.sort_unstable();
+		let len = estimates.len();
+		match self {
+			FeeBlend::Max => Some(estimates[len - 1]),
+			FeeBlend::Median if len % 2 == 0 => {
+				Some(((estimates[len / 2 - 1] as u64 + estimates[len / 2] as u64) / 2) as u32)
+			}
+			FeeBlend::Median => Some(estimates[len / 2]),
+		}
 	}
 }
 
+/// Where fee estimates come from and how they're combined.
+#[derive(Clone)]
+pub struct FeeConfig {
+	pub sources: Vec<FeeSource>,
+	pub blend: FeeBlend,
+	/// Estimates above this are discarded as bogus rather than risking overpaying.
+	pub max_sat_per_vb: u32,
+	pub targets: HashMap<Target, FeeTargetSettings>,
+}
+
+impl Default for FeeConfig {
+	fn default() -> Self {
+		let mut targets = HashMap::new();
+		for (target, conf_blocks) in
+			[(Target::Background, 144), (Target::Normal, 18), (Target::HighPriority, 6)].iter()
+		{
+			targets.insert(
+				*target,
+				FeeTargetSettings { conf_blocks: *conf_blocks, override_sat_per_kw: None },
+			);
+		}
+		Self { sources: Vec::new(), blend: FeeBlend::Max, max_sat_per_vb: 1000, targets }
+	}
+}
+
+impl FeeConfig {
+	/// Blends the (source, sat/KW) estimates for one target into the feerate we use, leaving out
+	/// those above `max_sat_per_vb`, which are returned for the caller to report.
+	fn combine(&self, estimates: Vec<(String, u32)>) -> (Option<u32>, Vec<(String, u32)>) {
+		let max_sat_per_kw = sat_per_vb_to_sat_per_kw(self.max_sat_per_vb as f64);
+		let (kept, discarded): (Vec<_>, Vec<_>) =
+			estimates.into_iter().partition(|(_, feerate)| *feerate <= max_sat_per_kw);
+		let mut kept = kept.into_iter().map(|(_, feerate)| feerate).collect();
+		let feerate =
+			self.blend.blend(&mut kept).map(|feerate| std::cmp::max(feerate, MIN_FEERATE));
+		(feerate, discarded)
+	}
+}
+
+/// A transaction we broadcast, tracked until it confirms.
+#[derive(Clone)]
+pub struct UnconfirmedTx {
+	pub tx: Transaction,
+	pub first_broadcast_at: u64,
+	pub rebroadcasts: u32,
+	/// Whether the transaction was in bitcoind's mempool the last time we checked.
+	pub in_mempool: bool,
+	/// Why bitcoind last refused to take the transaction back into its mempool, if it did.
+	pub last_error: Option<String>,
+	/// How many times in a row bitcoind told us the transaction's inputs are gone.
+	missing_inputs: u32,
+}
+
+/// How often unconfirmed transactions are checked on and rebroadcast.
+const REBROADCAST_INTERVAL: Duration = Duration::from_secs(600);
+/// After how many checks finding its inputs spent we assume a transaction confirmed and had its
+/// outputs spent since, or was double-spent, and stop tracking it.
+const MAX_MISSING_INPUTS_CHECKS: u32 = 6;
+
 /// The minimum feerate we are allowed to send, as specify by LDK.
 const MIN_FEERATE: u32 = 253;
 
 impl BitcoindClient {
 	pub(crate) async fn new(
 		host: String, port: u16, rpc_user: String, rpc_password: String,
+		cookie_path: Option<PathBuf>, backups: Vec<BitcoindRpcInfo>, fee_config: FeeConfig,
 		handle: tokio::runtime::Handle, logger: Arc<FilesystemLogger>,
+		block_fallback_url: Option<String>,
 	) -> std::io::Result<Self> {
-		let http_endpoint = HttpEndpoint::for_host(host.clone()).with_port(port);
-		let rpc_credentials =
-			base64::encode(format!("{}:{}", rpc_user.clone(), rpc_password.clone()));
-		let bitcoind_rpc_client = RpcClient::new(&rpc_credentials, http_endpoint)?;
-		let _dummy = bitcoind_rpc_client
-			.call_method::<BlockchainInfo>("getblockchaininfo", &vec![])
+		let rpc_info = BitcoindRpcInfo { host, port, user: rpc_user, password: rpc_password };
+		let primary = RpcEndpoint::new(rpc_info, cookie_path)?;
+		let _dummy = primary
+			.call_method::<BlockchainInfo>("getblockchaininfo", &vec![], &logger)
 			.await
 			.map_err(|_| {
 				std::io::Error::new(std::io::ErrorKind::PermissionDenied,
 				"Failed to make initial call to bitcoind - please check your RPC user/password and access settings")
 			})?;
+		let mut endpoints = vec![primary];
+		for backup in backups {
+			endpoints.push(RpcEndpoint::new(backup, None)?);
+		}
+		let endpoints = Arc::new(RpcEndpoints { endpoints });
 		let mut fees: HashMap<Target, AtomicU32> = HashMap::new();
-		fees.insert(Target::Background, AtomicU32::new(MIN_FEERATE));
-		fees.insert(Target::Normal, AtomicU32::new(2000));
-		fees.insert(Target::HighPriority, AtomicU32::new(5000));
+		for target in Target::ALL.iter() {
+			fees.insert(*target, AtomicU32::new(target.default_feerate()));
+		}
 		let client = Self {
-			bitcoind_rpc_client: Arc::new(bitcoind_rpc_client),
-			host,
-			port,
-			rpc_user,
-			rpc_password,
+			endpoints,
 			fees: Arc::new(fees),
+			fee_targets: Arc::new(Mutex::new(fee_config.targets.clone())),
+			unconfirmed_txs: Arc::new(Mutex::new(Vec::new())),
 			handle: handle.clone(),
 			logger,
+			block_fallback_url,
 		};
+		RpcEndpoints::start_health_checks(
+			Arc::clone(&client.endpoints),
+			&handle,
+			Arc::clone(&client.logger),
+		);
+		BitcoindClient::rebroadcast_unconfirmed_txs(
+			Arc::clone(&client.unconfirmed_txs),
+			Arc::clone(&client.endpoints),
+			&handle,
+			Arc::clone(&client.logger),
+		);
 		BitcoindClient::poll_for_fee_estimates(
 			client.fees.clone(),
-			client.bitcoind_rpc_client.clone(),
+			Arc::clone(&client.fee_targets),
+			client.endpoints.clone(),
+			fee_config,
 			handle,
+			Arc::clone(&client.logger),
 		);
 		Ok(client)
 	}
 
 	fn poll_for_fee_estimates(
-		fees: Arc<HashMap<Target, AtomicU32>>, rpc_client: Arc<RpcClient>,
-		handle: tokio::runtime::Handle,
+		fees: Arc<HashMap<Target, AtomicU32>>,
+		fee_targets: Arc<Mutex<HashMap<Target, FeeTargetSettings>>>, endpoints: Arc<RpcEndpoints>,
+		fee_config: FeeConfig, handle: tokio::runtime::Handle, logger: Arc<FilesystemLogger>,
 	) {
 		handle.spawn(async move {
+			let http_client =
+				reqwest::Client::builder().timeout(FEE_SOURCE_TIMEOUT).build().unwrap();
 			loop {
-				let background_estimate = {
-					let background_conf_target = serde_json::json!(144);
-					let background_estimate_mode = serde_json::json!("ECONOMICAL");
-					let resp = rpc_client
-						.call_method::<FeeResponse>(
-							"estimatesmartfee",
-							&vec![background_conf_target, background_estimate_mode],
-						)
-						.await
-						.unwrap();
-					match resp.feerate_sat_per_kw {
-						Some(feerate) => std::cmp::max(feerate, MIN_FEERATE),
-						None => MIN_FEERATE,
+				let mut fetched = Vec::new();
+				for source in fee_config.sources.iter() {
+					match source.fetch(&http_client).await {
+						Ok(estimates) => fetched.push((source, estimates)),
+						Err(e) => {
+							log_error!(
+								logger,
+								"Failed to fetch fee estimates from {}: {}",
+								source,
+								e
+							)
+						}
 					}
-				};
-
-				let normal_estimate = {
-					let normal_conf_target = serde_json::json!(18);
-					let normal_estimate_mode = serde_json::json!("ECONOMICAL");
-					let resp = rpc_client
+				}
+				for target in Target::ALL.iter() {
+					// Targets may be reconfigured at runtime, so we look them up every time.
+					let conf_target = fee_targets.lock().unwrap().get(target).unwrap().conf_blocks;
+					let resp = endpoints
 						.call_method::<FeeResponse>(
 							"estimatesmartfee",
-							&vec![normal_conf_target, normal_estimate_mode],
+							&vec![
+								serde_json::json!(conf_target),
+								serde_json::json!(target.estimate_mode()),
+							],
+							&logger,
 						)
-						.await
-						.unwrap();
-					match resp.feerate_sat_per_kw {
-						Some(feerate) => std::cmp::max(feerate, MIN_FEERATE),
-						None => 2000,
+						.await;
+					let mut estimates = Vec::new();
+					if let Ok(FeeResponse { feerate_sat_per_kw: Some(feerate), .. }) = &resp {
+						estimates.push(("bitcoind".to_string(), *feerate));
 					}
-				};
+					for (source, source_estimates) in fetched.iter() {
+						let feerate = estimate_for_target(source_estimates, conf_target);
+						estimates.push((source.to_string(), feerate));
+					}
+					let (estimate, discarded) = fee_config.combine(estimates);
+					for (source, feerate) in discarded {
+						log_error!(
+							logger,
+							"Discarding fee estimate of {} sat/KW from {}, above our maximum of {} sat/vB",
+							feerate,
+							source,
+							fee_config.max_sat_per_vb
+						);
+					}
+					let estimate = match estimate {
+						Some(feerate) => feerate,
+						// If no source can be reached, we keep using the last estimates we got.
+						None if resp.is_err() && fetched.is_empty() => continue,
+						None => target.default_feerate(),
+					};
+					fees.get(target).unwrap().store(estimate, Ordering::Release);
+				}
+				tokio::time::sleep(Duration::from_secs(60)).await;
+			}
+		});
+	}
 
-				let high_prio_estimate = {
-					let high_prio_conf_target = serde_json::json!(6);
-					let high_prio_estimate_mode = serde_json::json!("CONSERVATIVE");
-					let resp = rpc_client
-						.call_method::<FeeResponse>(
-							"estimatesmartfee",
-							&vec![high_prio_conf_target, high_prio_estimate_mode],
+	/// Periodically checks on the transactions we broadcast, putting those that were evicted from
+	/// bitcoind's mempool back in and having bitcoind relay the others again, until they confirm.
+	fn rebroadcast_unconfirmed_txs(
+		unconfirmed_txs: Arc<Mutex<Vec<UnconfirmedTx>>>, endpoints: Arc<RpcEndpoints>,
+		handle: &tokio::runtime::Handle, logger: Arc<FilesystemLogger>,
+	) {
+		handle.spawn(async move {
+			loop {
+				tokio::time::sleep(REBROADCAST_INTERVAL).await;
+				let txs = unconfirmed_txs.lock().unwrap().clone();
+				for tracked in txs {
+					let txid = tracked.tx.txid();
+					let in_mempool = endpoints
+						.call_method::<MempoolEntry>(
+							"getmempoolentry",
+							&[serde_json::json!(txid.to_string())],
+							&logger,
 						)
 						.await
-						.unwrap();
-
-					match resp.feerate_sat_per_kw {
-						Some(feerate) => std::cmp::max(feerate, MIN_FEERATE),
-						None => 5000,
+						.is_ok();
+					if tracked.in_mempool && !in_mempool {
+						log_info!(logger, "Transaction {} left the mempool", txid);
 					}
-				};
-
-				fees.get(&Target::Background)
-					.unwrap()
-					.store(background_estimate, Ordering::Release);
-				fees.get(&Target::Normal).unwrap().store(normal_estimate, Ordering::Release);
-				fees.get(&Target::HighPriority)
-					.unwrap()
-					.store(high_prio_estimate, Ordering::Release);
-				tokio::time::sleep(Duration::from_secs(60)).await;
+					// bitcoind relays transactions already in its mempool again when they're sent
+					// to it, and tells us if they already confirmed.
+					let tx_json = serde_json::json!(encode::serialize_hex(&tracked.tx));
+					let res = endpoints
+						.call_method::<Txid>("sendrawtransaction", &[tx_json], &logger)
+						.await;
+					let mut unconfirmed = unconfirmed_txs.lock().unwrap();
+					let pos = match unconfirmed.iter().position(|tracked| tracked.tx.txid() == txid)
+					{
+						Some(pos) => pos,
+						None => continue,
+					};
+					let tracked = &mut unconfirmed[pos];
+					tracked.rebroadcasts += 1;
+					match res {
+						Ok(_) => {
+							if !in_mempool {
+								log_info!(logger, "Rebroadcast evicted transaction {}", txid);
+							}
+							tracked.in_mempool = true;
+							tracked.last_error = None;
+							tracked.missing_inputs = 0;
+						}
+						Err(_) if in_mempool => tracked.in_mempool = true,
+						Err(e) => {
+							let err_str = e.to_string();
+							tracked.in_mempool = false;
+							if err_str.contains("already in block chain")
+								|| err_str.contains("already in utxo set")
+							{
+								unconfirmed.remove(pos);
+							} else if err_str.contains("missingorspent")
+								|| err_str.contains("Missing inputs")
+							{
+								tracked.missing_inputs += 1;
+								tracked.last_error = Some(err_str);
+								if tracked.missing_inputs >= MAX_MISSING_INPUTS_CHECKS {
+									log_info!(
+										logger,
+										"Giving up on transaction {} as its inputs were spent",
+										txid
+									);
+									unconfirmed.remove(pos);
+								}
+							} else {
+								log_error!(
+									logger,
+									"Failed to rebroadcast transaction {}: {}",
+									txid,
+									err_str
+								);
+								tracked.last_error = Some(err_str);
+							}
+						}
+					}
+				}
 			}
 		});
 	}
 
+	/// The transactions we broadcast that haven't confirmed yet, oldest first.
+	pub fn unconfirmed_txs(&self) -> Vec<UnconfirmedTx> {
+		self.unconfirmed_txs.lock().unwrap().clone()
+	}
+
+	pub fn fee_target_settings(&self, target: Target) -> FeeTargetSettings {
+		*self.fee_targets.lock().unwrap().get(&target).unwrap()
+	}
+
+	/// Changes how the feerate for `target` is picked. Overrides apply right away, while a new
+	/// confirmation target is picked up the next time estimates are polled.
+	pub fn set_fee_target_settings(&self, target: Target, settings: FeeTargetSettings) {
+		self.fee_targets.lock().unwrap().insert(target, settings);
+	}
+
 	pub fn get_new_rpc_client(&self) -> std::io::Result<RpcClient> {
-		let http_endpoint = HttpEndpoint::for_host(self.host.clone()).with_port(self.port);
-		let rpc_credentials =
-			base64::encode(format!("{}:{}", self.rpc_user.clone(), self.rpc_password.clone()));
-		RpcClient::new(&rpc_credentials, http_endpoint)
+		self.endpoints.primary().rpc_info.lock().unwrap().new_client()
 	}
 
 	pub async fn create_raw_transaction(&self, outputs: Vec<HashMap<String, f64>>) -> RawTx {
 		let outputs_json = serde_json::json!(outputs);
-		self.bitcoind_rpc_client
+		self.endpoints
+			.primary()
 			.call_method::<RawTx>(
 				"createrawtransaction",
 				&vec![serde_json::json!([]), outputs_json],
+				&self.logger,
 			)
 			.await
 			.unwrap()
@@ -195,73 +819,136 @@ impl BitcoindClient {
 			// change address or to a new channel output negotiated with the same node.
 			"replaceable": false,
 		});
-		self.bitcoind_rpc_client
-			.call_method("fundrawtransaction", &[raw_tx_json, options])
+		self.endpoints
+			.primary()
+			.call_method("fundrawtransaction", &[raw_tx_json, options], &self.logger)
 			.await
 			.unwrap()
 	}
 
 	pub async fn send_raw_transaction(&self, raw_tx: RawTx) {
 		let raw_tx_json = serde_json::json!(raw_tx.0);
-		self.bitcoind_rpc_client
-			.call_method::<Txid>("sendrawtransaction", &[raw_tx_json])
+		self.endpoints
+			.call_method::<Txid>("sendrawtransaction", &[raw_tx_json], &self.logger)
 			.await
 			.unwrap();
 	}
 
 	pub async fn sign_raw_transaction_with_wallet(&self, tx_hex: String) -> SignedTx {
 		let tx_hex_json = serde_json::json!(tx_hex);
-		self.bitcoind_rpc_client
-			.call_method("signrawtransactionwithwallet", &vec![tx_hex_json])
+		self.endpoints
+			.primary()
+			.call_method("signrawtransactionwithwallet", &vec![tx_hex_json], &self.logger)
 			.await
 			.unwrap()
 	}
 
-	pub async fn get_new_address(&self) -> Address {
-		let addr_args = vec![serde_json::json!("LDK output address")];
+	/// `address_type` is bitcoind's name for the type, e.g. `bech32m` for P2TR.
+	pub async fn get_new_address(&self, address_type: &str) -> Address {
+		let addr_args =
+			vec![serde_json::json!("LDK output address"), serde_json::json!(address_type)];
 		let addr = self
-			.bitcoind_rpc_client
-			.call_method::<NewAddress>("getnewaddress", &addr_args)
+			.endpoints
+			.primary()
+			.call_method::<NewAddress>("getnewaddress", &addr_args, &self.logger)
 			.await
 			.unwrap();
 		Address::from_str(addr.0.as_str()).unwrap()
 	}
 
+	pub async fn get_balances(&self) -> std::io::Result<Balances> {
+		self.endpoints.primary().call_method::<Balances>("getbalances", &[], &self.logger).await
+	}
+
+	/// Returns whether `outpoint` is unspent in the best chain.
+	pub async fn is_output_unspent(&self, outpoint: OutPoint) -> std::io::Result<bool> {
+		let params =
+			[serde_json::json!(outpoint.txid.to_string()), serde_json::json!(outpoint.vout)];
+		let status =
+			self.endpoints.call_method::<TxOutStatus>("gettxout", &params, &self.logger).await?;
+		Ok(status.0)
+	}
+
+	/// Returns the unspent outputs paying to any of `scripts`, scanning the whole UTXO set.
+	pub async fn scan_txout_set(
+		&self, scripts: &[Script],
+	) -> std::io::Result<Vec<(OutPoint, TxOut)>> {
+		let descriptors =
+			scripts.iter().map(|script| format!("raw({})", script.to_hex())).collect::<Vec<_>>();
+		let params = [serde_json::json!("start"), serde_json::json!(descriptors)];
+		let scan = self
+			.endpoints
+			.call_method::<TxOutSetScan>("scantxoutset", &params, &self.logger)
+			.await?;
+		Ok(scan.0)
+	}
+
 	pub async fn get_blockchain_info(&self) -> BlockchainInfo {
-		self.bitcoind_rpc_client
-			.call_method::<BlockchainInfo>("getblockchaininfo", &vec![])
+		self.endpoints
+			.call_method::<BlockchainInfo>("getblockchaininfo", &vec![], &self.logger)
 			.await
 			.unwrap()
 	}
 }
 
+/// Subscribes to the `hashblock` notifications bitcoind publishes on `endpoint` when started with
+/// `-zmqpubhashblock=<endpoint>`. The returned channel yields a message whenever a new block is
+/// connected, and is closed if the subscription fails later on.
+pub async fn subscribe_block_notifications(endpoint: &str) -> Result<mpsc::Receiver<()>, String> {
+	let mut socket = SubSocket::new();
+	socket.connect(endpoint).await.map_err(|e| e.to_string())?;
+	socket.subscribe("hashblock").await.map_err(|e| e.to_string())?;
+	// We only need to know that something changed, so notifications that arrive while the previous
+	// one hasn't been handled yet are coalesced.
+	let (sender, receiver) = mpsc::channel(1);
+	tokio::spawn(async move {
+		while socket.recv().await.is_ok() {
+			if let Err(mpsc::error::TrySendError::Closed(_)) = sender.try_send(()) {
+				return;
+			}
+		}
+	});
+	Ok(receiver)
+}
+
 impl FeeEstimator for BitcoindClient {
 	fn get_est_sat_per_1000_weight(&self, confirmation_target: ConfirmationTarget) -> u32 {
-		match confirmation_target {
-			ConfirmationTarget::Background => {
-				self.fees.get(&Target::Background).unwrap().load(Ordering::Acquire)
-			}
-			ConfirmationTarget::Normal => {
-				self.fees.get(&Target::Normal).unwrap().load(Ordering::Acquire)
-			}
-			ConfirmationTarget::HighPriority => {
-				self.fees.get(&Target::HighPriority).unwrap().load(Ordering::Acquire)
-			}
+		let target = match confirmation_target {
+			ConfirmationTarget::Background => Target::Background,
+			ConfirmationTarget::Normal => Target::Normal,
+			ConfirmationTarget::HighPriority => Target::HighPriority,
+		};
+		match self.fee_target_settings(target).override_sat_per_kw {
+			Some(feerate) => std::cmp::max(feerate, MIN_FEERATE),
+			None => self.fees.get(&target).unwrap().load(Ordering::Acquire),
 		}
 	}
 }
 
 impl BroadcasterInterface for BitcoindClient {
 	fn broadcast_transaction(&self, tx: &Transaction) {
-		let bitcoind_rpc_client = self.bitcoind_rpc_client.clone();
+		{
+			let mut unconfirmed_txs = self.unconfirmed_txs.lock().unwrap();
+			if unconfirmed_txs.iter().all(|tracked| tracked.tx.txid() != tx.txid()) {
+				unconfirmed_txs.push(UnconfirmedTx {
+					tx: tx.clone(),
+					first_broadcast_at: time_now_secs(),
+					rebroadcasts: 0,
+					in_mempool: true,
+					last_error: None,
+					missing_inputs: 0,
+				});
+			}
+		}
+		let endpoints = Arc::clone(&self.endpoints);
 		let tx_serialized = encode::serialize_hex(tx);
 		let tx_json = serde_json::json!(tx_serialized);
 		let logger = Arc::clone(&self.logger);
 		self.handle.spawn(async move {
 			// This may error due to RL calling `broadcast_transaction` with the same transaction
 			// multiple times, but the error is safe to ignore.
-			match bitcoind_rpc_client
-				.call_method::<Txid>("sendrawtransaction", &vec![tx_json])
+			match endpoints
+				.call_method::<Txid>("sendrawtransaction", &vec![tx_json], &logger)
 				.await
 			{
 				Ok(_) => {}
@@ -284,3 +971,278 @@ impl UtxoLookup for BitcoindClient {
 		todo!();
 	}
 }
+
+#[cfg(test)]
+mod failover_tests {
+	use super::*;
+	use std::sync::atomic::AtomicUsize;
+	use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+	use tokio::net::TcpListener;
+
+	/// A bitcoind stand-in answering every call with the same result, or RPC error.
+	struct TestBitcoind {
+		port: u16,
+		calls: Arc<AtomicUsize>,
+	}
+
+	async fn serve(response: Result<&'static str, &'static str>) -> TestBitcoind {
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let port = listener.local_addr().unwrap().port();
+		let calls = Arc::new(AtomicUsize::new(0));
+		let server_calls = Arc::clone(&calls);
+		tokio::spawn(async move {
+			loop {
+				let (stream, _) = listener.accept().await.unwrap();
+				let calls = Arc::clone(&server_calls);
+				tokio::spawn(async move {
+					let mut stream = BufReader::new(stream);
+					loop {
+						let mut content_length = 0;
+						loop {
+							let mut line = String::new();
+							if stream.read_line(&mut line).await.unwrap_or(0) == 0 {
+								return;
+							}
+							if line == "\r\n" {
+								break;
+							}
+							let line = line.to_ascii_lowercase();
+							if let Some(len) = line.strip_prefix("content-length:") {
+								content_length = len.trim().parse().unwrap();
+							}
+						}
+						let mut body = vec![0; content_length];
+						stream.read_exact(&mut body).await.unwrap();
+						calls.fetch_add(1, Ordering::AcqRel);
+						let id = serde_json::from_slice::<serde_json::Value>(&body).unwrap()["id"]
+							.clone();
+						let body = match response {
+							Ok(result) => {
+								serde_json::json!({ "result": result, "error": null, "id": id })
+							}
+							Err(message) => serde_json::json!({
+								"result": null,
+								"error": { "code": -4, "message": message },
+								"id": id,
+							}),
+						}
+						.to_string();
+						let reply = format!(
+							"HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+							body.len(),
+							body
+						);
+						stream.get_mut().write_all(reply.as_bytes()).await.unwrap();
+					}
+				});
+			}
+		});
+		TestBitcoind { port, calls }
+	}
+
+	fn endpoint(port: u16) -> RpcEndpoint {
+		let rpc_info = BitcoindRpcInfo {
+			host: "127.0.0.1".to_string(),
+			port,
+			user: "user".to_string(),
+			password: "password".to_string(),
+		};
+		RpcEndpoint::new(rpc_info, None).unwrap()
+	}
+
+	/// An endpoint whose bitcoind went away after we connected to it.
+	fn unreachable_endpoint() -> RpcEndpoint {
+		let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+		endpoint(listener.local_addr().unwrap().port())
+	}
+
+	fn test_logger() -> FilesystemLogger {
+		let dir = std::env::temp_dir().join("ldk-sample-failover-tests");
+		FilesystemLogger::new(dir.to_str().unwrap().to_string())
+	}
+
+	fn names(endpoints: &[&RpcEndpoint]) -> Vec<String> {
+		endpoints.iter().map(|endpoint| endpoint.name.clone()).collect()
+	}
+
+	#[tokio::test]
+	async fn test_by_preference() {
+		let servers = [serve(Ok("")).await, serve(Ok("")).await, serve(Ok("")).await];
+		let endpoints =
+			RpcEndpoints { endpoints: servers.iter().map(|s| endpoint(s.port)).collect() };
+		let all = names(&endpoints.endpoints.iter().collect::<Vec<_>>());
+		assert_eq!(names(&endpoints.by_preference()), all);
+		assert_eq!(endpoints.primary().name, all[0]);
+
+		// Unhealthy endpoints are only tried last, in order.
+		endpoints.endpoints[0].healthy.store(false, Ordering::Release);
+		assert_eq!(
+			names(&endpoints.by_preference()),
+			vec![all[1].clone(), all[2].clone(), all[0].clone()]
+		);
+		endpoints.endpoints[2].healthy.store(false, Ordering::Release);
+		assert_eq!(
+			names(&endpoints.by_preference()),
+			vec![all[1].clone(), all[0].clone(), all[2].clone()]
+		);
+		endpoints.endpoints[1].healthy.store(false, Ordering::Release);
+		assert_eq!(names(&endpoints.by_preference()), all);
+		// The primary stays the primary whatever its health.
+		assert_eq!(endpoints.primary().name, all[0]);
+	}
+
+	#[tokio::test]
+	async fn test_call_fails_over_unreachable_endpoints() {
+		let logger = test_logger();
+		let backup = serve(Ok("bcrt1qaddress")).await;
+		let endpoints =
+			RpcEndpoints { endpoints: vec![unreachable_endpoint(), endpoint(backup.port)] };
+		let address: NewAddress =
+			endpoints.call_method("getnewaddress", &[], &logger).await.unwrap();
+		assert_eq!(address.0, "bcrt1qaddress");
+		assert_eq!(backup.calls.load(Ordering::Acquire), 1);
+		assert!(!endpoints.endpoints[0].healthy.load(Ordering::Acquire));
+		assert!(endpoints.endpoints[1].healthy.load(Ordering::Acquire));
+
+		// The backup is now tried first.
+		let _: NewAddress = endpoints.call_method("getnewaddress", &[], &logger).await.unwrap();
+		assert_eq!(names(&endpoints.by_preference())[0], endpoints.endpoints[1].name);
+		assert_eq!(backup.calls.load(Ordering::Acquire), 2);
+	}
+
+	#[tokio::test]
+	async fn test_call_returns_rpc_errors_without_failing_over() {
+		let logger = test_logger();
+		let primary = serve(Err("Insufficient funds")).await;
+		let backup = serve(Ok("bcrt1qaddress")).await;
+		let endpoints =
+			RpcEndpoints { endpoints: vec![endpoint(primary.port), endpoint(backup.port)] };
+		let res: std::io::Result<NewAddress> =
+			endpoints.call_method("getnewaddress", &[], &logger).await;
+		assert_eq!(res.err().unwrap().kind(), std::io::ErrorKind::Other);
+		assert_eq!(primary.calls.load(Ordering::Acquire), 1);
+		assert_eq!(backup.calls.load(Ordering::Acquire), 0);
+		// bitcoind answered, so it's still healthy.
+		assert!(endpoints.endpoints[0].healthy.load(Ordering::Acquire));
+	}
+
+	#[tokio::test]
+	async fn test_call_fails_once_every_endpoint_is_unreachable() {
+		let logger = test_logger();
+		let endpoints =
+			RpcEndpoints { endpoints: vec![unreachable_endpoint(), unreachable_endpoint()] };
+		let res: std::io::Result<NewAddress> =
+			endpoints.call_method("getnewaddress", &[], &logger).await;
+		assert_ne!(res.err().unwrap().kind(), std::io::ErrorKind::Other);
+		assert!(endpoints.by_preference().iter().all(|e| !e.healthy.load(Ordering::Acquire)));
+	}
+}
+
+#[cfg(test)]
+mod fee_tests {
+	use super::*;
+
+	fn whatthefee_data(columns: &[&str]) -> serde_json::Value {
+		// 100 * ln(sat/vB) for 1, 10 and 100 sat/vB.
+		serde_json::json!({
+			"index": [6, 1, 144],
+			"columns": columns,
+			"data": [[0.0, 230.2585, 460.517], [230.2585, 460.517, 0.0], [0.0, 0.0, 230.2585]],
+		})
+	}
+
+	#[test]
+	fn test_blend_max() {
+		assert_eq!(FeeBlend::Max.blend(&mut Vec::new()), None);
+		assert_eq!(FeeBlend::Max.blend(&mut vec![1000]), Some(1000));
+		assert_eq!(FeeBlend::Max.blend(&mut vec![2000, 5000, 1000]), Some(5000));
+	}
+
+	#[test]
+	fn test_blend_median() {
+		assert_eq!(FeeBlend::Median.blend(&mut Vec::new()), None);
+		assert_eq!(FeeBlend::Median.blend(&mut vec![1000]), Some(1000));
+		assert_eq!(FeeBlend::Median.blend(&mut vec![5000, 1000, 2000]), Some(2000));
+		// An even number of estimates averages the middle two.
+		assert_eq!(FeeBlend::Median.blend(&mut vec![5000, 1000, 2000, 3000]), Some(2500));
+		assert_eq!(FeeBlend::Median.blend(&mut vec![u32::MAX, u32::MAX]), Some(u32::MAX));
+	}
+
+	#[test]
+	fn test_estimate_for_target() {
+		let estimates = [(1, 5000), (3, 4000), (6, 3000), (144, 1000)];
+		assert_eq!(estimate_for_target(&estimates, 1), 5000);
+		assert_eq!(estimate_for_target(&estimates, 6), 3000);
+		// Targets between two of the source's fall back to the further one.
+		assert_eq!(estimate_for_target(&estimates, 2), 4000);
+		assert_eq!(estimate_for_target(&estimates, 18), 1000);
+		// Targets beyond the furthest one use it.
+		assert_eq!(estimate_for_target(&estimates, 1008), 1000);
+		assert_eq!(estimate_for_target(&[(6, 3000)], 0), 3000);
+	}
+
+	#[test]
+	fn test_combine_discards_estimates_above_max() {
+		let config = FeeConfig { max_sat_per_vb: 10, ..Default::default() };
+		let estimates = vec![
+			("bitcoind".to_string(), 2000),
+			("mempool".to_string(), 2500),
+			("whatthefee".to_string(), 2501),
+		];
+		let (feerate, discarded) = config.combine(estimates);
+		// 10 sat/vB is 2500 sat/KW, which is still allowed.
+		assert_eq!(feerate, Some(2500));
+		assert_eq!(discarded, vec![("whatthefee".to_string(), 2501)]);
+
+		let config = FeeConfig { blend: FeeBlend::Median, ..config };
+		let (feerate, discarded) = config.combine(vec![
+			("bitcoind".to_string(), 2000),
+			("mempool".to_string(), 3000),
+			("whatthefee".to_string(), 1000),
+		]);
+		assert_eq!(feerate, Some(1500));
+		assert_eq!(discarded.len(), 1);
+
+		let (feerate, discarded) = config.combine(vec![("mempool".to_string(), 100_000)]);
+		assert_eq!(feerate, None);
+		assert_eq!(discarded.len(), 1);
+		assert_eq!(config.combine(Vec::new()), (None, Vec::new()));
+	}
+
+	#[test]
+	fn test_combine_applies_min_feerate() {
+		let config = FeeConfig::default();
+		let (feerate, _) = config.combine(vec![("mempool".to_string(), 100)]);
+		assert_eq!(feerate, Some(MIN_FEERATE));
+	}
+
+	#[test]
+	fn test_parse_whatthefee_estimates() {
+		// The 0.8 column is the closest to the confidence we pick.
+		let estimates = parse_whatthefee_estimates(&whatthefee_data(&["0.5", "0.8", "0.95"]));
+		assert_eq!(estimates, Some(vec![(1, 25000), (6, 2500), (144, 250)]));
+		let estimates = parse_whatthefee_estimates(&whatthefee_data(&["0.5", "0.7", "0.85"]));
+		assert_eq!(estimates, Some(vec![(1, 250), (6, 25000), (144, 2500)]));
+	}
+
+	#[test]
+	fn test_parse_whatthefee_estimates_malformed() {
+		assert_eq!(parse_whatthefee_estimates(&whatthefee_data(&["0.5", "NaN", "0.9"])), None);
+		assert_eq!(parse_whatthefee_estimates(&whatthefee_data(&["0.5", "inf", "0.9"])), None);
+		assert_eq!(parse_whatthefee_estimates(&whatthefee_data(&["0.5", "high", "0.9"])), None);
+		assert_eq!(parse_whatthefee_estimates(&whatthefee_data(&[])), None);
+		// Rows without the picked column.
+		assert_eq!(
+			parse_whatthefee_estimates(&whatthefee_data(&["0.5", "0.6", "0.7", "0.8"])),
+			None
+		);
+		let mut data = whatthefee_data(&["0.5", "0.8", "0.95"]);
+		data["index"] = serde_json::json!([6, 1]);
+		assert_eq!(parse_whatthefee_estimates(&data), None);
+		data["index"] = serde_json::json!([6, 1, 100_000]);
+		assert_eq!(parse_whatthefee_estimates(&data), None);
+		assert_eq!(parse_whatthefee_estimates(&serde_json::json!({})), None);
+		let empty = serde_json::json!({ "index": [], "columns": ["0.8"], "data": [] });
+		assert_eq!(parse_whatthefee_estimates(&empty), None);
+	}
+}
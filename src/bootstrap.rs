@@ -0,0 +1,104 @@
+use crate::cli;
+use crate::disk;
+use crate::proxy::PeerAddr;
+use crate::PeerManager;
+use bech32::FromBase32;
+use bitcoin::network::constants::Network;
+use bitcoin::secp256k1::PublicKey;
+use rand::{thread_rng, Rng};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use trust_dns_resolver::TokioAsyncResolver;
+
+/// The BOLT 10 DNS seeds used when no `--dns-seed` is given.
+pub(crate) fn default_dns_seeds(network: Network) -> Vec<String> {
+	let seeds: &[&str] = match network {
+		Network::Bitcoin => &["nodes.lightning.directory", "lseed.bitcoinstats.com"],
+		Network::Testnet => &["test.nodes.lightning.directory"],
+		_ => &[],
+	};
+	seeds.iter().map(|seed| seed.to_string()).collect()
+}
+
+/// Asks a BOLT 10 DNS seed for nodes. Seeds answer an SRV query with one record per node, its
+/// target being `<bech32 node_id>.<seed>`.
+async fn query_seed(
+	resolver: &TokioAsyncResolver, seed: &str, proxy: Option<SocketAddr>,
+) -> Result<Vec<(PublicKey, PeerAddr)>, String> {
+	let records = resolver
+		.srv_lookup(format!("_nodes._tcp.{}.", seed.trim_end_matches('.')))
+		.await
+		.map_err(|e| format!("failed to query DNS seed {}: {}", seed, e))?;
+	let mut peers = Vec::new();
+	for record in records.iter() {
+		let target = record.target().to_utf8();
+		let target = target.trim_end_matches('.');
+		let pubkey = match target.split('.').next().and_then(decode_node_id) {
+			Some(pubkey) => pubkey,
+			None => continue,
+		};
+		// With a proxy, the proxy resolves the node's address so it doesn't go through our DNS.
+		let addr = match proxy {
+			Some(_) => PeerAddr::Host { host: target.to_string(), port: record.port() },
+			None => match resolver.lookup_ip(target).await.ok().and_then(|ips| ips.iter().next()) {
+				Some(ip) => PeerAddr::Ip(SocketAddr::new(ip, record.port())),
+				None => continue,
+			},
+		};
+		peers.push((pubkey, addr));
+	}
+	Ok(peers)
+}
+
+/// Decodes the `ln`-prefixed bech32 node ID DNS seeds give nodes as their first label.
+fn decode_node_id(label: &str) -> Option<PublicKey> {
+	let (hrp, data, _) = bech32::decode(label).ok()?;
+	if hrp != "ln" {
+		return None;
+	}
+	PublicKey::from_slice(&Vec::<u8>::from_base32(&data).ok()?).ok()
+}
+
+/// Connects to up to `num_peers` nodes we aren't connected to yet, found through `seeds`, and
+/// records their addresses in the address book at `peer_data_path`. Returns the nodes connected
+/// to.
+pub(crate) async fn bootstrap(
+	seeds: &[String], num_peers: usize, peer_manager: Arc<PeerManager>, proxy: Option<SocketAddr>,
+	peer_data_path: &str,
+) -> Result<Vec<PublicKey>, String> {
+	if seeds.is_empty() {
+		return Err("there are no DNS seeds for this network, pass --dns-seed=<domain>".to_string());
+	}
+	let resolver = TokioAsyncResolver::tokio_from_system_conf()
+		.map_err(|e| format!("failed to set up the DNS resolver: {}", e))?;
+	let mut candidates = Vec::new();
+	for seed in seeds {
+		match query_seed(&resolver, seed, proxy).await {
+			Ok(peers) => candidates.extend(peers),
+			Err(e) => println!("ERROR: {}", e),
+		}
+	}
+	thread_rng().shuffle(&mut candidates);
+
+	let mut connected = Vec::new();
+	for (pubkey, addr) in candidates {
+		if connected.len() >= num_peers {
+			break;
+		}
+		if connected.contains(&pubkey)
+			|| peer_manager.get_peer_node_ids().iter().any(|(id, _)| *id == pubkey)
+		{
+			continue;
+		}
+		if cli::do_connect_peer(pubkey, addr.clone(), Arc::clone(&peer_manager), proxy)
+			.await
+			.is_ok()
+		{
+			let peer_info = format!("{}@{}", pubkey, addr);
+			let _ = disk::persist_channel_peer(Path::new(peer_data_path), &peer_info);
+			connected.push(pubkey);
+		}
+	}
+	Ok(connected)
+}
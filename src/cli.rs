@@ -1,44 +1,156 @@
+use crate::backup::BackupTarget;
+use crate::bitcoind_client::{BitcoindClient, BitcoindRpcInfo, FeeConfig, Target};
+use crate::bootstrap;
 use crate::disk;
+use crate::encryption::{self, EncryptionSetup, Unlocker};
 use crate::hex_utils;
+use crate::lnurl;
+use crate::mnemonic::MnemonicSetup;
+use crate::persist::{NodePersister, StorageBackend};
+use crate::proxy::{self, PeerAddr};
+use crate::rgs::RgsClient;
+use crate::router::RouteConstraints;
+use crate::scb;
+use crate::signer::NodeKeys;
+use crate::snapshot::SnapshotConfig;
+use crate::tor::{self, TorConfig};
+use crate::verify;
+use crate::wallet::{AddressType, OnchainWallet};
 use crate::{
-	ChannelManager, HTLCStatus, MillisatAmount, NetworkGraph, OnionMessenger, PaymentInfo,
-	PaymentInfoStorage, PeerManager,
+	persist_payments, time_now_secs, ChainMonitor, ChannelManager, ExternalFunding,
+	ExternalFundingStorage, ForwardInfo, ForwardingHistoryStorage, HTLCStatus, MillisatAmount,
+	NetworkGraph, OnionMessenger, PaymentInfo, PaymentInfoStorage, PeerConnectionsStorage,
+	PeerManager, ProbeConfig, ProbeStatsStorage, Router, Scorer, BOOTSTRAP_PEERS,
+	INBOUND_PAYMENTS_FNAME, OUTBOUND_PAYMENTS_FNAME,
 };
+use bitcoin::bech32::ToBase32;
+use bitcoin::blockdata::constants::genesis_block;
+use bitcoin::blockdata::transaction::Transaction;
+use bitcoin::consensus::encode;
 use bitcoin::hashes::sha256::Hash as Sha256;
 use bitcoin::hashes::Hash;
 use bitcoin::network::constants::Network;
 use bitcoin::secp256k1::PublicKey;
-use lightning::chain::keysinterface::{EntropySource, KeysManager};
-use lightning::ln::channelmanager::{PaymentId, Retry};
-use lightning::ln::msgs::NetAddress;
+use bitcoin::util::address::{Address, Payload};
+use bitcoin::util::psbt::PartiallySignedTransaction;
+use bitcoin::Txid;
+use lightning::chain::chaininterface::{ConfirmationTarget, FeeEstimator};
+use lightning::chain::keysinterface::{EntropySource, NodeSigner, Recipient};
+use lightning::ln::channelmanager::{
+	PaymentId, PhantomRouteHints, Retry, MIN_CLTV_EXPIRY_DELTA, MIN_FINAL_CLTV_EXPIRY_DELTA,
+};
+use lightning::ln::features::ChannelFeatures;
+use lightning::ln::msgs::{NetAddress, UnsignedChannelUpdate};
 use lightning::ln::{PaymentHash, PaymentPreimage};
 use lightning::onion_message::{CustomOnionMessageContents, Destination, OnionMessageContents};
-use lightning::routing::gossip::NodeId;
-use lightning::routing::router::{PaymentParameters, RouteParameters};
+use lightning::routing::gossip::{ChannelInfo, ChannelUpdateInfo, NodeId, NodeInfo, RoutingFees};
+use lightning::routing::router::{
+	find_route, PaymentParameters, Route, RouteHint, RouteHintHop, RouteParameters,
+};
+use lightning::routing::scoring::{ProbabilisticScorer, ProbabilisticScoringParameters};
 use lightning::util::config::{ChannelHandshakeConfig, ChannelHandshakeLimits, UserConfig};
-use lightning::util::ser::{Writeable, Writer};
-use lightning_invoice::payment::pay_invoice;
-use lightning_invoice::{utils, Currency, Invoice};
+use lightning::util::message_signing;
+use lightning::util::persist::KVStorePersister;
+use lightning::util::ser::{Readable, Writeable, Writer};
+use lightning_invoice::utils::create_phantom_invoice;
+use lightning_invoice::{Currency, Fallback, Invoice, InvoiceBuilder, InvoiceDescription};
+use qrcode::render::unicode;
+use qrcode::QrCode;
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use serde_json::json;
+use std::cmp;
+use std::collections::{HashMap, VecDeque};
 use std::env;
-use std::io;
-use std::io::Write;
-use std::net::{SocketAddr, ToSocketAddrs};
-use std::ops::Deref;
-use std::path::Path;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 pub(crate) struct LdkUserInfo {
 	pub(crate) bitcoind_rpc_username: String,
 	pub(crate) bitcoind_rpc_password: String,
 	pub(crate) bitcoind_rpc_port: u16,
+	/// The cookie file the RPC credentials were read from, if any.
+	pub(crate) bitcoind_rpc_cookie_path: Option<PathBuf>,
+	/// Endpoints to fail over to for chain data, fee estimates and broadcasting.
+	pub(crate) bitcoind_backup_rpcs: Vec<BitcoindRpcInfo>,
 	pub(crate) bitcoind_rpc_host: String,
 	pub(crate) ldk_storage_dir_path: String,
+	/// Where the node's state is kept, inside `ldk_storage_dir_path` unless it's remote.
+	pub(crate) storage: StorageBackend,
+	/// Whether to encrypt the node's state on startup, if it isn't already.
+	pub(crate) encryption: EncryptionSetup,
+	/// Read the passphrase from this file instead of prompting for it.
+	pub(crate) passphrase_file: Option<PathBuf>,
+	/// Wait for the passphrase to be given through `unlock` instead of prompting for it.
+	pub(crate) locked: bool,
+	/// The Tor daemon to run an onion service on, and connect to peers through if Tor-only.
+	pub(crate) tor: Option<TorConfig>,
+	/// The SOCKS5 proxy outbound peer connections go through, if any.
+	pub(crate) proxy: Option<SocketAddr>,
+	/// The BOLT 10 DNS seeds `bootstrap` finds peers through.
+	pub(crate) dns_seeds: Vec<String>,
+	/// The Rapid Gossip Sync server to sync the network graph from, if any.
+	pub(crate) rgs_url: Option<String>,
+	/// Serve Rapid Gossip Sync snapshots of our network graph through the HTTP API.
+	pub(crate) rgs_serve: bool,
+	/// How the scorer penalizes channels, and how fast what it learned about them decays.
+	pub(crate) scoring_params: ProbabilisticScoringParameters,
+	/// Write the scorer on our own timer rather than whenever the background processor does.
+	pub(crate) scorer_persist_interval: Option<Duration>,
+	/// Where to mirror the node's state to, if anywhere.
+	pub(crate) backup: Option<BackupTarget>,
+	/// A static channel backup to recover the channels of.
+	pub(crate) recover_backup: Option<PathBuf>,
+	/// Where and how often to take snapshots of the node's state, if at all.
+	pub(crate) snapshots: Option<SnapshotConfig>,
+	/// Set up a new node's keys seed from a BIP39 mnemonic.
+	pub(crate) mnemonic: Option<MnemonicSetup>,
+	/// The socket of the signer daemon holding the node's keys, instead of the keys seed.
+	pub(crate) remote_signer: Option<PathBuf>,
+	/// The secret shared by the nodes of the phantom node cluster the node is part of, if any.
+	pub(crate) phantom_secret: Option<PathBuf>,
+	/// Whether to create the phantom secret if it doesn't exist, for the first node of a cluster.
+	pub(crate) create_phantom_secret: bool,
+	/// The type of the addresses spendable outputs are swept to.
+	pub(crate) sweep_address_type: AddressType,
 	pub(crate) ldk_peer_listening_port: u16,
 	pub(crate) ldk_announced_listen_addr: Vec<NetAddress>,
 	pub(crate) ldk_announced_node_name: [u8; 32],
 	pub(crate) network: Network,
+	pub(crate) oneshot_command: Option<String>,
+	pub(crate) http_listen_addr: Option<SocketAddr>,
+	/// Where to serve the HTTP API's unauthenticated routes, the LNURL-withdraw ones, if at all.
+	pub(crate) http_public_listen_addr: Option<SocketAddr>,
+	/// The URL `http_public_listen_addr` is reachable at from the outside, used in LNURL-withdraw
+	/// links.
+	pub(crate) http_public_url: Option<String>,
+	/// bitcoind's `-zmqpubhashblock` endpoint, to be notified of new blocks instead of polling.
+	pub(crate) zmq_hashblock_endpoint: Option<String>,
+	/// An Esplora instance to fetch blocks from when a pruned bitcoind doesn't have them anymore.
+	pub(crate) block_fallback_esplora_url: Option<String>,
+	/// Fee estimation sources besides bitcoind, and how to combine them.
+	pub(crate) fee_config: FeeConfig,
+	/// Whether to claim keysend payments sent to us, or fail them back.
+	pub(crate) accept_keysend: bool,
+	/// How often to probe in the background and what, if at all.
+	pub(crate) probing: Option<ProbeConfig>,
+	/// How `sendpayment` and `keysend` retry failed payment paths unless told otherwise.
+	pub(crate) payment_retry: Retry,
+}
+
+/// How the read-only commands (`nodeinfo`, `listchannels`, ...) print their results.
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum OutputFormat {
+	Text,
+	Json,
 }
 
 struct UserOnionMessageContents {
@@ -58,32 +170,228 @@ impl Writeable for UserOnionMessageContents {
 	}
 }
 
+/// All commands understood by `poll_for_user_input`, used for tab completion.
+const COMMANDS: &[&str] = &[
+	"help",
+	"quit",
+	"exit",
+	"stop",
+	"openchannel",
+	"fundchannel",
+	"updatechannelpolicy",
+	"closechannel",
+	"forceclosechannel",
+	"abandonchannel",
+	"listchannels",
+	"connectpeer",
+	"bootstrap",
+	"disconnectpeer",
+	"listpeers",
+	"setreconnect",
+	"getnodeinfo",
+	"describegraph",
+	"importgraph",
+	"graphstats",
+	"setgossipsync",
+	"sendpayment",
+	"payaddress",
+	"paylnurl",
+	"decodeinvoice",
+	"keysend",
+	"queryroutes",
+	"probe",
+	"probestats",
+	"scorerinfo",
+	"resetscorer",
+	"listpayments",
+	"listforwards",
+	"getinvoice",
+	"addholdinvoice",
+	"getphantominvoice",
+	"getphantomroutehints",
+	"listinvoices",
+	"settleinvoice",
+	"cancelinvoice",
+	"signmessage",
+	"verifymessage",
+	"sendonionmessage",
+	"nodeinfo",
+	"estimatefees",
+	"listunconfirmed",
+	"setfees",
+	"chainstatus",
+	"newaddress",
+	"onchainbalance",
+	"backupstatus",
+	"exportbackup",
+	"verifystate",
+	"setformat",
+];
+
+/// Completes command names for the first word of a line, and the IDs of our channels and the
+/// public keys of our peers for any following word.
+struct CliHelper {
+	channel_manager: Arc<ChannelManager>,
+	peer_manager: Arc<PeerManager>,
+}
+
+impl Completer for CliHelper {
+	type Candidate = String;
+
+	fn complete(
+		&self, line: &str, pos: usize, _ctx: &Context<'_>,
+	) -> rustyline::Result<(usize, Vec<String>)> {
+		let word_start = line[..pos].rfind(char::is_whitespace).map(|idx| idx + 1).unwrap_or(0);
+		let prefix = &line[word_start..pos];
+		let candidates = if word_start == 0 {
+			COMMANDS.iter().map(|command| command.to_string()).collect::<Vec<_>>()
+		} else {
+			let mut candidates = Vec::new();
+			for chan_info in self.channel_manager.list_channels() {
+				candidates.push(hex_utils::hex_str(&chan_info.channel_id));
+				candidates.push(chan_info.counterparty.node_id.to_string());
+			}
+			for (pubkey, _) in self.peer_manager.get_peer_node_ids() {
+				candidates.push(pubkey.to_string());
+			}
+			candidates.sort();
+			candidates.dedup();
+			candidates
+		};
+		Ok((word_start, candidates.into_iter().filter(|c| c.starts_with(prefix)).collect()))
+	}
+}
+
+impl Hinter for CliHelper {
+	type Hint = String;
+}
+
+impl Highlighter for CliHelper {}
+
+impl Validator for CliHelper {}
+
+impl Helper for CliHelper {}
+
+/// Reads commands while the node is locked, until it's unlocked, here or through the HTTP API.
+/// Nothing's been read from the node's state yet, so quitting just exits.
+pub(crate) async fn poll_for_unlock(unlocker: Unlocker) {
+	let mut editor = match Editor::<()>::new() {
+		Ok(editor) => editor,
+		Err(e) => return println!("ERROR: failed to initialize the command prompt: {}", e),
+	};
+	while !unlocker.is_unlocked() {
+		let line = match editor.readline("(locked) > ") {
+			Ok(line) => line,
+			Err(ReadlineError::Interrupted) => continue,
+			Err(_) if unlocker.is_unlocked() => return,
+			Err(_) => std::process::exit(0),
+		};
+		if unlocker.is_unlocked() {
+			return;
+		}
+		// The passphrase may contain spaces, so it's the rest of the line.
+		let mut words = line.trim().splitn(2, ' ');
+		match words.next() {
+			Some("unlock") => {
+				let passphrase = match words.next() {
+					Some(passphrase) => passphrase.to_string(),
+					None => match encryption::read_passphrase(None, false) {
+						Ok(passphrase) => passphrase,
+						Err(e) => {
+							println!("ERROR: {}", e);
+							continue;
+						}
+					},
+				};
+				match unlocker.unlock(passphrase).await {
+					Ok(()) => return println!("SUCCESS: unlocked the node"),
+					Err(e) => println!("ERROR: failed to unlock the node: {}", e),
+				}
+			}
+			Some("help") => {
+				println!("\n  The node is locked, so only these commands are available:");
+				println!("      unlock [<passphrase>]");
+				println!("      quit");
+			}
+			Some("stop") | Some("quit") | Some("exit") => std::process::exit(0),
+			Some("") | None => {}
+			Some(_) => {
+				println!("ERROR: the node is locked, unlock it first with `unlock [<passphrase>]`")
+			}
+		}
+	}
+}
+
 pub(crate) async fn poll_for_user_input(
 	peer_manager: Arc<PeerManager>, channel_manager: Arc<ChannelManager>,
-	keys_manager: Arc<KeysManager>, network_graph: Arc<NetworkGraph>,
-	onion_messenger: Arc<OnionMessenger>, inbound_payments: PaymentInfoStorage,
-	outbound_payments: PaymentInfoStorage, ldk_data_dir: String, network: Network,
-	logger: Arc<disk::FilesystemLogger>,
+	chain_monitor: Arc<ChainMonitor>, bitcoind_client: Arc<BitcoindClient>,
+	wallet: Arc<OnchainWallet>, keys_manager: Arc<NodeKeys>, network_graph: Arc<NetworkGraph>,
+	scorer: Arc<Mutex<Scorer>>, router: Arc<Router>, probe_stats: ProbeStatsStorage,
+	external_fundings: ExternalFundingStorage, onion_messenger: Arc<OnionMessenger>,
+	inbound_payments: PaymentInfoStorage, outbound_payments: PaymentInfoStorage,
+	forwarding_history: ForwardingHistoryStorage, peer_connections: PeerConnectionsStorage,
+	proxy: Option<SocketAddr>, dns_seeds: Vec<String>, rgs_client: Option<Arc<RgsClient>>,
+	scoring_params: ProbabilisticScoringParameters, persister: Arc<NodePersister>,
+	ldk_data_dir: String, network: Network, logger: Arc<disk::FilesystemLogger>,
+	announced_listen_addr: Vec<NetAddress>, payment_retry: Retry, oneshot_command: Option<String>,
 ) {
-	println!(
-		"LDK startup successful. Enter \"help\" to view available commands. Press Ctrl-D to quit."
-	);
-	println!("LDK logs are available at <your-supplied-ldk-data-dir-path>/.ldk/logs");
-	println!("Local Node ID is {}.", channel_manager.get_our_node_id());
-	loop {
-		print!("> ");
-		io::stdout().flush().unwrap(); // Without flushing, the `>` doesn't print
-		let mut line = String::new();
-		if let Err(e) = io::stdin().read_line(&mut line) {
-			break println!("ERROR: {}", e);
-		}
+	// In one-shot mode we run the given command once and return, without ever reading stdin.
+	let oneshot = oneshot_command.is_some();
+	let mut oneshot_command = oneshot_command;
+	if !oneshot {
+		println!(
+			"LDK startup successful. Enter \"help\" to view available commands. Press Ctrl-D to quit."
+		);
+		println!("Press Tab to complete commands, channel IDs and peer public keys.");
+		println!("LDK logs are available at <your-supplied-ldk-data-dir-path>/.ldk/logs");
+		println!("Local Node ID is {}.", channel_manager.get_our_node_id());
+	}
+	let mut editor = match Editor::<CliHelper>::new() {
+		Ok(editor) => editor,
+		Err(e) => return println!("ERROR: failed to initialize the command prompt: {}", e),
+	};
+	editor.set_helper(Some(CliHelper {
+		channel_manager: Arc::clone(&channel_manager),
+		peer_manager: Arc::clone(&peer_manager),
+	}));
+	let history_path = format!("{}/cli_history", ldk_data_dir);
+	// There's no history yet on first startup.
+	let _ = editor.load_history(&history_path);
 
-		if line.len() == 0 {
-			// We hit EOF / Ctrl-D
-			break;
-		}
+	let mut output_format = OutputFormat::Text;
+	loop {
+		let line = if oneshot {
+			match oneshot_command.take() {
+				Some(command) => command,
+				None => break,
+			}
+		} else {
+			match editor.readline("> ") {
+				Ok(line) => {
+					if !line.trim().is_empty() {
+						editor.add_history_entry(line.as_str());
+						if let Err(e) = editor.save_history(&history_path) {
+							println!("ERROR: failed to save command history: {}", e);
+						}
+					}
+					line
+				}
+				// Ctrl-C only discards the current line, it doesn't stop the node.
+				Err(ReadlineError::Interrupted) => continue,
+				// We hit EOF / Ctrl-D
+				Err(ReadlineError::Eof) => break,
+				Err(e) => break println!("ERROR: {}", e),
+			}
+		};
 
-		let mut words = line.split_whitespace();
+		// `--json` may be passed to any command to get machine-readable output for that command
+		// only, regardless of the format set through `setformat`.
+		let format = if line.split_whitespace().any(|w| w == "--json") {
+			OutputFormat::Json
+		} else {
+			output_format
+		};
+		let mut words = line.split_whitespace().filter(|w| *w != "--json");
 		if let Some(word) = words.next() {
 			match word {
 				"help" => help(),
@@ -91,7 +399,7 @@ pub(crate) async fn poll_for_user_input(
 					let peer_pubkey_and_ip_addr = words.next();
 					let channel_value_sat = words.next();
 					if peer_pubkey_and_ip_addr.is_none() || channel_value_sat.is_none() {
-						println!("ERROR: openchannel has 2 required arguments: `openchannel pubkey@host:port channel_amt_satoshis` [--public]");
+						println!("ERROR: openchannel has 2 required arguments: `openchannel pubkey@host:port channel_amt_satoshis` [--public|--private] [--push-msat=<amt>] [--min-htlc-msat=<amt>] [--cltv-expiry-delta=<blocks>] [--fee-base-msat=<amt>] [--fee-proportional-millionths=<rate>] [--psbt]");
 						continue;
 					}
 					let peer_pubkey_and_ip_addr = peer_pubkey_and_ip_addr.unwrap();
@@ -110,36 +418,79 @@ pub(crate) async fn poll_for_user_input(
 						continue;
 					}
 
-					if connect_peer_if_necessary(pubkey, peer_addr, peer_manager.clone())
+					let options = match OpenChannelOptions::from_args(words) {
+						Ok(options) => options,
+						Err(e) => {
+							println!("ERROR: {}", e);
+							continue;
+						}
+					};
+
+					if connect_peer_if_necessary(pubkey, peer_addr, peer_manager.clone(), proxy)
 						.await
 						.is_err()
 					{
 						continue;
 					};
 
-					let announce_channel = match words.next() {
-						Some("--public") | Some("--public=true") => true,
-						Some("--public=false") => false,
-						Some(_) => {
-							println!("ERROR: invalid `--public` command format. Valid formats: `--public`, `--public=true` `--public=false`");
-							continue;
-						}
-						None => false,
-					};
-
-					if open_channel(
+					let temporary_channel_id = match open_channel(
 						pubkey,
 						chan_amt_sat.unwrap(),
-						announce_channel,
+						&options,
 						channel_manager.clone(),
-					)
-					.is_ok()
-					{
-						let peer_data_path = format!("{}/channel_peer_data", ldk_data_dir.clone());
-						let _ = disk::persist_channel_peer(
-							Path::new(&peer_data_path),
-							peer_pubkey_and_ip_addr,
-						);
+						&external_fundings,
+					) {
+						Ok(temporary_channel_id) => temporary_channel_id,
+						Err(()) => continue,
+					};
+					let peer_data_path = format!("{}/channel_peer_data", ldk_data_dir.clone());
+					let _ = disk::persist_channel_peer(
+						Path::new(&peer_data_path),
+						peer_pubkey_and_ip_addr,
+					);
+					if options.external_funding {
+						match await_external_funding(
+							temporary_channel_id,
+							&channel_manager,
+							&external_fundings,
+						)
+						.await
+						{
+							Ok(funding) => print_external_funding(
+								temporary_channel_id,
+								&funding,
+								network,
+								format,
+							),
+							Err(e) => println!("ERROR: {}", e),
+						}
+					}
+				}
+				"fundchannel" => {
+					let (channel_id_str, funding) = match (words.next(), words.next()) {
+						(Some(channel_id_str), Some(funding)) => (channel_id_str, funding),
+						_ => {
+							println!("ERROR: fundchannel requires a temporary channel ID and a signed funding transaction: `fundchannel <temporary_channel_id> <psbt_base64|tx_hex>`");
+							continue;
+						}
+					};
+					let channel_id_vec = hex_utils::to_vec(channel_id_str);
+					if channel_id_vec.is_none() || channel_id_vec.as_ref().unwrap().len() != 32 {
+						println!("ERROR: couldn't parse temporary_channel_id");
+						continue;
+					}
+					let mut temporary_channel_id = [0; 32];
+					temporary_channel_id.copy_from_slice(&channel_id_vec.unwrap());
+					match fund_channel(
+						temporary_channel_id,
+						funding,
+						&channel_manager,
+						&external_fundings,
+					) {
+						Ok(txid) => {
+							println!("SUCCESS: funding channel with transaction {}", txid)
+						}
+						Err(e) => println!("ERROR: {}", e),
 					}
 				}
 				"sendpayment" => {
@@ -157,7 +508,89 @@ pub(crate) async fn poll_for_user_input(
 						}
 					};
 
-					send_payment(&*channel_manager, &invoice, outbound_payments.clone());
+					let options = match PaymentOptions::from_args(words, payment_retry) {
+						Ok(options) => options,
+						Err(e) => {
+							println!("ERROR: {}", e);
+							continue;
+						}
+					};
+
+					let _ = send_payment(
+						&*channel_manager,
+						&*router,
+						&invoice,
+						options,
+						outbound_payments.clone(),
+						Arc::clone(&persister),
+					);
+				}
+				"payaddress" | "paylnurl" => {
+					let usage = if word == "payaddress" {
+						"`payaddress <user@domain> <amt_msats>`"
+					} else {
+						"`paylnurl <lnurl> <amt_msats>`"
+					};
+					let (target, amt_str) = match (words.next(), words.next()) {
+						(Some(target), Some(amt_str)) => (target, amt_str),
+						_ => {
+							println!(
+								"ERROR: {} requires a recipient and an amount: {}",
+								word, usage
+							);
+							continue;
+						}
+					};
+					let amt_msat: u64 = match amt_str.parse() {
+						Ok(amt) => amt,
+						Err(e) => {
+							println!("ERROR: couldn't parse amount_msat: {}", e);
+							continue;
+						}
+					};
+					let options = match PaymentOptions::from_args(words, payment_retry) {
+						Ok(options) => options,
+						Err(e) => {
+							println!("ERROR: {}", e);
+							continue;
+						}
+					};
+					let url = if word == "payaddress" {
+						lnurl::address_to_url(target)
+					} else {
+						lnurl::decode_lnurl(target)
+					};
+					let invoice = match url {
+						Ok(url) => lnurl::fetch_invoice(&url, amt_msat).await,
+						Err(e) => Err(e),
+					};
+					match invoice {
+						Ok(invoice) => {
+							let _ = send_payment(
+								&*channel_manager,
+								&*router,
+								&invoice,
+								options,
+								outbound_payments.clone(),
+								Arc::clone(&persister),
+							);
+						}
+						Err(e) => println!("ERROR: {}", e),
+					}
+				}
+				"decodeinvoice" => {
+					let invoice = match words.next().map(Invoice::from_str) {
+						Some(Ok(invoice)) => invoice,
+						Some(Err(e)) => {
+							println!("ERROR: invalid invoice: {:?}", e);
+							continue;
+						}
+						None => {
+							println!("ERROR: decodeinvoice requires an invoice: `decodeinvoice <invoice>`");
+							continue;
+						}
+					};
+					decode_invoice(&invoice, format);
 				}
 				"keysend" => {
 					let dest_pubkey = match words.next() {
@@ -187,12 +620,21 @@ pub(crate) async fn poll_for_user_input(
 							continue;
 						}
 					};
-					keysend(
+					let retry = match retry_from_args(words, payment_retry) {
+						Ok(retry) => retry,
+						Err(e) => {
+							println!("ERROR: {}", e);
+							continue;
+						}
+					};
+					let _ = keysend(
 						&*channel_manager,
 						dest_pubkey,
 						amt_msat,
+						retry,
 						&*keys_manager,
 						outbound_payments.clone(),
+						Arc::clone(&persister),
 					);
 				}
 				"getinvoice" => {
@@ -220,14 +662,203 @@ pub(crate) async fn poll_for_user_input(
 						continue;
 					}
 
-					get_invoice(
+					let (options, rest) = match InvoiceOptions::from_args(words, network) {
+						Ok(res) => res,
+						Err(e) => {
+							println!("ERROR: {}", e);
+							continue;
+						}
+					};
+					let qr = match rest[..] {
+						["--qr"] => true,
+						[] => false,
+						_ => {
+							println!("ERROR: unknown getinvoice argument {}", rest[0]);
+							continue;
+						}
+					};
+
+					if let Ok(invoice) = get_invoice(
 						amt_msat.unwrap(),
+						None,
+						options,
 						Arc::clone(&inbound_payments),
+						Arc::clone(&persister),
 						&*channel_manager,
 						Arc::clone(&keys_manager),
 						network,
 						expiry_secs.unwrap(),
+					) {
+						if qr {
+							// Uppercase lets the QR code use the denser alphanumeric mode.
+							print_qr(&format!("lightning:{}", invoice).to_uppercase());
+						}
+					}
+				}
+				"getphantominvoice" => {
+					let (amt_msat, expiry_secs) = match (words.next(), words.next()) {
+						(Some(amt_str), Some(expiry_str)) => {
+							match (amt_str.parse::<u64>(), expiry_str.parse::<u32>()) {
+								(Ok(amt_msat), Ok(expiry_secs)) => (amt_msat, expiry_secs),
+								_ => {
+									println!("ERROR: the amount and expiry must be numbers");
+									continue;
+								}
+							}
+						}
+						_ => {
+							println!("ERROR: getphantominvoice requires an amount in millisatoshis and an expiry in seconds: `getphantominvoice <amt_msats> <expiry_secs> [--description=<text>] [--phantom-hints=<hints>,...]`");
+							continue;
+						}
+					};
+					let mut description = "ldk-tutorial-node".to_string();
+					let mut route_hints = Vec::new();
+					let mut errored = false;
+					for arg in words {
+						let mut arg_parts = arg.splitn(2, '=');
+						match (arg_parts.next(), arg_parts.next()) {
+							(Some("--description"), Some(text)) => description = text.to_string(),
+							(Some("--phantom-hints"), Some(hints)) => {
+								match parse_phantom_route_hints(hints) {
+									Ok(hints) => route_hints = hints,
+									Err(e) => {
+										println!("ERROR: {}", e);
+										errored = true;
+									}
+								}
+							}
+							_ => {
+								println!("ERROR: unknown getphantominvoice argument {}", arg);
+								errored = true;
+							}
+						}
+					}
+					if errored {
+						continue;
+					}
+					match get_phantom_invoice(
+						amt_msat,
+						description,
+						expiry_secs,
+						route_hints,
+						Arc::clone(&inbound_payments),
+						Arc::clone(&persister),
+						&channel_manager,
+						Arc::clone(&keys_manager),
 						Arc::clone(&logger),
+						network,
+					) {
+						Ok(invoice) => println!("SUCCESS: generated invoice: {}", invoice),
+						Err(e) => println!("ERROR: {}", e),
+					}
+				}
+				"getphantomroutehints" => {
+					let hints = phantom_route_hints_hex(&channel_manager);
+					if format == OutputFormat::Json {
+						print_json(&json!({ "route_hints": hints }));
+					} else {
+						println!("{}", hints);
+					}
+				}
+				"addholdinvoice" => {
+					let payment_hash = match words.next().map(hex_utils::to_vec) {
+						Some(Some(hash_vec)) if hash_vec.len() == 32 => {
+							let mut payment_hash = [0; 32];
+							payment_hash.copy_from_slice(&hash_vec);
+							PaymentHash(payment_hash)
+						}
+						Some(_) => {
+							println!("ERROR: couldn't parse payment_hash");
+							continue;
+						}
+						None => {
+							println!("ERROR: addholdinvoice requires a payment hash: `addholdinvoice <payment_hash> <amt_msats> <expiry_secs>`");
+							continue;
+						}
+					};
+					let amt_msat: u64 = match words.next().map(|amt| amt.parse()) {
+						Some(Ok(amt)) => amt,
+						_ => {
+							println!("ERROR: addholdinvoice requires an amount in millisatoshis: `addholdinvoice <payment_hash> <amt_msats> <expiry_secs>`");
+							continue;
+						}
+					};
+					let expiry_secs: u32 = match words.next().map(|expiry| expiry.parse()) {
+						Some(Ok(expiry)) => expiry,
+						_ => {
+							println!("ERROR: addholdinvoice requires an expiry in seconds: `addholdinvoice <payment_hash> <amt_msats> <expiry_secs>`");
+							continue;
+						}
+					};
+
+					let options = match InvoiceOptions::from_args(words, network) {
+						Ok((options, rest)) if rest.is_empty() => options,
+						Ok((_, rest)) => {
+							println!("ERROR: unknown addholdinvoice argument {}", rest[0]);
+							continue;
+						}
+						Err(e) => {
+							println!("ERROR: {}", e);
+							continue;
+						}
+					};
+
+					let _ = get_invoice(
+						amt_msat,
+						Some(payment_hash),
+						options,
+						Arc::clone(&inbound_payments),
+						Arc::clone(&persister),
+						&*channel_manager,
+						Arc::clone(&keys_manager),
+						network,
+						expiry_secs,
+					);
+				}
+				"settleinvoice" => {
+					let payment_preimage = match words.next().map(hex_utils::to_vec) {
+						Some(Some(preimage_vec)) if preimage_vec.len() == 32 => {
+							let mut preimage = [0; 32];
+							preimage.copy_from_slice(&preimage_vec);
+							PaymentPreimage(preimage)
+						}
+						Some(_) => {
+							println!("ERROR: couldn't parse payment_preimage");
+							continue;
+						}
+						None => {
+							println!("ERROR: settleinvoice requires a payment preimage: `settleinvoice <payment_preimage>`");
+							continue;
+						}
+					};
+					let _ = settle_invoice(
+						payment_preimage,
+						Arc::clone(&inbound_payments),
+						Arc::clone(&persister),
+						&*channel_manager,
+					);
+				}
+				"cancelinvoice" => {
+					let payment_hash = match words.next().map(hex_utils::to_vec) {
+						Some(Some(hash_vec)) if hash_vec.len() == 32 => {
+							let mut payment_hash = [0; 32];
+							payment_hash.copy_from_slice(&hash_vec);
+							PaymentHash(payment_hash)
+						}
+						Some(_) => {
+							println!("ERROR: couldn't parse payment_hash");
+							continue;
+						}
+						None => {
+							println!("ERROR: cancelinvoice requires a payment hash: `cancelinvoice <payment_hash>`");
+							continue;
+						}
+					};
+					let _ = cancel_invoice(
+						payment_hash,
+						Arc::clone(&inbound_payments),
+						Arc::clone(&persister),
+						&*channel_manager,
 					);
 				}
 				"connectpeer" => {
@@ -244,17 +875,59 @@ pub(crate) async fn poll_for_user_input(
 								continue;
 							}
 						};
-					if connect_peer_if_necessary(pubkey, peer_addr, peer_manager.clone())
+					if connect_peer_if_necessary(pubkey, peer_addr, peer_manager.clone(), proxy)
 						.await
 						.is_ok()
 					{
+						let peer_data_path = format!("{}/channel_peer_data", ldk_data_dir);
+						let _ = disk::persist_channel_peer(
+							Path::new(&peer_data_path),
+							peer_pubkey_and_ip_addr.unwrap(),
+						);
 						println!("SUCCESS: connected to peer {}", pubkey);
 					}
 				}
+				"bootstrap" => {
+					let num_peers = match words.next().map(usize::from_str) {
+						Some(Ok(num_peers)) => num_peers,
+						Some(Err(_)) => {
+							println!("ERROR: bootstrap takes a number of peers: `bootstrap [<num_peers>]`");
+							continue;
+						}
+						None => BOOTSTRAP_PEERS,
+					};
+					let peer_data_path = format!("{}/channel_peer_data", ldk_data_dir);
+					match bootstrap::bootstrap(
+						&dns_seeds,
+						num_peers,
+						Arc::clone(&peer_manager),
+						proxy,
+						&peer_data_path,
+					)
+					.await
+					{
+						Ok(peers) if format == OutputFormat::Json => {
+							print_json(&bootstrap_json(&peers))
+						}
+						Ok(peers) => {
+							for pubkey in peers.iter() {
+								println!("SUCCESS: connected to peer {}", pubkey);
+							}
+							if peers.len() < num_peers {
+								println!(
+									"Only found {} of {} peers through the DNS seeds",
+									peers.len(),
+									num_peers
+								);
+							}
+						}
+						Err(e) => println!("ERROR: {}", e),
+					}
+				}
 				"disconnectpeer" => {
 					let peer_pubkey = words.next();
 					if peer_pubkey.is_none() {
-						println!("ERROR: disconnectpeer requires peer public key: `disconnectpeer <peer_pubkey>`");
+						println!("ERROR: disconnectpeer requires peer public key: `disconnectpeer <peer_pubkey> [--no-reconnect]`");
 						continue;
 					}
 
@@ -267,8 +940,24 @@ pub(crate) async fn poll_for_user_input(
 							}
 						};
 
+					let no_reconnect = match words.next() {
+						Some("--no-reconnect") => true,
+						Some(arg) => {
+							println!("ERROR: unknown disconnectpeer argument {}", arg);
+							continue;
+						}
+						None => false,
+					};
+
+					if no_reconnect
+						&& set_reconnect(peer_pubkey, false, &peer_connections, &ldk_data_dir)
+							.is_err()
+					{
+						continue;
+					}
 					if do_disconnect_peer(
 						peer_pubkey,
+						no_reconnect,
 						peer_manager.clone(),
 						channel_manager.clone(),
 					)
@@ -277,27 +966,105 @@ pub(crate) async fn poll_for_user_input(
 						println!("SUCCESS: disconnected from peer {}", peer_pubkey);
 					}
 				}
-				"listchannels" => list_channels(&channel_manager, &network_graph),
-				"listpayments" => {
-					list_payments(inbound_payments.clone(), outbound_payments.clone())
-				}
-				"closechannel" => {
-					let channel_id_str = words.next();
-					if channel_id_str.is_none() {
-						println!("ERROR: closechannel requires a channel ID: `closechannel <channel_id> <peer_pubkey>`");
-						continue;
-					}
-					let channel_id_vec = hex_utils::to_vec(channel_id_str.unwrap());
-					if channel_id_vec.is_none() || channel_id_vec.as_ref().unwrap().len() != 32 {
-						println!("ERROR: couldn't parse channel_id");
-						continue;
-					}
-					let mut channel_id = [0; 32];
-					channel_id.copy_from_slice(&channel_id_vec.unwrap());
-
-					let peer_pubkey_str = words.next();
+				"setreconnect" => {
+					let peer_pubkey = match words.next().map(hex_utils::to_compressed_pubkey) {
+						Some(Some(pubkey)) => pubkey,
+						Some(None) => {
+							println!("ERROR: couldn't parse peer_pubkey");
+							continue;
+						}
+						None => {
+							println!("ERROR: setreconnect requires a peer pubkey: `setreconnect <peer_pubkey> <on|off>`");
+							continue;
+						}
+					};
+					let reconnect = match words.next() {
+						Some("on") => true,
+						Some("off") => false,
+						_ => {
+							println!("ERROR: setreconnect requires `on` or `off`: `setreconnect <peer_pubkey> <on|off>`");
+							continue;
+						}
+					};
+					if set_reconnect(peer_pubkey, reconnect, &peer_connections, &ldk_data_dir)
+						.is_ok()
+					{
+						println!(
+							"SUCCESS: automatic reconnection to {} turned {}",
+							peer_pubkey,
+							if reconnect { "on" } else { "off" }
+						);
+					}
+				}
+				"updatechannelpolicy" => {
+					let target = match words.next().map(PolicyTarget::from_hex) {
+						Some(Some(target)) => target,
+						Some(None) => {
+							println!("ERROR: couldn't parse channel ID or peer pubkey");
+							continue;
+						}
+						None => {
+							println!("ERROR: updatechannelpolicy requires a channel ID or peer pubkey: `updatechannelpolicy <channel_id|peer_pubkey> [--fee-base-msat=<amt>] [--fee-proportional-millionths=<rate>] [--cltv-expiry-delta=<blocks>]`");
+							continue;
+						}
+					};
+					let update = match ChannelPolicyUpdate::from_args(words) {
+						Ok(update) => update,
+						Err(e) => {
+							println!("ERROR: {}", e);
+							continue;
+						}
+					};
+					if let Ok(num_updated) =
+						update_channel_policy(&target, &update, &channel_manager)
+					{
+						println!("SUCCESS: updated the policy of {} channel(s)", num_updated);
+					}
+				}
+				"listchannels" => list_channels(&channel_manager, &network_graph, format),
+				"listpayments" => {
+					let filter = match PaymentFilter::from_args(words) {
+						Ok(filter) => filter,
+						Err(e) => {
+							println!("ERROR: {}", e);
+							continue;
+						}
+					};
+					list_payments(
+						inbound_payments.clone(),
+						outbound_payments.clone(),
+						&filter,
+						format,
+					)
+				}
+				"listinvoices" => list_invoices(&inbound_payments, format),
+				"listforwards" => {
+					let filter = match ForwardFilter::from_args(words) {
+						Ok(filter) => filter,
+						Err(e) => {
+							println!("ERROR: {}", e);
+							continue;
+						}
+					};
+					list_forwards(&forwarding_history, &filter, format)
+				}
+				"closechannel" => {
+					let channel_id_str = words.next();
+					if channel_id_str.is_none() {
+						println!("ERROR: closechannel requires a channel ID: `closechannel <channel_id> <peer_pubkey> [--force] [--sat-per-vbyte=<feerate>]`");
+						continue;
+					}
+					let channel_id_vec = hex_utils::to_vec(channel_id_str.unwrap());
+					if channel_id_vec.is_none() || channel_id_vec.as_ref().unwrap().len() != 32 {
+						println!("ERROR: couldn't parse channel_id");
+						continue;
+					}
+					let mut channel_id = [0; 32];
+					channel_id.copy_from_slice(&channel_id_vec.unwrap());
+
+					let peer_pubkey_str = words.next();
 					if peer_pubkey_str.is_none() {
-						println!("ERROR: closechannel requires a peer pubkey: `closechannel <channel_id> <peer_pubkey>`");
+						println!("ERROR: closechannel requires a peer pubkey: `closechannel <channel_id> <peer_pubkey> [--force] [--sat-per-vbyte=<feerate>]`");
 						continue;
 					}
 					let peer_pubkey_vec = match hex_utils::to_vec(peer_pubkey_str.unwrap()) {
@@ -315,7 +1082,45 @@ pub(crate) async fn poll_for_user_input(
 						}
 					};
 
-					close_channel(channel_id, peer_pubkey, channel_manager.clone());
+					let mut force = false;
+					let mut target_sat_per_vbyte = None;
+					let mut errored = false;
+					for arg in words {
+						let mut arg_parts = arg.splitn(2, '=');
+						match (arg_parts.next(), arg_parts.next()) {
+							(Some("--force"), None) => force = true,
+							(Some("--sat-per-vbyte"), Some(feerate)) => match feerate.parse() {
+								Ok(feerate) => target_sat_per_vbyte = Some(feerate),
+								Err(_) => {
+									println!("ERROR: --sat-per-vbyte must be a number");
+									errored = true;
+								}
+							},
+							_ => {
+								println!("ERROR: unknown closechannel argument {}", arg);
+								errored = true;
+							}
+						}
+					}
+					if errored {
+						continue;
+					}
+
+					if force {
+						if target_sat_per_vbyte.is_some() {
+							println!("ERROR: a force-close pays the fee of the latest commitment transaction, --sat-per-vbyte can't be set");
+							continue;
+						}
+						let _ =
+							force_close_channel(channel_id, peer_pubkey, channel_manager.clone());
+					} else {
+						let _ = close_channel(
+							channel_id,
+							peer_pubkey,
+							target_sat_per_vbyte,
+							channel_manager.clone(),
+						);
+					}
 				}
 				"forceclosechannel" => {
 					let channel_id_str = words.next();
@@ -351,23 +1156,396 @@ pub(crate) async fn poll_for_user_input(
 						}
 					};
 
-					force_close_channel(channel_id, peer_pubkey, channel_manager.clone());
+					let _ = force_close_channel(channel_id, peer_pubkey, channel_manager.clone());
+				}
+				"abandonchannel" => {
+					let channel_id = match words.next().map(hex_utils::to_vec) {
+						Some(Some(channel_id_vec)) if channel_id_vec.len() == 32 => {
+							let mut channel_id = [0; 32];
+							channel_id.copy_from_slice(&channel_id_vec);
+							channel_id
+						}
+						Some(_) => {
+							println!("ERROR: couldn't parse channel_id");
+							continue;
+						}
+						None => {
+							println!("ERROR: abandonchannel requires a channel ID: `abandonchannel <channel_id> <peer_pubkey>`");
+							continue;
+						}
+					};
+					let peer_pubkey = match words.next().map(hex_utils::to_compressed_pubkey) {
+						Some(Some(pubkey)) => pubkey,
+						Some(None) => {
+							println!("ERROR: couldn't parse peer_pubkey");
+							continue;
+						}
+						None => {
+							println!("ERROR: abandonchannel requires a peer pubkey: `abandonchannel <channel_id> <peer_pubkey>`");
+							continue;
+						}
+					};
+
+					let _ = abandon_channel(channel_id, peer_pubkey, channel_manager.clone());
+				}
+				"nodeinfo" => {
+					let qr = match words.next() {
+						Some("--qr") => true,
+						Some(arg) => {
+							println!("ERROR: unknown nodeinfo argument {}", arg);
+							continue;
+						}
+						None => false,
+					};
+					node_info(&channel_manager, &peer_manager, &announced_listen_addr, qr, format)
+				}
+				"estimatefees" => estimate_fees(&bitcoind_client, format),
+				"listunconfirmed" => list_unconfirmed(&bitcoind_client, format),
+				"setfees" => {
+					let target = match words.next().map(Target::from_str) {
+						Some(Ok(target)) => target,
+						Some(Err(e)) => {
+							println!("ERROR: {}", e);
+							continue;
+						}
+						None => {
+							println!("ERROR: setfees requires a fee target: `setfees <background|normal|high_priority> [--conf-target=<blocks>] [--sat-per-vbyte=<feerate>|--no-override]`");
+							continue;
+						}
+					};
+					let mut settings = bitcoind_client.fee_target_settings(target);
+					let mut errored = false;
+					for arg in words {
+						let mut arg_parts = arg.splitn(2, '=');
+						match (arg_parts.next(), arg_parts.next()) {
+							(Some("--conf-target"), Some(blocks)) => {
+								match blocks.parse() {
+									Ok(blocks) if blocks > 0 => settings.conf_blocks = blocks,
+									_ => {
+										println!("ERROR: --conf-target must be a positive number of blocks");
+										errored = true;
+									}
+								}
+							}
+							(Some("--sat-per-vbyte"), Some(feerate)) => {
+								match feerate.parse::<u32>() {
+									Ok(feerate) if feerate > 0 => {
+										settings.override_sat_per_kw =
+											Some(feerate.saturating_mul(250))
+									}
+									_ => {
+										println!(
+											"ERROR: --sat-per-vbyte must be a positive number"
+										);
+										errored = true;
+									}
+								}
+							}
+							(Some("--no-override"), None) => settings.override_sat_per_kw = None,
+							_ => {
+								println!("ERROR: unknown setfees argument {}", arg);
+								errored = true;
+							}
+						}
+					}
+					if errored {
+						continue;
+					}
+					bitcoind_client.set_fee_target_settings(target, settings);
+					println!("SUCCESS: updated the {} fee target", target.name());
+				}
+				"chainstatus" => chain_status(&channel_manager, &bitcoind_client, format).await,
+				"newaddress" => {
+					let mut address_type = AddressType::P2wpkh;
+					let mut errored = false;
+					for arg in words {
+						let mut arg_parts = arg.splitn(2, '=');
+						match (arg_parts.next(), arg_parts.next()) {
+							(Some("--type"), Some(type_str)) => match type_str.parse() {
+								Ok(parsed) => address_type = parsed,
+								Err(e) => {
+									println!("ERROR: {}", e);
+									errored = true;
+								}
+							},
+							_ => {
+								println!("ERROR: unknown newaddress argument {}", arg);
+								errored = true;
+							}
+						}
+					}
+					if errored {
+						continue;
+					}
+					match wallet.get_new_address(address_type).await {
+						Ok(address) if format == OutputFormat::Json => {
+							print_json(&json!({ "address": address.to_string() }))
+						}
+						Ok(address) => println!("{}", address),
+						Err(e) => println!("ERROR: failed to get a new address: {}", e),
+					}
+				}
+				"onchainbalance" => onchain_balance(&wallet, format).await,
+				"backupstatus" => backup_status(&persister, format),
+				"verifystate" => verify_state(
+					&persister,
+					&channel_manager,
+					&chain_monitor,
+					&keys_manager,
+					&bitcoind_client,
+					&network_graph,
+					&scorer,
+					&logger,
+					format,
+				),
+				"exportbackup" => {
+					let path = match words.next() {
+						Some(path) => path,
+						None => {
+							println!("ERROR: exportbackup requires a path: `exportbackup <path>`");
+							continue;
+						}
+					};
+					match scb::export(
+						Path::new(path),
+						&channel_manager,
+						&persister,
+						&keys_manager,
+						&ldk_data_dir,
+					) {
+						Ok(num_channels) => println!(
+							"SUCCESS: wrote a static channel backup of {} channel(s) to {}",
+							num_channels, path
+						),
+						Err(e) => println!("ERROR: failed to export the backup: {}", e),
+					}
+				}
+				"listpeers" => {
+					list_peers(&peer_manager, &channel_manager, &peer_connections, format)
 				}
-				"nodeinfo" => node_info(&channel_manager, &peer_manager),
-				"listpeers" => list_peers(peer_manager.clone()),
+				"queryroutes" => {
+					let dest_pubkey = match words.next().map(hex_utils::to_compressed_pubkey) {
+						Some(Some(pubkey)) => pubkey,
+						Some(None) => {
+							println!("ERROR: couldn't parse destination pubkey");
+							continue;
+						}
+						None => {
+							println!("ERROR: queryroutes requires a destination pubkey and an amount: `queryroutes <dest_pubkey> <amt_msat>`");
+							continue;
+						}
+					};
+					let amt_msat: u64 = match words.next().map(|amt| amt.parse()) {
+						Some(Ok(amt)) => amt,
+						_ => {
+							println!("ERROR: queryroutes requires an amount in millisatoshis: `queryroutes <dest_pubkey> <amt_msat>`");
+							continue;
+						}
+					};
+					query_routes(
+						dest_pubkey,
+						amt_msat,
+						&channel_manager,
+						&network_graph,
+						&scorer,
+						&*keys_manager,
+						Arc::clone(&logger),
+						format,
+					);
+				}
+				"probe" => {
+					let dest_pubkey = match words.next().map(hex_utils::to_compressed_pubkey) {
+						Some(Some(pubkey)) => pubkey,
+						Some(None) => {
+							println!("ERROR: couldn't parse destination pubkey");
+							continue;
+						}
+						None => {
+							println!("ERROR: probe requires a destination pubkey and an amount: `probe <dest_pubkey> <amt_msat>`");
+							continue;
+						}
+					};
+					let amt_msat: u64 = match words.next().map(|amt| amt.parse()) {
+						Some(Ok(amt)) => amt,
+						_ => {
+							println!("ERROR: probe requires an amount in millisatoshis: `probe <dest_pubkey> <amt_msat>`");
+							continue;
+						}
+					};
+					match send_probes(
+						dest_pubkey,
+						amt_msat,
+						&channel_manager,
+						&network_graph,
+						&scorer,
+						&*keys_manager,
+						Arc::clone(&logger),
+					) {
+						Ok(num_sent) => println!(
+							"SUCCESS: sent {} probe(s), see probestats for the results",
+							num_sent
+						),
+						Err(e) => println!("ERROR: {}", e),
+					}
+				}
+				"probestats" => list_probe_stats(&probe_stats, &scorer, format),
+				"scorerinfo" => scorer_info(&scorer, &network_graph, &scoring_params, format),
+				"resetscorer" => match reset_scorer(
+					&scorer,
+					&network_graph,
+					&logger,
+					&scoring_params,
+					&persister,
+				) {
+					Ok(()) => println!("SUCCESS: the scorer forgot what it learned about channels"),
+					Err(e) => println!("ERROR: {}", e),
+				},
+				"getnodeinfo" => {
+					let node_pubkey = match words.next().map(hex_utils::to_compressed_pubkey) {
+						Some(Some(pubkey)) => pubkey,
+						Some(None) => {
+							println!("ERROR: couldn't parse node_pubkey");
+							continue;
+						}
+						None => {
+							println!("ERROR: getnodeinfo requires a node pubkey: `getnodeinfo <node_pubkey>`");
+							continue;
+						}
+					};
+					get_node_info(node_pubkey, &network_graph, format);
+				}
+				"describegraph" => {
+					let mut path = None;
+					let mut csv = false;
+					let mut unknown_arg = None;
+					for arg in words {
+						match arg {
+							"--csv" => csv = true,
+							_ if path.is_none() => path = Some(arg),
+							_ => unknown_arg = Some(arg),
+						}
+					}
+					if let Some(arg) = unknown_arg {
+						println!("ERROR: unknown argument {}", arg);
+						continue;
+					}
+					match path {
+						Some(path) => {
+							let graph = if csv {
+								graph_csv(&network_graph)
+							} else {
+								graph_json(&network_graph).to_string()
+							};
+							match fs::write(path, graph) {
+								Ok(()) => println!("SUCCESS: wrote the network graph to {}", path),
+								Err(e) => println!("ERROR: failed to write {}: {}", path, e),
+							}
+						}
+						None if csv => print!("{}", graph_csv(&network_graph)),
+						None => print_json(&graph_json(&network_graph)),
+					}
+				}
+				"importgraph" => {
+					let path =
+						match words.next() {
+							Some(path) => path,
+							None => {
+								println!("ERROR: importgraph requires a file: `importgraph <input_file>`");
+								continue;
+							}
+						};
+					let graph = match fs::read_to_string(path)
+						.map_err(|e| e.to_string())
+						.and_then(|graph| serde_json::from_str(&graph).map_err(|e| e.to_string()))
+					{
+						Ok(graph) => graph,
+						Err(e) => {
+							println!("ERROR: failed to read {}: {}", path, e);
+							continue;
+						}
+					};
+					match import_graph(&network_graph, &graph, network) {
+						Ok((num_channels, num_updates)) => println!(
+							"SUCCESS: imported {} new channels and {} channel policies from {}",
+							num_channels, num_updates, path
+						),
+						Err(e) => println!("ERROR: {}", e),
+					}
+				}
+				"graphstats" => {
+					graph_stats(&network_graph, &channel_manager.get_our_node_id(), format)
+				}
+				"setgossipsync" => {
+					let rgs =
+						match words.next() {
+							Some("rgs") => true,
+							Some("p2p") => false,
+							_ => {
+								println!("ERROR: setgossipsync requires a mode: `setgossipsync <rgs|p2p>`");
+								continue;
+							}
+						};
+					match set_gossip_sync(rgs, rgs_client.as_ref()) {
+						Ok(()) if rgs => {
+							println!("SUCCESS: syncing the network graph through Rapid Gossip Sync")
+						}
+						Ok(()) => {
+							println!("SUCCESS: only learning the network graph from peers' gossip")
+						}
+						Err(e) => println!("ERROR: {}", e),
+					}
+				}
+				"setformat" => match words.next() {
+					Some("text") => output_format = OutputFormat::Text,
+					Some("json") => output_format = OutputFormat::Json,
+					_ => println!("ERROR: setformat requires a format: `setformat <text|json>`"),
+				},
 				"signmessage" => {
 					const MSG_STARTPOS: usize = "signmessage".len() + 1;
 					if line.as_bytes().len() <= MSG_STARTPOS {
 						println!("ERROR: signmsg requires a message");
 						continue;
 					}
-					println!(
-						"{:?}",
-						lightning::util::message_signing::sign(
-							&line.as_bytes()[MSG_STARTPOS..],
-							&keys_manager.get_node_secret_key()
-						)
-					);
+					// A trailing `--json` selects the output format, it isn't part of the message.
+					let message =
+						line[MSG_STARTPOS..].trim_end().trim_end_matches("--json").trim_end();
+					match keys_manager.sign_message(message.as_bytes()) {
+						Ok(signature) if format == OutputFormat::Json => {
+							print_json(&json!({ "signature": signature }))
+						}
+						Ok(signature) => println!("{}", signature),
+						Err(()) => println!("ERROR: failed to sign message"),
+					}
+				}
+				"verifymessage" => {
+					let signature = match words.next() {
+						Some(signature) => signature,
+						None => {
+							println!("ERROR: verifymessage requires a signature and a message: `verifymessage <signature> <message>`");
+							continue;
+						}
+					};
+					let message = line[line.find(signature).unwrap() + signature.len()..]
+						.trim()
+						.trim_end_matches("--json")
+						.trim_end();
+					if message.is_empty() {
+						println!("ERROR: verifymessage requires a message: `verifymessage <signature> <message>`");
+						continue;
+					}
+					match verify_message_json(message.as_bytes(), signature, &network_graph) {
+						Ok(res) if format == OutputFormat::Json => print_json(&res),
+						Ok(res) => {
+							println!("\t{{");
+							println!("\t\t pubkey: {}", res["pubkey"].as_str().unwrap());
+							println!("\t\t known_node: {}", res["known_node"]);
+							if let Some(alias) = res["alias"].as_str() {
+								println!("\t\t alias: {}", alias);
+							}
+							println!("\t}},");
+						}
+						Err(()) => println!("ERROR: invalid signature"),
+					}
 				}
 				"sendonionmessage" => {
 					let path_pks_str = words.next();
@@ -426,7 +1604,7 @@ pub(crate) async fn poll_for_user_input(
 						Err(e) => println!("ERROR: failed to send onion message: {:?}", e),
 					}
 				}
-				"quit" | "exit" => break,
+				"stop" | "quit" | "exit" => break,
 				_ => println!("Unknown command. See `\"help\" for available commands."),
 			}
 		}
@@ -442,60 +1620,631 @@ fn help() {
 	println!("  Command [arguments]");
 	println!("\nCOMMANDS:");
 	println!("  help\tShows a list of commands.");
+	println!("  stop\tShut the node down gracefully and close the application.");
 	println!("  quit\tClose the application.");
 	println!("\n  Channels:");
-	println!("      openchannel pubkey@host:port <amt_satoshis> [--public]");
-	println!("      closechannel <channel_id> <peer_pubkey>");
+	println!("      openchannel pubkey@host:port <amt_satoshis> [--public|--private] [--push-msat=<amt>]");
+	println!("                  [--min-htlc-msat=<amt>] [--cltv-expiry-delta=<blocks>]");
+	println!("                  [--fee-base-msat=<amt>] [--fee-proportional-millionths=<rate>]");
+	println!("                  [--psbt]");
+	println!("      fundchannel <temporary_channel_id> <psbt_base64|tx_hex>");
+	println!("      closechannel <channel_id> <peer_pubkey> [--force] [--sat-per-vbyte=<feerate>]");
 	println!("      forceclosechannel <channel_id> <peer_pubkey>");
+	println!("      abandonchannel <channel_id> <peer_pubkey>");
+	println!("      updatechannelpolicy <channel_id|peer_pubkey> [--fee-base-msat=<amt>]");
+	println!(
+		"                  [--fee-proportional-millionths=<rate>] [--cltv-expiry-delta=<blocks>]"
+	);
 	println!("      listchannels");
 	println!("\n  Peers:");
 	println!("      connectpeer pubkey@host:port");
-	println!("      disconnectpeer <peer_pubkey>");
+	println!("      bootstrap [<num_peers>]");
+	println!("      disconnectpeer <peer_pubkey> [--no-reconnect]");
+	println!("      setreconnect <peer_pubkey> <on|off>");
 	println!("      listpeers");
 	println!("\n  Payments:");
-	println!("      sendpayment <invoice>");
-	println!("      keysend <dest_pubkey> <amt_msats>");
-	println!("      listpayments");
+	println!("      sendpayment <invoice> [--max-attempts=<n>|--retry-timeout-secs=<secs>]");
+	println!("                  [--max-parts=<n>|--no-mpp] [--max-fee-sat=<sats>]");
+	println!("                  [--max-fee-percent=<percent>] [--max-hops=<n>]");
+	println!("                  [--avoid-node=<pubkey>]* [--first-hop-channel=<channel_id>]*");
+	println!("      payaddress <user@domain> <amt_msats> [<sendpayment options>]");
+	println!("      paylnurl <lnurl> <amt_msats> [<sendpayment options>]");
+	println!(
+		"      keysend <dest_pubkey> <amt_msats> [--max-attempts=<n>|--retry-timeout-secs=<secs>]"
+	);
+	println!("      queryroutes <dest_pubkey> <amt_msats>");
+	println!("      probe <dest_pubkey> <amt_msats>");
+	println!("      probestats");
+	println!("      scorerinfo");
+	println!("      resetscorer");
+	println!("      listpayments [--incoming|--outgoing] [--status=<pending|held|succeeded|failed>] [--limit=<n>] [--offset=<n>]");
+	println!("      listforwards [--since=<unix_secs>] [--until=<unix_secs>] [--limit=<n>]");
 	println!("\n  Invoices:");
-	println!("      getinvoice <amt_msats> <expiry_secs>");
+	println!("      getinvoice <amt_msats> <expiry_secs> [<invoice options>] [--qr]");
+	println!("      addholdinvoice <payment_hash> <amt_msats> <expiry_secs> [<invoice options>]");
+	println!("      getphantominvoice <amt_msats> <expiry_secs> [--description=<text>]");
+	println!("                        [--phantom-hints=<hints>,...]");
+	println!("      getphantomroutehints");
+	println!("        where <invoice options> are [--description=<text>|--description-hash=<hex>]");
+	println!("        [--fallback-address=<address>] [--route-hints=<channel_id>,...|none]");
+	println!("      listinvoices");
+	println!("      settleinvoice <payment_preimage>");
+	println!("      cancelinvoice <payment_hash>");
+	println!("      decodeinvoice <invoice>");
 	println!("\n  Other:");
 	println!("      signmessage <message>");
+	println!("      verifymessage <signature> <message>");
 	println!(
 		"      sendonionmessage <node_id_1,node_id_2,..,destination_node_id> <type> <hex_bytes>"
 	);
-	println!("      nodeinfo");
+	println!("      nodeinfo [--qr]");
+	println!("      estimatefees");
+	println!("      listunconfirmed");
+	println!("      setfees <background|normal|high_priority> [--conf-target=<blocks>]");
+	println!("          [--sat-per-vbyte=<feerate>|--no-override]");
+	println!("      chainstatus");
+	println!("      newaddress [--type=<p2wpkh|p2tr>]");
+	println!("      onchainbalance");
+	println!("      backupstatus");
+	println!("      exportbackup <path>");
+	println!("      verifystate");
+	println!("\n  Network graph:");
+	println!("      getnodeinfo <node_pubkey>");
+	println!("      describegraph [<output_file>] [--csv]");
+	println!("      importgraph <input_file>");
+	println!("      graphstats");
+	println!("      setgossipsync <rgs|p2p>");
+	println!("      setformat <text|json>");
+	println!(
+		"\n  Append --json to nodeinfo, estimatefees, listunconfirmed, chainstatus, newaddress,"
+	);
+	println!("  onchainbalance, backupstatus, verifystate, listchannels, listpeers, bootstrap,");
+	println!("  listpayments, listinvoices, listforwards, queryroutes, probestats, scorerinfo,");
+	println!(
+		"  graphstats, signmessage, verifymessage, openchannel --psbt or getphantomroutehints"
+	);
+	println!("  to get JSON output.");
+}
+
+/// Renders `data` as a QR code made of Unicode half blocks. Colors are inverted so that the code
+/// scans from terminals with a dark background.
+fn print_qr(data: &str) {
+	match QrCode::new(data.as_bytes()) {
+		Ok(code) => println!(
+			"{}",
+			code.render::<unicode::Dense1x2>()
+				.dark_color(unicode::Dense1x2::Light)
+				.light_color(unicode::Dense1x2::Dark)
+				.build()
+		),
+		Err(e) => println!("ERROR: failed to render QR code: {}", e),
+	}
+}
+
+fn print_json(value: &serde_json::Value) {
+	println!("{}", serde_json::to_string_pretty(value).unwrap());
+}
+
+fn htlc_status_str(status: &HTLCStatus) -> &'static str {
+	match status {
+		HTLCStatus::Pending => "pending",
+		HTLCStatus::Held => "held",
+		HTLCStatus::Succeeded => "succeeded",
+		HTLCStatus::Failed => "failed",
+	}
+}
+
+/// The `pubkey@host:port` URIs other nodes can connect to us with.
+fn node_uris(
+	channel_manager: &Arc<ChannelManager>, announced_listen_addr: &[NetAddress],
+) -> Vec<String> {
+	let node_pubkey = channel_manager.get_our_node_id();
+	announced_listen_addr
+		.iter()
+		.map(|address| format!("{}@{}", node_pubkey, net_address_str(address)))
+		.collect()
+}
+
+pub(crate) fn node_info_json(
+	channel_manager: &Arc<ChannelManager>, peer_manager: &Arc<PeerManager>,
+	announced_listen_addr: &[NetAddress],
+) -> serde_json::Value {
+	let chans = channel_manager.list_channels();
+	json!({
+		"node_pubkey": channel_manager.get_our_node_id().to_string(),
+		"uris": node_uris(channel_manager, announced_listen_addr),
+		"num_channels": chans.len(),
+		"num_usable_channels": chans.iter().filter(|c| c.is_usable).count(),
+		"local_balance_msat": chans.iter().map(|c| c.balance_msat).sum::<u64>(),
+		"num_peers": peer_manager.get_peer_node_ids().len(),
+	})
+}
+
+/// Recovers the node that signed `message`, and whether it is one we know from the network graph,
+/// which is what makes a signature meaningful as a proof of node ownership.
+pub(crate) fn verify_message_json(
+	message: &[u8], signature: &str, network_graph: &Arc<NetworkGraph>,
+) -> Result<serde_json::Value, ()> {
+	let pubkey = message_signing::recover_pk(message, signature).map_err(|_| ())?;
+	let graph = network_graph.read_only();
+	let node = graph.nodes().get(&NodeId::from_pubkey(&pubkey));
+	let alias = node
+		.and_then(|node_info| node_info.announcement_info.as_ref())
+		.map(|announcement| announcement.alias.to_string());
+	Ok(json!({
+		"pubkey": pubkey.to_string(),
+		"known_node": node.is_some(),
+		"alias": alias,
+	}))
 }
 
-fn node_info(channel_manager: &Arc<ChannelManager>, peer_manager: &Arc<PeerManager>) {
+fn node_info(
+	channel_manager: &Arc<ChannelManager>, peer_manager: &Arc<PeerManager>,
+	announced_listen_addr: &[NetAddress], qr: bool, format: OutputFormat,
+) {
+	if format == OutputFormat::Json {
+		print_json(&node_info_json(channel_manager, peer_manager, announced_listen_addr));
+		return;
+	}
+	let uris = node_uris(channel_manager, announced_listen_addr);
+	let chans = channel_manager.list_channels();
+	let num_usable_channels = chans.iter().filter(|c| c.is_usable).count();
+	let local_balance_msat = chans.iter().map(|c| c.balance_msat).sum::<u64>();
+	let num_peers = peer_manager.get_peer_node_ids().len();
 	println!("\t{{");
 	println!("\t\t node_pubkey: {}", channel_manager.get_our_node_id());
-	let chans = channel_manager.list_channels();
+	for uri in uris.iter() {
+		println!("\t\t uri: {}", uri);
+	}
 	println!("\t\t num_channels: {}", chans.len());
-	println!("\t\t num_usable_channels: {}", chans.iter().filter(|c| c.is_usable).count());
-	let local_balance_msat = chans.iter().map(|c| c.balance_msat).sum::<u64>();
+	println!("\t\t num_usable_channels: {}", num_usable_channels);
 	println!("\t\t local_balance_msat: {}", local_balance_msat);
-	println!("\t\t num_peers: {}", peer_manager.get_peer_node_ids().len());
+	println!("\t\t num_peers: {}", num_peers);
 	println!("\t}},");
+	if qr {
+		// Without a public address, the pubkey alone at least saves typing it.
+		match uris.first() {
+			Some(uri) => print_qr(uri),
+			None => print_qr(&channel_manager.get_our_node_id().to_string()),
+		}
+	}
+}
+
+/// The feerates the node currently uses for each confirmation target, and how they're picked.
+pub(crate) fn fees_json(bitcoind_client: &BitcoindClient) -> serde_json::Value {
+	let targets = [
+		(Target::Background, ConfirmationTarget::Background),
+		(Target::Normal, ConfirmationTarget::Normal),
+		(Target::HighPriority, ConfirmationTarget::HighPriority),
+	];
+	let fees = targets
+		.iter()
+		.map(|(target, confirmation_target)| {
+			let sat_per_kw = bitcoind_client.get_est_sat_per_1000_weight(*confirmation_target);
+			let settings = bitcoind_client.fee_target_settings(*target);
+			json!({
+				"target": target.name(),
+				"sat_per_kw": sat_per_kw,
+				"sat_per_vbyte": sat_per_kw as f64 / 250.0,
+				"conf_target_blocks": settings.conf_blocks,
+				"overridden": settings.override_sat_per_kw.is_some(),
+			})
+		})
+		.collect::<Vec<_>>();
+	json!(fees)
 }
 
-fn list_peers(peer_manager: Arc<PeerManager>) {
+fn estimate_fees(bitcoind_client: &BitcoindClient, format: OutputFormat) {
+	let fees = fees_json(bitcoind_client);
+	if format == OutputFormat::Json {
+		print_json(&fees);
+		return;
+	}
 	println!("\t{{");
-	for (pubkey, _) in peer_manager.get_peer_node_ids() {
-		println!("\t\t pubkey: {}", pubkey);
+	for fee in fees.as_array().unwrap() {
+		let source = if fee["overridden"].as_bool().unwrap() {
+			"static override".to_string()
+		} else {
+			format!("estimated for {} blocks", fee["conf_target_blocks"])
+		};
+		println!(
+			"\t\t {}: {} sat/kw ({} sat/vB, {})",
+			fee["target"].as_str().unwrap(),
+			fee["sat_per_kw"],
+			fee["sat_per_vbyte"],
+			source
+		);
 	}
 	println!("\t}},");
 }
 
-fn list_channels(channel_manager: &Arc<ChannelManager>, network_graph: &Arc<NetworkGraph>) {
+/// How long a transaction we broadcast may stay unconfirmed before we call it stuck.
+const STUCK_TX_AGE_SECS: u64 = 6 * 60 * 60;
+
+/// The transactions the node broadcast that haven't confirmed yet, oldest first.
+pub(crate) fn unconfirmed_json(bitcoind_client: &BitcoindClient) -> serde_json::Value {
+	let now = time_now_secs();
+	let txs = bitcoind_client
+		.unconfirmed_txs()
+		.iter()
+		.map(|tracked| {
+			let age_secs = now.saturating_sub(tracked.first_broadcast_at);
+			json!({
+				"txid": tracked.tx.txid().to_string(),
+				"first_broadcast_at": tracked.first_broadcast_at,
+				"age_secs": age_secs,
+				"rebroadcasts": tracked.rebroadcasts,
+				"in_mempool": tracked.in_mempool,
+				"stuck": !tracked.in_mempool || age_secs > STUCK_TX_AGE_SECS,
+				"last_error": tracked.last_error,
+			})
+		})
+		.collect::<Vec<_>>();
+	json!(txs)
+}
+
+fn list_unconfirmed(bitcoind_client: &BitcoindClient, format: OutputFormat) {
+	let txs = unconfirmed_json(bitcoind_client);
+	if format == OutputFormat::Json {
+		print_json(&txs);
+		return;
+	}
 	print!("[");
-	for chan_info in channel_manager.list_channels() {
+	for tx in txs.as_array().unwrap() {
 		println!("");
 		println!("\t{{");
-		println!("\t\tchannel_id: {},", hex_utils::hex_str(&chan_info.channel_id[..]));
-		if let Some(funding_txo) = chan_info.funding_txo {
-			println!("\t\tfunding_txid: {},", funding_txo.txid);
+		println!("\t\ttxid: {},", tx["txid"].as_str().unwrap());
+		println!("\t\tage_secs: {},", tx["age_secs"]);
+		println!("\t\trebroadcasts: {},", tx["rebroadcasts"]);
+		println!("\t\tin_mempool: {},", tx["in_mempool"]);
+		println!("\t\tstuck: {},", tx["stuck"]);
+		if let Some(error) = tx["last_error"].as_str() {
+			println!("\t\tlast_error: {},", error);
 		}
-
+		println!("\t}},");
+	}
+	println!("]");
+}
+
+/// How far the backup replication lags behind the node's state, `null` if it isn't enabled.
+pub(crate) fn backup_status_json(persister: &NodePersister) -> serde_json::Value {
+	match persister.backup_status() {
+		Some(status) => json!({
+			"target": status.target.to_string(),
+			"pending_writes": status.pending_writes,
+			"lag_secs": status.lag_secs,
+			"replicated_writes": status.replicated_writes,
+			"last_replicated_at": status.last_replicated_at,
+			"verification_failures": status.verification_failures,
+			"last_error": status.last_error,
+		}),
+		None => serde_json::Value::Null,
+	}
+}
+
+fn backup_status(persister: &NodePersister, format: OutputFormat) {
+	let status = backup_status_json(persister);
+	if format == OutputFormat::Json {
+		print_json(&status);
+		return;
+	}
+	if status.is_null() {
+		println!("Backup replication isn't enabled, start the node with --backup=<target>");
+		return;
+	}
+	println!("{{");
+	println!("\ttarget: {},", status["target"].as_str().unwrap());
+	println!("\tpending_writes: {},", status["pending_writes"]);
+	println!("\tlag_secs: {},", status["lag_secs"]);
+	println!("\treplicated_writes: {},", status["replicated_writes"]);
+	if let Some(at) = status["last_replicated_at"].as_u64() {
+		println!("\tlast_replicated_at: {},", at);
+	}
+	println!("\tverification_failures: {},", status["verification_failures"]);
+	if let Some(error) = status["last_error"].as_str() {
+		println!("\tlast_error: {},", error);
+	}
+	println!("}}");
+}
+
+/// Reads back everything the node has stored, reporting what's corrupted and the monitors of
+/// channels the `ChannelManager` no longer has.
+pub(crate) fn verify_state_json(
+	persister: &NodePersister, channel_manager: &ChannelManager, chain_monitor: &Arc<ChainMonitor>,
+	keys_manager: &Arc<NodeKeys>, bitcoind_client: &Arc<BitcoindClient>,
+	network_graph: &Arc<NetworkGraph>, scorer: &Arc<Mutex<Scorer>>,
+	logger: &Arc<disk::FilesystemLogger>,
+) -> serde_json::Value {
+	let report = verify::verify_state(
+		persister,
+		channel_manager,
+		chain_monitor,
+		keys_manager,
+		bitcoind_client,
+		network_graph,
+		scorer,
+		logger,
+	);
+	json!({
+		"ok": report.errors.is_empty(),
+		"monitors": report.monitors,
+		"journaled_updates": report.journaled_updates,
+		"manager_channels": report.manager_channels,
+		"errors": report.errors,
+		"orphaned_monitors": report.orphaned_monitors.iter().map(|(funding_txo, claimable_sats)| json!({
+			"funding_txo": format!("{}:{}", funding_txo.txid, funding_txo.index),
+			"claimable_sats": claimable_sats,
+		})).collect::<Vec<_>>(),
+	})
+}
+
+fn verify_state(
+	persister: &NodePersister, channel_manager: &ChannelManager, chain_monitor: &Arc<ChainMonitor>,
+	keys_manager: &Arc<NodeKeys>, bitcoind_client: &Arc<BitcoindClient>,
+	network_graph: &Arc<NetworkGraph>, scorer: &Arc<Mutex<Scorer>>,
+	logger: &Arc<disk::FilesystemLogger>, format: OutputFormat,
+) {
+	let report = verify_state_json(
+		persister,
+		channel_manager,
+		chain_monitor,
+		keys_manager,
+		bitcoind_client,
+		network_graph,
+		scorer,
+		logger,
+	);
+	if format == OutputFormat::Json {
+		print_json(&report);
+		return;
+	}
+	println!("{{");
+	println!("\tmonitors: {},", report["monitors"]);
+	println!("\tjournaled_updates: {},", report["journaled_updates"]);
+	if let Some(channels) = report["manager_channels"].as_u64() {
+		println!("\tmanager_channels: {},", channels);
+	}
+	print!("\terrors: [");
+	for error in report["errors"].as_array().unwrap() {
+		println!("");
+		println!("\t\t{},", error.as_str().unwrap());
+	}
+	println!("\t],");
+	print!("\torphaned_monitors: [");
+	for monitor in report["orphaned_monitors"].as_array().unwrap() {
+		println!("");
+		println!("\t\t{{");
+		println!("\t\t\tfunding_txo: {},", monitor["funding_txo"].as_str().unwrap());
+		println!("\t\t\tclaimable_sats: {},", monitor["claimable_sats"]);
+		println!("\t\t}},");
+	}
+	println!("\t],");
+	println!("}}");
+	if report["ok"].as_bool() == Some(true) {
+		println!("SUCCESS: the node's state reads back intact");
+	} else {
+		println!("ERROR: the node's state is corrupted, see the errors above");
+	}
+}
+
+/// Switches between syncing the network graph from the Rapid Gossip Sync server, on top of our
+/// peers' gossip, and only using our peers' gossip. Switching back to RGS syncs right away.
+pub(crate) fn set_gossip_sync(
+	rgs: bool, rgs_client: Option<&Arc<RgsClient>>,
+) -> Result<(), String> {
+	let rgs_client = match rgs_client {
+		Some(rgs_client) => rgs_client,
+		None if rgs => return Err("Rapid Gossip Sync requires --rgs-url=<url>".to_string()),
+		None => return Ok(()),
+	};
+	if rgs && !rgs_client.is_enabled() {
+		let rgs_client = Arc::clone(rgs_client);
+		tokio::spawn(async move {
+			if let Err(e) = rgs_client.sync().await {
+				println!("ERROR: failed to sync the network graph from the RGS server: {}", e);
+			}
+		});
+	}
+	rgs_client.set_enabled(rgs);
+	Ok(())
+}
+
+/// Compares the best block the `ChannelManager` has been synced to with bitcoind's.
+pub(crate) async fn chain_status_json(
+	channel_manager: &Arc<ChannelManager>, bitcoind_client: &BitcoindClient,
+) -> serde_json::Value {
+	let best_block = channel_manager.current_best_block();
+	let bitcoind_info = bitcoind_client.get_blockchain_info().await;
+	json!({
+		"best_block_height": best_block.height(),
+		"best_block_hash": best_block.block_hash().to_string(),
+		"bitcoind_block_height": bitcoind_info.latest_height,
+		"bitcoind_block_hash": bitcoind_info.latest_blockhash.to_string(),
+		"synced": best_block.block_hash() == bitcoind_info.latest_blockhash,
+	})
+}
+
+async fn chain_status(
+	channel_manager: &Arc<ChannelManager>, bitcoind_client: &BitcoindClient, format: OutputFormat,
+) {
+	let status = chain_status_json(channel_manager, bitcoind_client).await;
+	if format == OutputFormat::Json {
+		print_json(&status);
+		return;
+	}
+	println!("\t{{");
+	println!("\t\t best_block_height: {}", status["best_block_height"]);
+	println!("\t\t best_block_hash: {}", status["best_block_hash"].as_str().unwrap());
+	println!("\t\t bitcoind_block_height: {}", status["bitcoind_block_height"]);
+	println!("\t\t bitcoind_block_hash: {}", status["bitcoind_block_hash"].as_str().unwrap());
+	println!("\t\t synced: {}", status["synced"]);
+	println!("\t}},");
+}
+
+pub(crate) async fn onchain_balance_json(
+	wallet: &OnchainWallet,
+) -> Result<serde_json::Value, String> {
+	let balance = wallet.get_balance().await?;
+	Ok(json!({
+		"confirmed_sats": balance.confirmed,
+		"unconfirmed_sats": balance.unconfirmed,
+		"immature_sats": balance.immature,
+	}))
+}
+
+async fn onchain_balance(wallet: &OnchainWallet, format: OutputFormat) {
+	let balance = match onchain_balance_json(wallet).await {
+		Ok(balance) => balance,
+		Err(e) => {
+			println!("ERROR: failed to get the on-chain balance: {}", e);
+			return;
+		}
+	};
+	if format == OutputFormat::Json {
+		print_json(&balance);
+		return;
+	}
+	println!("\t{{");
+	println!("\t\t confirmed_sats: {}", balance["confirmed_sats"]);
+	println!("\t\t unconfirmed_sats: {}", balance["unconfirmed_sats"]);
+	println!("\t\t immature_sats: {}", balance["immature_sats"]);
+	println!("\t}},");
+}
+
+pub(crate) fn bootstrap_json(peers: &[PublicKey]) -> serde_json::Value {
+	json!({ "connected": peers.iter().map(|pubkey| pubkey.to_string()).collect::<Vec<_>>() })
+}
+
+/// Lists both our connected peers and our channel peers we're not currently connected to.
+pub(crate) fn peers_json(
+	peer_manager: &Arc<PeerManager>, channel_manager: &Arc<ChannelManager>,
+	peer_connections: &PeerConnectionsStorage,
+) -> serde_json::Value {
+	let connected_peers = peer_manager.get_peer_node_ids();
+	let mut disconnected_channel_peers = channel_manager
+		.list_channels()
+		.iter()
+		.map(|chan| chan.counterparty.node_id)
+		.filter(|id| !connected_peers.iter().any(|(pk, _)| id == pk))
+		.collect::<Vec<_>>();
+	disconnected_channel_peers.sort();
+	disconnected_channel_peers.dedup();
+
+	let connections = peer_connections.lock().unwrap();
+	let now = time_now_secs();
+	let peers = connected_peers
+		.iter()
+		.map(|(pubkey, address)| (*pubkey, address.as_ref(), true))
+		.chain(disconnected_channel_peers.into_iter().map(|pubkey| (pubkey, None, false)))
+		.map(|(pubkey, address, connected)| {
+			let connected_since =
+				if connected { connections.connected_since.get(&pubkey).copied() } else { None };
+			json!({
+				"pubkey": pubkey.to_string(),
+				"address": address.map(net_address_str),
+				"connected": connected,
+				"connected_since": connected_since,
+				"uptime_secs": connected_since.map(|since| now.saturating_sub(since)),
+				"reconnect": !connections.no_reconnect.contains(&pubkey),
+			})
+		})
+		.collect::<Vec<_>>();
+	json!(peers)
+}
+
+fn list_peers(
+	peer_manager: &Arc<PeerManager>, channel_manager: &Arc<ChannelManager>,
+	peer_connections: &PeerConnectionsStorage, format: OutputFormat,
+) {
+	let peers = peers_json(peer_manager, channel_manager, peer_connections);
+	if format == OutputFormat::Json {
+		print_json(&peers);
+		return;
+	}
+	println!("\t{{");
+	for peer in peers.as_array().unwrap() {
+		println!("\t\t pubkey: {}", peer["pubkey"].as_str().unwrap());
+		if let Some(address) = peer["address"].as_str() {
+			println!("\t\t\t address: {}", address);
+		}
+		match peer["uptime_secs"].as_u64() {
+			Some(uptime) => println!("\t\t\t connected for {}s", uptime),
+			None if peer["connected"].as_bool().unwrap() => println!("\t\t\t connected"),
+			None => println!("\t\t\t disconnected"),
+		}
+		if !peer["reconnect"].as_bool().unwrap() {
+			println!("\t\t\t automatic reconnection: off");
+		}
+	}
+	println!("\t}},");
+}
+
+/// Turns automatic reconnection to a channel peer on or off, persisting the choice.
+pub(crate) fn set_reconnect(
+	pubkey: PublicKey, reconnect: bool, peer_connections: &PeerConnectionsStorage,
+	ldk_data_dir: &str,
+) -> Result<(), ()> {
+	let mut connections = peer_connections.lock().unwrap();
+	if reconnect {
+		connections.no_reconnect.remove(&pubkey);
+	} else {
+		connections.no_reconnect.insert(pubkey);
+	}
+	let path = format!("{}/no_reconnect_peers", ldk_data_dir);
+	disk::persist_no_reconnect_peers(Path::new(&path), &connections.no_reconnect)
+		.map_err(|e| println!("ERROR: failed to persist reconnection settings: {}", e))
+}
+
+pub(crate) fn channels_json(
+	channel_manager: &Arc<ChannelManager>, network_graph: &Arc<NetworkGraph>,
+) -> serde_json::Value {
+	let graph = network_graph.read_only();
+	let channels = channel_manager
+		.list_channels()
+		.iter()
+		.map(|chan_info| {
+			let peer_alias = graph
+				.nodes()
+				.get(&NodeId::from_pubkey(&chan_info.counterparty.node_id))
+				.and_then(|node_info| node_info.announcement_info.as_ref())
+				.map(|announcement| announcement.alias.to_string());
+			json!({
+				"channel_id": hex_utils::hex_str(&chan_info.channel_id[..]),
+				"funding_txid": chan_info.funding_txo.map(|txo| txo.txid.to_string()),
+				"peer_pubkey": hex_utils::hex_str(&chan_info.counterparty.node_id.serialize()),
+				"peer_alias": peer_alias,
+				"short_channel_id": chan_info.short_channel_id,
+				"is_channel_ready": chan_info.is_channel_ready,
+				"channel_value_satoshis": chan_info.channel_value_satoshis,
+				"local_balance_msat": chan_info.balance_msat,
+				"available_balance_for_send_msat":
+					if chan_info.is_usable { Some(chan_info.outbound_capacity_msat) } else { None },
+				"available_balance_for_recv_msat":
+					if chan_info.is_usable { Some(chan_info.inbound_capacity_msat) } else { None },
+				"channel_can_send_payments": chan_info.is_usable,
+				"public": chan_info.is_public,
+			})
+		})
+		.collect::<Vec<_>>();
+	json!(channels)
+}
+
+fn list_channels(
+	channel_manager: &Arc<ChannelManager>, network_graph: &Arc<NetworkGraph>, format: OutputFormat,
+) {
+	if format == OutputFormat::Json {
+		print_json(&channels_json(channel_manager, network_graph));
+		return;
+	}
+	print!("[");
+	for chan_info in channel_manager.list_channels() {
+		println!("");
+		println!("\t{{");
+		println!("\t\tchannel_id: {},", hex_utils::hex_str(&chan_info.channel_id[..]));
+		if let Some(funding_txo) = chan_info.funding_txo {
+			println!("\t\tfunding_txid: {},", funding_txo.txid);
+		}
+
 		println!(
 			"\t\tpeer_pubkey: {},",
 			hex_utils::hex_str(&chan_info.counterparty.node_id.serialize())
@@ -527,96 +2276,1036 @@ fn list_channels(channel_manager: &Arc<ChannelManager>, network_graph: &Arc<Netw
 	println!("]");
 }
 
-fn list_payments(inbound_payments: PaymentInfoStorage, outbound_payments: PaymentInfoStorage) {
+/// Which payments `listpayments` shows, newest first.
+#[derive(Default)]
+pub(crate) struct PaymentFilter {
+	direction: Option<PaymentDirection>,
+	status: Option<HTLCStatus>,
+	limit: Option<usize>,
+	offset: usize,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum PaymentDirection {
+	Inbound,
+	Outbound,
+}
+
+impl PaymentFilter {
+	/// Parses `[--incoming|--outgoing] [--status=<status>] [--limit=<n>] [--offset=<n>]`.
+	pub(crate) fn from_args<'a, I: Iterator<Item = &'a str>>(args: I) -> Result<Self, String> {
+		let mut filter = PaymentFilter::default();
+		for arg in args {
+			let mut arg_parts = arg.splitn(2, '=');
+			match (arg_parts.next(), arg_parts.next()) {
+				(Some("--incoming"), None) => filter.direction = Some(PaymentDirection::Inbound),
+				(Some("--outgoing"), None) => filter.direction = Some(PaymentDirection::Outbound),
+				(Some("--status"), Some(status)) => {
+					filter.status = Some(match status {
+						"pending" => HTLCStatus::Pending,
+						"held" => HTLCStatus::Held,
+						"succeeded" => HTLCStatus::Succeeded,
+						"failed" => HTLCStatus::Failed,
+						_ => return Err(format!("unknown payment status {}", status)),
+					})
+				}
+				(Some("--limit"), Some(limit)) => {
+					filter.limit =
+						Some(limit.parse().map_err(|_| "--limit must be a number".to_string())?)
+				}
+				(Some("--offset"), Some(offset)) => {
+					filter.offset =
+						offset.parse().map_err(|_| "--offset must be a number".to_string())?
+				}
+				_ => return Err(format!("unknown listpayments argument {}", arg)),
+			}
+		}
+		Ok(filter)
+	}
+
+	fn select<'a>(
+		&self, inbound: &'a HashMap<PaymentHash, PaymentInfo>,
+		outbound: &'a HashMap<PaymentHash, PaymentInfo>,
+	) -> Vec<(&'a PaymentHash, &'a PaymentInfo, PaymentDirection)> {
+		let mut payments = inbound
+			.iter()
+			.map(|(hash, info)| (hash, info, PaymentDirection::Inbound))
+			.chain(outbound.iter().map(|(hash, info)| (hash, info, PaymentDirection::Outbound)))
+			.filter(|(_, info, direction)| {
+				self.direction.map_or(true, |d| d == *direction)
+					&& self.status.map_or(true, |s| s == info.status)
+			})
+			.collect::<Vec<_>>();
+		payments.sort_by(|a, b| b.1.created_at.cmp(&a.1.created_at).then(a.0 .0.cmp(&b.0 .0)));
+		payments.into_iter().skip(self.offset).take(self.limit.unwrap_or(usize::MAX)).collect()
+	}
+}
+
+fn direction_str(direction: PaymentDirection) -> &'static str {
+	match direction {
+		PaymentDirection::Inbound => "inbound",
+		PaymentDirection::Outbound => "outbound",
+	}
+}
+
+pub(crate) fn payments_json(
+	inbound_payments: &PaymentInfoStorage, outbound_payments: &PaymentInfoStorage,
+	filter: &PaymentFilter,
+) -> serde_json::Value {
+	let inbound = inbound_payments.lock().unwrap();
+	let outbound = outbound_payments.lock().unwrap();
+	let payments = filter
+		.select(&inbound, &outbound)
+		.into_iter()
+		.map(|(payment_hash, payment_info, direction)| {
+			json!({
+				"amount_millisatoshis": payment_info.amt_msat.0,
+				"payment_hash": hex_utils::hex_str(&payment_hash.0),
+				"payment_preimage": payment_info.preimage.map(|p| hex_utils::hex_str(&p.0)),
+				"htlc_direction": direction_str(direction),
+				"htlc_status": htlc_status_str(&payment_info.status),
+				"fee_paid_msat": payment_info.fee_paid_msat,
+				"created_at": payment_info.created_at,
+				"settled_at": payment_info.settled_at,
+			})
+		})
+		.collect::<Vec<_>>();
+	json!(payments)
+}
+
+fn list_payments(
+	inbound_payments: PaymentInfoStorage, outbound_payments: PaymentInfoStorage,
+	filter: &PaymentFilter, format: OutputFormat,
+) {
+	if format == OutputFormat::Json {
+		print_json(&payments_json(&inbound_payments, &outbound_payments, filter));
+		return;
+	}
 	let inbound = inbound_payments.lock().unwrap();
 	let outbound = outbound_payments.lock().unwrap();
 	print!("[");
-	for (payment_hash, payment_info) in inbound.deref() {
+	for (payment_hash, payment_info, direction) in filter.select(&inbound, &outbound) {
 		println!("");
 		println!("\t{{");
 		println!("\t\tamount_millisatoshis: {},", payment_info.amt_msat);
 		println!("\t\tpayment_hash: {},", hex_utils::hex_str(&payment_hash.0));
-		println!("\t\thtlc_direction: inbound,");
-		println!(
-			"\t\thtlc_status: {},",
-			match payment_info.status {
-				HTLCStatus::Pending => "pending",
-				HTLCStatus::Succeeded => "succeeded",
-				HTLCStatus::Failed => "failed",
-			}
-		);
+		if let Some(preimage) = payment_info.preimage {
+			println!("\t\tpayment_preimage: {},", hex_utils::hex_str(&preimage.0));
+		}
+		println!("\t\thtlc_direction: {},", direction_str(direction));
+		println!("\t\thtlc_status: {},", htlc_status_str(&payment_info.status));
+		if let Some(fee_paid_msat) = payment_info.fee_paid_msat {
+			println!("\t\tfee_paid_msat: {},", fee_paid_msat);
+		}
+		println!("\t\tcreated_at: {},", payment_info.created_at);
+		if let Some(settled_at) = payment_info.settled_at {
+			println!("\t\tsettled_at: {},", settled_at);
+		}
 
 		println!("\t}},");
 	}
+	println!("]");
+}
 
-	for (payment_hash, payment_info) in outbound.deref() {
+/// Whether the invoice we created for a payment can still be paid. Canceled invoices are the ones
+/// whose payments we failed back.
+fn invoice_state_str(payment_info: &PaymentInfo, invoice: &Invoice) -> &'static str {
+	match payment_info.status {
+		HTLCStatus::Succeeded => "settled",
+		HTLCStatus::Failed => "canceled",
+		HTLCStatus::Held => "held",
+		HTLCStatus::Pending if invoice.is_expired() => "expired",
+		HTLCStatus::Pending => "open",
+	}
+}
+
+/// The invoices we created, oldest first.
+pub(crate) fn invoices_json(inbound_payments: &PaymentInfoStorage) -> serde_json::Value {
+	let inbound = inbound_payments.lock().unwrap();
+	let mut invoices = inbound
+		.iter()
+		.filter_map(|(payment_hash, payment_info)| {
+			let invoice = Invoice::from_str(payment_info.invoice.as_ref()?).ok()?;
+			let description = match invoice.description() {
+				InvoiceDescription::Direct(description) => Some(description.clone().into_inner()),
+				InvoiceDescription::Hash(_) => None,
+			};
+			Some(json!({
+				"payment_hash": hex_utils::hex_str(&payment_hash.0),
+				"amount_msat": payment_info.amt_msat.0,
+				"state": invoice_state_str(payment_info, &invoice),
+				"description": description,
+				"created_at": payment_info.created_at,
+				"expires_at": invoice.duration_since_epoch().as_secs() + invoice.expiry_time().as_secs(),
+				"settled_at": payment_info.settled_at,
+				"invoice": invoice.to_string(),
+			}))
+		})
+		.collect::<Vec<_>>();
+	invoices.sort_by_key(|invoice| invoice["created_at"].as_u64());
+	json!(invoices)
+}
+
+fn list_invoices(inbound_payments: &PaymentInfoStorage, format: OutputFormat) {
+	let invoices = invoices_json(inbound_payments);
+	if format == OutputFormat::Json {
+		print_json(&invoices);
+		return;
+	}
+	print!("[");
+	for invoice in invoices.as_array().unwrap() {
 		println!("");
 		println!("\t{{");
-		println!("\t\tamount_millisatoshis: {},", payment_info.amt_msat);
-		println!("\t\tpayment_hash: {},", hex_utils::hex_str(&payment_hash.0));
-		println!("\t\thtlc_direction: outbound,");
-		println!(
-			"\t\thtlc_status: {},",
-			match payment_info.status {
-				HTLCStatus::Pending => "pending",
-				HTLCStatus::Succeeded => "succeeded",
-				HTLCStatus::Failed => "failed",
+		println!("\t\tpayment_hash: {},", invoice["payment_hash"].as_str().unwrap());
+		println!("\t\tamount_msat: {},", invoice["amount_msat"]);
+		println!("\t\tstate: {},", invoice["state"].as_str().unwrap());
+		if let Some(description) = invoice["description"].as_str() {
+			println!("\t\tdescription: {},", description);
+		}
+		println!("\t\tcreated_at: {},", invoice["created_at"]);
+		println!("\t\texpires_at: {},", invoice["expires_at"]);
+		if let Some(settled_at) = invoice["settled_at"].as_u64() {
+			println!("\t\tsettled_at: {},", settled_at);
+		}
+		println!("\t\tinvoice: {},", invoice["invoice"].as_str().unwrap());
+		println!("\t}},");
+	}
+	println!("]");
+}
+
+/// Which forwards `listforwards` shows, newest first.
+#[derive(Default)]
+pub(crate) struct ForwardFilter {
+	since: Option<u64>,
+	until: Option<u64>,
+	limit: Option<usize>,
+}
+
+impl ForwardFilter {
+	/// Parses `[--since=<unix_secs>] [--until=<unix_secs>] [--limit=<n>]`.
+	pub(crate) fn from_args<'a, I: Iterator<Item = &'a str>>(args: I) -> Result<Self, String> {
+		let mut filter = ForwardFilter::default();
+		for arg in args {
+			let mut arg_parts = arg.splitn(2, '=');
+			match (arg_parts.next(), arg_parts.next()) {
+				(Some("--since"), Some(since)) => {
+					filter.since = Some(
+						since
+							.parse()
+							.map_err(|_| "--since must be a UNIX timestamp".to_string())?,
+					)
+				}
+				(Some("--until"), Some(until)) => {
+					filter.until = Some(
+						until
+							.parse()
+							.map_err(|_| "--until must be a UNIX timestamp".to_string())?,
+					)
+				}
+				(Some("--limit"), Some(limit)) => {
+					filter.limit =
+						Some(limit.parse().map_err(|_| "--limit must be a number".to_string())?)
+				}
+				_ => return Err(format!("unknown listforwards argument {}", arg)),
 			}
-		);
+		}
+		Ok(filter)
+	}
+
+	fn matches(&self, forward: &ForwardInfo) -> bool {
+		self.since.map_or(true, |since| forward.forwarded_at >= since)
+			&& self.until.map_or(true, |until| forward.forwarded_at < until)
+	}
+}
+
+/// Lists the matching forwards along with totals over all of them, regardless of `--limit`.
+pub(crate) fn forwards_json(
+	forwarding_history: &ForwardingHistoryStorage, filter: &ForwardFilter,
+) -> serde_json::Value {
+	let history = forwarding_history.lock().unwrap();
+	let matching = history.0.iter().rev().filter(|forward| filter.matches(forward));
+	let forwards = matching
+		.clone()
+		.take(filter.limit.unwrap_or(usize::MAX))
+		.map(|forward| {
+			json!({
+				"prev_channel_id": forward.prev_channel_id.map(|id| hex_utils::hex_str(&id)),
+				"next_channel_id": forward.next_channel_id.map(|id| hex_utils::hex_str(&id)),
+				"fee_earned_msat": forward.fee_earned_msat,
+				"claim_from_onchain_tx": forward.claim_from_onchain_tx,
+				"forwarded_at": forward.forwarded_at,
+			})
+		})
+		.collect::<Vec<_>>();
+	json!({
+		"forwards": forwards,
+		"num_forwards": matching.clone().count(),
+		"total_fee_earned_msat": matching.filter_map(|forward| forward.fee_earned_msat).sum::<u64>(),
+	})
+}
+
+fn list_forwards(
+	forwarding_history: &ForwardingHistoryStorage, filter: &ForwardFilter, format: OutputFormat,
+) {
+	let forwards = forwards_json(forwarding_history, filter);
+	if format == OutputFormat::Json {
+		print_json(&forwards);
+		return;
+	}
+	print!("[");
+	for forward in forwards["forwards"].as_array().unwrap() {
+		println!("");
+		println!("\t{{");
+		if let Some(channel_id) = forward["prev_channel_id"].as_str() {
+			println!("\t\tprev_channel_id: {},", channel_id);
+		}
+		if let Some(channel_id) = forward["next_channel_id"].as_str() {
+			println!("\t\tnext_channel_id: {},", channel_id);
+		}
+		if let Some(fee_earned_msat) = forward["fee_earned_msat"].as_u64() {
+			println!("\t\tfee_earned_msat: {},", fee_earned_msat);
+		}
+		println!("\t\tclaim_from_onchain_tx: {},", forward["claim_from_onchain_tx"]);
+		println!("\t\tforwarded_at: {},", forward["forwarded_at"]);
+		println!("\t}},");
+	}
+	println!("]");
+	println!("num_forwards: {}", forwards["num_forwards"]);
+	println!("total_fee_earned_msat: {}", forwards["total_fee_earned_msat"]);
+}
+
+pub(crate) fn invoice_json(invoice: &Invoice) -> serde_json::Value {
+	let (description, description_hash) = match invoice.description() {
+		InvoiceDescription::Direct(description) => (Some(description.clone().into_inner()), None),
+		InvoiceDescription::Hash(hash) => (None, Some(hex_utils::hex_str(&hash.0.into_inner()))),
+	};
+	let route_hints = invoice
+		.route_hints()
+		.iter()
+		.map(|hint| {
+			let hops = hint
+				.0
+				.iter()
+				.map(|hop| {
+					json!({
+						"src_node_id": hop.src_node_id.to_string(),
+						"short_channel_id": hop.short_channel_id,
+						"fee_base_msat": hop.fees.base_msat,
+						"fee_proportional_millionths": hop.fees.proportional_millionths,
+						"cltv_expiry_delta": hop.cltv_expiry_delta,
+					})
+				})
+				.collect::<Vec<_>>();
+			json!(hops)
+		})
+		.collect::<Vec<_>>();
+	json!({
+		"payee_pubkey": invoice.recover_payee_pub_key().to_string(),
+		"payment_hash": hex_utils::hex_str(&invoice.payment_hash().into_inner()),
+		"amount_msat": invoice.amount_milli_satoshis(),
+		"description": description,
+		"description_hash": description_hash,
+		"timestamp": invoice.duration_since_epoch().as_secs(),
+		"expiry_secs": invoice.expiry_time().as_secs(),
+		"is_expired": invoice.is_expired(),
+		"min_final_cltv_expiry_delta": invoice.min_final_cltv_expiry_delta(),
+		"features": invoice.features().map(|features| format!("{:?}", features)),
+		"route_hints": route_hints,
+	})
+}
+
+fn decode_invoice(invoice: &Invoice, format: OutputFormat) {
+	let decoded = invoice_json(invoice);
+	if format == OutputFormat::Json {
+		print_json(&decoded);
+		return;
+	}
+	println!("\t{{");
+	for (field, value) in decoded.as_object().unwrap() {
+		match value {
+			serde_json::Value::Null => {}
+			serde_json::Value::String(value) => println!("\t\t {}: {}", field, value),
+			serde_json::Value::Array(route_hints) => {
+				println!("\t\t {}: [", field);
+				for hops in route_hints {
+					println!("\t\t\t [");
+					for hop in hops.as_array().unwrap() {
+						println!("\t\t\t\t {},", hop);
+					}
+					println!("\t\t\t ],");
+				}
+				println!("\t\t ]");
+			}
+			value => println!("\t\t {}: {}", field, value),
+		}
+	}
+	println!("\t}},");
+}
+
+fn net_address_str(address: &NetAddress) -> String {
+	match address {
+		NetAddress::IPv4 { addr, port } => SocketAddr::from((*addr, *port)).to_string(),
+		NetAddress::IPv6 { addr, port } => SocketAddr::from((*addr, *port)).to_string(),
+		NetAddress::OnionV3 { ed25519_pubkey, checksum, version, port } => {
+			format!("{}:{}", tor::onion_v3_host(ed25519_pubkey, *checksum, *version), port)
+		}
+		_ => format!("{:?}", address),
+	}
+}
+
+fn channel_policy_json(update: &Option<ChannelUpdateInfo>) -> serde_json::Value {
+	match update {
+		Some(update) => json!({
+			"enabled": update.enabled,
+			"last_update": update.last_update,
+			"cltv_expiry_delta": update.cltv_expiry_delta,
+			"htlc_minimum_msat": update.htlc_minimum_msat,
+			"htlc_maximum_msat": update.htlc_maximum_msat,
+			"fee_base_msat": update.fees.base_msat,
+			"fee_proportional_millionths": update.fees.proportional_millionths,
+		}),
+		None => serde_json::Value::Null,
+	}
+}
+
+fn graph_channel_json(short_channel_id: u64, channel: &ChannelInfo) -> serde_json::Value {
+	json!({
+		"short_channel_id": short_channel_id,
+		"node_one": channel.node_one.to_string(),
+		"node_two": channel.node_two.to_string(),
+		"capacity_sats": channel.capacity_sats,
+		"node_one_policy": channel_policy_json(&channel.one_to_two),
+		"node_two_policy": channel_policy_json(&channel.two_to_one),
+	})
+}
+
+fn graph_node_json(node_id: &NodeId, node: &NodeInfo) -> serde_json::Value {
+	let announcement = node.announcement_info.as_ref();
+	json!({
+		"node_id": node_id.to_string(),
+		"alias": announcement.map(|a| a.alias.to_string()),
+		"rgb": announcement.map(|a| hex_utils::hex_str(&a.rgb)),
+		"addresses": announcement
+			.map(|a| a.addresses.iter().map(net_address_str).collect::<Vec<_>>())
+			.unwrap_or_default(),
+		"features": announcement.map(|a| format!("{:?}", a.features)),
+		"last_update": announcement.map(|a| a.last_update),
+		"channels": node.channels,
+	})
+}
+
+/// Dumps all nodes and channels (including both directions' policies) we know of.
+pub(crate) fn graph_json(network_graph: &Arc<NetworkGraph>) -> serde_json::Value {
+	let graph = network_graph.read_only();
+	let nodes = graph
+		.nodes()
+		.unordered_iter()
+		.map(|(node_id, node)| graph_node_json(node_id, node))
+		.collect::<Vec<_>>();
+	let channels = graph
+		.channels()
+		.unordered_iter()
+		.map(|(scid, channel)| graph_channel_json(*scid, channel))
+		.collect::<Vec<_>>();
+	json!({ "nodes": nodes, "channels": channels })
+}
+
+/// Looks a node up in the network graph, along with all of its known channels.
+pub(crate) fn node_graph_json(
+	node_pubkey: &PublicKey, network_graph: &Arc<NetworkGraph>,
+) -> Option<serde_json::Value> {
+	let graph = network_graph.read_only();
+	let node_id = NodeId::from_pubkey(node_pubkey);
+	let node = graph.nodes().get(&node_id)?;
+	let mut node_json = graph_node_json(&node_id, node);
+	node_json["channels"] = json!(node
+		.channels
+		.iter()
+		.filter_map(|scid| graph.channels().get(scid).map(|chan| graph_channel_json(*scid, chan)))
+		.collect::<Vec<_>>());
+	Some(node_json)
+}
+
+/// Dumps every known channel policy as a row of a CSV edge list, from the node it's announced by
+/// to the other one, the way graph analysis tools import directed graphs.
+fn graph_csv(network_graph: &Arc<NetworkGraph>) -> String {
+	let mut csv = "source,target,short_channel_id,capacity_sats,enabled,last_update,\
+		cltv_expiry_delta,htlc_minimum_msat,htlc_maximum_msat,fee_base_msat,\
+		fee_proportional_millionths\n"
+		.to_string();
+	let graph = network_graph.read_only();
+	for (short_channel_id, channel) in graph.channels().unordered_iter() {
+		let directions = [
+			(&channel.node_one, &channel.node_two, &channel.one_to_two),
+			(&channel.node_two, &channel.node_one, &channel.two_to_one),
+		];
+		for (source, target, update) in directions.iter() {
+			if let Some(update) = update {
+				csv.push_str(&format!(
+					"{},{},{},{},{},{},{},{},{},{},{}\n",
+					source,
+					target,
+					short_channel_id,
+					channel.capacity_sats.map(|sats| sats.to_string()).unwrap_or_default(),
+					update.enabled,
+					update.last_update,
+					update.cltv_expiry_delta,
+					update.htlc_minimum_msat,
+					update.htlc_maximum_msat,
+					update.fees.base_msat,
+					update.fees.proportional_millionths
+				));
+			}
+		}
+	}
+	csv
+}
+
+/// Adds the channels and policies of a graph dumped by `describegraph` to our network graph,
+/// returning how many of each were new to it. Nothing is checked against the chain: channels are
+/// added without their capacity, like Rapid Gossip Sync does, and the graph rejects policies older
+/// than the ones it has.
+pub(crate) fn import_graph(
+	network_graph: &NetworkGraph, graph: &serde_json::Value, network: Network,
+) -> Result<(usize, usize), String> {
+	let channels = graph["channels"].as_array().ok_or("the graph has no `channels` array")?;
+	let chain_hash = genesis_block(network).header.block_hash();
+	let now = time_now_secs();
+	let (mut num_channels, mut num_updates) = (0, 0);
+	for channel in channels {
+		let short_channel_id = channel["short_channel_id"].as_u64();
+		let node_one = channel["node_one"].as_str().and_then(hex_utils::to_compressed_pubkey);
+		let node_two = channel["node_two"].as_str().and_then(hex_utils::to_compressed_pubkey);
+		let (short_channel_id, node_one, node_two) = match (short_channel_id, node_one, node_two) {
+			(Some(short_channel_id), Some(node_one), Some(node_two)) => {
+				(short_channel_id, node_one, node_two)
+			}
+			_ => return Err(format!("malformed channel {}", channel)),
+		};
+		if network_graph
+			.add_channel_from_partial_announcement(
+				short_channel_id,
+				now,
+				ChannelFeatures::empty(),
+				node_one,
+				node_two,
+			)
+			.is_ok()
+		{
+			num_channels += 1;
+		}
+		for (direction, name) in [(0, "node_one_policy"), (1, "node_two_policy")].iter() {
+			let policy = &channel[*name];
+			if policy.is_null() {
+				continue;
+			}
+			let field = |field: &str| {
+				policy[field].as_u64().ok_or_else(|| {
+					format!("{} of channel {} has no {}", name, short_channel_id, field)
+				})
+			};
+			let enabled = policy["enabled"].as_bool().unwrap_or(true);
+			let update = UnsignedChannelUpdate {
+				chain_hash,
+				short_channel_id,
+				timestamp: field("last_update")? as u32,
+				flags: if enabled { *direction } else { *direction | 0b10 },
+				cltv_expiry_delta: field("cltv_expiry_delta")? as u16,
+				htlc_minimum_msat: field("htlc_minimum_msat")?,
+				htlc_maximum_msat: field("htlc_maximum_msat")?,
+				fee_base_msat: field("fee_base_msat")? as u32,
+				fee_proportional_millionths: field("fee_proportional_millionths")? as u32,
+				excess_data: Vec::new(),
+			};
+			if network_graph.update_channel_unsigned(&update).is_ok() {
+				num_updates += 1;
+			}
+		}
+	}
+	Ok((num_channels, num_updates))
+}
+
+fn median(mut values: Vec<u64>) -> Option<u64> {
+	values.sort_unstable();
+	values.get(values.len() / 2).copied()
+}
 
+/// Summarizes the network graph, and where our node sits in it: how many nodes have more channels
+/// than it, and how many channels away it is from the nodes it can reach.
+pub(crate) fn graph_stats_json(
+	network_graph: &NetworkGraph, our_node_pubkey: &PublicKey,
+) -> serde_json::Value {
+	let graph = network_graph.read_only();
+	let mut capacities = Vec::new();
+	let mut fee_base_msats = Vec::new();
+	let mut fee_proportional_millionths = Vec::new();
+	let mut cltv_expiry_deltas = Vec::new();
+	for (_, channel) in graph.channels().unordered_iter() {
+		capacities.extend(channel.capacity_sats);
+		for update in [&channel.one_to_two, &channel.two_to_one].iter().filter_map(|u| u.as_ref()) {
+			fee_base_msats.push(update.fees.base_msat as u64);
+			fee_proportional_millionths.push(update.fees.proportional_millionths as u64);
+			cltv_expiry_deltas.push(update.cltv_expiry_delta as u64);
+		}
+	}
+	let num_nodes = graph.nodes().unordered_iter().count();
+	let num_channels = graph.channels().unordered_iter().count();
+
+	let our_node_id = NodeId::from_pubkey(our_node_pubkey);
+	let our_channels = graph.nodes().get(&our_node_id).map_or(0, |node| node.channels.len());
+	let nodes_with_more_channels = graph
+		.nodes()
+		.unordered_iter()
+		.filter(|(_, node)| node.channels.len() > our_channels)
+		.count();
+	// A breadth-first search gives the fewest channels to each node we can reach.
+	let mut hops: HashMap<NodeId, usize> = HashMap::new();
+	hops.insert(our_node_id, 0);
+	let mut queue = VecDeque::new();
+	queue.push_back(our_node_id);
+	while let Some(node_id) = queue.pop_front() {
+		let distance = hops[&node_id];
+		let node = match graph.nodes().get(&node_id) {
+			Some(node) => node,
+			None => continue,
+		};
+		for short_channel_id in node.channels.iter() {
+			if let Some(channel) = graph.channels().get(short_channel_id) {
+				let peer =
+					if channel.node_one == node_id { channel.node_two } else { channel.node_one };
+				if !hops.contains_key(&peer) {
+					hops.insert(peer, distance + 1);
+					queue.push_back(peer);
+				}
+			}
+		}
+	}
+	let reachable_nodes = hops.len() - 1;
+	let total_hops: usize = hops.values().sum();
+	let other_nodes = num_nodes.saturating_sub(1).max(1) as f64;
+	// Closeness as Wasserman and Faust define it for graphs that aren't fully connected.
+	let closeness_centrality = if total_hops == 0 {
+		0.0
+	} else {
+		(reachable_nodes as f64 / other_nodes) * (reachable_nodes as f64 / total_hops as f64)
+	};
+	let average_hops =
+		if reachable_nodes == 0 { None } else { Some(total_hops as f64 / reachable_nodes as f64) };
+	json!({
+		"num_nodes": num_nodes,
+		"num_channels": num_channels,
+		"total_capacity_sats": capacities.iter().sum::<u64>(),
+		"median_capacity_sats": median(capacities),
+		"median_fee_base_msat": median(fee_base_msats),
+		"median_fee_proportional_millionths": median(fee_proportional_millionths),
+		"median_cltv_expiry_delta": median(cltv_expiry_deltas),
+		"our_node": {
+			"num_channels": our_channels,
+			"nodes_with_more_channels": nodes_with_more_channels,
+			"degree_centrality": our_channels as f64 / other_nodes,
+			"reachable_nodes": reachable_nodes,
+			"average_hops": average_hops,
+			"closeness_centrality": closeness_centrality,
+		},
+	})
+}
+
+fn graph_stats(network_graph: &NetworkGraph, our_node_pubkey: &PublicKey, format: OutputFormat) {
+	let stats = graph_stats_json(network_graph, our_node_pubkey);
+	if format == OutputFormat::Json {
+		print_json(&stats);
+		return;
+	}
+	println!("{{");
+	for (name, value) in stats.as_object().unwrap() {
+		if let Some(our_node) = value.as_object() {
+			println!("	{}: {{", name);
+			for (name, value) in our_node {
+				println!("		{}: {},", name, value);
+			}
+			println!("	}},");
+		} else {
+			println!("	{}: {},", name, value);
+		}
+	}
+	println!("}}");
+}
+
+fn get_node_info(node_pubkey: PublicKey, network_graph: &Arc<NetworkGraph>, format: OutputFormat) {
+	let node = match node_graph_json(&node_pubkey, network_graph) {
+		Some(node) => node,
+		None => {
+			println!("ERROR: node {} is not in our network graph", node_pubkey);
+			return;
+		}
+	};
+	if format == OutputFormat::Json {
+		print_json(&node);
+		return;
+	}
+	println!("\t{{");
+	println!("\t\t node_id: {}", node_pubkey);
+	if let Some(alias) = node["alias"].as_str() {
+		println!("\t\t alias: {}", alias);
+	}
+	for address in node["addresses"].as_array().unwrap() {
+		println!("\t\t address: {}", address.as_str().unwrap());
+	}
+	if let Some(features) = node["features"].as_str() {
+		println!("\t\t features: {}", features);
+	}
+	println!("\t\t channels: [");
+	for channel in node["channels"].as_array().unwrap() {
+		println!("\t\t\t {},", channel);
+	}
+	println!("\t\t ]");
+	println!("\t}},");
+}
+
+/// Runs the router as if we were about to pay `amt_msat` to `dest_pubkey`, without sending anything.
+fn find_route_to<E: EntropySource>(
+	dest_pubkey: PublicKey, amt_msat: u64, channel_manager: &Arc<ChannelManager>,
+	network_graph: &Arc<NetworkGraph>, scorer: &Arc<Mutex<Scorer>>, entropy_source: &E,
+	logger: Arc<disk::FilesystemLogger>,
+) -> Result<Route, String> {
+	let route_params = RouteParameters {
+		payment_params: PaymentParameters::from_node_id(dest_pubkey, 40),
+		final_value_msat: amt_msat,
+	};
+	let first_hops = channel_manager.list_usable_channels();
+	let first_hop_refs = first_hops.iter().collect::<Vec<_>>();
+	find_route(
+		&channel_manager.get_our_node_id(),
+		&route_params,
+		&**network_graph,
+		Some(&first_hop_refs[..]),
+		logger,
+		&*scorer.lock().unwrap(),
+		&entropy_source.get_secure_random_bytes(),
+	)
+	.map_err(|e| e.err)
+}
+
+pub(crate) fn route_json<E: EntropySource>(
+	dest_pubkey: PublicKey, amt_msat: u64, channel_manager: &Arc<ChannelManager>,
+	network_graph: &Arc<NetworkGraph>, scorer: &Arc<Mutex<Scorer>>, entropy_source: &E,
+	logger: Arc<disk::FilesystemLogger>,
+) -> Result<serde_json::Value, String> {
+	let route = find_route_to(
+		dest_pubkey,
+		amt_msat,
+		channel_manager,
+		network_graph,
+		scorer,
+		entropy_source,
+		logger,
+	)?;
+
+	let paths = route
+		.paths
+		.iter()
+		.map(|path| {
+			let hops = path
+				.iter()
+				.map(|hop| {
+					json!({
+						"pubkey": hop.pubkey.to_string(),
+						"short_channel_id": hop.short_channel_id,
+						"fee_msat": hop.fee_msat,
+						"cltv_expiry_delta": hop.cltv_expiry_delta,
+					})
+				})
+				.collect::<Vec<_>>();
+			// The last hop's "fee" is the amount delivered to the destination.
+			let (last_hop, forwarding_hops) = path.split_last().unwrap();
+			json!({
+				"amount_msat": last_hop.fee_msat,
+				"total_fees_msat": forwarding_hops.iter().map(|hop| hop.fee_msat).sum::<u64>(),
+				"total_cltv_expiry_delta": path.iter().map(|hop| hop.cltv_expiry_delta).sum::<u32>(),
+				"hops": hops,
+			})
+		})
+		.collect::<Vec<_>>();
+	Ok(json!({
+		"total_amount_msat": route.get_total_amount(),
+		"total_fees_msat": route.get_total_fees(),
+		"paths": paths,
+	}))
+}
+
+/// Sends probes along the route we'd use to pay `amt_msat` to `dest_pubkey`, returning how many
+/// were sent. The results come back as `ProbeSuccessful`/`ProbeFailed` events, which the
+/// background processor feeds into the scorer.
+pub(crate) fn send_probes<E: EntropySource>(
+	dest_pubkey: PublicKey, amt_msat: u64, channel_manager: &Arc<ChannelManager>,
+	network_graph: &Arc<NetworkGraph>, scorer: &Arc<Mutex<Scorer>>, entropy_source: &E,
+	logger: Arc<disk::FilesystemLogger>,
+) -> Result<usize, String> {
+	let route = find_route_to(
+		dest_pubkey,
+		amt_msat,
+		channel_manager,
+		network_graph,
+		scorer,
+		entropy_source,
+		logger,
+	)
+	.map_err(|e| format!("failed to find a route to probe: {}", e))?;
+	let mut num_sent = 0;
+	let mut last_err = None;
+	for path in route.paths {
+		match channel_manager.send_probe(path) {
+			Ok(_) => num_sent += 1,
+			Err(e) => last_err = Some(format!("failed to send probe: {:?}", e)),
+		}
+	}
+	match last_err {
+		Some(e) if num_sent == 0 => Err(e),
+		_ => Ok(num_sent),
+	}
+}
+
+/// What we know about each channel we probed: our own success counts, and the liquidity range
+/// the scorer now estimates for it.
+pub(crate) fn probe_stats_json(
+	probe_stats: &ProbeStatsStorage, scorer: &Arc<Mutex<Scorer>>,
+) -> serde_json::Value {
+	let probe_stats = probe_stats.lock().unwrap();
+	let scorer = scorer.lock().unwrap();
+	let mut channels = probe_stats.iter().collect::<Vec<_>>();
+	channels.sort_by_key(|((short_channel_id, _), _)| *short_channel_id);
+	let channels = channels
+		.into_iter()
+		.map(|((short_channel_id, target), stats)| {
+			let liquidity = scorer
+				.estimated_channel_liquidity_range(*short_channel_id, &NodeId::from_pubkey(target));
+			json!({
+				"short_channel_id": short_channel_id,
+				"target": target.to_string(),
+				"successes": stats.successes,
+				"failures": stats.failures,
+				"last_probed_at": stats.last_probed_at,
+				"liquidity_min_msat": liquidity.map(|(min, _)| min),
+				"liquidity_max_msat": liquidity.map(|(_, max)| max),
+			})
+		})
+		.collect::<Vec<_>>();
+	json!(channels)
+}
+
+fn list_probe_stats(
+	probe_stats: &ProbeStatsStorage, scorer: &Arc<Mutex<Scorer>>, format: OutputFormat,
+) {
+	let stats = probe_stats_json(probe_stats, scorer);
+	if format == OutputFormat::Json {
+		print_json(&stats);
+		return;
+	}
+	print!("[");
+	for channel in stats.as_array().unwrap() {
+		println!("");
+		println!("\t{{");
+		println!("\t\tshort_channel_id: {},", channel["short_channel_id"]);
+		println!("\t\ttarget: {},", channel["target"].as_str().unwrap());
+		println!("\t\tsuccesses: {},", channel["successes"]);
+		println!("\t\tfailures: {},", channel["failures"]);
+		println!("\t\tlast_probed_at: {},", channel["last_probed_at"]);
+		if let (Some(min), Some(max)) =
+			(channel["liquidity_min_msat"].as_u64(), channel["liquidity_max_msat"].as_u64())
+		{
+			println!("\t\testimated_liquidity_msat: {}-{},", min, max);
+		}
 		println!("\t}},");
 	}
 	println!("]");
 }
 
+fn scoring_params_json(params: &ProbabilisticScoringParameters) -> serde_json::Value {
+	json!({
+		"base_penalty_msat": params.base_penalty_msat,
+		"base_penalty_amount_multiplier_msat": params.base_penalty_amount_multiplier_msat,
+		"liquidity_penalty_multiplier_msat": params.liquidity_penalty_multiplier_msat,
+		"liquidity_penalty_amount_multiplier_msat": params.liquidity_penalty_amount_multiplier_msat,
+		"historical_liquidity_penalty_multiplier_msat":
+			params.historical_liquidity_penalty_multiplier_msat,
+		"historical_liquidity_penalty_amount_multiplier_msat":
+			params.historical_liquidity_penalty_amount_multiplier_msat,
+		"anti_probing_penalty_msat": params.anti_probing_penalty_msat,
+		"liquidity_offset_half_life_secs": params.liquidity_offset_half_life.as_secs(),
+		"historical_no_updates_half_life_secs": params.historical_no_updates_half_life.as_secs(),
+	})
+}
+
+/// The scorer's parameters, and the liquidity range it has learned for each channel, in the
+/// direction towards `target`.
+pub(crate) fn scorer_info_json(
+	scorer: &Arc<Mutex<Scorer>>, network_graph: &Arc<NetworkGraph>,
+	params: &ProbabilisticScoringParameters,
+) -> serde_json::Value {
+	// The scorer reads the graph itself, so it isn't kept locked while asking the scorer.
+	let mut directions = network_graph
+		.read_only()
+		.channels()
+		.unordered_iter()
+		.flat_map(|(short_channel_id, channel)| {
+			vec![(*short_channel_id, channel.node_two), (*short_channel_id, channel.node_one)]
+		})
+		.collect::<Vec<_>>();
+	directions.sort();
+	let scorer = scorer.lock().unwrap();
+	let channels = directions
+		.into_iter()
+		.filter_map(|(short_channel_id, target)| {
+			let (min, max) = scorer.estimated_channel_liquidity_range(short_channel_id, &target)?;
+			Some(json!({
+				"short_channel_id": short_channel_id,
+				"target": target.to_string(),
+				"liquidity_min_msat": min,
+				"liquidity_max_msat": max,
+			}))
+		})
+		.collect::<Vec<_>>();
+	json!({ "params": scoring_params_json(params), "channels": channels })
+}
+
+fn scorer_info(
+	scorer: &Arc<Mutex<Scorer>>, network_graph: &Arc<NetworkGraph>,
+	params: &ProbabilisticScoringParameters, format: OutputFormat,
+) {
+	let info = scorer_info_json(scorer, network_graph, params);
+	if format == OutputFormat::Json {
+		print_json(&info);
+		return;
+	}
+	println!("{{");
+	println!("	params: {{");
+	for (name, value) in info["params"].as_object().unwrap() {
+		println!("		{}: {},", name, value);
+	}
+	println!("	}},");
+	print!("	channels: [");
+	for channel in info["channels"].as_array().unwrap() {
+		println!("");
+		println!(
+			"		{} towards {}: {}-{} msat,",
+			channel["short_channel_id"],
+			channel["target"].as_str().unwrap(),
+			channel["liquidity_min_msat"],
+			channel["liquidity_max_msat"]
+		);
+	}
+	println!("	],");
+	println!("}}");
+}
+
+/// Replaces the scorer with a new one that knows nothing about channel liquidity, and persists
+/// it right away so it stays that way across a restart.
+pub(crate) fn reset_scorer(
+	scorer: &Arc<Mutex<Scorer>>, network_graph: &Arc<NetworkGraph>,
+	logger: &Arc<disk::FilesystemLogger>, params: &ProbabilisticScoringParameters,
+	persister: &NodePersister,
+) -> Result<(), String> {
+	let mut scorer = scorer.lock().unwrap();
+	*scorer =
+		ProbabilisticScorer::new(params.clone(), Arc::clone(network_graph), Arc::clone(logger));
+	persister.persist_scorer(&scorer).map_err(|e| format!("failed to persist the scorer: {}", e))
+}
+
+fn query_routes<E: EntropySource>(
+	dest_pubkey: PublicKey, amt_msat: u64, channel_manager: &Arc<ChannelManager>,
+	network_graph: &Arc<NetworkGraph>, scorer: &Arc<Mutex<Scorer>>, entropy_source: &E,
+	logger: Arc<disk::FilesystemLogger>, format: OutputFormat,
+) {
+	let route = match route_json(
+		dest_pubkey,
+		amt_msat,
+		channel_manager,
+		network_graph,
+		scorer,
+		entropy_source,
+		logger,
+	) {
+		Ok(route) => route,
+		Err(e) => {
+			println!("ERROR: failed to find a route: {}", e);
+			return;
+		}
+	};
+	if format == OutputFormat::Json {
+		print_json(&route);
+		return;
+	}
+	println!("\t{{");
+	println!("\t\t total_amount_msat: {}", route["total_amount_msat"]);
+	println!("\t\t total_fees_msat: {}", route["total_fees_msat"]);
+	for path in route["paths"].as_array().unwrap() {
+		println!("\t\t path: {{");
+		println!("\t\t\t amount_msat: {}", path["amount_msat"]);
+		println!("\t\t\t total_fees_msat: {}", path["total_fees_msat"]);
+		println!("\t\t\t total_cltv_expiry_delta: {}", path["total_cltv_expiry_delta"]);
+		for hop in path["hops"].as_array().unwrap() {
+			println!(
+				"\t\t\t hop: {} via {} (fee_msat: {}, cltv_expiry_delta: {})",
+				hop["pubkey"].as_str().unwrap(),
+				hop["short_channel_id"],
+				hop["fee_msat"],
+				hop["cltv_expiry_delta"]
+			);
+		}
+		println!("\t\t }},");
+	}
+	println!("\t}},");
+}
+
 pub(crate) async fn connect_peer_if_necessary(
-	pubkey: PublicKey, peer_addr: SocketAddr, peer_manager: Arc<PeerManager>,
+	pubkey: PublicKey, peer_addr: PeerAddr, peer_manager: Arc<PeerManager>,
+	proxy: Option<SocketAddr>,
 ) -> Result<(), ()> {
 	for (node_pubkey, _) in peer_manager.get_peer_node_ids() {
 		if node_pubkey == pubkey {
 			return Ok(());
 		}
 	}
-	let res = do_connect_peer(pubkey, peer_addr, peer_manager).await;
+	let res = do_connect_peer(pubkey, peer_addr, peer_manager, proxy).await;
 	if res.is_err() {
 		println!("ERROR: failed to connect to peer");
 	}
 	res
 }
 
+/// Connects through the SOCKS5 proxy at `proxy` if given.
 pub(crate) async fn do_connect_peer(
-	pubkey: PublicKey, peer_addr: SocketAddr, peer_manager: Arc<PeerManager>,
+	pubkey: PublicKey, peer_addr: PeerAddr, peer_manager: Arc<PeerManager>,
+	proxy: Option<SocketAddr>,
 ) -> Result<(), ()> {
-	match lightning_net_tokio::connect_outbound(Arc::clone(&peer_manager), pubkey, peer_addr).await
-	{
-		Some(connection_closed_future) => {
-			let mut connection_closed_future = Box::pin(connection_closed_future);
-			loop {
-				match futures::poll!(&mut connection_closed_future) {
-					std::task::Poll::Ready(_) => {
-						return Err(());
-					}
-					std::task::Poll::Pending => {}
-				}
-				// Avoid blocking the tokio context by sleeping a bit
-				match peer_manager.get_peer_node_ids().iter().find(|(id, _)| *id == pubkey) {
-					Some(_) => return Ok(()),
-					None => tokio::time::sleep(Duration::from_millis(10)).await,
-				}
+	let stream = proxy::connect(&peer_addr, proxy).await.map_err(|_| ())?;
+	let mut connection_closed_future =
+		Box::pin(lightning_net_tokio::setup_outbound(Arc::clone(&peer_manager), pubkey, stream));
+	loop {
+		match futures::poll!(&mut connection_closed_future) {
+			std::task::Poll::Ready(_) => {
+				return Err(());
 			}
+			std::task::Poll::Pending => {}
+		}
+		// Avoid blocking the tokio context by sleeping a bit
+		match peer_manager.get_peer_node_ids().iter().find(|(id, _)| *id == pubkey) {
+			Some(_) => return Ok(()),
+			None => tokio::time::sleep(Duration::from_millis(10)).await,
 		}
-		None => Err(()),
 	}
 }
 
-fn do_disconnect_peer(
-	pubkey: bitcoin::secp256k1::PublicKey, peer_manager: Arc<PeerManager>,
+/// Unless `no_reconnect` is set, peers we have channels with are left alone, as we'd just
+/// reconnect to them right away.
+pub(crate) fn do_disconnect_peer(
+	pubkey: bitcoin::secp256k1::PublicKey, no_reconnect: bool, peer_manager: Arc<PeerManager>,
 	channel_manager: Arc<ChannelManager>,
 ) -> Result<(), ()> {
 	//check for open channels with peer
 	for channel in channel_manager.list_channels() {
-		if channel.counterparty.node_id == pubkey {
-			println!("Error: Node has an active channel with this peer, close any channels first");
+		if !no_reconnect && channel.counterparty.node_id == pubkey {
+			println!("Error: Node has an active channel with this peer, close any channels first or pass --no-reconnect");
 			return Err(());
 		}
 	}
@@ -632,55 +3321,411 @@ fn do_disconnect_peer(
 	Ok(())
 }
 
-fn open_channel(
-	peer_pubkey: PublicKey, channel_amt_sat: u64, announced_channel: bool,
-	channel_manager: Arc<ChannelManager>,
-) -> Result<(), ()> {
-	let config = UserConfig {
+/// Per-channel settings for `openchannel`, overriding the defaults of the node's `UserConfig`.
+#[derive(Default)]
+pub(crate) struct OpenChannelOptions {
+	pub(crate) announced_channel: bool,
+	pub(crate) push_msat: u64,
+	pub(crate) htlc_minimum_msat: Option<u64>,
+	pub(crate) cltv_expiry_delta: Option<u16>,
+	pub(crate) forwarding_fee_base_msat: Option<u32>,
+	pub(crate) forwarding_fee_proportional_millionths: Option<u32>,
+	/// Whether the user funds the channel with `fundchannel`, rather than our wallet.
+	pub(crate) external_funding: bool,
+}
+
+impl OpenChannelOptions {
+	/// Parses `[--public|--private] [--push-msat=<amt>] [--min-htlc-msat=<amt>]
+	/// [--cltv-expiry-delta=<blocks>] [--fee-base-msat=<amt>] [--fee-proportional-millionths=<rate>]
+	/// [--psbt]`.
+	pub(crate) fn from_args<'a, I: Iterator<Item = &'a str>>(args: I) -> Result<Self, String> {
+		fn parse_num<T: FromStr>(name: &str, value: &str) -> Result<T, String> {
+			value.parse().map_err(|_| format!("{} must be a number", name))
+		}
+
+		let mut options = OpenChannelOptions::default();
+		for arg in args {
+			let mut arg_parts = arg.splitn(2, '=');
+			match (arg_parts.next(), arg_parts.next()) {
+				(Some("--public"), None) | (Some("--public"), Some("true")) => {
+					options.announced_channel = true
+				}
+				(Some("--public"), Some("false")) | (Some("--private"), None) => {
+					options.announced_channel = false
+				}
+				(Some("--push-msat"), Some(amt)) => {
+					options.push_msat = parse_num("--push-msat", amt)?
+				}
+				(Some("--min-htlc-msat"), Some(amt)) => {
+					options.htlc_minimum_msat = Some(parse_num("--min-htlc-msat", amt)?)
+				}
+				(Some("--cltv-expiry-delta"), Some(delta)) => {
+					options.cltv_expiry_delta = Some(parse_num("--cltv-expiry-delta", delta)?)
+				}
+				(Some("--fee-base-msat"), Some(fee)) => {
+					options.forwarding_fee_base_msat = Some(parse_num("--fee-base-msat", fee)?)
+				}
+				(Some("--fee-proportional-millionths"), Some(fee)) => {
+					options.forwarding_fee_proportional_millionths =
+						Some(parse_num("--fee-proportional-millionths", fee)?)
+				}
+				(Some("--psbt"), None) => options.external_funding = true,
+				_ => return Err(format!("unknown openchannel argument {}", arg)),
+			}
+		}
+		Ok(options)
+	}
+}
+
+pub(crate) fn open_channel(
+	peer_pubkey: PublicKey, channel_amt_sat: u64, options: &OpenChannelOptions,
+	channel_manager: Arc<ChannelManager>, external_fundings: &ExternalFundingStorage,
+) -> Result<[u8; 32], ()> {
+	let mut config = UserConfig {
 		channel_handshake_limits: ChannelHandshakeLimits {
 			// lnd's max to_self_delay is 2016, so we want to be compatible.
 			their_to_self_delay: 2016,
 			..Default::default()
 		},
 		channel_handshake_config: ChannelHandshakeConfig {
-			announced_channel,
+			announced_channel: options.announced_channel,
 			..Default::default()
 		},
 		..Default::default()
 	};
+	if let Some(htlc_minimum_msat) = options.htlc_minimum_msat {
+		config.channel_handshake_config.our_htlc_minimum_msat = htlc_minimum_msat;
+	}
+	if let Some(cltv_expiry_delta) = options.cltv_expiry_delta {
+		config.channel_config.cltv_expiry_delta = cltv_expiry_delta;
+	}
+	if let Some(fee_base_msat) = options.forwarding_fee_base_msat {
+		config.channel_config.forwarding_fee_base_msat = fee_base_msat;
+	}
+	if let Some(fee_proportional_millionths) = options.forwarding_fee_proportional_millionths {
+		config.channel_config.forwarding_fee_proportional_millionths = fee_proportional_millionths;
+	}
 
-	match channel_manager.create_channel(peer_pubkey, channel_amt_sat, 0, 0, Some(config)) {
-		Ok(_) => {
+	// Held until the channel is recorded, so its funding output can't be generated before.
+	let mut external_fundings = external_fundings.lock().unwrap();
+	match channel_manager.create_channel(
+		peer_pubkey,
+		channel_amt_sat,
+		options.push_msat,
+		0,
+		Some(config),
+	) {
+		Ok(temporary_channel_id) => {
+			if options.external_funding {
+				external_fundings.insert(temporary_channel_id, None);
+			}
 			println!("EVENT: initiated channel with peer {}. ", peer_pubkey);
-			return Ok(());
+			return Ok(temporary_channel_id);
+		}
+		Err(e) => {
+			println!("ERROR: failed to open channel: {:?}", e);
+			return Err(());
+		}
+	}
+}
+
+/// How long `openchannel --psbt` waits for the peer to accept the channel.
+const EXTERNAL_FUNDING_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Waits for the funding output of a channel opened with `openchannel --psbt`. Channels the peer
+/// doesn't accept in time are dropped.
+pub(crate) async fn await_external_funding(
+	temporary_channel_id: [u8; 32], channel_manager: &ChannelManager,
+	external_fundings: &ExternalFundingStorage,
+) -> Result<ExternalFunding, String> {
+	let deadline = tokio::time::Instant::now() + EXTERNAL_FUNDING_TIMEOUT;
+	while tokio::time::Instant::now() < deadline {
+		match external_fundings.lock().unwrap().get(&temporary_channel_id) {
+			Some(Some(funding)) => return Ok(funding.clone()),
+			Some(None) => {}
+			None => return Err("the peer refused the channel".to_string()),
+		}
+		tokio::time::sleep(Duration::from_millis(100)).await;
+	}
+	let mut fundings = external_fundings.lock().unwrap();
+	if let Some(Some(funding)) = fundings.get(&temporary_channel_id) {
+		return Ok(funding.clone());
+	}
+	fundings.remove(&temporary_channel_id);
+	drop(fundings);
+	let channels = channel_manager.list_channels();
+	if let Some(chan) = channels.iter().find(|chan| chan.channel_id == temporary_channel_id) {
+		let _ = channel_manager.force_close_without_broadcasting_txn(
+			&temporary_channel_id,
+			&chan.counterparty.node_id,
+		);
+	}
+	Err("the peer didn't accept the channel in time".to_string())
+}
+
+pub(crate) fn external_funding_json(
+	temporary_channel_id: [u8; 32], funding: &ExternalFunding, network: Network,
+) -> serde_json::Value {
+	json!({
+		"temporary_channel_id": hex_utils::hex_str(&temporary_channel_id),
+		"output_script": hex_utils::hex_str(funding.output_script.as_bytes()),
+		"address": Address::from_script(&funding.output_script, network).map(|a| a.to_string()),
+		"amount_sat": funding.channel_value_satoshis,
+	})
+}
+
+fn print_external_funding(
+	temporary_channel_id: [u8; 32], funding: &ExternalFunding, network: Network,
+	format: OutputFormat,
+) {
+	let funding = external_funding_json(temporary_channel_id, funding, network);
+	if format == OutputFormat::Json {
+		print_json(&funding);
+		return;
+	}
+	println!(
+		"Fund the channel with a transaction paying {} sats to {} (output script {}), then pass it \
+		 to `fundchannel {} <psbt_base64|tx_hex>` once signed. Don't broadcast it yourself.",
+		funding["amount_sat"],
+		funding["address"].as_str().unwrap_or("no address"),
+		funding["output_script"].as_str().unwrap(),
+		funding["temporary_channel_id"].as_str().unwrap()
+	);
+}
+
+/// Reads a funding transaction given as a hex-encoded raw transaction or a finalized PSBT in
+/// base64.
+fn parse_funding_transaction(funding: &str) -> Result<Transaction, String> {
+	if let Some(bytes) = hex_utils::to_vec(funding) {
+		return encode::deserialize(&bytes).map_err(|e| format!("invalid transaction: {}", e));
+	}
+	let bytes = base64::decode(funding)
+		.map_err(|_| "expected a PSBT in base64 or a transaction in hex".to_string())?;
+	let psbt: PartiallySignedTransaction =
+		encode::deserialize(&bytes).map_err(|e| format!("invalid PSBT: {}", e))?;
+	if psbt
+		.inputs
+		.iter()
+		.any(|input| input.final_script_witness.is_none() && input.final_script_sig.is_none())
+	{
+		return Err("the PSBT isn't finalized".to_string());
+	}
+	Ok(psbt.extract_tx())
+}
+
+/// Completes the funding of a channel opened with `openchannel --psbt`, once the transaction
+/// pays its funding output exactly and can't be malleated. LDK broadcasts it when the peer has
+/// signed our first commitment transaction.
+pub(crate) fn fund_channel(
+	temporary_channel_id: [u8; 32], funding: &str, channel_manager: &ChannelManager,
+	external_fundings: &ExternalFundingStorage,
+) -> Result<Txid, String> {
+	let expected = match external_fundings.lock().unwrap().get(&temporary_channel_id) {
+		Some(Some(funding)) => funding.clone(),
+		Some(None) => return Err("the peer hasn't accepted the channel yet".to_string()),
+		None => return Err("no channel opened with --psbt awaits funding with this ID".to_string()),
+	};
+	let tx = parse_funding_transaction(funding)?;
+	let mut funding_outputs =
+		tx.output.iter().filter(|output| output.script_pubkey == expected.output_script);
+	match (funding_outputs.next(), funding_outputs.next()) {
+		(Some(output), None) if output.value == expected.channel_value_satoshis => {}
+		(Some(output), None) => {
+			return Err(format!(
+				"the funding output pays {} sats instead of {}",
+				output.value, expected.channel_value_satoshis
+			))
+		}
+		_ => return Err("the transaction must pay the funding output script once".to_string()),
+	}
+	if tx.input.iter().any(|input| input.witness.is_empty()) {
+		return Err("all inputs must be signed and spend segwit outputs".to_string());
+	}
+	let txid = tx.txid();
+	channel_manager
+		.funding_transaction_generated(&temporary_channel_id, &expected.counterparty_node_id, tx)
+		.map_err(|e| format!("failed to fund the channel: {:?}", e))?;
+	external_fundings.lock().unwrap().remove(&temporary_channel_id);
+	Ok(txid)
+}
+
+/// Parses `[--max-attempts=<n>|--retry-timeout-secs=<secs>]`, which `sendpayment` and `keysend`
+/// take to override the node's default retry strategy.
+pub(crate) fn retry_from_args<'a, I: Iterator<Item = &'a str>>(
+	args: I, default: Retry,
+) -> Result<Retry, String> {
+	let mut retry = default;
+	for arg in args {
+		if !parse_retry_arg(arg, &mut retry)? {
+			return Err(format!("unknown argument {}", arg));
+		}
+	}
+	Ok(retry)
+}
+
+fn parse_retry_arg(arg: &str, retry: &mut Retry) -> Result<bool, String> {
+	let mut arg_parts = arg.splitn(2, '=');
+	match (arg_parts.next(), arg_parts.next()) {
+		(Some("--max-attempts"), Some(attempts)) => {
+			*retry = Retry::Attempts(
+				attempts.parse().map_err(|_| "--max-attempts must be a number".to_string())?,
+			)
+		}
+		(Some("--retry-timeout-secs"), Some(secs)) => {
+			*retry = Retry::Timeout(Duration::from_secs(
+				secs.parse().map_err(|_| "--retry-timeout-secs must be a number".to_string())?,
+			))
+		}
+		_ => return Ok(false),
+	}
+	Ok(true)
+}
+
+/// Parses `--max-fee-sat=<sats>`, `--max-fee-percent=<percent>`, `--max-hops=<n>`,
+/// `--avoid-node=<pubkey>` and `--first-hop-channel=<channel_id>`, the last two repeatable.
+fn parse_route_arg(arg: &str, route: &mut RouteConstraints) -> Result<bool, String> {
+	let mut arg_parts = arg.splitn(2, '=');
+	match (arg_parts.next(), arg_parts.next()) {
+		(Some("--max-fee-sat"), Some(sats)) => {
+			let sats: u64 =
+				sats.parse().map_err(|_| "--max-fee-sat must be a number".to_string())?;
+			route.max_fee_msat = Some(sats.saturating_mul(1000));
+		}
+		(Some("--max-fee-percent"), Some(percent)) => match percent.parse::<f64>() {
+			Ok(percent) if percent >= 0.0 && percent.is_finite() => {
+				route.max_fee_percent = Some(percent)
+			}
+			_ => return Err("--max-fee-percent must be a non-negative number".to_string()),
+		},
+		(Some("--max-hops"), Some(hops)) => match hops.parse() {
+			Ok(hops) if hops > 0 => route.max_hops = Some(hops),
+			_ => return Err("--max-hops must be at least 1".to_string()),
+		},
+		(Some("--avoid-node"), Some(pubkey)) => match hex_utils::to_compressed_pubkey(pubkey) {
+			Some(pubkey) => route.avoid_nodes.push(pubkey),
+			None => return Err(format!("--avoid-node: couldn't parse pubkey {}", pubkey)),
+		},
+		(Some("--first-hop-channel"), Some(channel_id_str)) => {
+			match hex_utils::to_vec(channel_id_str) {
+				Some(channel_id_vec) if channel_id_vec.len() == 32 => {
+					let mut channel_id = [0; 32];
+					channel_id.copy_from_slice(&channel_id_vec);
+					route.first_hop_channels.push(channel_id);
+				}
+				_ => return Err(format!("--first-hop-channel: bad channel_id {}", channel_id_str)),
+			}
+		}
+		_ => return Ok(false),
+	}
+	Ok(true)
+}
+
+/// Per-payment overrides taken by `sendpayment`.
+pub(crate) struct PaymentOptions {
+	pub(crate) retry: Retry,
+	/// The maximum number of paths the payment may be split over, or `None` for LDK's default.
+	pub(crate) max_parts: Option<u8>,
+	pub(crate) route: RouteConstraints,
+}
+
+impl PaymentOptions {
+	pub(crate) fn new(
+		retry: Retry, max_parts: Option<u8>, no_mpp: bool, route: RouteConstraints,
+	) -> Result<Self, String> {
+		if max_parts == Some(0) {
+			return Err("--max-parts must be at least 1".to_string());
+		}
+		if no_mpp && max_parts.map_or(false, |parts| parts != 1) {
+			return Err("--no-mpp can't be combined with --max-parts".to_string());
+		}
+		Ok(Self { retry, max_parts: if no_mpp { Some(1) } else { max_parts }, route })
+	}
+
+	/// Parses the retry and route arguments plus `[--max-parts=<n>|--no-mpp]`.
+	pub(crate) fn from_args<'a, I: Iterator<Item = &'a str>>(
+		args: I, default_retry: Retry,
+	) -> Result<Self, String> {
+		let mut retry = default_retry;
+		let mut max_parts = None;
+		let mut no_mpp = false;
+		let mut route = RouteConstraints::default();
+		for arg in args {
+			if parse_retry_arg(arg, &mut retry)? || parse_route_arg(arg, &mut route)? {
+				continue;
+			}
+			let mut arg_parts = arg.splitn(2, '=');
+			match (arg_parts.next(), arg_parts.next()) {
+				(Some("--max-parts"), Some(parts)) => {
+					max_parts = Some(
+						parts
+							.parse()
+							.map_err(|_| "--max-parts must be between 1 and 255".to_string())?,
+					)
+				}
+				(Some("--no-mpp"), None) => no_mpp = true,
+				_ => return Err(format!("unknown argument {}", arg)),
+			}
+		}
+		Self::new(retry, max_parts, no_mpp, route)
+	}
+}
+
+pub(crate) fn send_payment(
+	channel_manager: &ChannelManager, router: &Router, invoice: &Invoice, options: PaymentOptions,
+	payment_storage: PaymentInfoStorage, persister: Arc<NodePersister>,
+) -> Result<(), ()> {
+	let amt_msat = match invoice.amount_milli_satoshis() {
+		Some(amt) => amt,
+		None => {
+			println!("ERROR: invoices without an amount are not supported");
+			print!("> ");
+			return Err(());
+		}
+	};
+	let payment_hash = PaymentHash(invoice.payment_hash().clone().into_inner());
+	let payment_secret = Some(invoice.payment_secret().clone());
+
+	let mut payment_params = PaymentParameters::from_node_id(
+		invoice.recover_payee_pub_key(),
+		invoice.min_final_cltv_expiry_delta() as u32,
+	)
+	.with_expiry_time(invoice.duration_since_epoch().as_secs() + invoice.expiry_time().as_secs())
+	.with_route_hints(invoice.route_hints());
+	if let Some(features) = invoice.features() {
+		payment_params = payment_params.with_features(features.clone());
+	}
+	if let Some(max_parts) = options.max_parts {
+		payment_params.max_path_count = max_parts;
+	}
+	let route_params = RouteParameters { payment_params, final_value_msat: amt_msat };
+
+	// The router looks the constraints up on every attempt, retries included.
+	router.set_constraints(PaymentId(payment_hash.0), amt_msat, options.route);
+	let status = match channel_manager.send_payment_with_retry(
+		payment_hash,
+		&payment_secret,
+		PaymentId(payment_hash.0),
+		route_params,
+		options.retry,
+	) {
+		Ok(()) => {
+			let payee_pubkey = invoice.recover_payee_pub_key();
+			println!("EVENT: initiated sending {} msats to {}", amt_msat, payee_pubkey);
+			print!("> ");
+			HTLCStatus::Pending
 		}
 		Err(e) => {
-			println!("ERROR: failed to open channel: {:?}", e);
-			return Err(());
+			router.remove_constraints(&PaymentId(payment_hash.0));
+			println!("ERROR: failed to send payment: {:?}", e);
+			print!("> ");
+			HTLCStatus::Failed
 		}
-	}
-}
+	};
 
-fn send_payment(
-	channel_manager: &ChannelManager, invoice: &Invoice, payment_storage: PaymentInfoStorage,
-) {
-	let status =
-		match pay_invoice(invoice, Retry::Timeout(Duration::from_secs(10)), channel_manager) {
-			Ok(_payment_id) => {
-				let payee_pubkey = invoice.recover_payee_pub_key();
-				let amt_msat = invoice.amount_milli_satoshis().unwrap();
-				println!("EVENT: initiated sending {} msats to {}", amt_msat, payee_pubkey);
-				print!("> ");
-				HTLCStatus::Pending
-			}
-			Err(e) => {
-				println!("ERROR: failed to send payment: {:?}", e);
-				print!("> ");
-				HTLCStatus::Failed
-			}
-		};
-	let payment_hash = PaymentHash(invoice.payment_hash().clone().into_inner());
-	let payment_secret = Some(invoice.payment_secret().clone());
+	let res = match status {
+		HTLCStatus::Failed => Err(()),
+		_ => Ok(()),
+	};
 
 	let mut payments = payment_storage.lock().unwrap();
 	payments.insert(
@@ -689,15 +3734,22 @@ fn send_payment(
 			preimage: None,
 			secret: payment_secret,
 			status,
-			amt_msat: MillisatAmount(invoice.amount_milli_satoshis()),
+			amt_msat: MillisatAmount(Some(amt_msat)),
+			fee_paid_msat: None,
+			created_at: time_now_secs(),
+			settled_at: None,
+			invoice: None,
 		},
 	);
+	// The payment is on its way already, so failing here could get it sent again.
+	let _ = persist_payments(&persister, OUTBOUND_PAYMENTS_FNAME, &payments);
+	res
 }
 
-fn keysend<E: EntropySource>(
-	channel_manager: &ChannelManager, payee_pubkey: PublicKey, amt_msat: u64, entropy_source: &E,
-	payment_storage: PaymentInfoStorage,
-) {
+pub(crate) fn keysend<E: EntropySource>(
+	channel_manager: &ChannelManager, payee_pubkey: PublicKey, amt_msat: u64, retry: Retry,
+	entropy_source: &E, payment_storage: PaymentInfoStorage, persister: Arc<NodePersister>,
+) -> Result<(), ()> {
 	let payment_preimage = PaymentPreimage(entropy_source.get_secure_random_bytes());
 	let payment_hash = PaymentHash(Sha256::hash(&payment_preimage.0[..]).into_inner());
 
@@ -709,7 +3761,7 @@ fn keysend<E: EntropySource>(
 		Some(payment_preimage),
 		PaymentId(payment_hash.0),
 		route_params,
-		Retry::Timeout(Duration::from_secs(10)),
+		retry,
 	) {
 		Ok(_payment_hash) => {
 			println!("EVENT: initiated sending {} msats to {}", amt_msat, payee_pubkey);
@@ -723,6 +3775,11 @@ fn keysend<E: EntropySource>(
 		}
 	};
 
+	let res = match status {
+		HTLCStatus::Failed => Err(()),
+		_ => Ok(()),
+	};
+
 	let mut payments = payment_storage.lock().unwrap();
 	payments.insert(
 		payment_hash,
@@ -731,15 +3788,174 @@ fn keysend<E: EntropySource>(
 			secret: None,
 			status,
 			amt_msat: MillisatAmount(Some(amt_msat)),
+			fee_paid_msat: None,
+			created_at: time_now_secs(),
+			settled_at: None,
+			invoice: None,
 		},
 	);
+	// The payment is on its way already, so failing here could get it sent again.
+	let _ = persist_payments(&persister, OUTBOUND_PAYMENTS_FNAME, &payments);
+	res
 }
 
-fn get_invoice(
-	amt_msat: u64, payment_storage: PaymentInfoStorage, channel_manager: &ChannelManager,
-	keys_manager: Arc<KeysManager>, network: Network, expiry_secs: u32,
-	logger: Arc<disk::FilesystemLogger>,
-) {
+/// Creates an invoice for `amt_msat`. If `payment_hash` is given, it's a hold invoice: we don't
+/// know the preimage, so incoming payments are held until `settle_invoice` or `cancel_invoice`.
+/// What `getinvoice` and `addholdinvoice` put in the invoice besides the amount and expiry.
+pub(crate) struct InvoiceOptions {
+	pub(crate) description: String,
+	/// Committed to instead of `description` if set, for descriptions too long for an invoice.
+	pub(crate) description_hash: Option<[u8; 32]>,
+	/// An on-chain address the payer may fall back to.
+	pub(crate) fallback_address: Option<bitcoin::Address>,
+	/// The channels to include route hints for, or `None` to only include them for our private
+	/// channels when we have no public ones.
+	pub(crate) route_hint_channels: Option<Vec<[u8; 32]>>,
+}
+
+impl Default for InvoiceOptions {
+	fn default() -> Self {
+		Self {
+			description: "ldk-tutorial-node".to_string(),
+			description_hash: None,
+			fallback_address: None,
+			route_hint_channels: None,
+		}
+	}
+}
+
+impl InvoiceOptions {
+	/// Parses `[--description=<text>|--description-hash=<hex>] [--fallback-address=<address>]
+	/// [--route-hints=<channel_id>,...|none]`, returning any argument it doesn't know.
+	pub(crate) fn from_args<'a, I: Iterator<Item = &'a str>>(
+		args: I, network: Network,
+	) -> Result<(Self, Vec<&'a str>), String> {
+		let mut options = InvoiceOptions::default();
+		let mut rest = Vec::new();
+		for arg in args {
+			let mut arg_parts = arg.splitn(2, '=');
+			match (arg_parts.next(), arg_parts.next()) {
+				(Some("--description"), Some(description)) => {
+					options.description = description.to_string()
+				}
+				(Some("--description-hash"), Some(hash)) => match hex_utils::to_vec(hash) {
+					Some(hash) if hash.len() == 32 => {
+						let mut description_hash = [0; 32];
+						description_hash.copy_from_slice(&hash);
+						options.description_hash = Some(description_hash);
+					}
+					_ => return Err("--description-hash must be 32 hex-encoded bytes".to_string()),
+				},
+				(Some("--fallback-address"), Some(address)) => {
+					options.fallback_address = Some(parse_fallback_address(address, network)?)
+				}
+				(Some("--route-hints"), Some("none")) => {
+					options.route_hint_channels = Some(Vec::new())
+				}
+				(Some("--route-hints"), Some(channel_ids)) => {
+					let mut channels = Vec::new();
+					for channel_id in channel_ids.split(',') {
+						match hex_utils::to_vec(channel_id) {
+							Some(id) if id.len() == 32 => {
+								let mut channel_id = [0; 32];
+								channel_id.copy_from_slice(&id);
+								channels.push(channel_id);
+							}
+							_ => return Err(format!("invalid channel id {}", channel_id)),
+						}
+					}
+					options.route_hint_channels = Some(channels);
+				}
+				_ => rest.push(arg),
+			}
+		}
+		Ok((options, rest))
+	}
+}
+
+pub(crate) fn parse_fallback_address(
+	address: &str, network: Network,
+) -> Result<bitcoin::Address, String> {
+	match bitcoin::Address::from_str(address) {
+		Ok(address) if address.is_valid_for_network(network) => Ok(address),
+		Ok(_) => Err(format!("{} is not an address for {}", address, network)),
+		Err(e) => Err(format!("invalid fallback address: {}", e)),
+	}
+}
+
+fn fallback_from_address(address: &bitcoin::Address) -> Fallback {
+	match &address.payload {
+		Payload::PubkeyHash(hash) => Fallback::PubKeyHash(hash.into_inner()),
+		Payload::ScriptHash(hash) => Fallback::ScriptHash(hash.into_inner()),
+		Payload::WitnessProgram { version, program } => {
+			Fallback::SegWitProgram { version: *version, program: program.clone() }
+		}
+	}
+}
+
+/// Builds a single-hop route hint through each of the given usable channels, or through all of our
+/// usable channels if none of them is public (otherwise payers can find us through the graph).
+fn route_hints(
+	channel_manager: &ChannelManager, channel_ids: &Option<Vec<[u8; 32]>>,
+) -> Result<Vec<RouteHint>, String> {
+	let usable_channels = channel_manager.list_usable_channels();
+	let channels = match channel_ids {
+		Some(channel_ids) => {
+			let mut channels = Vec::new();
+			for channel_id in channel_ids {
+				match usable_channels.iter().find(|chan| chan.channel_id == *channel_id) {
+					Some(chan) => channels.push(chan),
+					None => {
+						return Err(format!(
+							"channel {} is not usable",
+							hex_utils::hex_str(channel_id)
+						))
+					}
+				}
+			}
+			channels
+		}
+		None if usable_channels.iter().any(|chan| chan.is_public) => Vec::new(),
+		None => usable_channels.iter().collect(),
+	};
+
+	let mut hints = Vec::new();
+	for chan in channels {
+		let (forwarding_info, scid) =
+			match (&chan.counterparty.forwarding_info, chan.get_inbound_payment_scid()) {
+				(Some(forwarding_info), Some(scid)) => (forwarding_info, scid),
+				_ => {
+					// We can't hint at a channel our peer hasn't told us its forwarding policy for yet.
+					if channel_ids.is_some() {
+						return Err(format!(
+							"channel {} can't be used in route hints yet",
+							hex_utils::hex_str(&chan.channel_id)
+						));
+					}
+					continue;
+				}
+			};
+		hints.push(RouteHint(vec![RouteHintHop {
+			src_node_id: chan.counterparty.node_id,
+			short_channel_id: scid,
+			fees: RoutingFees {
+				base_msat: forwarding_info.fee_base_msat,
+				proportional_millionths: forwarding_info.fee_proportional_millionths,
+			},
+			cltv_expiry_delta: forwarding_info.cltv_expiry_delta,
+			htlc_minimum_msat: chan.inbound_htlc_minimum_msat,
+			htlc_maximum_msat: chan.inbound_htlc_maximum_msat,
+		}]));
+	}
+	Ok(hints)
+}
+
+pub(crate) fn get_invoice(
+	amt_msat: u64, payment_hash: Option<PaymentHash>, options: InvoiceOptions,
+	payment_storage: PaymentInfoStorage, persister: Arc<NodePersister>,
+	channel_manager: &ChannelManager, keys_manager: Arc<NodeKeys>, network: Network,
+	expiry_secs: u32,
+) -> Result<Invoice, ()> {
 	let mut payments = payment_storage.lock().unwrap();
 	let currency = match network {
 		Network::Bitcoin => Currency::Bitcoin,
@@ -747,59 +3963,442 @@ fn get_invoice(
 		Network::Regtest => Currency::Regtest,
 		Network::Signet => Currency::Signet,
 	};
-	let invoice = match utils::create_invoice_from_channelmanager(
-		channel_manager,
-		keys_manager,
-		logger,
-		currency,
-		Some(amt_msat),
-		"ldk-tutorial-node".to_string(),
-		expiry_secs,
-		None,
-	) {
+	let hints = match route_hints(channel_manager, &options.route_hint_channels) {
+		Ok(hints) => hints,
+		Err(e) => {
+			println!("ERROR: failed to create invoice: {}", e);
+			return Err(());
+		}
+	};
+	let res = match payment_hash {
+		Some(payment_hash) => channel_manager
+			.create_inbound_payment_for_hash(payment_hash, Some(amt_msat), expiry_secs, None)
+			.map(|payment_secret| (payment_hash, payment_secret)),
+		None => channel_manager.create_inbound_payment(Some(amt_msat), expiry_secs, None),
+	};
+	let (payment_hash, payment_secret) = match res {
+		Ok(res) => res,
+		Err(()) => {
+			println!("ERROR: failed to create invoice: invalid amount or expiry");
+			return Err(());
+		}
+	};
+
+	let builder = InvoiceBuilder::new(currency);
+	let builder = match options.description_hash {
+		Some(hash) => builder.description_hash(Sha256::from_inner(hash)),
+		None => builder.description(options.description),
+	};
+	let mut builder = builder
+		.payment_hash(Sha256::from_inner(payment_hash.0))
+		.payment_secret(payment_secret)
+		.basic_mpp()
+		.min_final_cltv_expiry_delta(MIN_FINAL_CLTV_EXPIRY_DELTA.into())
+		.duration_since_epoch(Duration::from_secs(time_now_secs()))
+		.expiry_time(Duration::from_secs(expiry_secs.into()))
+		.amount_milli_satoshis(amt_msat);
+	for hint in hints {
+		builder = builder.private_route(hint);
+	}
+	if let Some(address) = &options.fallback_address {
+		builder = builder.fallback(fallback_from_address(address));
+	}
+	let raw_invoice = match builder.build_raw() {
+		Ok(raw_invoice) => raw_invoice,
+		Err(e) => {
+			println!("ERROR: failed to create invoice: {:?}", e);
+			return Err(());
+		}
+	};
+	// Signed through the `NodeSigner`, as the node key may be held by a remote signer.
+	let hrp = raw_invoice.hrp.to_string();
+	let invoice_data = raw_invoice.data.to_base32();
+	let signed_invoice = match raw_invoice
+		.sign(|_| keys_manager.sign_invoice(hrp.as_bytes(), &invoice_data, Recipient::Node))
+	{
+		Ok(signed_invoice) => signed_invoice,
+		Err(()) => {
+			println!("ERROR: failed to sign invoice");
+			return Err(());
+		}
+	};
+	let invoice = match Invoice::from_signed(signed_invoice) {
 		Ok(inv) => {
 			println!("SUCCESS: generated invoice: {}", inv);
 			inv
 		}
 		Err(e) => {
 			println!("ERROR: failed to create invoice: {:?}", e);
-			return;
+			return Err(());
 		}
 	};
 
-	let payment_hash = PaymentHash(invoice.payment_hash().clone().into_inner());
 	payments.insert(
 		payment_hash,
 		PaymentInfo {
 			preimage: None,
-			secret: Some(invoice.payment_secret().clone()),
+			secret: Some(payment_secret),
 			status: HTLCStatus::Pending,
 			amt_msat: MillisatAmount(Some(amt_msat)),
+			fee_paid_msat: None,
+			created_at: time_now_secs(),
+			settled_at: None,
+			invoice: Some(invoice.to_string()),
 		},
 	);
+	persist_payments(&persister, INBOUND_PAYMENTS_FNAME, &payments)?;
+	Ok(invoice)
 }
 
-fn close_channel(
-	channel_id: [u8; 32], counterparty_node_id: PublicKey, channel_manager: Arc<ChannelManager>,
-) {
-	match channel_manager.close_channel(&channel_id, &counterparty_node_id) {
-		Ok(()) => println!("EVENT: initiating channel close"),
-		Err(e) => println!("ERROR: failed to close channel: {:?}", e),
+/// Our channels, serialized for the other nodes of our phantom node cluster to include in the
+/// phantom invoices they create.
+pub(crate) fn phantom_route_hints_hex(channel_manager: &ChannelManager) -> String {
+	hex_utils::hex_str(&channel_manager.get_phantom_route_hints().encode())
+}
+
+/// Parses the comma-separated output of other nodes' `getphantomroutehints`.
+pub(crate) fn parse_phantom_route_hints(hints: &str) -> Result<Vec<PhantomRouteHints>, String> {
+	hints
+		.split(',')
+		.map(|hint| {
+			hex_utils::to_vec(hint)
+				.and_then(|bytes| PhantomRouteHints::read(&mut &bytes[..]).ok())
+				.ok_or_else(|| format!("invalid phantom route hints {}", hint))
+		})
+		.collect()
+}
+
+/// Creates an invoice to the phantom node of our cluster, payable through our channels and those
+/// of the nodes `route_hints` came from. Whichever node the payment reaches claims it, as they all
+/// derive its preimage from the secret they share.
+pub(crate) fn get_phantom_invoice(
+	amt_msat: u64, description: String, expiry_secs: u32, mut route_hints: Vec<PhantomRouteHints>,
+	payment_storage: PaymentInfoStorage, persister: Arc<NodePersister>,
+	channel_manager: &ChannelManager, keys_manager: Arc<NodeKeys>,
+	logger: Arc<disk::FilesystemLogger>, network: Network,
+) -> Result<Invoice, String> {
+	if !matches!(*keys_manager, NodeKeys::Phantom(_)) {
+		return Err(
+			"the node isn't part of a phantom node cluster, see --phantom-secret".to_string()
+		);
+	}
+	let currency = match network {
+		Network::Bitcoin => Currency::Bitcoin,
+		Network::Testnet => Currency::BitcoinTestnet,
+		Network::Regtest => Currency::Regtest,
+		Network::Signet => Currency::Signet,
+	};
+	let our_hints = channel_manager.get_phantom_route_hints();
+	route_hints.retain(|hints| hints.real_node_pubkey != our_hints.real_node_pubkey);
+	route_hints.push(our_hints);
+	let invoice = create_phantom_invoice(
+		Some(amt_msat),
+		None,
+		description,
+		expiry_secs,
+		route_hints,
+		Arc::clone(&keys_manager),
+		Arc::clone(&keys_manager),
+		logger,
+		currency,
+		Some(MIN_FINAL_CLTV_EXPIRY_DELTA),
+		Duration::from_secs(time_now_secs()),
+	)
+	.map_err(|e| format!("failed to create invoice: {:?}", e))?;
+
+	let mut payments = payment_storage.lock().unwrap();
+	payments.insert(
+		PaymentHash(invoice.payment_hash().into_inner()),
+		PaymentInfo {
+			preimage: None,
+			secret: Some(*invoice.payment_secret()),
+			status: HTLCStatus::Pending,
+			amt_msat: MillisatAmount(Some(amt_msat)),
+			fee_paid_msat: None,
+			created_at: time_now_secs(),
+			settled_at: None,
+			invoice: Some(invoice.to_string()),
+		},
+	);
+	persist_payments(&persister, INBOUND_PAYMENTS_FNAME, &payments)
+		.map_err(|()| "failed to persist the invoice".to_string())?;
+	Ok(invoice)
+}
+
+/// Claims the payment held for a hold invoice, revealing `payment_preimage` to the payer.
+pub(crate) fn settle_invoice(
+	payment_preimage: PaymentPreimage, payment_storage: PaymentInfoStorage,
+	persister: Arc<NodePersister>, channel_manager: &ChannelManager,
+) -> Result<(), ()> {
+	let payment_hash = PaymentHash(Sha256::hash(&payment_preimage.0).into_inner());
+	let mut payments = payment_storage.lock().unwrap();
+	match payments.get_mut(&payment_hash) {
+		Some(payment) if payment.status == HTLCStatus::Held => {
+			// `PaymentClaimed` won't tell us the preimage of a hold invoice, so keep it now.
+			payment.preimage = Some(payment_preimage);
+		}
+		Some(_) => {
+			println!("ERROR: no payment is being held for this invoice");
+			return Err(());
+		}
+		None => {
+			println!("ERROR: no invoice found for this preimage");
+			return Err(());
+		}
+	}
+	persist_payments(&persister, INBOUND_PAYMENTS_FNAME, &payments)?;
+	channel_manager.claim_funds(payment_preimage);
+	println!("EVENT: settling hold invoice {}", hex_utils::hex_str(&payment_hash.0));
+	Ok(())
+}
+
+/// Fails back any payment held for a hold invoice, and any that arrives for it later on.
+pub(crate) fn cancel_invoice(
+	payment_hash: PaymentHash, payment_storage: PaymentInfoStorage, persister: Arc<NodePersister>,
+	channel_manager: &ChannelManager,
+) -> Result<(), ()> {
+	let mut payments = payment_storage.lock().unwrap();
+	match payments.get_mut(&payment_hash) {
+		Some(payment)
+			if payment.status == HTLCStatus::Pending || payment.status == HTLCStatus::Held =>
+		{
+			payment.status = HTLCStatus::Failed;
+			payment.settled_at = Some(time_now_secs());
+		}
+		Some(_) => {
+			println!("ERROR: invoice was already settled or canceled");
+			return Err(());
+		}
+		None => {
+			println!("ERROR: no invoice found for this payment hash");
+			return Err(());
+		}
+	}
+	persist_payments(&persister, INBOUND_PAYMENTS_FNAME, &payments)?;
+	channel_manager.fail_htlc_backwards(&payment_hash);
+	println!("SUCCESS: canceled invoice {}", hex_utils::hex_str(&payment_hash.0));
+	Ok(())
+}
+
+pub(crate) fn close_channel(
+	channel_id: [u8; 32], counterparty_node_id: PublicKey, target_sat_per_vbyte: Option<u32>,
+	channel_manager: Arc<ChannelManager>,
+) -> Result<(), ()> {
+	let res = match target_sat_per_vbyte {
+		// LDK takes feerates in sat per 1000 weight units and won't go below 253.
+		Some(sat_per_vbyte) => channel_manager.close_channel_with_target_feerate(
+			&channel_id,
+			&counterparty_node_id,
+			cmp::max(sat_per_vbyte.saturating_mul(250), 253),
+		),
+		None => channel_manager.close_channel(&channel_id, &counterparty_node_id),
+	};
+	match res {
+		Ok(()) => {
+			println!("EVENT: initiating channel close");
+			Ok(())
+		}
+		Err(e) => {
+			println!("ERROR: failed to close channel: {:?}", e);
+			Err(())
+		}
+	}
+}
+
+/// The channels `updatechannelpolicy` applies to.
+pub(crate) enum PolicyTarget {
+	Channel([u8; 32]),
+	Peer(PublicKey),
+}
+
+impl PolicyTarget {
+	/// Parses either a channel ID or the public key of a peer, to target all our channels with it.
+	pub(crate) fn from_hex(hex: &str) -> Option<Self> {
+		if let Some(pubkey) = hex_utils::to_compressed_pubkey(hex) {
+			return Some(PolicyTarget::Peer(pubkey));
+		}
+		match hex_utils::to_vec(hex) {
+			Some(channel_id_vec) if channel_id_vec.len() == 32 => {
+				let mut channel_id = [0; 32];
+				channel_id.copy_from_slice(&channel_id_vec);
+				Some(PolicyTarget::Channel(channel_id))
+			}
+			_ => None,
+		}
+	}
+}
+
+/// Routing policy changes for `updatechannelpolicy`. Fields left unset keep each channel's current
+/// value.
+#[derive(Default)]
+pub(crate) struct ChannelPolicyUpdate {
+	pub(crate) forwarding_fee_base_msat: Option<u32>,
+	pub(crate) forwarding_fee_proportional_millionths: Option<u32>,
+	pub(crate) cltv_expiry_delta: Option<u16>,
+}
+
+impl ChannelPolicyUpdate {
+	/// Parses `[--fee-base-msat=<amt>] [--fee-proportional-millionths=<rate>]
+	/// [--cltv-expiry-delta=<blocks>]`.
+	pub(crate) fn from_args<'a, I: Iterator<Item = &'a str>>(args: I) -> Result<Self, String> {
+		fn parse_num<T: FromStr>(name: &str, value: &str) -> Result<T, String> {
+			value.parse().map_err(|_| format!("{} must be a number", name))
+		}
+
+		let mut update = ChannelPolicyUpdate::default();
+		for arg in args {
+			let mut arg_parts = arg.splitn(2, '=');
+			match (arg_parts.next(), arg_parts.next()) {
+				(Some("--fee-base-msat"), Some(fee)) => {
+					update.forwarding_fee_base_msat = Some(parse_num("--fee-base-msat", fee)?)
+				}
+				(Some("--fee-proportional-millionths"), Some(fee)) => {
+					update.forwarding_fee_proportional_millionths =
+						Some(parse_num("--fee-proportional-millionths", fee)?)
+				}
+				(Some("--cltv-expiry-delta"), Some(delta)) => {
+					update.cltv_expiry_delta = Some(parse_num("--cltv-expiry-delta", delta)?)
+				}
+				// LDK only lets us change a channel's `ChannelConfig` after it's open, the HTLC
+				// limits are fixed at channel opening.
+				(Some("--min-htlc-msat"), _) | (Some("--max-htlc-msat"), _) => {
+					return Err(format!(
+						"{} can't be changed on an open channel, set it in openchannel instead",
+						arg.splitn(2, '=').next().unwrap()
+					))
+				}
+				_ => return Err(format!("unknown updatechannelpolicy argument {}", arg)),
+			}
+		}
+		Ok(update)
+	}
+}
+
+/// Applies `update` to every targeted channel, returning how many were updated. Nothing is applied
+/// unless the update is valid for every targeted channel.
+pub(crate) fn update_channel_policy(
+	target: &PolicyTarget, update: &ChannelPolicyUpdate, channel_manager: &Arc<ChannelManager>,
+) -> Result<usize, ()> {
+	if let Some(delta) = update.cltv_expiry_delta {
+		if delta < MIN_CLTV_EXPIRY_DELTA {
+			println!("ERROR: the CLTV expiry delta must be at least {}", MIN_CLTV_EXPIRY_DELTA);
+			return Err(());
+		}
+	}
+	let channels = channel_manager
+		.list_channels()
+		.into_iter()
+		.filter(|chan| match target {
+			PolicyTarget::Channel(channel_id) => chan.channel_id == *channel_id,
+			PolicyTarget::Peer(pubkey) => chan.counterparty.node_id == *pubkey,
+		})
+		.collect::<Vec<_>>();
+	if channels.is_empty() {
+		println!("ERROR: no matching channels found");
+		return Err(());
+	}
+	let mut configs = Vec::with_capacity(channels.len());
+	for chan in channels.iter() {
+		// Only channels last serialized by very old versions of LDK don't report their config.
+		let mut config = match chan.config {
+			Some(config) => config,
+			None => {
+				println!(
+					"ERROR: unknown current config for channel {}",
+					hex_utils::hex_str(&chan.channel_id)
+				);
+				return Err(());
+			}
+		};
+		if let Some(fee) = update.forwarding_fee_base_msat {
+			config.forwarding_fee_base_msat = fee;
+		}
+		if let Some(fee) = update.forwarding_fee_proportional_millionths {
+			config.forwarding_fee_proportional_millionths = fee;
+		}
+		if let Some(delta) = update.cltv_expiry_delta {
+			config.cltv_expiry_delta = delta;
+		}
+		configs.push(config);
+	}
+	for (num_updated, (chan, config)) in channels.iter().zip(configs.iter()).enumerate() {
+		// This only fails if the channel closed since we listed it.
+		if let Err(e) = channel_manager.update_channel_config(
+			&chan.counterparty.node_id,
+			&[chan.channel_id],
+			config,
+		) {
+			println!(
+				"ERROR: failed to update channel {} after updating {} of {} channel(s): {:?}",
+				hex_utils::hex_str(&chan.channel_id),
+				num_updated,
+				channels.len(),
+				e
+			);
+			return Err(());
+		}
 	}
+	Ok(channels.len())
 }
 
-fn force_close_channel(
+pub(crate) fn force_close_channel(
 	channel_id: [u8; 32], counterparty_node_id: PublicKey, channel_manager: Arc<ChannelManager>,
-) {
+) -> Result<(), ()> {
 	match channel_manager.force_close_broadcasting_latest_txn(&channel_id, &counterparty_node_id) {
-		Ok(()) => println!("EVENT: initiating channel force-close"),
-		Err(e) => println!("ERROR: failed to force-close channel: {:?}", e),
+		Ok(()) => {
+			println!("EVENT: initiating channel force-close");
+			Ok(())
+		}
+		Err(e) => {
+			println!("ERROR: failed to force-close channel: {:?}", e);
+			Err(())
+		}
+	}
+}
+
+/// Gets rid of a channel that never became usable, e.g. because the peer vanished before the
+/// funding transaction confirmed. Channels that are ready have to be closed instead.
+pub(crate) fn abandon_channel(
+	channel_id: [u8; 32], counterparty_node_id: PublicKey, channel_manager: Arc<ChannelManager>,
+) -> Result<(), ()> {
+	let chan = match channel_manager.list_channels().into_iter().find(|chan| {
+		chan.channel_id == channel_id && chan.counterparty.node_id == counterparty_node_id
+	}) {
+		Some(chan) => chan,
+		None => {
+			println!("ERROR: channel not found");
+			return Err(());
+		}
+	};
+	if chan.is_channel_ready {
+		println!("ERROR: channel is ready, use closechannel or forceclosechannel instead");
+		return Err(());
+	}
+
+	let res = if chan.funding_txo.is_none() {
+		// Nothing was signed yet, so there's no ChannelMonitor and no transaction to broadcast.
+		channel_manager.force_close_without_broadcasting_txn(&channel_id, &counterparty_node_id)
+	} else {
+		// The funding transaction may still confirm, in which case our latest commitment
+		// transaction and the ChannelMonitor are what get our funds back.
+		println!("WARNING: the funding transaction was already signed, broadcasting our latest commitment transaction in case it confirms");
+		channel_manager.force_close_broadcasting_latest_txn(&channel_id, &counterparty_node_id)
+	};
+	match res {
+		Ok(()) => {
+			println!("EVENT: abandoning channel");
+			Ok(())
+		}
+		Err(e) => {
+			println!("ERROR: failed to abandon channel: {:?}", e);
+			Err(())
+		}
 	}
 }
 
 pub(crate) fn parse_peer_info(
 	peer_pubkey_and_ip_addr: String,
-) -> Result<(PublicKey, SocketAddr), std::io::Error> {
+) -> Result<(PublicKey, PeerAddr), std::io::Error> {
 	let mut pubkey_and_addr = peer_pubkey_and_ip_addr.split("@");
 	let pubkey = pubkey_and_addr.next();
 	let peer_addr_str = pubkey_and_addr.next();
@@ -810,11 +4409,11 @@ pub(crate) fn parse_peer_info(
 		));
 	}
 
-	let peer_addr = peer_addr_str.unwrap().to_socket_addrs().map(|mut r| r.next());
-	if peer_addr.is_err() || peer_addr.as_ref().unwrap().is_none() {
+	let peer_addr = PeerAddr::from_str(peer_addr_str.unwrap());
+	if peer_addr.is_err() {
 		return Err(std::io::Error::new(
 			std::io::ErrorKind::Other,
-			"ERROR: couldn't parse pubkey@host:port into a socket address",
+			"ERROR: couldn't parse pubkey@host:port into a peer address",
 		));
 	}
 
@@ -826,5 +4425,261 @@ pub(crate) fn parse_peer_info(
 		));
 	}
 
-	Ok((pubkey.unwrap(), peer_addr.unwrap().unwrap()))
+	Ok((pubkey.unwrap(), peer_addr.unwrap()))
+}
+
+#[cfg(test)]
+mod parser_tests {
+	use super::*;
+	use bitcoin::secp256k1::{Secp256k1, SecretKey};
+
+	const CHANNEL_ID: &str = "0101010101010101010101010101010101010101010101010101010101010101";
+
+	fn test_pubkey(byte: u8) -> PublicKey {
+		PublicKey::from_secret_key(&Secp256k1::new(), &SecretKey::from_slice(&[byte; 32]).unwrap())
+	}
+
+	fn test_address(network: Network) -> bitcoin::Address {
+		bitcoin::Address::p2wpkh(&bitcoin::PublicKey::new(test_pubkey(1)), network).unwrap()
+	}
+
+	#[test]
+	fn test_invoice_options() {
+		let (options, rest) =
+			InvoiceOptions::from_args(std::iter::empty(), Network::Regtest).unwrap();
+		assert_eq!(options.description, "ldk-tutorial-node");
+		assert!(options.description_hash.is_none());
+		assert!(options.fallback_address.is_none());
+		assert!(options.route_hint_channels.is_none());
+		assert!(rest.is_empty());
+
+		let address = test_address(Network::Regtest).to_string();
+		let args = [
+			"--description=coffee=2".to_string(),
+			format!("--description-hash={}", CHANNEL_ID),
+			format!("--fallback-address={}", address),
+			format!("--route-hints={},{}", CHANNEL_ID, CHANNEL_ID),
+			"--private".to_string(),
+		];
+		let (options, rest) =
+			InvoiceOptions::from_args(args.iter().map(String::as_str), Network::Regtest).unwrap();
+		assert_eq!(options.description, "coffee=2");
+		assert_eq!(options.description_hash, Some([1; 32]));
+		assert_eq!(options.fallback_address.unwrap().to_string(), address);
+		assert_eq!(options.route_hint_channels, Some(vec![[1; 32], [1; 32]]));
+		assert_eq!(rest, vec!["--private"]);
+
+		let (options, _) =
+			InvoiceOptions::from_args(std::iter::once("--route-hints=none"), Network::Regtest)
+				.unwrap();
+		assert_eq!(options.route_hint_channels, Some(Vec::new()));
+	}
+
+	#[test]
+	fn test_invoice_options_invalid() {
+		let mainnet_address = format!("--fallback-address={}", test_address(Network::Bitcoin));
+		for arg in [
+			"--description-hash=0101",
+			"--description-hash=zz",
+			"--fallback-address=notanaddress",
+			mainnet_address.as_str(),
+			"--route-hints=0101",
+			format!("--route-hints={},", CHANNEL_ID).as_str(),
+		] {
+			assert!(
+				InvoiceOptions::from_args(std::iter::once(arg), Network::Regtest).is_err(),
+				"{} was accepted",
+				arg
+			);
+		}
+	}
+
+	#[test]
+	fn test_payment_filter() {
+		let filter = PaymentFilter::from_args(std::iter::empty()).unwrap();
+		assert!(filter.direction.is_none());
+		assert!(filter.status.is_none());
+		assert_eq!(filter.limit, None);
+		assert_eq!(filter.offset, 0);
+
+		let args = ["--incoming", "--status=succeeded", "--limit=10", "--offset=20"];
+		let filter = PaymentFilter::from_args(args.iter().copied()).unwrap();
+		assert!(filter.direction == Some(PaymentDirection::Inbound));
+		assert!(filter.status == Some(HTLCStatus::Succeeded));
+		assert_eq!(filter.limit, Some(10));
+		assert_eq!(filter.offset, 20);
+
+		let filter =
+			PaymentFilter::from_args(["--incoming", "--outgoing"].iter().copied()).unwrap();
+		assert!(filter.direction == Some(PaymentDirection::Outbound));
+		for (status, expected) in [
+			("pending", HTLCStatus::Pending),
+			("held", HTLCStatus::Held),
+			("failed", HTLCStatus::Failed),
+		] {
+			let arg = format!("--status={}", status);
+			let filter = PaymentFilter::from_args(std::iter::once(arg.as_str())).unwrap();
+			assert!(filter.status == Some(expected));
+		}
+	}
+
+	#[test]
+	fn test_payment_filter_invalid() {
+		for arg in [
+			"--status=settled",
+			"--status",
+			"--limit=ten",
+			"--limit=-1",
+			"--offset=",
+			"--incoming=true",
+			"--direction=in",
+		] {
+			assert!(
+				PaymentFilter::from_args(std::iter::once(arg)).is_err(),
+				"{} was accepted",
+				arg
+			);
+		}
+	}
+
+	#[test]
+	fn test_open_channel_options() {
+		let options = OpenChannelOptions::from_args(std::iter::empty()).unwrap();
+		assert!(!options.announced_channel);
+		assert_eq!(options.push_msat, 0);
+		assert_eq!(options.htlc_minimum_msat, None);
+		assert_eq!(options.cltv_expiry_delta, None);
+		assert_eq!(options.forwarding_fee_base_msat, None);
+		assert_eq!(options.forwarding_fee_proportional_millionths, None);
+		assert!(!options.external_funding);
+
+		let args = [
+			"--public",
+			"--push-msat=1000",
+			"--min-htlc-msat=1",
+			"--cltv-expiry-delta=144",
+			"--fee-base-msat=1000",
+			"--fee-proportional-millionths=100",
+			"--psbt",
+		];
+		let options = OpenChannelOptions::from_args(args.iter().copied()).unwrap();
+		assert!(options.announced_channel);
+		assert_eq!(options.push_msat, 1000);
+		assert_eq!(options.htlc_minimum_msat, Some(1));
+		assert_eq!(options.cltv_expiry_delta, Some(144));
+		assert_eq!(options.forwarding_fee_base_msat, Some(1000));
+		assert_eq!(options.forwarding_fee_proportional_millionths, Some(100));
+		assert!(options.external_funding);
+
+		for (args, announced) in [
+			(&["--public=true"][..], true),
+			(&["--public=false"][..], false),
+			(&["--public", "--private"][..], false),
+		] {
+			let options = OpenChannelOptions::from_args(args.iter().copied()).unwrap();
+			assert_eq!(options.announced_channel, announced);
+		}
+	}
+
+	#[test]
+	fn test_open_channel_options_invalid() {
+		for arg in [
+			"--public=yes",
+			"--private=true",
+			"--push-msat=1k",
+			"--push-msat",
+			"--cltv-expiry-delta=65536",
+			"--fee-base-msat=-1",
+			"--fee-proportional-millionths=0.1",
+			"--psbt=true",
+			"--announce",
+		] {
+			assert!(
+				OpenChannelOptions::from_args(std::iter::once(arg)).is_err(),
+				"{} was accepted",
+				arg
+			);
+		}
+	}
+
+	#[test]
+	fn test_channel_policy_update() {
+		let update = ChannelPolicyUpdate::from_args(std::iter::empty()).unwrap();
+		assert_eq!(update.forwarding_fee_base_msat, None);
+		assert_eq!(update.forwarding_fee_proportional_millionths, None);
+		assert_eq!(update.cltv_expiry_delta, None);
+
+		let args =
+			["--fee-base-msat=0", "--fee-proportional-millionths=250", "--cltv-expiry-delta=72"];
+		let update = ChannelPolicyUpdate::from_args(args.iter().copied()).unwrap();
+		assert_eq!(update.forwarding_fee_base_msat, Some(0));
+		assert_eq!(update.forwarding_fee_proportional_millionths, Some(250));
+		assert_eq!(update.cltv_expiry_delta, Some(72));
+	}
+
+	#[test]
+	fn test_channel_policy_update_invalid() {
+		let err = ChannelPolicyUpdate::from_args(std::iter::once("--min-htlc-msat=1")).err();
+		assert!(err.unwrap().starts_with("--min-htlc-msat can't be changed"));
+		let err = ChannelPolicyUpdate::from_args(std::iter::once("--max-htlc-msat")).err();
+		assert!(err.unwrap().starts_with("--max-htlc-msat can't be changed"));
+		for arg in ["--fee-base-msat=high", "--cltv-expiry-delta=-1", "--fee-base-msat", "--public"]
+		{
+			assert!(
+				ChannelPolicyUpdate::from_args(std::iter::once(arg)).is_err(),
+				"{} was accepted",
+				arg
+			);
+		}
+	}
+
+	#[test]
+	fn test_parse_phantom_route_hints() {
+		let hints = [
+			PhantomRouteHints {
+				channels: Vec::new(),
+				phantom_scid: 42,
+				real_node_pubkey: test_pubkey(1),
+			},
+			PhantomRouteHints {
+				channels: Vec::new(),
+				phantom_scid: 43,
+				real_node_pubkey: test_pubkey(2),
+			},
+		];
+		let encoded =
+			hints.iter().map(|hint| hex_utils::hex_str(&hint.encode())).collect::<Vec<_>>();
+
+		let parsed = parse_phantom_route_hints(&encoded[0]).unwrap();
+		assert_eq!(parsed.len(), 1);
+		assert_eq!(parsed[0].phantom_scid, 42);
+		assert_eq!(parsed[0].real_node_pubkey, test_pubkey(1));
+
+		let parsed = parse_phantom_route_hints(&encoded.join(",")).unwrap();
+		assert_eq!(parsed.len(), 2);
+		assert_eq!(parsed[1].phantom_scid, 43);
+		assert_eq!(parsed[1].real_node_pubkey, test_pubkey(2));
+	}
+
+	#[test]
+	fn test_parse_phantom_route_hints_invalid() {
+		let hint = hex_utils::hex_str(
+			&PhantomRouteHints {
+				channels: Vec::new(),
+				phantom_scid: 42,
+				real_node_pubkey: test_pubkey(1),
+			}
+			.encode(),
+		);
+		for hints in [
+			"",
+			"zz",
+			"0102",
+			&hint[..hint.len() - 2],
+			format!("{},", hint).as_str(),
+			format!("{},00", hint).as_str(),
+		] {
+			assert!(parse_phantom_route_hints(hints).is_err(), "{} was accepted", hints);
+		}
+	}
 }
@@ -1,5 +1,7 @@
+use bitcoin::blockdata::script::Script;
+use bitcoin::blockdata::transaction::{OutPoint, TxOut};
 use bitcoin::hashes::hex::FromHex;
-use bitcoin::BlockHash;
+use bitcoin::{Amount, BlockHash, Txid};
 use lightning_block_sync::http::JsonResponse;
 use std::convert::TryInto;
 
@@ -50,6 +52,22 @@ impl TryInto<NewAddress> for JsonResponse {
 	}
 }
 
+/// An entry in bitcoind's mempool, we only care that there is one.
+pub struct MempoolEntry;
+
+impl TryInto<MempoolEntry> for JsonResponse {
+	type Error = std::io::Error;
+	fn try_into(self) -> std::io::Result<MempoolEntry> {
+		if !self.0.is_object() {
+			return Err(std::io::Error::new(
+				std::io::ErrorKind::InvalidData,
+				"expected a mempool entry",
+			));
+		}
+		Ok(MempoolEntry)
+	}
+}
+
 pub struct FeeResponse {
 	pub feerate_sat_per_kw: Option<u32>,
 	pub errored: bool,
@@ -91,3 +109,69 @@ impl TryInto<BlockchainInfo> for JsonResponse {
 		})
 	}
 }
+
+/// Whether `gettxout` found the output unspent, it returns null otherwise.
+pub struct TxOutStatus(pub bool);
+
+impl TryInto<TxOutStatus> for JsonResponse {
+	type Error = std::io::Error;
+	fn try_into(self) -> std::io::Result<TxOutStatus> {
+		Ok(TxOutStatus(!self.0.is_null()))
+	}
+}
+
+/// The unspent outputs `scantxoutset` found.
+pub struct TxOutSetScan(pub Vec<(OutPoint, TxOut)>);
+
+impl TryInto<TxOutSetScan> for JsonResponse {
+	type Error = std::io::Error;
+	fn try_into(self) -> std::io::Result<TxOutSetScan> {
+		let invalid = || {
+			std::io::Error::new(std::io::ErrorKind::InvalidData, "invalid scantxoutset response")
+		};
+		let mut outputs = Vec::new();
+		for unspent in self.0["unspents"].as_array().ok_or_else(invalid)? {
+			let txid = unspent["txid"].as_str().and_then(|txid| Txid::from_hex(txid).ok());
+			let vout = unspent["vout"].as_u64();
+			let script =
+				unspent["scriptPubKey"].as_str().and_then(|hex| Vec::<u8>::from_hex(hex).ok());
+			let amount = unspent["amount"].as_f64().and_then(|btc| Amount::from_btc(btc).ok());
+			match (txid, vout, script, amount) {
+				(Some(txid), Some(vout), Some(script), Some(amount)) => outputs.push((
+					OutPoint { txid, vout: vout as u32 },
+					TxOut { value: amount.to_sat(), script_pubkey: Script::from(script) },
+				)),
+				_ => return Err(invalid()),
+			}
+		}
+		Ok(TxOutSetScan(outputs))
+	}
+}
+
+/// The balances of bitcoind's wallet, in satoshis.
+pub struct Balances {
+	pub trusted: u64,
+	pub untrusted_pending: u64,
+	pub immature: u64,
+}
+
+impl TryInto<Balances> for JsonResponse {
+	type Error = std::io::Error;
+	fn try_into(self) -> std::io::Result<Balances> {
+		let sats = |name: &str| {
+			self.0["mine"][name].as_f64().and_then(|btc| Amount::from_btc(btc).ok()).ok_or_else(
+				|| {
+					std::io::Error::new(
+						std::io::ErrorKind::InvalidData,
+						"invalid getbalances response",
+					)
+				},
+			)
+		};
+		Ok(Balances {
+			trusted: sats("trusted")?.to_sat(),
+			untrusted_pending: sats("untrusted_pending")?.to_sat(),
+			immature: sats("immature")?.to_sat(),
+		})
+	}
+}
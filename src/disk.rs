@@ -1,15 +1,22 @@
-use crate::{cli, NetworkGraph};
+use crate::hex_utils;
+use crate::persist::NodePersister;
+use crate::proxy::PeerAddr;
+use crate::{
+	cli, ForwardingHistory, NetworkGraph, PaymentInfo, ProbeStats, Scorer, WithdrawLink,
+	FORWARDING_HISTORY_FNAME, PAYMENTS_SERIALIZATION_VERSION, PROBE_STATS_FNAME,
+	WITHDRAW_LINKS_FNAME,
+};
 use bitcoin::secp256k1::PublicKey;
 use bitcoin::Network;
 use chrono::Utc;
+use lightning::ln::PaymentHash;
 use lightning::routing::scoring::{ProbabilisticScorer, ProbabilisticScoringParameters};
 use lightning::util::logger::{Logger, Record};
-use lightning::util::ser::{ReadableArgs, Writer};
-use std::collections::HashMap;
+use lightning::util::ser::{Readable, ReadableArgs, Writer};
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
-use std::net::SocketAddr;
 use std::path::Path;
 use std::sync::Arc;
 
@@ -47,15 +54,22 @@ impl Logger for FilesystemLogger {
 			.unwrap();
 	}
 }
+
+/// How many addresses the address book in `channel_peer_data` keeps for each peer.
+const MAX_ADDRESSES_PER_PEER: usize = 8;
+
+/// Records that a peer was reached at an address, in the address book in `channel_peer_data`.
+/// Lines are only appended, the last one for an address being the most recent.
 pub(crate) fn persist_channel_peer(path: &Path, peer_info: &str) -> std::io::Result<()> {
 	let mut file = fs::OpenOptions::new().create(true).append(true).open(path)?;
 	file.write_all(format!("{}\n", peer_info).as_bytes())
 }
 
+/// Returns the addresses we know each peer at, the most recently recorded first.
 pub(crate) fn read_channel_peer_data(
 	path: &Path,
-) -> Result<HashMap<PublicKey, SocketAddr>, std::io::Error> {
-	let mut peer_data = HashMap::new();
+) -> Result<HashMap<PublicKey, Vec<PeerAddr>>, std::io::Error> {
+	let mut peer_data: HashMap<PublicKey, Vec<PeerAddr>> = HashMap::new();
 	if !Path::new(&path).exists() {
 		return Ok(HashMap::new());
 	}
@@ -63,8 +77,10 @@ pub(crate) fn read_channel_peer_data(
 	let reader = BufReader::new(file);
 	for line in reader.lines() {
 		match cli::parse_peer_info(line.unwrap()) {
-			Ok((pubkey, socket_addr)) => {
-				peer_data.insert(pubkey, socket_addr);
+			Ok((pubkey, peer_addr)) => {
+				let addrs = peer_data.entry(pubkey).or_insert_with(Vec::new);
+				addrs.retain(|addr| *addr != peer_addr);
+				addrs.insert(0, peer_addr);
 			}
 			Err(e) => return Err(e),
 		}
@@ -72,11 +88,114 @@ pub(crate) fn read_channel_peer_data(
 	Ok(peer_data)
 }
 
+/// Rewrites the address book without its duplicate lines, keeping the `MAX_ADDRESSES_PER_PEER`
+/// most recent addresses of each peer.
+pub(crate) fn compact_channel_peer_data(path: &Path) -> std::io::Result<()> {
+	let peer_data = read_channel_peer_data(path)?;
+	let mut lines = String::new();
+	for (pubkey, addrs) in peer_data.iter() {
+		for addr in addrs.iter().take(MAX_ADDRESSES_PER_PEER).rev() {
+			lines.push_str(&format!("{}@{}\n", pubkey, addr));
+		}
+	}
+	let tmp_path = path.with_extension("tmp");
+	fs::write(&tmp_path, lines)?;
+	fs::rename(&tmp_path, path)
+}
+
+pub(crate) fn read_no_reconnect_peers(path: &Path) -> HashSet<PublicKey> {
+	let mut peers = HashSet::new();
+	if let Ok(file) = File::open(path) {
+		for line in BufReader::new(file).lines() {
+			if let Some(pubkey) = line.ok().and_then(|line| hex_utils::to_compressed_pubkey(&line))
+			{
+				peers.insert(pubkey);
+			}
+		}
+	}
+	peers
+}
+
+pub(crate) fn persist_no_reconnect_peers(
+	path: &Path, peers: &HashSet<PublicKey>,
+) -> std::io::Result<()> {
+	let mut file = File::create(path)?;
+	for pubkey in peers.iter() {
+		file.write_all(format!("{}\n", pubkey).as_bytes())?;
+	}
+	Ok(())
+}
+
+/// Reads payments persisted in any version of the format. Failing to read them has to stop the
+/// node, as starting with an empty history would overwrite it.
+pub(crate) fn read_payment_info(
+	persister: &NodePersister, key: &str,
+) -> Result<HashMap<PaymentHash, PaymentInfo>, String> {
+	let bytes = match persister.read(key) {
+		Ok(Some(bytes)) => bytes,
+		Ok(None) => return Ok(HashMap::new()),
+		Err(e) => return Err(format!("failed to read payments from {}: {}", key, e)),
+	};
+	decode_payments(&bytes).ok_or_else(|| {
+		format!(
+			"failed to decode payments from {}, they may have been written by a newer version",
+			key
+		)
+	})
+}
+
+pub(crate) fn decode_payments(bytes: &[u8]) -> Option<HashMap<PaymentHash, PaymentInfo>> {
+	// The version byte of versioned files can also be the first byte of a version 0 file, so fall
+	// back to version 0 if the file doesn't decode as a versioned one.
+	if let Some((&version, mut reader)) = bytes.split_first() {
+		if version == PAYMENTS_SERIALIZATION_VERSION {
+			if let Ok(payments) = HashMap::read(&mut reader) {
+				if reader.is_empty() {
+					return Some(payments);
+				}
+			}
+		}
+	}
+	// Version 0 files only differ by their lack of version byte, so there is nothing to migrate.
+	let mut reader = bytes;
+	match HashMap::read(&mut reader) {
+		Ok(payments) if reader.is_empty() => Some(payments),
+		_ => None,
+	}
+}
+
+pub(crate) fn read_forwarding_history(persister: &NodePersister) -> ForwardingHistory {
+	if let Ok(Some(bytes)) = persister.read(FORWARDING_HISTORY_FNAME) {
+		if let Ok(history) = ForwardingHistory::read(&mut &bytes[..]) {
+			return history;
+		}
+	}
+	ForwardingHistory(Vec::new())
+}
+
+pub(crate) fn read_withdraw_links(persister: &NodePersister) -> HashMap<[u8; 32], WithdrawLink> {
+	if let Ok(Some(bytes)) = persister.read(WITHDRAW_LINKS_FNAME) {
+		if let Ok(links) = HashMap::<[u8; 32], WithdrawLink>::read(&mut &bytes[..]) {
+			return links;
+		}
+	}
+	HashMap::new()
+}
+
+pub(crate) fn read_probe_stats(persister: &NodePersister) -> HashMap<(u64, PublicKey), ProbeStats> {
+	if let Ok(Some(bytes)) = persister.read(PROBE_STATS_FNAME) {
+		if let Ok(stats) = HashMap::<(u64, PublicKey), ProbeStats>::read(&mut &bytes[..]) {
+			return stats;
+		}
+	}
+	HashMap::new()
+}
+
 pub(crate) fn read_network(
-	path: &Path, network: Network, logger: Arc<FilesystemLogger>,
+	persister: &NodePersister, network: Network, logger: Arc<FilesystemLogger>,
 ) -> NetworkGraph {
-	if let Ok(file) = File::open(path) {
-		if let Ok(graph) = NetworkGraph::read(&mut BufReader::new(file), logger.clone()) {
+	if let Ok(Some(bytes)) = persister.read("network_graph") {
+		if let Ok(graph) = NetworkGraph::read(&mut &bytes[..], logger.clone()) {
 			return graph;
 		}
 	}
@@ -84,12 +203,12 @@ pub(crate) fn read_network(
 }
 
 pub(crate) fn read_scorer(
-	path: &Path, graph: Arc<NetworkGraph>, logger: Arc<FilesystemLogger>,
-) -> ProbabilisticScorer<Arc<NetworkGraph>, Arc<FilesystemLogger>> {
-	let params = ProbabilisticScoringParameters::default();
-	if let Ok(file) = File::open(path) {
+	persister: &NodePersister, graph: Arc<NetworkGraph>, logger: Arc<FilesystemLogger>,
+	params: ProbabilisticScoringParameters,
+) -> Scorer {
+	if let Ok(Some(bytes)) = persister.read("scorer") {
 		let args = (params.clone(), Arc::clone(&graph), Arc::clone(&logger));
-		if let Ok(scorer) = ProbabilisticScorer::read(&mut BufReader::new(file), args) {
+		if let Ok(scorer) = ProbabilisticScorer::read(&mut &bytes[..], args) {
 			return scorer;
 		}
 	}
@@ -0,0 +1,354 @@
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use rand::{thread_rng, Rng};
+use std::convert::TryInto;
+use std::fs;
+use std::io;
+use std::path::Path;
+use tokio::sync::{mpsc, oneshot};
+
+/// The key the encryption header is stored under, unencrypted, next to the rest of the node's
+/// state.
+pub(crate) const ENCRYPTION_HEADER_KEY: &str = "encryption";
+
+/// Prefixes every encrypted value, so they can be told apart from ones written before the node's
+/// state was encrypted.
+const MAGIC: &[u8] = b"LDKENC\x00\x01";
+const NONCE_LEN: usize = 12;
+const SALT_LEN: usize = 16;
+const HEADER_VERSION: u8 = 1;
+/// What the header's check value decrypts to, to tell a wrong passphrase apart from corrupted
+/// state.
+const CHECK_PLAINTEXT: &[u8] = b"ldk-sample encryption check";
+
+/// How to treat the node's state on startup with regards to encryption.
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum EncryptionSetup {
+	/// Unlock the state if it's encrypted, leave it unencrypted otherwise.
+	Unlock,
+	/// Encrypt a new node's state.
+	New,
+	/// Encrypt an existing node's state in place.
+	Existing,
+}
+
+/// Encrypts values with ChaCha20-Poly1305 under a key derived from the passphrase. Each value is
+/// bound to the key it's stored under, so values can't be swapped around.
+pub(crate) struct Cipher {
+	aead: ChaCha20Poly1305,
+}
+
+impl Cipher {
+	pub(crate) fn from_key(key: &[u8; 32]) -> Self {
+		Cipher { aead: ChaCha20Poly1305::new(Key::from_slice(key)) }
+	}
+
+	pub(crate) fn is_encrypted(bytes: &[u8]) -> bool {
+		bytes.starts_with(MAGIC)
+	}
+
+	/// Returns `MAGIC || nonce || ciphertext`.
+	pub(crate) fn encrypt(&self, key: &str, plaintext: &[u8]) -> Vec<u8> {
+		let mut nonce = [0; NONCE_LEN];
+		thread_rng().fill_bytes(&mut nonce);
+		let ciphertext = self
+			.aead
+			.encrypt(Nonce::from_slice(&nonce), Payload { msg: plaintext, aad: key.as_bytes() })
+			.expect("ChaCha20-Poly1305 encryption can't fail");
+		let mut bytes = Vec::with_capacity(MAGIC.len() + NONCE_LEN + ciphertext.len());
+		bytes.extend_from_slice(MAGIC);
+		bytes.extend_from_slice(&nonce);
+		bytes.extend_from_slice(&ciphertext);
+		bytes
+	}
+
+	pub(crate) fn decrypt(&self, key: &str, bytes: &[u8]) -> io::Result<Vec<u8>> {
+		if !Cipher::is_encrypted(bytes) {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidData,
+				format!(
+					"{} isn't encrypted, restart with --encrypt-existing to finish encrypting the node's state",
+					key
+				),
+			));
+		}
+		if bytes.len() < MAGIC.len() + NONCE_LEN {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidData,
+				format!("{} is truncated", key),
+			));
+		}
+		let (nonce, ciphertext) = bytes[MAGIC.len()..].split_at(NONCE_LEN);
+		self.aead
+			.decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: key.as_bytes() })
+			.map_err(|_| {
+				io::Error::new(io::ErrorKind::InvalidData, format!("failed to decrypt {}", key))
+			})
+	}
+}
+
+/// The key derivation parameters and salt, along with a check value encrypted under the derived
+/// key, serialized as `version || m_cost || t_cost || p_cost || salt || check`.
+pub(crate) struct EncryptionHeader {
+	m_cost: u32,
+	t_cost: u32,
+	p_cost: u32,
+	salt: [u8; SALT_LEN],
+	check: Vec<u8>,
+}
+
+impl EncryptionHeader {
+	/// Derives a key from `passphrase` under a fresh salt.
+	pub(crate) fn new(passphrase: &str) -> Result<(Self, Cipher), String> {
+		let mut salt = [0; SALT_LEN];
+		thread_rng().fill_bytes(&mut salt);
+		let mut header = EncryptionHeader {
+			m_cost: Params::DEFAULT_M_COST,
+			t_cost: Params::DEFAULT_T_COST,
+			p_cost: Params::DEFAULT_P_COST,
+			salt,
+			check: Vec::new(),
+		};
+		let cipher = header.derive_cipher(passphrase)?;
+		header.check = cipher.encrypt(ENCRYPTION_HEADER_KEY, CHECK_PLAINTEXT);
+		Ok((header, cipher))
+	}
+
+	pub(crate) fn unlock(&self, passphrase: &str) -> Result<Cipher, String> {
+		let cipher = self.derive_cipher(passphrase)?;
+		match cipher.decrypt(ENCRYPTION_HEADER_KEY, &self.check) {
+			Ok(check) if check == CHECK_PLAINTEXT => Ok(cipher),
+			_ => Err("wrong passphrase".to_string()),
+		}
+	}
+
+	fn derive_cipher(&self, passphrase: &str) -> Result<Cipher, String> {
+		let params = Params::new(self.m_cost, self.t_cost, self.p_cost, Some(32))
+			.map_err(|e| format!("invalid key derivation parameters: {}", e))?;
+		let mut key = [0; 32];
+		Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+			.hash_password_into(passphrase.as_bytes(), &self.salt, &mut key)
+			.map_err(|e| format!("failed to derive the encryption key: {}", e))?;
+		Ok(Cipher::from_key(&key))
+	}
+
+	pub(crate) fn encode(&self) -> Vec<u8> {
+		let mut bytes = vec![HEADER_VERSION];
+		bytes.extend_from_slice(&self.m_cost.to_be_bytes());
+		bytes.extend_from_slice(&self.t_cost.to_be_bytes());
+		bytes.extend_from_slice(&self.p_cost.to_be_bytes());
+		bytes.extend_from_slice(&self.salt);
+		bytes.extend_from_slice(&self.check);
+		bytes
+	}
+
+	pub(crate) fn decode(bytes: &[u8]) -> Result<Self, String> {
+		if bytes.len() < 1 + 12 + SALT_LEN || bytes[0] != HEADER_VERSION {
+			return Err("unknown encryption header format".to_string());
+		}
+		let u32_at = |i: usize| u32::from_be_bytes(bytes[i..i + 4].try_into().unwrap());
+		Ok(EncryptionHeader {
+			m_cost: u32_at(1),
+			t_cost: u32_at(5),
+			p_cost: u32_at(9),
+			salt: bytes[13..13 + SALT_LEN].try_into().unwrap(),
+			check: bytes[13 + SALT_LEN..].to_vec(),
+		})
+	}
+}
+
+/// Reads the passphrase from `passphrase_file` if given, or prompts for it, twice if `confirm`.
+pub(crate) fn read_passphrase(
+	passphrase_file: Option<&Path>, confirm: bool,
+) -> Result<String, String> {
+	if let Some(path) = passphrase_file {
+		let passphrase = fs::read_to_string(path)
+			.map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+		return Ok(passphrase.trim_end_matches(&['\r', '\n'][..]).to_string());
+	}
+	let prompt = |msg: &str| {
+		rpassword::prompt_password(msg).map_err(|e| format!("failed to read passphrase: {}", e))
+	};
+	let passphrase = prompt("Passphrase: ")?;
+	if confirm {
+		if passphrase.is_empty() {
+			return Err("the passphrase can't be empty".to_string());
+		}
+		if prompt("Repeat passphrase: ")? != passphrase {
+			return Err("passphrases don't match".to_string());
+		}
+	}
+	Ok(passphrase)
+}
+
+/// A passphrase given through `unlock`, with where to say whether it was the right one.
+type UnlockRequest = (String, oneshot::Sender<Result<(), String>>);
+
+/// Sends the passphrases given through `unlock` to the node while it's locked.
+#[derive(Clone)]
+pub(crate) struct Unlocker {
+	requests: mpsc::UnboundedSender<UnlockRequest>,
+}
+
+impl Unlocker {
+	/// Returns once the node checked the passphrase, with why it refused it if it did.
+	pub(crate) async fn unlock(&self, passphrase: String) -> Result<(), String> {
+		let (reply_sender, reply) = oneshot::channel();
+		if self.requests.send((passphrase, reply_sender)).is_err() {
+			return Err("the node is already unlocked".to_string());
+		}
+		reply.await.unwrap_or_else(|_| Err("the node stopped waiting to be unlocked".to_string()))
+	}
+
+	pub(crate) fn is_unlocked(&self) -> bool {
+		self.requests.is_closed()
+	}
+}
+
+/// The passphrases given through `unlock`, for the node to wait on while it's locked.
+pub(crate) struct UnlockRequests {
+	requests: mpsc::UnboundedReceiver<UnlockRequest>,
+}
+
+impl UnlockRequests {
+	/// Waits for `unlock` to be given the passphrase `header` was created with.
+	pub(crate) async fn wait(mut self, header: &EncryptionHeader) -> Result<Cipher, String> {
+		while let Some((passphrase, reply)) = self.requests.recv().await {
+			match header.unlock(&passphrase) {
+				Ok(cipher) => {
+					let _ = reply.send(Ok(()));
+					return Ok(cipher);
+				}
+				Err(e) => {
+					let _ = reply.send(Err(e));
+				}
+			}
+		}
+		Err("nothing is left to unlock the node through".to_string())
+	}
+}
+
+/// Returns both ends of the lock the node waits on when started with `--locked`.
+pub(crate) fn lock() -> (Unlocker, UnlockRequests) {
+	let (requests_sender, requests) = mpsc::unbounded_channel();
+	(Unlocker { requests: requests_sender }, UnlockRequests { requests })
+}
+
+#[cfg(test)]
+mod encryption_tests {
+	use super::*;
+
+	const PASSPHRASE: &str = "correct horse battery staple";
+
+	/// A header with the cheapest key derivation parameters, to keep the tests fast.
+	fn test_header(passphrase: &str) -> (EncryptionHeader, Cipher) {
+		let mut header = EncryptionHeader {
+			m_cost: Params::MIN_M_COST,
+			t_cost: Params::MIN_T_COST,
+			p_cost: Params::MIN_P_COST,
+			salt: [42; SALT_LEN],
+			check: Vec::new(),
+		};
+		let cipher = header.derive_cipher(passphrase).unwrap();
+		header.check = cipher.encrypt(ENCRYPTION_HEADER_KEY, CHECK_PLAINTEXT);
+		(header, cipher)
+	}
+
+	#[test]
+	fn test_encrypt_decrypt_round_trip() {
+		let cipher = Cipher::from_key(&[1; 32]);
+		let bytes = cipher.encrypt("manager", b"channel manager");
+		assert!(Cipher::is_encrypted(&bytes));
+		assert_eq!(cipher.decrypt("manager", &bytes).unwrap(), b"channel manager");
+
+		let empty = cipher.encrypt("manager", b"");
+		assert_eq!(cipher.decrypt("manager", &empty).unwrap(), b"");
+	}
+
+	#[test]
+	fn test_decrypt_wrong_key() {
+		let bytes = Cipher::from_key(&[1; 32]).encrypt("manager", b"channel manager");
+		assert!(Cipher::from_key(&[2; 32]).decrypt("manager", &bytes).is_err());
+	}
+
+	#[test]
+	fn test_decrypt_under_another_storage_key() {
+		let cipher = Cipher::from_key(&[1; 32]);
+		let bytes = cipher.encrypt("manager", b"channel manager");
+		assert!(cipher.decrypt("scorer", &bytes).is_err());
+	}
+
+	#[test]
+	fn test_decrypt_tampered() {
+		let cipher = Cipher::from_key(&[1; 32]);
+		let bytes = cipher.encrypt("manager", b"channel manager");
+		for i in MAGIC.len()..bytes.len() {
+			let mut tampered = bytes.clone();
+			tampered[i] ^= 1;
+			assert!(cipher.decrypt("manager", &tampered).is_err());
+		}
+		assert!(cipher.decrypt("manager", &bytes[..bytes.len() - 1]).is_err());
+		assert!(cipher.decrypt("manager", &bytes[..MAGIC.len() + NONCE_LEN - 1]).is_err());
+	}
+
+	#[test]
+	fn test_decrypt_unencrypted() {
+		let cipher = Cipher::from_key(&[1; 32]);
+		assert!(!Cipher::is_encrypted(b"channel manager"));
+		assert!(cipher.decrypt("manager", b"channel manager").is_err());
+	}
+
+	#[test]
+	fn test_header_encode_decode_round_trip() {
+		let (header, _) = test_header(PASSPHRASE);
+		let decoded = EncryptionHeader::decode(&header.encode()).unwrap();
+		assert_eq!(decoded.m_cost, header.m_cost);
+		assert_eq!(decoded.t_cost, header.t_cost);
+		assert_eq!(decoded.p_cost, header.p_cost);
+		assert_eq!(decoded.salt, header.salt);
+		assert_eq!(decoded.check, header.check);
+		assert_eq!(decoded.encode(), header.encode());
+	}
+
+	#[test]
+	fn test_header_decode_malformed() {
+		let (header, _) = test_header(PASSPHRASE);
+		let bytes = header.encode();
+		assert!(EncryptionHeader::decode(&bytes[..1 + 12 + SALT_LEN - 1]).is_err());
+		let mut unknown_version = bytes.clone();
+		unknown_version[0] = HEADER_VERSION + 1;
+		assert!(EncryptionHeader::decode(&unknown_version).is_err());
+		assert!(EncryptionHeader::decode(&[]).is_err());
+	}
+
+	#[test]
+	fn test_header_unlock() {
+		let (header, cipher) = test_header(PASSPHRASE);
+		let decoded = EncryptionHeader::decode(&header.encode()).unwrap();
+		let unlocked = decoded.unlock(PASSPHRASE).unwrap();
+		let bytes = cipher.encrypt("manager", b"channel manager");
+		assert_eq!(unlocked.decrypt("manager", &bytes).unwrap(), b"channel manager");
+	}
+
+	#[test]
+	fn test_header_unlock_wrong_passphrase() {
+		let (header, _) = test_header(PASSPHRASE);
+		assert_eq!(header.unlock("wrong passphrase").err().unwrap(), "wrong passphrase");
+	}
+
+	#[test]
+	fn test_header_unlock_tampered() {
+		let (header, _) = test_header(PASSPHRASE);
+		let mut bytes = header.encode();
+		// Changing the salt derives another key, which the check value doesn't decrypt under.
+		bytes[13] ^= 1;
+		let tampered = EncryptionHeader::decode(&bytes).unwrap();
+		assert!(tampered.unlock(PASSPHRASE).is_err());
+		let mut bytes = header.encode();
+		let last = bytes.len() - 1;
+		bytes[last] ^= 1;
+		let tampered = EncryptionHeader::decode(&bytes).unwrap();
+		assert!(tampered.unlock(PASSPHRASE).is_err());
+	}
+}
@@ -0,0 +1,166 @@
+use bech32::{FromBase32, ToBase32, Variant};
+use bitcoin::hashes::sha256::Hash as Sha256;
+use bitcoin::hashes::Hash;
+use lightning_invoice::{Invoice, InvoiceDescription};
+use serde_json::Value;
+use std::str::FromStr;
+use std::time::Duration;
+
+const HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Turns a bech32-encoded `lnurl1...` string (optionally prefixed with `lightning:`) into the URL
+/// it encodes.
+pub(crate) fn decode_lnurl(lnurl: &str) -> Result<String, String> {
+	let lnurl = lnurl.trim();
+	let lnurl = match lnurl.get(..10) {
+		Some(prefix) if prefix.eq_ignore_ascii_case("lightning:") => &lnurl[10..],
+		_ => lnurl,
+	};
+	let (hrp, data, _) = bech32::decode(lnurl).map_err(|e| format!("invalid lnurl: {}", e))?;
+	if hrp != "lnurl" {
+		return Err("invalid lnurl: expected an lnurl1... string".to_string());
+	}
+	let bytes = Vec::<u8>::from_base32(&data).map_err(|e| format!("invalid lnurl: {}", e))?;
+	String::from_utf8(bytes).map_err(|_| "invalid lnurl: not a URL".to_string())
+}
+
+/// Encodes `url` as an `LNURL1...` string, uppercased as wallets expect in QR codes.
+pub(crate) fn encode_lnurl(url: &str) -> String {
+	bech32::encode("lnurl", url.as_bytes().to_base32(), Variant::Bech32).unwrap().to_uppercase()
+}
+
+/// Returns the LNURL-pay URL behind a `user@domain` Lightning Address, as specified by LUD-16.
+pub(crate) fn address_to_url(address: &str) -> Result<String, String> {
+	let mut parts = address.splitn(2, '@');
+	match (parts.next(), parts.next()) {
+		(Some(user), Some(domain)) if !user.is_empty() && !domain.is_empty() => {
+			let scheme = if domain.ends_with(".onion") { "http" } else { "https" };
+			Ok(format!("{}://{}/.well-known/lnurlp/{}", scheme, domain, user.to_lowercase()))
+		}
+		_ => Err("invalid lightning address: expected user@domain".to_string()),
+	}
+}
+
+async fn get_json(client: &reqwest::Client, url: &str) -> Result<Value, String> {
+	let response: Value = client
+		.get(url)
+		.send()
+		.await
+		.map_err(|e| format!("request to {} failed: {}", url, e))?
+		.json()
+		.await
+		.map_err(|e| format!("invalid response from {}: {}", url, e))?;
+	if response["status"].as_str().map_or(false, |s| s.eq_ignore_ascii_case("ERROR")) {
+		return Err(format!(
+			"{} returned an error: {}",
+			url,
+			response["reason"].as_str().unwrap_or("no reason given")
+		));
+	}
+	Ok(response)
+}
+
+/// Runs the LNURL-pay flow (LUD-06) against `url`: fetches the pay parameters, requests an invoice
+/// for `amt_msat` from the callback and checks it actually commits to that amount and to the
+/// metadata we were shown. The returned invoice still has to be paid.
+pub(crate) async fn fetch_invoice(url: &str, amt_msat: u64) -> Result<Invoice, String> {
+	let client = reqwest::Client::builder()
+		.timeout(HTTP_TIMEOUT)
+		.build()
+		.map_err(|e| format!("failed to create HTTP client: {}", e))?;
+
+	let params = get_json(&client, url).await?;
+	if params["tag"].as_str() != Some("payRequest") {
+		return Err(format!("{} is not an LNURL-pay endpoint", url));
+	}
+	let (callback, min_sendable, max_sendable, metadata) = match (
+		params["callback"].as_str(),
+		params["minSendable"].as_u64(),
+		params["maxSendable"].as_u64(),
+		params["metadata"].as_str(),
+	) {
+		(Some(callback), Some(min), Some(max), Some(metadata)) => (callback, min, max, metadata),
+		_ => return Err(format!("{} returned malformed pay parameters", url)),
+	};
+	if amt_msat < min_sendable || amt_msat > max_sendable {
+		return Err(format!(
+			"amount must be between {} and {} msats for this recipient",
+			min_sendable, max_sendable
+		));
+	}
+
+	let separator = if callback.contains('?') { '&' } else { '?' };
+	let callback_url = format!("{}{}amount={}", callback, separator, amt_msat);
+	let response = get_json(&client, &callback_url).await?;
+	let invoice = match response["pr"].as_str().map(Invoice::from_str) {
+		Some(Ok(invoice)) => invoice,
+		Some(Err(e)) => return Err(format!("recipient returned an invalid invoice: {:?}", e)),
+		None => return Err("recipient didn't return an invoice".to_string()),
+	};
+
+	if invoice.amount_milli_satoshis() != Some(amt_msat) {
+		return Err("recipient returned an invoice for the wrong amount".to_string());
+	}
+	match invoice.description() {
+		InvoiceDescription::Hash(hash) if hash.0 == Sha256::hash(metadata.as_bytes()) => {}
+		_ => return Err("invoice description hash doesn't match the LNURL metadata".to_string()),
+	}
+	Ok(invoice)
+}
+
+#[cfg(test)]
+mod lnurl_tests {
+	use super::*;
+
+	// The example of LUD-01.
+	const LNURL: &str = "LNURL1DP68GURN8GHJ7UM9WFMXJCM99E3K7MF0V9CXJ0M385EKVCENXC6R2C35XVUKXEFCV5MKVV34X5EKZD3EV56NYD3HXQURZEPEXEJXXEPNXSCRVWFNV9NXZCN9XQ6XYEFHVGCXXCMYXYMNSERXFQ5FNS";
+	const URL: &str =
+		"https://service.com/api?q=3fc3645b439ce8e7f2553a69e5267081d96dcd340693afabe04be7b0ccd178df";
+
+	#[test]
+	fn test_decode_lnurl() {
+		assert_eq!(decode_lnurl(LNURL).unwrap(), URL);
+		assert_eq!(decode_lnurl(&LNURL.to_lowercase()).unwrap(), URL);
+		assert_eq!(decode_lnurl(&format!("lightning:{}", LNURL)).unwrap(), URL);
+		assert_eq!(decode_lnurl(&format!("LIGHTNING:{}\n", LNURL)).unwrap(), URL);
+	}
+
+	#[test]
+	fn test_decode_lnurl_invalid() {
+		// Bad checksum.
+		let mut bad_checksum = LNURL.to_string();
+		bad_checksum.pop();
+		bad_checksum.push('Q');
+		assert!(decode_lnurl(&bad_checksum).is_err());
+		// Not an lnurl.
+		let other_hrp =
+			bech32::encode("lnbc", URL.as_bytes().to_base32(), Variant::Bech32).unwrap();
+		assert!(decode_lnurl(&other_hrp).is_err());
+		// Not UTF-8.
+		let not_utf8 = bech32::encode("lnurl", [0xff, 0xfe].to_base32(), Variant::Bech32).unwrap();
+		assert!(decode_lnurl(&not_utf8).is_err());
+		assert!(decode_lnurl("").is_err());
+	}
+
+	#[test]
+	fn test_address_to_url() {
+		assert_eq!(
+			address_to_url("Satoshi@example.com").unwrap(),
+			"https://example.com/.well-known/lnurlp/satoshi"
+		);
+		assert_eq!(
+			address_to_url("alice@example.onion").unwrap(),
+			"http://example.onion/.well-known/lnurlp/alice"
+		);
+		assert!(address_to_url("example.com").is_err());
+		assert!(address_to_url("@example.com").is_err());
+		assert!(address_to_url("alice@").is_err());
+	}
+
+	#[test]
+	fn test_encode_lnurl() {
+		assert_eq!(encode_lnurl(URL), LNURL);
+		let url = "https://example.com/lnurlw/00?k1=ab";
+		assert_eq!(decode_lnurl(&encode_lnurl(url)).unwrap(), url);
+	}
+}
@@ -1,65 +1,121 @@
+mod api;
 mod args;
+mod backup;
 pub mod bitcoind_client;
+mod bootstrap;
 mod cli;
 mod convert;
 mod disk;
+mod encryption;
 mod hex_utils;
+mod lnurl;
+mod mnemonic;
+mod persist;
+mod proxy;
+mod rgs;
+mod router;
+mod scb;
+mod signer;
+mod snapshot;
+mod tor;
+mod verify;
+mod wallet;
 
 use crate::bitcoind_client::BitcoindClient;
 use crate::disk::FilesystemLogger;
-use bitcoin::blockdata::transaction::Transaction;
-use bitcoin::consensus::encode;
+use crate::mnemonic::MnemonicSetup;
+use crate::persist::{JournalingPersister, NodePersister};
+use crate::proxy::PeerAddr;
+use crate::signer::{ChannelKeys, NodeKeys, RemoteSigner};
+use crate::tor::TorControl;
+use crate::wallet::{AddressType, BdkWallet, OnchainWallet, WalletRpcConfig};
+use bitcoin::blockdata::block::{Block, BlockHeader};
+use bitcoin::blockdata::script::Script;
 use bitcoin::network::constants::Network;
-use bitcoin::secp256k1::Secp256k1;
+use bitcoin::secp256k1::{PublicKey, Secp256k1};
 use bitcoin::BlockHash;
-use bitcoin_bech32::WitnessProgram;
 use lightning::chain;
 use lightning::chain::chaininterface::{BroadcasterInterface, ConfirmationTarget, FeeEstimator};
-use lightning::chain::keysinterface::{EntropySource, InMemorySigner, KeysManager};
+use lightning::chain::channelmonitor::ANTI_REORG_DELAY;
+use lightning::chain::keysinterface::{EntropySource, KeysManager, PhantomKeysManager};
 use lightning::chain::{chainmonitor, ChannelMonitorUpdateStatus};
 use lightning::chain::{Filter, Watch};
 use lightning::ln::channelmanager;
-use lightning::ln::channelmanager::{
-	ChainParameters, ChannelManagerReadArgs, SimpleArcChannelManager,
-};
-use lightning::ln::peer_handler::{IgnoringMessageHandler, MessageHandler, SimpleArcPeerManager};
+use lightning::ln::channelmanager::{ChainParameters, ChannelManagerReadArgs, PaymentId};
+use lightning::ln::msgs::DecodeError;
+use lightning::ln::peer_handler;
+use lightning::ln::peer_handler::{IgnoringMessageHandler, MessageHandler};
 use lightning::ln::{PaymentHash, PaymentPreimage, PaymentSecret};
-use lightning::onion_message::SimpleArcOnionMessenger;
+use lightning::onion_message;
 use lightning::routing::gossip;
 use lightning::routing::gossip::{NodeId, P2PGossipSync};
-use lightning::routing::router::DefaultRouter;
+use lightning::routing::router::RouteHop;
+use lightning::routing::scoring::ProbabilisticScorer;
 use lightning::util::config::UserConfig;
 use lightning::util::events::{Event, PaymentPurpose};
-use lightning::util::ser::ReadableArgs;
+use lightning::util::logger::Logger;
+use lightning::util::persist::KVStorePersister;
+use lightning::util::ser::{Readable, ReadableArgs, Writeable, Writer};
+use lightning::{
+	impl_writeable_tlv_based, impl_writeable_tlv_based_enum, log_error, log_info, log_warn,
+};
 use lightning_background_processor::{BackgroundProcessor, GossipSync};
 use lightning_block_sync::init;
 use lightning_block_sync::poll;
 use lightning_block_sync::SpvClient;
 use lightning_block_sync::UnboundedCache;
 use lightning_net_tokio::SocketDescriptor;
-use lightning_persister::FilesystemPersister;
 use rand::{thread_rng, Rng};
+use std::cmp;
 use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::convert::TryInto;
+use std::env;
 use std::fmt;
 use std::fs;
 use std::fs::File;
 use std::io;
 use std::io::Write;
+use std::net::SocketAddr;
 use std::path::Path;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::sync::{Arc, Mutex};
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant, SystemTime};
+#[cfg(not(target_os = "windows"))]
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{broadcast, mpsc, oneshot};
 
+#[derive(Clone, Copy, PartialEq)]
 pub(crate) enum HTLCStatus {
 	Pending,
+	/// A payment to a hold invoice arrived, and waits to be settled or canceled.
+	Held,
 	Succeeded,
 	Failed,
 }
 
+impl_writeable_tlv_based_enum!(HTLCStatus,
+	(0, Pending) => {},
+	(1, Succeeded) => {},
+	(2, Failed) => {},
+	(3, Held) => {};
+);
+
 pub(crate) struct MillisatAmount(Option<u64>);
 
+impl Writeable for MillisatAmount {
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), io::Error> {
+		self.0.write(w)
+	}
+}
+
+impl Readable for MillisatAmount {
+	fn read<R: io::Read>(r: &mut R) -> Result<Self, DecodeError> {
+		let amt: Option<u64> = Readable::read(r)?;
+		Ok(MillisatAmount(amt))
+	}
+}
+
 impl fmt::Display for MillisatAmount {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 		match self.0 {
@@ -74,40 +130,318 @@ pub(crate) struct PaymentInfo {
 	secret: Option<PaymentSecret>,
 	status: HTLCStatus,
 	amt_msat: MillisatAmount,
+	fee_paid_msat: Option<u64>,
+	/// When the invoice was created (inbound) or the payment was initiated (outbound), in seconds
+	/// since the UNIX epoch.
+	created_at: u64,
+	/// When the payment was claimed (inbound) or its preimage received (outbound).
+	settled_at: Option<u64>,
+	/// The invoice we created for an inbound payment, if any.
+	invoice: Option<String>,
 }
 
+impl_writeable_tlv_based!(PaymentInfo, {
+	(0, preimage, required),
+	(2, secret, required),
+	(4, status, required),
+	(6, amt_msat, required),
+	(8, fee_paid_msat, option),
+	(10, created_at, required),
+	(12, settled_at, option),
+	(14, invoice, option),
+});
+
 pub(crate) type PaymentInfoStorage = Arc<Mutex<HashMap<PaymentHash, PaymentInfo>>>;
 
-type ChainMonitor = chainmonitor::ChainMonitor<
-	InMemorySigner,
+/// The format payments are persisted in. Version 0 is the bare map written before the format was
+/// versioned; see `disk::read_payment_info` for how older versions are migrated.
+pub(crate) const PAYMENTS_SERIALIZATION_VERSION: u8 = 1;
+
+/// Writes a payments map prefixed with `PAYMENTS_SERIALIZATION_VERSION`.
+pub(crate) struct VersionedPayments<'a>(pub(crate) &'a HashMap<PaymentHash, PaymentInfo>);
+
+impl<'a> Writeable for VersionedPayments<'a> {
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), io::Error> {
+		PAYMENTS_SERIALIZATION_VERSION.write(w)?;
+		self.0.write(w)
+	}
+}
+
+/// Writes the payments map stored under `key`. A full disk or a storage backend hiccup is reported
+/// rather than taking the node down in the middle of a payment.
+pub(crate) fn persist_payments(
+	persister: &NodePersister, key: &str, payments: &HashMap<PaymentHash, PaymentInfo>,
+) -> Result<(), ()> {
+	persister.persist(key, &VersionedPayments(payments)).map_err(|e| {
+		println!("ERROR: failed to persist {}: {}", key, e);
+	})
+}
+
+/// A payment we forwarded, as reported by `Event::PaymentForwarded`.
+pub(crate) struct ForwardInfo {
+	prev_channel_id: Option<[u8; 32]>,
+	next_channel_id: Option<[u8; 32]>,
+	fee_earned_msat: Option<u64>,
+	claim_from_onchain_tx: bool,
+	/// In seconds since the UNIX epoch.
+	forwarded_at: u64,
+}
+
+impl_writeable_tlv_based!(ForwardInfo, {
+	(0, prev_channel_id, option),
+	(2, next_channel_id, option),
+	(4, fee_earned_msat, option),
+	(6, claim_from_onchain_tx, required),
+	(8, forwarded_at, required),
+});
+
+/// The most forwards `ForwardingHistory` keeps, as it's rewritten in full after every forward.
+const FORWARDING_HISTORY_MAX_LEN: usize = 10_000;
+
+/// The last `FORWARDING_HISTORY_MAX_LEN` forwards we've made, oldest first.
+pub(crate) struct ForwardingHistory(Vec<ForwardInfo>);
+
+impl ForwardingHistory {
+	/// Records `forward`, dropping the oldest forwards once the history is full.
+	fn push(&mut self, forward: ForwardInfo) {
+		if self.0.len() >= FORWARDING_HISTORY_MAX_LEN {
+			self.0.drain(..self.0.len() + 1 - FORWARDING_HISTORY_MAX_LEN);
+		}
+		self.0.push(forward);
+	}
+}
+
+impl Writeable for ForwardingHistory {
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), io::Error> {
+		(self.0.len() as u64).write(w)?;
+		for forward in self.0.iter() {
+			forward.write(w)?;
+		}
+		Ok(())
+	}
+}
+
+impl Readable for ForwardingHistory {
+	fn read<R: io::Read>(r: &mut R) -> Result<Self, DecodeError> {
+		let len: u64 = Readable::read(r)?;
+		let mut forwards = Vec::new();
+		for _ in 0..len {
+			forwards.push(Readable::read(r)?);
+		}
+		Ok(ForwardingHistory(forwards))
+	}
+}
+
+pub(crate) type ForwardingHistoryStorage = Arc<Mutex<ForwardingHistory>>;
+
+/// An LNURL-withdraw link issued through the HTTP API, which can be redeemed once.
+pub(crate) struct WithdrawLink {
+	min_withdrawable_msat: u64,
+	max_withdrawable_msat: u64,
+	description: String,
+	/// In seconds since the UNIX epoch.
+	expires_at: u64,
+	redeemed: bool,
+}
+
+impl_writeable_tlv_based!(WithdrawLink, {
+	(0, min_withdrawable_msat, required),
+	(2, max_withdrawable_msat, required),
+	(4, description, required),
+	(6, expires_at, required),
+	(8, redeemed, required),
+});
+
+/// Keyed by the link's `k1` secret.
+pub(crate) type WithdrawLinkStorage = Arc<Mutex<HashMap<[u8; 32], WithdrawLink>>>;
+
+/// Connection bookkeeping on top of what the `PeerManager` tracks.
+pub(crate) struct PeerConnections {
+	/// When each connected peer was first seen connected, in seconds since the UNIX epoch.
+	connected_since: HashMap<PublicKey, u64>,
+	/// Channel peers we don't automatically reconnect to, persisted in `no_reconnect_peers`.
+	no_reconnect: HashSet<PublicKey>,
+}
+
+pub(crate) type PeerConnectionsStorage = Arc<Mutex<PeerConnections>>;
+
+/// The results of our probes through one channel, towards `target` in the key of
+/// `ProbeStatsStorage`.
+#[derive(Default)]
+pub(crate) struct ProbeStats {
+	successes: u64,
+	failures: u64,
+	/// In seconds since the UNIX epoch.
+	last_probed_at: u64,
+}
+
+impl_writeable_tlv_based!(ProbeStats, {
+	(0, successes, required),
+	(2, failures, required),
+	(4, last_probed_at, required),
+});
+
+/// Keyed by short channel ID and the node the probes were going to through that channel.
+pub(crate) type ProbeStatsStorage = Arc<Mutex<HashMap<(u64, PublicKey), ProbeStats>>>;
+
+/// How often background probing runs, and what it probes.
+#[derive(Clone, PartialEq)]
+pub(crate) struct ProbeConfig {
+	pub(crate) interval: Duration,
+	/// The amount each round tries to send to every target.
+	pub(crate) amount_msat: u64,
+	/// The nodes to probe, or the `num_targets` best-connected nodes of the graph if empty.
+	pub(crate) targets: Vec<PublicKey>,
+	pub(crate) num_targets: usize,
+}
+
+impl Default for ProbeConfig {
+	fn default() -> Self {
+		ProbeConfig {
+			interval: Duration::from_secs(0),
+			amount_msat: 50_000_000,
+			targets: Vec::new(),
+			num_targets: 3,
+		}
+	}
+}
+
+/// The output a channel opened with `openchannel --psbt` must be funded with.
+#[derive(Clone)]
+pub(crate) struct ExternalFunding {
+	pub(crate) counterparty_node_id: PublicKey,
+	pub(crate) output_script: Script,
+	pub(crate) channel_value_satoshis: u64,
+}
+
+/// Channels opened with `openchannel --psbt`, keyed by their temporary channel ID, with their
+/// funding output once the peer accepted them.
+pub(crate) type ExternalFundingStorage = Arc<Mutex<HashMap<[u8; 32], Option<ExternalFunding>>>>;
+
+/// How often we poll bitcoind for new blocks when we're also getting ZMQ notifications for them.
+const ZMQ_FALLBACK_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long syncing to bitcoind's best block may keep failing before we alert the operator.
+const BLOCK_SYNC_ALERT_AFTER: Duration = Duration::from_secs(300);
+
+/// Passes blocks on to `listener`, counting those disconnected since we last looked so the block
+/// sync loop can tell how deep a reorg went.
+struct ReorgTracker<L: chain::Listen> {
+	listener: L,
+	blocks_disconnected: AtomicU32,
+}
+
+impl<L: chain::Listen> chain::Listen for ReorgTracker<L> {
+	fn filtered_block_connected(
+		&self, header: &BlockHeader, txdata: &chain::transaction::TransactionData, height: u32,
+	) {
+		self.listener.filtered_block_connected(header, txdata, height);
+	}
+
+	fn block_connected(&self, block: &Block, height: u32) {
+		self.listener.block_connected(block, height);
+	}
+
+	fn block_disconnected(&self, header: &BlockHeader, height: u32) {
+		self.blocks_disconnected.fetch_add(1, Ordering::AcqRel);
+		self.listener.block_disconnected(header, height);
+	}
+}
+
+/// Records a probe result for every hop of `path`: hops before the one that failed (all of them if
+/// `failed_short_channel_id` is `None`) could forward the amount, the failed one couldn't.
+fn record_probe(
+	probe_stats: &ProbeStatsStorage, persister: &NodePersister, path: &[RouteHop],
+	failed_short_channel_id: Option<u64>,
+) {
+	let mut probe_stats = probe_stats.lock().unwrap();
+	let now = time_now_secs();
+	for hop in path {
+		let stats = probe_stats.entry((hop.short_channel_id, hop.pubkey)).or_default();
+		stats.last_probed_at = now;
+		if Some(hop.short_channel_id) == failed_short_channel_id {
+			stats.failures += 1;
+			break;
+		}
+		stats.successes += 1;
+	}
+	if let Err(e) = persister.persist(PROBE_STATS_FNAME, &*probe_stats) {
+		println!("ERROR: failed to persist the probe stats: {}", e);
+	}
+}
+
+pub(crate) const INBOUND_PAYMENTS_FNAME: &str = "inbound_payments";
+pub(crate) const OUTBOUND_PAYMENTS_FNAME: &str = "outbound_payments";
+pub(crate) const FORWARDING_HISTORY_FNAME: &str = "forwarding_history";
+pub(crate) const WITHDRAW_LINKS_FNAME: &str = "withdraw_links";
+pub(crate) const PROBE_STATS_FNAME: &str = "probe_stats";
+pub(crate) const KEYS_SEED_FNAME: &str = "keys_seed";
+
+/// How long we first wait to reconnect to a channel peer we failed to reconnect to, doubled with
+/// every failure up to `RECONNECT_MAX_BACKOFF`.
+const RECONNECT_MIN_BACKOFF: Duration = Duration::from_secs(2);
+const RECONNECT_MAX_BACKOFF: Duration = Duration::from_secs(3600);
+
+/// How many peers a new node connects to through the DNS seeds.
+pub(crate) const BOOTSTRAP_PEERS: usize = 3;
+
+/// How long a graceful shutdown waits for pending `ChannelMonitor` updates before giving up.
+const MONITOR_PERSISTENCE_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub(crate) fn time_now_secs() -> u64 {
+	SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs()
+}
+
+pub(crate) type ChainMonitor = chainmonitor::ChainMonitor<
+	ChannelKeys,
 	Arc<dyn Filter + Send + Sync>,
 	Arc<BitcoindClient>,
 	Arc<BitcoindClient>,
 	Arc<FilesystemLogger>,
-	Arc<FilesystemPersister>,
+	Arc<JournalingPersister>,
 >;
 
-pub(crate) type PeerManager = SimpleArcPeerManager<
+pub(crate) type PeerManager = peer_handler::PeerManager<
 	SocketDescriptor,
-	ChainMonitor,
-	BitcoindClient,
-	BitcoindClient,
-	BitcoindClient,
-	FilesystemLogger,
+	Arc<ChannelManager>,
+	Arc<P2PGossipSync<Arc<NetworkGraph>, Arc<BitcoindClient>, Arc<FilesystemLogger>>>,
+	Arc<OnionMessenger>,
+	Arc<FilesystemLogger>,
+	IgnoringMessageHandler,
+	Arc<NodeKeys>,
 >;
 
-pub(crate) type ChannelManager =
-	SimpleArcChannelManager<ChainMonitor, BitcoindClient, BitcoindClient, FilesystemLogger>;
+pub(crate) type ChannelManager = channelmanager::ChannelManager<
+	Arc<ChainMonitor>,
+	Arc<BitcoindClient>,
+	Arc<NodeKeys>,
+	Arc<NodeKeys>,
+	Arc<NodeKeys>,
+	Arc<BitcoindClient>,
+	Arc<Router>,
+	Arc<FilesystemLogger>,
+>;
 
 pub(crate) type NetworkGraph = gossip::NetworkGraph<Arc<FilesystemLogger>>;
 
-type OnionMessenger = SimpleArcOnionMessenger<FilesystemLogger>;
+type OnionMessenger = onion_message::OnionMessenger<
+	Arc<NodeKeys>,
+	Arc<NodeKeys>,
+	Arc<FilesystemLogger>,
+	IgnoringMessageHandler,
+>;
+
+pub(crate) type Scorer = ProbabilisticScorer<Arc<NetworkGraph>, Arc<FilesystemLogger>>;
+
+pub(crate) type Router = router::ConstrainedRouter;
 
 async fn handle_ldk_events(
-	channel_manager: &Arc<ChannelManager>, bitcoind_client: &BitcoindClient,
-	network_graph: &NetworkGraph, keys_manager: &KeysManager,
+	channel_manager: &Arc<ChannelManager>, router: &Router, bitcoind_client: &BitcoindClient,
+	wallet: &OnchainWallet, network_graph: &NetworkGraph, keys_manager: &NodeKeys,
 	inbound_payments: &PaymentInfoStorage, outbound_payments: &PaymentInfoStorage,
-	network: Network, event: &Event,
+	forwarding_history: &ForwardingHistoryStorage, probe_stats: &ProbeStatsStorage,
+	external_fundings: &ExternalFundingStorage, persister: &NodePersister, network: Network,
+	accept_keysend: bool, sweep_address_type: AddressType, event: &Event,
 ) {
 	match event {
 		Event::FundingGenerationReady {
@@ -117,41 +451,48 @@ async fn handle_ldk_events(
 			output_script,
 			..
 		} => {
-			// Construct the raw transaction with one output, that is paid the amount of the
-			// channel.
-			let addr = WitnessProgram::from_scriptpubkey(
-				&output_script[..],
-				match network {
-					Network::Bitcoin => bitcoin_bech32::constants::Network::Bitcoin,
-					Network::Testnet => bitcoin_bech32::constants::Network::Testnet,
-					Network::Regtest => bitcoin_bech32::constants::Network::Regtest,
-					Network::Signet => bitcoin_bech32::constants::Network::Signet,
-				},
-			)
-			.expect("Lightning funding tx should always be to a SegWit output")
-			.to_address();
-			let mut outputs = vec![HashMap::with_capacity(1)];
-			outputs[0].insert(addr, *channel_value_satoshis as f64 / 100_000_000.0);
-			let raw_tx = bitcoind_client.create_raw_transaction(outputs).await;
-
-			// Have your wallet put the inputs into the transaction such that the output is
-			// satisfied.
-			let funded_tx = bitcoind_client.fund_raw_transaction(raw_tx).await;
-
-			// Sign the final funding transaction and broadcast it.
-			let signed_tx = bitcoind_client.sign_raw_transaction_with_wallet(funded_tx.hex).await;
-			assert_eq!(signed_tx.complete, true);
-			let final_tx: Transaction =
-				encode::deserialize(&hex_utils::to_vec(&signed_tx.hex).unwrap()).unwrap();
+			// Channels opened with `--psbt` are funded by the user, through `fundchannel`.
+			if let Some(funding) = external_fundings.lock().unwrap().get_mut(temporary_channel_id) {
+				*funding = Some(ExternalFunding {
+					counterparty_node_id: *counterparty_node_id,
+					output_script: output_script.clone(),
+					channel_value_satoshis: *channel_value_satoshis,
+				});
+				return;
+			}
+			let tx_feerate =
+				bitcoind_client.get_est_sat_per_1000_weight(ConfirmationTarget::Normal);
+			let final_tx = match wallet
+				.create_funding_transaction(
+					output_script,
+					*channel_value_satoshis,
+					tx_feerate,
+					network,
+				)
+				.await
+			{
+				Ok(tx) => tx,
+				Err(e) => {
+					println!("\nERROR: failed to fund the channel: {}", e);
+					let _ = channel_manager.force_close_without_broadcasting_txn(
+						temporary_channel_id,
+						counterparty_node_id,
+					);
+					print!("> ");
+					io::stdout().flush().unwrap();
+					return;
+				}
+			};
 			// Give the funding transaction back to LDK for opening the channel.
 			if channel_manager
 				.funding_transaction_generated(
 					&temporary_channel_id,
 					counterparty_node_id,
-					final_tx,
+					final_tx.clone(),
 				)
 				.is_err()
 			{
+				wallet.release_inputs(&final_tx);
 				println!(
 					"\nERROR: Channel went away before we could fund it. The peer disconnected or refused the channel.");
 				print!("> ");
@@ -175,9 +516,42 @@ async fn handle_ldk_events(
 			io::stdout().flush().unwrap();
 			let payment_preimage = match purpose {
 				PaymentPurpose::InvoicePayment { payment_preimage, .. } => *payment_preimage,
+				PaymentPurpose::SpontaneousPayment(_) if !accept_keysend => {
+					channel_manager.fail_htlc_backwards(payment_hash);
+					println!(
+						"\nEVENT: rejected keysend payment with payment hash {}, as keysend payments are disabled",
+						hex_utils::hex_str(&payment_hash.0),
+					);
+					print!("> ");
+					io::stdout().flush().unwrap();
+					return;
+				}
 				PaymentPurpose::SpontaneousPayment(preimage) => Some(*preimage),
 			};
-			channel_manager.claim_funds(payment_preimage.unwrap());
+			match payment_preimage {
+				Some(payment_preimage) => channel_manager.claim_funds(payment_preimage),
+				// LDK only lacks the preimage for hold invoices, whose payments we hold until
+				// they're settled or canceled.
+				None => {
+					let mut payments = inbound_payments.lock().unwrap();
+					match payments.get_mut(payment_hash) {
+						Some(payment) if payment.status == HTLCStatus::Failed => {
+							channel_manager.fail_htlc_backwards(payment_hash);
+						}
+						Some(payment) => {
+							payment.status = HTLCStatus::Held;
+							let _ = persist_payments(persister, INBOUND_PAYMENTS_FNAME, &payments);
+							println!(
+								"\nEVENT: holding payment for hold invoice {}, use settleinvoice or cancelinvoice",
+								hex_utils::hex_str(&payment_hash.0),
+							);
+							print!("> ");
+							io::stdout().flush().unwrap();
+						}
+						None => channel_manager.fail_htlc_backwards(payment_hash),
+					}
+				}
+			}
 		}
 		Event::PaymentClaimed { payment_hash, purpose, amount_msat, receiver_node_id: _ } => {
 			println!(
@@ -198,8 +572,10 @@ async fn handle_ldk_events(
 				Entry::Occupied(mut e) => {
 					let payment = e.get_mut();
 					payment.status = HTLCStatus::Succeeded;
-					payment.preimage = payment_preimage;
+					// Hold invoice preimages were recorded when they were settled.
+					payment.preimage = payment_preimage.or(payment.preimage);
 					payment.secret = payment_secret;
+					payment.settled_at = Some(time_now_secs());
 				}
 				Entry::Vacant(e) => {
 					e.insert(PaymentInfo {
@@ -207,16 +583,24 @@ async fn handle_ldk_events(
 						secret: payment_secret,
 						status: HTLCStatus::Succeeded,
 						amt_msat: MillisatAmount(Some(*amount_msat)),
+						fee_paid_msat: None,
+						created_at: time_now_secs(),
+						settled_at: Some(time_now_secs()),
+						invoice: None,
 					});
 				}
 			}
+			let _ = persist_payments(persister, INBOUND_PAYMENTS_FNAME, &payments);
 		}
 		Event::PaymentSent { payment_preimage, payment_hash, fee_paid_msat, .. } => {
+			router.remove_constraints(&PaymentId(payment_hash.0));
 			let mut payments = outbound_payments.lock().unwrap();
 			for (hash, payment) in payments.iter_mut() {
 				if *hash == *payment_hash {
 					payment.preimage = Some(*payment_preimage);
 					payment.status = HTLCStatus::Succeeded;
+					payment.fee_paid_msat = *fee_paid_msat;
+					payment.settled_at = Some(time_now_secs());
 					println!(
 						"\nEVENT: successfully sent payment of {} millisatoshis{} from \
 								 payment hash {:?} with preimage {:?}",
@@ -233,15 +617,19 @@ async fn handle_ldk_events(
 					io::stdout().flush().unwrap();
 				}
 			}
+			let _ = persist_payments(persister, OUTBOUND_PAYMENTS_FNAME, &payments);
 		}
 		Event::OpenChannelRequest { .. } => {
 			// Unreachable, we don't set manually_accept_inbound_channels
 		}
 		Event::PaymentPathSuccessful { .. } => {}
 		Event::PaymentPathFailed { .. } => {}
-		Event::ProbeSuccessful { .. } => {}
-		Event::ProbeFailed { .. } => {}
+		Event::ProbeSuccessful { path, .. } => record_probe(probe_stats, persister, path, None),
+		Event::ProbeFailed { path, short_channel_id, .. } => {
+			record_probe(probe_stats, persister, path, *short_channel_id)
+		}
 		Event::PaymentFailed { payment_hash, .. } => {
+			router.remove_constraints(&PaymentId(payment_hash.0));
 			print!(
 				"\nEVENT: Failed to send payment to payment hash {:?}: exhausted payment retry attempts",
 				hex_utils::hex_str(&payment_hash.0)
@@ -254,6 +642,7 @@ async fn handle_ldk_events(
 				let payment = payments.get_mut(&payment_hash).unwrap();
 				payment.status = HTLCStatus::Failed;
 			}
+			let _ = persist_payments(persister, OUTBOUND_PAYMENTS_FNAME, &payments);
 		}
 		Event::PaymentForwarded {
 			prev_channel_id,
@@ -261,6 +650,20 @@ async fn handle_ldk_events(
 			fee_earned_msat,
 			claim_from_onchain_tx,
 		} => {
+			{
+				let mut history = forwarding_history.lock().unwrap();
+				history.push(ForwardInfo {
+					prev_channel_id: *prev_channel_id,
+					next_channel_id: *next_channel_id,
+					fee_earned_msat: *fee_earned_msat,
+					claim_from_onchain_tx: *claim_from_onchain_tx,
+					forwarded_at: time_now_secs(),
+				});
+				if let Err(e) = persister.persist(FORWARDING_HISTORY_FNAME, &*history) {
+					println!("ERROR: failed to persist the forwarding history: {}", e);
+				}
+			}
+
 			let read_only_network_graph = network_graph.read_only();
 			let nodes = read_only_network_graph.nodes();
 			let channels = channel_manager.list_channels();
@@ -322,20 +725,29 @@ async fn handle_ldk_events(
 			});
 		}
 		Event::SpendableOutputs { outputs } => {
-			let destination_address = bitcoind_client.get_new_address().await;
+			let destination_address = match wallet.get_new_address(sweep_address_type).await {
+				Ok(address) => address,
+				Err(e) => {
+					println!(
+						"ERROR: failed to get an address to sweep spendable outputs to: {}",
+						e
+					);
+					return;
+				}
+			};
 			let output_descriptors = &outputs.iter().map(|a| a).collect::<Vec<_>>();
 			let tx_feerate =
 				bitcoind_client.get_est_sat_per_1000_weight(ConfirmationTarget::Normal);
-			let spending_tx = keys_manager
-				.spend_spendable_outputs(
-					output_descriptors,
-					Vec::new(),
-					destination_address.script_pubkey(),
-					tx_feerate,
-					&Secp256k1::new(),
-				)
-				.unwrap();
-			bitcoind_client.broadcast_transaction(&spending_tx);
+			match keys_manager.spend_spendable_outputs(
+				output_descriptors,
+				Vec::new(),
+				destination_address.script_pubkey(),
+				tx_feerate,
+				&Secp256k1::new(),
+			) {
+				Ok(spending_tx) => bitcoind_client.broadcast_transaction(&spending_tx),
+				Err(()) => println!("ERROR: failed to sweep {} spendable output(s)", outputs.len()),
+			}
 		}
 		Event::ChannelReady {
 			ref channel_id,
@@ -352,6 +764,7 @@ async fn handle_ldk_events(
 			io::stdout().flush().unwrap();
 		}
 		Event::ChannelClosed { channel_id, reason, user_channel_id: _ } => {
+			external_fundings.lock().unwrap().remove(channel_id);
 			println!(
 				"\nEVENT: Channel {} closed due to: {:?}",
 				hex_utils::hex_str(channel_id),
@@ -360,9 +773,10 @@ async fn handle_ldk_events(
 			print!("> ");
 			io::stdout().flush().unwrap();
 		}
-		Event::DiscardFunding { .. } => {
-			// A "real" node should probably "lock" the UTXOs spent in funding transactions until
-			// the funding transaction either confirms, or this event is generated.
+		Event::DiscardFunding { transaction, .. } => {
+			// The channel closed before its funding transaction was broadcast, so the coins it
+			// spent are ours to spend again.
+			wallet.release_inputs(transaction);
 		}
 		Event::HTLCIntercepted { .. } => {}
 	}
@@ -388,8 +802,12 @@ async fn start_ldk() {
 		args.bitcoind_rpc_port,
 		args.bitcoind_rpc_username.clone(),
 		args.bitcoind_rpc_password.clone(),
+		args.bitcoind_rpc_cookie_path.clone(),
+		args.bitcoind_backup_rpcs.clone(),
+		args.fee_config.clone(),
 		tokio::runtime::Handle::current(),
 		Arc::clone(&logger),
+		args.block_fallback_esplora_url.clone(),
 	)
 	.await
 	{
@@ -428,48 +846,222 @@ async fn start_ldk() {
 	let broadcaster = bitcoind_client.clone();
 
 	// Step 4: Initialize Persist
-	let persister = Arc::new(FilesystemPersister::new(ldk_data_dir.clone()));
+
+	// A node started with `--locked` takes its passphrase through `unlock`, from the CLI or the
+	// HTTP API, which only serve that until then.
+	let mut locked_cli = None;
+	let mut locked_api = None;
+	let api_token = match args.http_listen_addr {
+		Some(_) => match api::read_or_create_api_token(&ldk_data_dir) {
+			Ok(token) => Some(token),
+			Err(e) => {
+				println!("ERROR: {}", e);
+				return;
+			}
+		},
+		None => None,
+	};
+	let unlock_requests = if args.locked {
+		let (unlocker, unlock_requests) = encryption::lock();
+		println!("The node is locked until it's given its passphrase through `unlock`.");
+		let cli_waiting = args.oneshot_command.is_none();
+		if let (Some(http_listen_addr), Some(token)) = (args.http_listen_addr, api_token.clone()) {
+			let (stop_sender, stop) = oneshot::channel();
+			let server =
+				api::serve_locked(http_listen_addr, unlocker.clone(), cli_waiting, token, stop);
+			locked_api = Some((stop_sender, tokio::spawn(server)));
+		}
+		if cli_waiting {
+			locked_cli = Some(tokio::spawn(cli::poll_for_unlock(unlocker)));
+		}
+		Some(unlock_requests)
+	} else {
+		None
+	};
+	let mut persister = match NodePersister::open(
+		args.storage.clone(),
+		&ldk_data_dir,
+		args.encryption,
+		args.passphrase_file.as_deref(),
+		unlock_requests,
+	)
+	.await
+	{
+		Ok(persister) => persister,
+		Err(e) => {
+			println!("ERROR: failed to open the node's storage: {}", e);
+			// The prompt taking `unlock` would otherwise keep the process waiting on stdin.
+			if locked_cli.is_some() {
+				std::process::exit(1);
+			}
+			return;
+		}
+	};
+	// Free the HTTP API's address for the full API.
+	if let Some((stop_sender, server)) = locked_api {
+		let _ = stop_sender.send(());
+		let _ = server.await;
+	}
+	if let Some(target) = args.backup.clone() {
+		if let Err(e) = persister.replicate_to(target) {
+			println!("ERROR: failed to start backup replication: {}", e);
+			return;
+		}
+	}
+	if args.scorer_persist_interval.is_some() {
+		persister.persist_scorer_on_timer();
+	}
+	let persister = Arc::new(persister);
 
 	// Step 5: Initialize the ChainMonitor
+	let monitor_persister = Arc::new(JournalingPersister::new(persister.clone()));
 	let chain_monitor: Arc<ChainMonitor> = Arc::new(chainmonitor::ChainMonitor::new(
 		None,
 		broadcaster.clone(),
 		logger.clone(),
 		fee_estimator.clone(),
-		persister.clone(),
+		monitor_persister.clone(),
 	));
 
-	// Step 6: Initialize the KeysManager
+	// Step 6: Initialize the KeysManager, or connect to the remote signer holding the node's keys
 
 	// The key seed that we use to derive the node privkey (that corresponds to the node pubkey) and
 	// other secret key material.
-	let keys_seed_path = format!("{}/keys_seed", ldk_data_dir.clone());
-	let keys_seed = if let Ok(seed) = fs::read(keys_seed_path.clone()) {
-		assert_eq!(seed.len(), 32);
-		let mut key = [0; 32];
-		key.copy_from_slice(&seed);
-		key
-	} else {
-		let mut key = [0; 32];
-		thread_rng().fill_bytes(&mut key);
-		match File::create(keys_seed_path.clone()) {
-			Ok(mut f) => {
-				f.write_all(&key).expect("Failed to write node keys seed to disk");
-				f.sync_all().expect("Failed to sync node keys seed to disk");
+	let keys_seed_path = format!("{}/{}", ldk_data_dir.clone(), KEYS_SEED_FNAME);
+	let existing_seed = fs::read(keys_seed_path.clone());
+	if existing_seed.is_ok() && args.mnemonic.is_some() {
+		println!("ERROR: the node already has a keys seed, --mnemonic only sets up new nodes");
+		return;
+	}
+	if existing_seed.is_ok() && args.remote_signer.is_some() {
+		println!(
+			"ERROR: the node already has a keys seed, its channels can't move to a remote signer"
+		);
+		return;
+	}
+	// Our on-chain wallet is derived from the keys seed too, so without one on this host we keep
+	// using bitcoind's wallet.
+	let (keys_manager, wallet) = match args.remote_signer.clone() {
+		Some(socket_path) => match RemoteSigner::connect(socket_path) {
+			Ok(signer) => (
+				Arc::new(NodeKeys::Remote(signer)),
+				Arc::new(OnchainWallet::Bitcoind(Arc::clone(&bitcoind_client))),
+			),
+			Err(()) => {
+				println!("ERROR: failed to reach the remote signer");
+				return;
 			}
-			Err(e) => {
-				println!("ERROR: Unable to create keys seed file {}: {}", keys_seed_path, e);
+		},
+		None => {
+			// A wallet with a new seed can't have received anything before now.
+			let wallet_birthday =
+				if existing_seed.is_err() && args.mnemonic != Some(MnemonicSetup::Import) {
+					time_now_secs()
+				} else {
+					0
+				};
+			if existing_seed.is_err() && matches!(persister.read("manager"), Ok(Some(_))) {
+				println!("ERROR: the node has channels but no keys seed, start it with --remote-signer if its keys are held by a signer daemon");
 				return;
 			}
+			let keys_seed = if let Ok(seed) = existing_seed {
+				let seed = match persister.unseal(KEYS_SEED_FNAME, seed) {
+					Ok(seed) => seed,
+					Err(e) => {
+						println!("ERROR: failed to read keys seed file {}: {}", keys_seed_path, e);
+						return;
+					}
+				};
+				assert_eq!(seed.len(), 32);
+				let mut key = [0; 32];
+				key.copy_from_slice(&seed);
+				key
+			} else {
+				let key = match args.mnemonic {
+					Some(setup) => match mnemonic::setup(setup) {
+						Ok(key) => key,
+						Err(e) => {
+							println!(
+								"ERROR: failed to set up the keys seed from a mnemonic: {}",
+								e
+							);
+							return;
+						}
+					},
+					None => {
+						let mut key = [0; 32];
+						thread_rng().fill_bytes(&mut key);
+						key
+					}
+				};
+				match File::create(keys_seed_path.clone()) {
+					Ok(mut f) => {
+						f.write_all(&persister.seal(KEYS_SEED_FNAME, key.to_vec()))
+							.expect("Failed to write node keys seed to disk");
+						f.sync_all().expect("Failed to sync node keys seed to disk");
+					}
+					Err(e) => {
+						println!(
+							"ERROR: Unable to create keys seed file {}: {}",
+							keys_seed_path, e
+						);
+						return;
+					}
+				}
+				key
+			};
+			let rpc = WalletRpcConfig {
+				host: args.bitcoind_rpc_host.clone(),
+				port: args.bitcoind_rpc_port,
+				user: args.bitcoind_rpc_username.clone(),
+				password: args.bitcoind_rpc_password.clone(),
+				cookie_path: args.bitcoind_rpc_cookie_path.clone(),
+			};
+			let wallet =
+				match BdkWallet::new(&keys_seed, args.network, &ldk_data_dir, rpc, wallet_birthday)
+				{
+					Ok(wallet) => wallet,
+					Err(e) => {
+						println!("ERROR: failed to open the on-chain wallet: {}", e);
+						return;
+					}
+				};
+			let cur = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap();
+			let keys = match args.phantom_secret.as_ref() {
+				Some(path) => match signer::read_phantom_secret(path, args.create_phantom_secret) {
+					Ok(phantom_secret) => NodeKeys::Phantom(PhantomKeysManager::new(
+						&keys_seed,
+						cur.as_secs(),
+						cur.subsec_nanos(),
+						&phantom_secret,
+					)),
+					Err(e) => {
+						println!("ERROR: failed to read the phantom secret: {}", e);
+						return;
+					}
+				},
+				None => {
+					NodeKeys::Local(KeysManager::new(&keys_seed, cur.as_secs(), cur.subsec_nanos()))
+				}
+			};
+			(Arc::new(keys), Arc::new(OnchainWallet::Bdk(wallet)))
 		}
-		key
 	};
-	let cur = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap();
-	let keys_manager = Arc::new(KeysManager::new(&keys_seed, cur.as_secs(), cur.subsec_nanos()));
+	let recovered_channels = match args.recover_backup.as_ref() {
+		Some(path) => match scb::read(path, &keys_manager) {
+			Ok(channels) => Some(channels),
+			Err(e) => {
+				println!("ERROR: failed to read static channel backup {}: {}", path.display(), e);
+				return;
+			}
+		},
+		None => None,
+	};
 
 	// Step 7: Read ChannelMonitor state from disk
-	let mut channelmonitors =
-		persister.read_channelmonitors(keys_manager.clone(), keys_manager.clone()).unwrap();
+	let mut channelmonitors = monitor_persister
+		.read_channelmonitors(keys_manager.clone(), &broadcaster, &fee_estimator, &logger)
+		.unwrap();
 
 	// Step 8: Poll for the best chain tip, which may be used by the channel manager & spv client
 	let polled_chain_tip = init::validate_best_block_header(bitcoind_client.as_ref())
@@ -477,19 +1069,17 @@ async fn start_ldk() {
 		.expect("Failed to fetch best block header and best block");
 
 	// Step 9: Initialize routing ProbabilisticScorer
-	let network_graph_path = format!("{}/network_graph", ldk_data_dir.clone());
-	let network_graph =
-		Arc::new(disk::read_network(Path::new(&network_graph_path), args.network, logger.clone()));
+	let network_graph = Arc::new(disk::read_network(&persister, args.network, logger.clone()));
 
-	let scorer_path = format!("{}/scorer", ldk_data_dir.clone());
 	let scorer = Arc::new(Mutex::new(disk::read_scorer(
-		Path::new(&scorer_path),
+		&persister,
 		Arc::clone(&network_graph),
 		Arc::clone(&logger),
+		args.scoring_params.clone(),
 	)));
 
 	// Step 10: Create Router
-	let router = Arc::new(DefaultRouter::new(
+	let router = Arc::new(Router::new(
 		network_graph.clone(),
 		logger.clone(),
 		keys_manager.get_secure_random_bytes(),
@@ -501,7 +1091,7 @@ async fn start_ldk() {
 	user_config.channel_handshake_limits.force_announced_channel_preference = false;
 	let mut restarting_node = true;
 	let (channel_manager_blockhash, channel_manager) = {
-		if let Ok(mut f) = fs::File::open(format!("{}/manager", ldk_data_dir.clone())) {
+		if let Some(manager_bytes) = persister.read("manager").unwrap() {
 			let mut channel_monitor_mut_references = Vec::new();
 			for (_, channel_monitor) in channelmonitors.iter_mut() {
 				channel_monitor_mut_references.push(channel_monitor);
@@ -513,12 +1103,12 @@ async fn start_ldk() {
 				fee_estimator.clone(),
 				chain_monitor.clone(),
 				broadcaster.clone(),
-				router,
+				router.clone(),
 				logger.clone(),
 				user_config,
 				channel_monitor_mut_references,
 			);
-			<(BlockHash, ChannelManager)>::read(&mut f, read_args).unwrap()
+			<(BlockHash, ChannelManager)>::read(&mut &manager_bytes[..], read_args).unwrap()
 		} else {
 			// We're starting a fresh node.
 			restarting_node = false;
@@ -531,7 +1121,7 @@ async fn start_ldk() {
 				fee_estimator.clone(),
 				chain_monitor.clone(),
 				broadcaster.clone(),
-				router,
+				router.clone(),
 				logger.clone(),
 				keys_manager.clone(),
 				keys_manager.clone(),
@@ -597,6 +1187,13 @@ async fn start_ldk() {
 		logger.clone(),
 	));
 
+	// Rapid Gossip Sync gets us most of the graph from a server right away, rather than having it
+	// trickle in from our peers' gossip, which keeps being applied on top of it.
+	let rgs_client = args
+		.rgs_url
+		.clone()
+		.map(|url| Arc::new(rgs::RgsClient::new(Arc::clone(&network_graph), url)));
+
 	// Step 15: Initialize the PeerManager
 	let channel_manager: Arc<ChannelManager> = Arc::new(channel_manager);
 	let onion_messenger: Arc<OnionMessenger> = Arc::new(OnionMessenger::new(
@@ -627,10 +1224,38 @@ async fn start_ldk() {
 
 	let peer_manager_connection_handler = peer_manager.clone();
 	let listening_port = args.ldk_peer_listening_port;
+
+	// Have Tor serve an onion service for our listener, if asked to, and announce it. The control
+	// connection is held until the node stops, as Tor removes the service when it's closed.
+	let mut announced_listen_addr = args.ldk_announced_listen_addr.clone();
+	let mut _tor_control = None;
+	if let Some(tor_config) = args.tor.as_ref() {
+		let onion_address = match TorControl::connect(tor_config).await {
+			Ok(mut control) => {
+				let onion_address = control.add_onion_service(&ldk_data_dir, listening_port).await;
+				_tor_control = Some(control);
+				onion_address
+			}
+			Err(e) => Err(e),
+		};
+		match onion_address {
+			Ok(onion_address) => announced_listen_addr.push(onion_address),
+			Err(e) => {
+				println!("ERROR: failed to set up the Tor onion service: {}", e);
+				return;
+			}
+		}
+	}
+	// In Tor-only mode, peers only reach us through the onion service, and `args.proxy` is Tor's
+	// SOCKS port so we only reach them through Tor.
+	let proxy = args.proxy;
+	let tor_only = args.tor.as_ref().map_or(false, |tor| tor.only);
+	let listen_host = if tor_only { "127.0.0.1" } else { "[::]" };
+
 	let stop_listen_connect = Arc::new(AtomicBool::new(false));
 	let stop_listen = Arc::clone(&stop_listen_connect);
 	tokio::spawn(async move {
-		let listener = tokio::net::TcpListener::bind(format!("[::]:{}", listening_port))
+		let listener = tokio::net::TcpListener::bind(format!("{}:{}", listen_host, listening_port))
 			.await
 			.expect("Failed to bind to listen port - is something else already listening on it?");
 		loop {
@@ -649,98 +1274,420 @@ async fn start_ldk() {
 		}
 	});
 
+	// Events are also published for the HTTP API's websocket clients, if any are listening.
+	let (api_event_sender, _) = broadcast::channel(1024);
+
 	// Step 17: Connect and Disconnect Blocks
 	let channel_manager_listener = channel_manager.clone();
 	let chain_monitor_listener = chain_monitor.clone();
 	let bitcoind_block_source = bitcoind_client.clone();
 	let network = args.network;
+	// With ZMQ block notifications, we poll as soon as bitcoind tells us about a new block and only
+	// fall back to polling every `ZMQ_FALLBACK_POLL_INTERVAL` in case we miss one.
+	let mut block_notifications = match &args.zmq_hashblock_endpoint {
+		Some(endpoint) => match bitcoind_client::subscribe_block_notifications(endpoint).await {
+			Ok(notifications) => Some(notifications),
+			Err(e) => {
+				println!(
+					"WARNING: failed to subscribe to ZMQ block notifications at {}, polling bitcoind instead: {}",
+					endpoint, e
+				);
+				None
+			}
+		},
+		None => None,
+	};
+	let sync_logger = Arc::clone(&logger);
+	let sync_events = api_event_sender.clone();
 	tokio::spawn(async move {
 		let chain_poller = poll::ChainPoller::new(bitcoind_block_source.as_ref(), network);
-		let chain_listener = (chain_monitor_listener, channel_manager_listener);
+		let chain_listener = ReorgTracker {
+			listener: (chain_monitor_listener, channel_manager_listener),
+			blocks_disconnected: AtomicU32::new(0),
+		};
 		let mut spv_client = SpvClient::new(chain_tip, chain_poller, &mut cache, &chain_listener);
+		let mut failing_since: Option<Instant> = None;
+		let mut alerted = false;
 		loop {
-			spv_client.poll_best_tip().await.unwrap();
-			tokio::time::sleep(Duration::from_secs(1)).await;
+			// Failing to sync (e.g. while bitcoind restarts) must not stop us from trying again.
+			match spv_client.poll_best_tip().await {
+				Ok(_) => {
+					if alerted {
+						println!("\nBlock sync with bitcoind recovered");
+						print!("> ");
+						io::stdout().flush().unwrap();
+					}
+					failing_since = None;
+					alerted = false;
+				}
+				Err(e) => {
+					log_error!(sync_logger, "Failed to sync to bitcoind's best block: {:?}", e);
+					let since = *failing_since.get_or_insert_with(Instant::now);
+					if !alerted && since.elapsed() >= BLOCK_SYNC_ALERT_AFTER {
+						alerted = true;
+						println!(
+							"\nALERT: failed to sync to bitcoind's best block for {} seconds: {:?}",
+							since.elapsed().as_secs(),
+							e
+						);
+						print!("> ");
+						io::stdout().flush().unwrap();
+						let _ = sync_events.send(serde_json::json!({
+							"type": "alert",
+							"reason": "block_sync_failing",
+							"failing_for_secs": since.elapsed().as_secs(),
+						}));
+					}
+				}
+			}
+			// Blocks may be disconnected even if we failed to connect the new chain afterwards.
+			let depth = chain_listener.blocks_disconnected.swap(0, Ordering::AcqRel);
+			if depth > 0 {
+				let best_block = chain_listener.listener.1.current_best_block();
+				log_info!(
+					sync_logger,
+					"Reorg of {} block(s), best block is now {} at height {}",
+					depth,
+					best_block.block_hash(),
+					best_block.height()
+				);
+				// LDK considers transactions with ANTI_REORG_DELAY confirmations final, so a reorg
+				// this deep may have undone things it won't revisit on its own.
+				if depth >= ANTI_REORG_DELAY {
+					println!(
+						"\nALERT: {}-block reorg detected, deeper than the {} blocks on-chain claims are considered final after. Check the node's channels and on-chain balances.",
+						depth, ANTI_REORG_DELAY
+					);
+					print!("> ");
+					io::stdout().flush().unwrap();
+				}
+				let _ = sync_events.send(serde_json::json!({
+					"type": "reorg",
+					"depth": depth,
+					"best_block_hash": best_block.block_hash().to_string(),
+					"best_block_height": best_block.height(),
+					"deep": depth >= ANTI_REORG_DELAY,
+				}));
+			}
+			let subscription_lost = match block_notifications.as_mut() {
+				Some(notifications) => {
+					let next =
+						tokio::time::timeout(ZMQ_FALLBACK_POLL_INTERVAL, notifications.recv());
+					matches!(next.await, Ok(None))
+				}
+				None => {
+					tokio::time::sleep(Duration::from_secs(1)).await;
+					false
+				}
+			};
+			if subscription_lost {
+				println!("\nWARNING: lost ZMQ block notifications, polling bitcoind instead");
+				print!("> ");
+				io::stdout().flush().unwrap();
+				block_notifications = None;
+			}
 		}
 	});
 
 	// Step 18: Handle LDK Events
 	let channel_manager_event_listener = channel_manager.clone();
+	let router_events = router.clone();
 	let keys_manager_listener = keys_manager.clone();
-	// TODO: persist payment info to disk
-	let inbound_payments: PaymentInfoStorage = Arc::new(Mutex::new(HashMap::new()));
-	let outbound_payments: PaymentInfoStorage = Arc::new(Mutex::new(HashMap::new()));
+	// Starting with an empty map would have the next payment overwrite the history we can't read.
+	let (inbound_payments, outbound_payments) = match (
+		disk::read_payment_info(&persister, INBOUND_PAYMENTS_FNAME),
+		disk::read_payment_info(&persister, OUTBOUND_PAYMENTS_FNAME),
+	) {
+		(Ok(inbound), Ok(outbound)) => {
+			let inbound: PaymentInfoStorage = Arc::new(Mutex::new(inbound));
+			let outbound: PaymentInfoStorage = Arc::new(Mutex::new(outbound));
+			(inbound, outbound)
+		}
+		(Err(e), _) | (_, Err(e)) => {
+			println!("ERROR: {}", e);
+			return;
+		}
+	};
+	let forwarding_history: ForwardingHistoryStorage =
+		Arc::new(Mutex::new(disk::read_forwarding_history(&persister)));
 	let inbound_pmts_for_events = inbound_payments.clone();
 	let outbound_pmts_for_events = outbound_payments.clone();
+	let forwarding_history_for_events = forwarding_history.clone();
+	let probe_stats: ProbeStatsStorage = Arc::new(Mutex::new(disk::read_probe_stats(&persister)));
+	let probe_stats_for_events = probe_stats.clone();
+	let external_fundings: ExternalFundingStorage = Arc::new(Mutex::new(HashMap::new()));
+	let external_fundings_for_events = external_fundings.clone();
+	let persister_events = persister.clone();
 	let network = args.network;
+	let accept_keysend = args.accept_keysend;
+	let sweep_address_type = args.sweep_address_type;
 	let bitcoind_rpc = bitcoind_client.clone();
+	let wallet_events = wallet.clone();
 	let network_graph_events = network_graph.clone();
+	let api_events = api_event_sender.clone();
 	let handle = tokio::runtime::Handle::current();
 	let event_handler = move |event: Event| {
 		handle.block_on(handle_ldk_events(
 			&channel_manager_event_listener,
+			&router_events,
 			&bitcoind_rpc,
+			&wallet_events,
 			&network_graph_events,
 			&keys_manager_listener,
 			&inbound_pmts_for_events,
 			&outbound_pmts_for_events,
+			&forwarding_history_for_events,
+			&probe_stats_for_events,
+			&external_fundings_for_events,
+			&persister_events,
 			network,
+			accept_keysend,
+			sweep_address_type,
 			&event,
 		));
+		if let Some(event_json) = api::event_json(&event) {
+			let _ = api_events.send(event_json);
+		}
 	};
 
 	// Step 19: Persist ChannelManager and NetworkGraph
-	let persister = Arc::new(FilesystemPersister::new(ldk_data_dir.clone()));
+	let bp_persister = Arc::clone(&persister);
 
 	// Step 20: Background Processing
-	let background_processor = BackgroundProcessor::start(
-		persister,
-		event_handler,
-		chain_monitor.clone(),
-		channel_manager.clone(),
-		GossipSync::p2p(gossip_sync.clone()),
-		peer_manager.clone(),
-		logger.clone(),
-		Some(scorer.clone()),
-	);
-
-	// Regularly reconnect to channel peers.
-	let connect_cm = Arc::clone(&channel_manager);
-	let connect_pm = Arc::clone(&peer_manager);
-	let peer_data_path = format!("{}/channel_peer_data", ldk_data_dir.clone());
-	let stop_connect = Arc::clone(&stop_listen_connect);
-	tokio::spawn(async move {
-		let mut interval = tokio::time::interval(Duration::from_secs(1));
-		loop {
+	let background_processor = match rgs_client.as_ref() {
+		Some(rgs_client) => BackgroundProcessor::start(
+			bp_persister,
+			event_handler,
+			chain_monitor.clone(),
+			channel_manager.clone(),
+			GossipSync::rapid(rgs_client.gossip_sync()),
+			peer_manager.clone(),
+			logger.clone(),
+			Some(scorer.clone()),
+		),
+		None => BackgroundProcessor::start(
+			bp_persister,
+			event_handler,
+			chain_monitor.clone(),
+			channel_manager.clone(),
+			GossipSync::p2p(gossip_sync.clone()),
+			peer_manager.clone(),
+			logger.clone(),
+			Some(scorer.clone()),
+		),
+	};
+	if let Some(rgs_client) = rgs_client.as_ref() {
+		tokio::spawn(Arc::clone(rgs_client).run());
+	}
+	let rgs_server = if args.rgs_serve {
+		let rgs_server = Arc::new(rgs::RgsServer::new(Arc::clone(&network_graph), args.network));
+		tokio::spawn(Arc::clone(&rgs_server).run());
+		Some(rgs_server)
+	} else {
+		None
+	};
+
+	// Regularly reconnect to channel peers, and keep track of how long our peers have been
+	// connected.
+	let peer_connections: PeerConnectionsStorage = Arc::new(Mutex::new(PeerConnections {
+		connected_since: HashMap::new(),
+		no_reconnect: disk::read_no_reconnect_peers(Path::new(&format!(
+			"{}/no_reconnect_peers",
+			ldk_data_dir
+		))),
+	}));
+	let peer_data_path = format!("{}/channel_peer_data", ldk_data_dir);
+	if let Err(e) = disk::compact_channel_peer_data(Path::new(&peer_data_path)) {
+		println!("ERROR: failed to compact the channel peer address book: {}", e);
+	}
+	tokio::spawn(reconnect_channel_peers(
+		Arc::clone(&channel_manager),
+		Arc::clone(&peer_manager),
+		Arc::clone(&network_graph),
+		Arc::clone(&peer_connections),
+		peer_data_path.clone(),
+		proxy,
+		Arc::clone(&stop_listen_connect),
+	));
+
+	// A new node knows no one, so find some peers to learn the network graph from.
+	if channel_manager.list_channels().is_empty()
+		&& network_graph.read_only().nodes().unordered_iter().next().is_none()
+		&& args.oneshot_command.is_none()
+	{
+		let dns_seeds = args.dns_seeds.clone();
+		let bootstrap_peer_manager = Arc::clone(&peer_manager);
+		tokio::spawn(async move {
+			match bootstrap::bootstrap(
+				&dns_seeds,
+				BOOTSTRAP_PEERS,
+				bootstrap_peer_manager,
+				proxy,
+				&peer_data_path,
+			)
+			.await
+			{
+				Ok(peers) => println!("EVENT: bootstrapped {} peers from DNS seeds", peers.len()),
+				Err(e) => println!("ERROR: failed to bootstrap peers: {}", e),
+			}
+		});
+	}
+
+	// Get back what we can of the channels in a static channel backup, if asked to.
+	if let Some(channels) = recovered_channels {
+		tokio::spawn(scb::recover(
+			channels,
+			Arc::clone(&channel_manager),
+			Arc::clone(&peer_manager),
+			Arc::clone(&bitcoind_client),
+			Arc::clone(&keys_manager),
+			Arc::clone(&wallet),
+			args.sweep_address_type,
+			proxy,
+		));
+	}
+
+	// Write what the scorer learned in batches, rather than whenever the background processor
+	// does, if asked to.
+	if let Some(persist_interval) = args.scorer_persist_interval {
+		let timer_scorer = Arc::clone(&scorer);
+		let timer_persister = Arc::clone(&persister);
+		tokio::spawn(async move {
+			let mut interval = tokio::time::interval(persist_interval);
+			// The first tick fires right away, when there's nothing new to write.
 			interval.tick().await;
-			match disk::read_channel_peer_data(Path::new(&peer_data_path)) {
-				Ok(info) => {
-					let peers = connect_pm.get_peer_node_ids();
-					for node_id in connect_cm
-						.list_channels()
-						.iter()
-						.map(|chan| chan.counterparty.node_id)
-						.filter(|id| !peers.iter().any(|(pk, _)| id == pk))
-					{
-						if stop_connect.load(Ordering::Acquire) {
-							return;
-						}
-						for (pubkey, peer_addr) in info.iter() {
-							if *pubkey == node_id {
-								let _ = cli::do_connect_peer(
-									*pubkey,
-									peer_addr.clone(),
-									Arc::clone(&connect_pm),
-								)
-								.await;
-							}
-						}
+			loop {
+				interval.tick().await;
+				let res = timer_persister.persist_scorer(&timer_scorer.lock().unwrap());
+				if let Err(e) = res {
+					println!("ERROR: failed to persist the scorer: {}", e);
+				}
+			}
+		});
+	}
+
+	// Keep the on-chain wallet in sync with the chain.
+	tokio::spawn(OnchainWallet::run_sync(Arc::clone(&wallet)));
+
+	// Regularly snapshot the node's state, if enabled.
+	if let Some(config) = args.snapshots.clone() {
+		tokio::spawn(snapshot::run(config, Arc::clone(&persister), ldk_data_dir.clone()));
+	}
+
+	// Regularly probe the routes to the configured nodes, or the best-connected ones in the graph,
+	// if enabled, so the scorer learns about channel liquidity before we need to pay through those
+	// channels.
+	if let Some(config) = args.probing.clone() {
+		let probe_cm = Arc::clone(&channel_manager);
+		let probe_graph = Arc::clone(&network_graph);
+		let probe_scorer = Arc::clone(&scorer);
+		let probe_keys_manager = Arc::clone(&keys_manager);
+		let probe_logger = Arc::clone(&logger);
+		tokio::spawn(async move {
+			let mut interval = tokio::time::interval(config.interval);
+			loop {
+				interval.tick().await;
+				let targets = if config.targets.is_empty() {
+					let our_node_id = NodeId::from_pubkey(&probe_cm.get_our_node_id());
+					let mut nodes = probe_graph
+						.read_only()
+						.nodes()
+						.unordered_iter()
+						.filter(|(node_id, _)| **node_id != our_node_id)
+						.map(|(node_id, node_info)| (*node_id, node_info.channels.len()))
+						.collect::<Vec<_>>();
+					nodes.sort_by(|a, b| b.1.cmp(&a.1));
+					nodes
+						.into_iter()
+						.take(config.num_targets)
+						.filter_map(|(node_id, _)| PublicKey::from_slice(node_id.as_slice()).ok())
+						.collect()
+				} else {
+					config.targets.clone()
+				};
+				for target in targets {
+					// The console is for the user's own commands, background failures only go
+					// to the log.
+					if let Err(e) = cli::send_probes(
+						target,
+						config.amount_msat,
+						&probe_cm,
+						&probe_graph,
+						&probe_scorer,
+						&*probe_keys_manager,
+						Arc::clone(&probe_logger),
+					) {
+						log_warn!(probe_logger, "Background probe to {} failed: {}", target, e);
 					}
 				}
-				Err(e) => println!("ERROR: errored reading channel peer info from disk: {:?}", e),
 			}
+		});
+	}
+
+	// Any of the CLI, the HTTP API or a SIGTERM can ask the node to shut down. The flag tells the
+	// shutdown task whether it also has to exit the process, as the CLI may still be blocked
+	// reading stdin when the request doesn't come from it.
+	let (shutdown_sender, mut shutdown_receiver) = mpsc::channel::<bool>(1);
+	#[cfg(not(target_os = "windows"))]
+	{
+		let sigterm_shutdown_sender = shutdown_sender.clone();
+		tokio::spawn(async move {
+			let mut sigterm = match signal(SignalKind::terminate()) {
+				Ok(sigterm) => sigterm,
+				Err(e) => {
+					println!("ERROR: failed to install SIGTERM handler: {}", e);
+					return;
+				}
+			};
+			sigterm.recv().await;
+			let _ = sigterm_shutdown_sender.send(true).await;
+		});
+	}
+
+	// Serve the HTTP API, if enabled.
+	if let (Some(http_listen_addr), Some(token)) = (args.http_listen_addr, api_token) {
+		let api_state = Arc::new(api::ApiState {
+			peer_manager: Arc::clone(&peer_manager),
+			channel_manager: Arc::clone(&channel_manager),
+			chain_monitor: Arc::clone(&chain_monitor),
+			bitcoind_client: Arc::clone(&bitcoind_client),
+			wallet: Arc::clone(&wallet),
+			keys_manager: Arc::clone(&keys_manager),
+			network_graph: Arc::clone(&network_graph),
+			scorer: Arc::clone(&scorer),
+			router: Arc::clone(&router),
+			probe_stats: Arc::clone(&probe_stats),
+			external_fundings: Arc::clone(&external_fundings),
+			inbound_payments: Arc::clone(&inbound_payments),
+			outbound_payments: Arc::clone(&outbound_payments),
+			forwarding_history: Arc::clone(&forwarding_history),
+			peer_connections: Arc::clone(&peer_connections),
+			proxy,
+			dns_seeds: args.dns_seeds.clone(),
+			rgs_client: rgs_client.clone(),
+			rgs_server,
+			scoring_params: args.scoring_params.clone(),
+			persister: Arc::clone(&persister),
+			ldk_data_dir: ldk_data_dir.clone(),
+			network: args.network,
+			announced_listen_addr: announced_listen_addr.clone(),
+			payment_retry: args.payment_retry,
+			public_url: args.http_public_listen_addr.map(|addr| {
+				args.http_public_url.clone().unwrap_or_else(|| format!("http://{}", addr))
+			}),
+			withdraw_links: Arc::new(Mutex::new(disk::read_withdraw_links(&persister))),
+			logger: Arc::clone(&logger),
+			events: api_event_sender,
+			shutdown: shutdown_sender.clone(),
+		});
+		if let Some(http_public_listen_addr) = args.http_public_listen_addr {
+			tokio::spawn(api::serve_public(http_public_listen_addr, Arc::clone(&api_state)));
 		}
-	});
+		tokio::spawn(api::serve(http_listen_addr, api_state, token));
+	}
+
+	let payment_retry = args.payment_retry;
+	let oneshot_command = args.oneshot_command.clone();
 
 	// Regularly broadcast our node_announcement. This is only required (or possible) if we have
 	// some public channels, and is only useful if we have public listen address(es) to announce.
@@ -748,7 +1695,8 @@ async fn start_ldk() {
 	// to avoid churn in the global network graph.
 	let peer_man = Arc::clone(&peer_manager);
 	let network = args.network;
-	if !args.ldk_announced_listen_addr.is_empty() {
+	if !announced_listen_addr.is_empty() {
+		let announcement_addrs = announced_listen_addr.clone();
 		tokio::spawn(async move {
 			let mut interval = tokio::time::interval(Duration::from_secs(60));
 			loop {
@@ -756,34 +1704,201 @@ async fn start_ldk() {
 				peer_man.broadcast_node_announcement(
 					[0; 3],
 					args.ldk_announced_node_name,
-					args.ldk_announced_listen_addr.clone(),
+					announcement_addrs.clone(),
 				);
 			}
 		});
 	}
 
-	// Start the CLI.
+	let shutdown_peer_manager = Arc::clone(&peer_manager);
+	let shutdown_chain_monitor = Arc::clone(&chain_monitor);
+	let shutdown_persister = Arc::clone(&persister);
+	let shutdown_scorer = Arc::clone(&scorer);
+	let shutdown_task = tokio::spawn(async move {
+		let exit_process = shutdown_receiver.recv().await.unwrap_or(false);
+		stop_node(
+			stop_listen_connect,
+			shutdown_peer_manager,
+			shutdown_chain_monitor,
+			background_processor,
+			shutdown_persister,
+			shutdown_scorer,
+		)
+		.await;
+		if exit_process {
+			std::process::exit(0);
+		}
+	});
+
+	// Start the CLI, once the prompt taking `unlock` has let go of stdin.
+	if let Some(locked_cli) = locked_cli {
+		let _ = locked_cli.await;
+	}
 	cli::poll_for_user_input(
 		Arc::clone(&peer_manager),
 		Arc::clone(&channel_manager),
+		Arc::clone(&chain_monitor),
+		Arc::clone(&bitcoind_client),
+		Arc::clone(&wallet),
 		Arc::clone(&keys_manager),
 		Arc::clone(&network_graph),
+		Arc::clone(&scorer),
+		Arc::clone(&router),
+		Arc::clone(&probe_stats),
+		Arc::clone(&external_fundings),
 		Arc::clone(&onion_messenger),
 		inbound_payments,
 		outbound_payments,
+		forwarding_history,
+		peer_connections,
+		proxy,
+		args.dns_seeds.clone(),
+		rgs_client,
+		args.scoring_params.clone(),
+		Arc::clone(&persister),
 		ldk_data_dir.clone(),
 		network,
 		Arc::clone(&logger),
+		announced_listen_addr,
+		payment_retry,
+		oneshot_command,
 	)
 	.await;
 
-	// Disconnect our peers and stop accepting new connections. This ensures we don't continue
-	// updating our channel data after we've stopped the background processor.
+	// The CLI exited, so shut down unless a shutdown is already in progress, in which case this
+	// waits for it to finish.
+	let _ = shutdown_sender.try_send(false);
+	shutdown_task.await.unwrap();
+}
+
+/// Keeps track of how long our peers have been connected, and reconnects to the peers we have
+/// channels with. The addresses we know them at are the ones in `peer_data_path` and the ones
+/// they announce, and whichever works is kept in `peer_data_path` for next time. Peers we fail
+/// to reconnect to are retried with exponential backoff, jittered so all of a restarted node's
+/// peers aren't retried at once.
+async fn reconnect_channel_peers(
+	channel_manager: Arc<ChannelManager>, peer_manager: Arc<PeerManager>,
+	network_graph: Arc<NetworkGraph>, peer_connections: PeerConnectionsStorage,
+	peer_data_path: String, proxy: Option<SocketAddr>, stop_connect: Arc<AtomicBool>,
+) {
+	// When to next try each peer, and how long we waited before that.
+	let mut backoffs: HashMap<PublicKey, (Instant, Duration)> = HashMap::new();
+	let mut interval = tokio::time::interval(Duration::from_secs(1));
+	loop {
+		interval.tick().await;
+		let peers = peer_manager.get_peer_node_ids();
+		let no_reconnect = {
+			let mut connections = peer_connections.lock().unwrap();
+			let now = time_now_secs();
+			connections.connected_since.retain(|id, _| peers.iter().any(|(pk, _)| id == pk));
+			for (pubkey, _) in peers.iter() {
+				connections.connected_since.entry(*pubkey).or_insert(now);
+			}
+			connections.no_reconnect.clone()
+		};
+		let peer_addrs = match disk::read_channel_peer_data(Path::new(&peer_data_path)) {
+			Ok(peer_addrs) => peer_addrs,
+			Err(e) => {
+				println!("ERROR: errored reading channel peer info from disk: {:?}", e);
+				HashMap::new()
+			}
+		};
+		let mut disconnected = channel_manager
+			.list_channels()
+			.iter()
+			.map(|chan| chan.counterparty.node_id)
+			.filter(|id| !peers.iter().any(|(pk, _)| id == pk))
+			.filter(|id| !no_reconnect.contains(id))
+			.collect::<Vec<_>>();
+		disconnected.sort();
+		disconnected.dedup();
+		backoffs.retain(|id, _| disconnected.contains(id));
+
+		for node_id in disconnected {
+			if stop_connect.load(Ordering::Acquire) {
+				return;
+			}
+			if backoffs.get(&node_id).map_or(false, |(retry_at, _)| Instant::now() < *retry_at) {
+				continue;
+			}
+			// The addresses that worked most recently are tried first.
+			let mut addrs = peer_addrs.get(&node_id).cloned().unwrap_or_default();
+			if let Some(announcement) = network_graph
+				.read_only()
+				.node(&NodeId::from_pubkey(&node_id))
+				.and_then(|node| node.announcement_info.as_ref())
+			{
+				for addr in announcement.addresses.iter().filter_map(PeerAddr::from_net_address) {
+					if !addrs.contains(&addr) {
+						addrs.push(addr);
+					}
+				}
+			}
+			let mut connected = false;
+			for addr in addrs {
+				let res =
+					cli::do_connect_peer(node_id, addr.clone(), Arc::clone(&peer_manager), proxy)
+						.await;
+				if res.is_ok() {
+					if peer_addrs.get(&node_id).and_then(|addrs| addrs.first()) != Some(&addr) {
+						let peer_info = format!("{}@{}", node_id, addr);
+						let _ = disk::persist_channel_peer(Path::new(&peer_data_path), &peer_info);
+					}
+					connected = true;
+					break;
+				}
+			}
+			if connected {
+				backoffs.remove(&node_id);
+			} else {
+				let backoff = match backoffs.get(&node_id) {
+					Some((_, backoff)) => cmp::min(*backoff * 2, RECONNECT_MAX_BACKOFF),
+					None => RECONNECT_MIN_BACKOFF,
+				};
+				let jitter_ms = thread_rng().gen_range(0, backoff.as_millis() as u64 / 2 + 1);
+				let retry_at = Instant::now() + backoff + Duration::from_millis(jitter_ms);
+				backoffs.insert(node_id, (retry_at, backoff));
+			}
+		}
+	}
+}
+
+/// Stops the node in an order that leaves nothing half-done on disk: no new connections or HTLCs
+/// first, then any pending `ChannelMonitor` writes, and finally the last `ChannelManager`, network
+/// graph and scorer persistence done by the background processor.
+async fn stop_node(
+	stop_listen_connect: Arc<AtomicBool>, peer_manager: Arc<PeerManager>,
+	chain_monitor: Arc<ChainMonitor>, background_processor: BackgroundProcessor,
+	persister: Arc<NodePersister>, scorer: Arc<Mutex<Scorer>>,
+) {
+	println!("Shutting down...");
+
+	// Disconnect our peers and stop accepting new connections. Without connected peers no new
+	// HTLCs can be added to our channels, and we don't continue updating our channel data after
+	// we've stopped the background processor.
 	stop_listen_connect.store(true, Ordering::Release);
 	peer_manager.disconnect_all_peers();
 
-	// Stop the background processor.
-	background_processor.stop().unwrap();
+	// Wait for in-flight ChannelMonitor updates to be persisted.
+	let mut waited = Duration::from_secs(0);
+	while chain_monitor.list_pending_monitor_updates().values().any(|updates| !updates.is_empty()) {
+		if waited >= MONITOR_PERSISTENCE_TIMEOUT {
+			println!("WARNING: timed out waiting for ChannelMonitor updates to be persisted");
+			break;
+		}
+		tokio::time::sleep(Duration::from_millis(100)).await;
+		waited += Duration::from_millis(100);
+	}
+
+	// Stop the background processor, which persists the ChannelManager one last time.
+	if let Err(e) = background_processor.stop() {
+		println!("ERROR: failed to stop the background processor: {}", e);
+	}
+	// The background processor's last write of the scorer is skipped if it's written on a timer.
+	if let Err(e) = persister.persist_scorer(&scorer.lock().unwrap()) {
+		println!("ERROR: failed to persist the scorer: {}", e);
+	}
+	println!("Shutdown complete.");
 }
 
 #[tokio::main]
@@ -811,5 +1926,79 @@ pub async fn main() {
 		}
 	}
 
+	// `cargo run migrate-storage ...` copies the node's state between storage backends,
+	// `cargo run restore-snapshot ...` restores a snapshot of it, and `cargo run signer-daemon ...`
+	// holds a node's keys for it to sign with over --remote-signer, instead of starting the node.
+	match env::args().nth(1).as_deref() {
+		Some("migrate-storage") => {
+			migrate_storage().await;
+			return;
+		}
+		Some("restore-snapshot") => {
+			restore_snapshot().await;
+			return;
+		}
+		#[cfg(unix)]
+		Some("signer-daemon") => {
+			signer_daemon().await;
+			return;
+		}
+		#[cfg(not(unix))]
+		Some("signer-daemon") => {
+			println!(
+				"ERROR: the signer daemon listens on a Unix socket, which this platform lacks"
+			);
+			return;
+		}
+		_ => {}
+	}
+
 	start_ldk().await;
 }
+
+async fn migrate_storage() {
+	let args = match args::parse_migrate_storage_args() {
+		Ok(args) => args,
+		Err(()) => return,
+	};
+	let ldk_data_dir = format!("{}/.ldk", args.ldk_storage_dir_path);
+	match persist::migrate_storage(&args.from, &args.to, &ldk_data_dir).await {
+		Ok(num_values) => println!(
+			"SUCCESS: copied and verified {} value(s) from {} to {} storage, start the node with --storage={}",
+			num_values,
+			args.from.name(),
+			args.to.name(),
+			args.to.name()
+		),
+		Err(e) => println!("ERROR: failed to migrate the node's storage: {}", e),
+	}
+}
+
+async fn restore_snapshot() {
+	let args = match args::parse_restore_snapshot_args() {
+		Ok(args) => args,
+		Err(()) => return,
+	};
+	let ldk_data_dir = format!("{}/.ldk", args.ldk_storage_dir_path);
+	match snapshot::restore(args.timestamp, &args.snapshot_dir, &args.storage, &ldk_data_dir).await
+	{
+		Ok(num_values) => println!(
+			"SUCCESS: restored {} value(s) from snapshot {} to {} storage",
+			num_values,
+			args.timestamp,
+			args.storage.name()
+		),
+		Err(e) => println!("ERROR: failed to restore snapshot {}: {}", args.timestamp, e),
+	}
+}
+
+#[cfg(unix)]
+async fn signer_daemon() {
+	let args = match args::parse_signer_daemon_args() {
+		Ok(args) => args,
+		Err(()) => return,
+	};
+	if let Err(e) = signer::run_daemon(&args.signer_dir, &args.socket_path, args.mnemonic).await {
+		println!("ERROR: signer daemon stopped: {}", e);
+	}
+}
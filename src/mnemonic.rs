@@ -0,0 +1,68 @@
+use bip39::{Language, Mnemonic};
+use bitcoin::network::constants::Network;
+use bitcoin::util::bip32::ExtendedPrivKey;
+use rand::{thread_rng, Rng};
+use std::io::{self, BufRead, Write};
+
+/// How a new node's keys seed is set up from a BIP39 mnemonic.
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum MnemonicSetup {
+	/// Generate a new mnemonic and show it once.
+	New,
+	/// Restore a node from a mnemonic it was set up with before.
+	Import,
+}
+
+/// The `KeysManager` seed is the private key of the BIP32 master key of the mnemonic's BIP39
+/// seed, as in LDK Node, so the same words restore the node there too.
+fn derive_keys_seed(mnemonic: &Mnemonic, passphrase: &str) -> [u8; 32] {
+	// The network only sets the extended key's version bytes, not the key itself.
+	let master = ExtendedPrivKey::new_master(Network::Bitcoin, &mnemonic.to_seed(passphrase))
+		.expect("a 64-byte seed is always a valid master seed");
+	master.private_key.secret_bytes()
+}
+
+fn read_line(prompt: &str) -> Result<String, String> {
+	print!("{}", prompt);
+	io::stdout().flush().unwrap();
+	let mut line = String::new();
+	io::stdin().lock().read_line(&mut line).map_err(|e| format!("failed to read input: {}", e))?;
+	Ok(line.trim().to_string())
+}
+
+fn read_secret(prompt: &str) -> Result<String, String> {
+	rpassword::prompt_password(prompt).map_err(|e| format!("failed to read input: {}", e))
+}
+
+/// Walks the user through generating or importing a mnemonic and its optional passphrase, and
+/// returns the keys seed they derive.
+pub(crate) fn setup(setup: MnemonicSetup) -> Result<[u8; 32], String> {
+	let mnemonic = match setup {
+		MnemonicSetup::New => {
+			let mut entropy = [0; 32];
+			thread_rng().fill_bytes(&mut entropy);
+			let mnemonic = Mnemonic::from_entropy(&entropy)
+				.expect("32 bytes of entropy always make a valid mnemonic");
+			println!("Write down these 24 words. They, and the passphrase if you set one, are the only way to recover the node's keys, and won't be shown again:");
+			for (i, word) in mnemonic.to_string().split(' ').enumerate() {
+				println!("{:>4}. {}", i + 1, word);
+			}
+			while read_line("Type \"yes\" once they're written down: ")? != "yes" {}
+			mnemonic
+		}
+		MnemonicSetup::Import => {
+			let words = read_secret("Mnemonic words: ")?;
+			let words = words.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase();
+			Mnemonic::parse_in(Language::English, &words)
+				.map_err(|e| format!("invalid mnemonic: {}", e))?
+		}
+	};
+	let passphrase = read_secret("BIP39 passphrase (leave empty for none): ")?;
+	if setup == MnemonicSetup::New
+		&& !passphrase.is_empty()
+		&& read_secret("Repeat BIP39 passphrase: ")? != passphrase
+	{
+		return Err("passphrases don't match".to_string());
+	}
+	Ok(derive_keys_seed(&mnemonic, &passphrase))
+}
@@ -0,0 +1,1421 @@
+use crate::backup::{BackupStatus, BackupTarget, Replicator};
+use crate::disk::FilesystemLogger;
+use crate::encryption::{
+	read_passphrase, Cipher, EncryptionHeader, EncryptionSetup, UnlockRequests,
+	ENCRYPTION_HEADER_KEY,
+};
+use crate::signer::{ChannelKeys, NodeKeys};
+use crate::{
+	Scorer, FORWARDING_HISTORY_FNAME, INBOUND_PAYMENTS_FNAME, KEYS_SEED_FNAME,
+	OUTBOUND_PAYMENTS_FNAME, PROBE_STATS_FNAME, WITHDRAW_LINKS_FNAME,
+};
+use bitcoin::hashes::hex::FromHex;
+use bitcoin::{BlockHash, Txid};
+use lightning::chain::chaininterface::{BroadcasterInterface, FeeEstimator};
+use lightning::chain::chainmonitor::{MonitorUpdateId, Persist};
+use lightning::chain::channelmonitor::{
+	ChannelMonitor, ChannelMonitorUpdate, CLOSED_CHANNEL_UPDATE_ID,
+};
+use lightning::chain::transaction::OutPoint;
+use lightning::chain::ChannelMonitorUpdateStatus;
+use lightning::util::persist::KVStorePersister;
+use lightning::util::ser::{Readable, ReadableArgs, Writeable, Writer};
+use lightning_persister::FilesystemPersister;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::HashMap;
+use std::fs;
+use std::future::Future;
+use std::io;
+use std::io::Write;
+use std::ops::Deref;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio_postgres::NoTls;
+use vss_client::client::VssClient;
+use vss_client::error::VssError;
+use vss_client::types::{
+	DeleteObjectRequest, GetObjectRequest, KeyValue, ListKeyVersionsRequest, PutObjectRequest,
+};
+
+/// The file the SQLite backend keeps everything in, inside the LDK data directory.
+pub(crate) const SQLITE_DB_FNAME: &str = "ldk_node.sqlite";
+
+/// Where each channel's `ChannelMonitor` is stored, under `<funding_txid>_<funding_index>`.
+pub(crate) const MONITORS_DIR: &str = "monitors";
+/// Where the `ChannelMonitorUpdate`s not yet folded into their monitor are journaled, under
+/// `<funding_txid>_<funding_index>_<update_id>`.
+pub(crate) const MONITOR_UPDATES_DIR: &str = "monitor_updates";
+
+/// The directories the monitors and their journaled updates are stored under.
+const STATE_DIRS: &[&str] = &[MONITORS_DIR, MONITOR_UPDATES_DIR];
+
+/// The keys the node's state is stored under, besides those under `STATE_DIRS`.
+const STATE_KEYS: &[&str] = &[
+	"manager",
+	"network_graph",
+	"scorer",
+	INBOUND_PAYMENTS_FNAME,
+	OUTBOUND_PAYMENTS_FNAME,
+	FORWARDING_HISTORY_FNAME,
+	WITHDRAW_LINKS_FNAME,
+	PROBE_STATS_FNAME,
+];
+
+/// Where the node keeps its state: the `ChannelManager`, `ChannelMonitor`s, network graph, scorer
+/// and our own payment and forwarding records, all stored under a key, encrypted if the node was
+/// set up to.
+pub(crate) struct NodePersister {
+	store: Store,
+	cipher: Option<Cipher>,
+	ldk_data_dir: String,
+	replicator: Option<Replicator>,
+	/// Whether the scorer is written on our own timer, through `persist_scorer`, rather than
+	/// whenever the background processor writes it.
+	scorer_on_timer: bool,
+}
+
+enum Store {
+	/// One file per key in the LDK data directory.
+	Filesystem(FilesystemPersister),
+	/// A single SQLite database, each write being atomic.
+	Sqlite(SqliteStore),
+	/// A Postgres database, so the state can live on a replicated server instead of local disk.
+	Postgres(PostgresStore),
+	/// A remote Versioned Storage Service, so the node can run on hosts without durable disks.
+	Vss(VssStore),
+}
+
+/// Writes bytes as they are, unlike `Vec<u8>` which is length-prefixed.
+struct RawBytes<'a>(&'a [u8]);
+
+impl Writeable for RawBytes<'_> {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), io::Error> {
+		writer.write_all(self.0)
+	}
+}
+
+/// Whether a backend kept in the LDK data directory holds a node's state that wasn't migrated
+/// elsewhere.
+fn local_store_holds_state(storage: &StorageBackend, ldk_data_dir: &str) -> Result<bool, String> {
+	let store = match storage {
+		StorageBackend::Filesystem if Path::new(ldk_data_dir).join("manager").exists() => {
+			Store::Filesystem(FilesystemPersister::new(ldk_data_dir.to_string()))
+		}
+		StorageBackend::Sqlite if Path::new(ldk_data_dir).join(SQLITE_DB_FNAME).exists() => {
+			Store::Sqlite(SqliteStore::open(&Path::new(ldk_data_dir).join(SQLITE_DB_FNAME))?)
+		}
+		_ => return Ok(false),
+	};
+	Ok(store.read("manager").map_err(|e| e.to_string())?.is_some()
+		&& store.read(MIGRATED_TO_KEY).map_err(|e| e.to_string())?.is_none())
+}
+
+impl Store {
+	async fn open(storage: &StorageBackend, ldk_data_dir: &str) -> Result<Self, String> {
+		Ok(match storage {
+			StorageBackend::Filesystem => {
+				Store::Filesystem(FilesystemPersister::new(ldk_data_dir.to_string()))
+			}
+			StorageBackend::Sqlite => {
+				Store::Sqlite(SqliteStore::open(&Path::new(ldk_data_dir).join(SQLITE_DB_FNAME))?)
+			}
+			StorageBackend::Postgres(url) => Store::Postgres(PostgresStore::open(url).await?),
+			StorageBackend::Vss { url, store_id } => Store::Vss(VssStore::new(url, store_id)),
+		})
+	}
+
+	fn read(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+		match self {
+			Store::Filesystem(persister) => {
+				match fs::read(Path::new(&persister.get_data_dir()).join(key)) {
+					Ok(bytes) => Ok(Some(bytes)),
+					Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+					Err(e) => Err(e),
+				}
+			}
+			Store::Sqlite(store) => store.read(key),
+			Store::Postgres(store) => store.read(key),
+			Store::Vss(store) => store.read(key),
+		}
+	}
+
+	fn write(&self, key: &str, value: &[u8]) -> io::Result<()> {
+		match self {
+			Store::Filesystem(persister) => persister.persist(key, &RawBytes(value)),
+			Store::Sqlite(store) => store.write(key, value),
+			Store::Postgres(store) => store.write(key, value),
+			Store::Vss(store) => store.write(key, value),
+		}
+	}
+
+	/// Like `read`, but always from the store itself rather than a cache.
+	fn read_back(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+		if let Store::Vss(store) = self {
+			store.cache.lock().unwrap().remove(key);
+		}
+		self.read(key)
+	}
+
+	fn remove(&self, key: &str) -> io::Result<()> {
+		match self {
+			Store::Filesystem(persister) => {
+				match fs::remove_file(Path::new(&persister.get_data_dir()).join(key)) {
+					Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+					_ => Ok(()),
+				}
+			}
+			Store::Sqlite(store) => store.remove(key),
+			Store::Postgres(store) => store.remove(key),
+			Store::Vss(store) => store.remove(key),
+		}
+	}
+
+	/// Returns the (key, value) pairs stored under `<dir>/`.
+	fn read_dir(&self, dir: &str) -> io::Result<Vec<(String, Vec<u8>)>> {
+		match self {
+			Store::Filesystem(persister) => {
+				let path = Path::new(&persister.get_data_dir()).join(dir);
+				if !path.exists() {
+					return Ok(Vec::new());
+				}
+				let mut values = Vec::new();
+				for entry in fs::read_dir(path)? {
+					let entry = entry?;
+					let name = entry.file_name().into_string().map_err(|_| {
+						io::Error::new(
+							io::ErrorKind::InvalidData,
+							format!("invalid file name in {}", dir),
+						)
+					})?;
+					// Left behind by a write that didn't complete.
+					if name.ends_with(".tmp") {
+						continue;
+					}
+					values.push((format!("{}/{}", dir, name), fs::read(entry.path())?));
+				}
+				Ok(values)
+			}
+			Store::Sqlite(store) => store.read_dir(dir),
+			Store::Postgres(store) => store.read_dir(dir),
+			Store::Vss(store) => store.read_dir(dir),
+		}
+	}
+
+	/// Returns the (key, value) pairs stored under every one of `STATE_DIRS`.
+	fn read_state_dirs(&self) -> io::Result<Vec<(String, Vec<u8>)>> {
+		let mut values = Vec::new();
+		for dir in STATE_DIRS {
+			values.extend(self.read_dir(dir)?);
+		}
+		Ok(values)
+	}
+}
+
+impl NodePersister {
+	/// Opens the store, refusing to if another local backend already holds a node's state, as
+	/// we'd otherwise start a fresh node and lose track of its channels, or if its state was
+	/// migrated elsewhere. Then unlocks it, or sets up its encryption, as `encryption` asks.
+	pub(crate) async fn open(
+		storage: StorageBackend, ldk_data_dir: &str, encryption: EncryptionSetup,
+		passphrase_file: Option<&Path>, unlock_requests: Option<UnlockRequests>,
+	) -> Result<Self, String> {
+		let store = Store::open(&storage, ldk_data_dir).await?;
+		if let Some(name) = store.read(MIGRATED_TO_KEY).map_err(|e| e.to_string())? {
+			let name = String::from_utf8_lossy(&name);
+			return Err(format!(
+				"the node's state was migrated to {} storage, start with --storage={}",
+				name, name
+			));
+		}
+		if store.read("manager").map_err(|e| e.to_string())?.is_none() {
+			for local in [StorageBackend::Filesystem, StorageBackend::Sqlite].iter() {
+				if *local != storage && local_store_holds_state(local, ldk_data_dir)? {
+					return Err(format!(
+						"the {} storage in {} holds the node's state, start with --storage={}",
+						local.name(),
+						ldk_data_dir,
+						local.name()
+					));
+				}
+			}
+		}
+		let mut persister = NodePersister {
+			store,
+			cipher: None,
+			ldk_data_dir: ldk_data_dir.to_string(),
+			replicator: None,
+			scorer_on_timer: false,
+		};
+		persister.setup_encryption(encryption, passphrase_file, unlock_requests).await?;
+		Ok(persister)
+	}
+
+	/// When the node is started with `--locked`, the passphrase comes from `unlock_requests`
+	/// rather than a file or prompt.
+	async fn setup_encryption(
+		&mut self, encryption: EncryptionSetup, passphrase_file: Option<&Path>,
+		unlock_requests: Option<UnlockRequests>,
+	) -> Result<(), String> {
+		let header = self.store.read(ENCRYPTION_HEADER_KEY).map_err(|e| e.to_string())?;
+		let cipher = match (header, encryption, unlock_requests) {
+			(Some(header), _, Some(unlock_requests)) => {
+				unlock_requests.wait(&EncryptionHeader::decode(&header)?).await?
+			}
+			(Some(header), _, None) => {
+				let header = EncryptionHeader::decode(&header)?;
+				header.unlock(&read_passphrase(passphrase_file, false)?)?
+			}
+			(None, _, Some(_)) => {
+				return Err("--locked is only used once the node's state is encrypted".to_string())
+			}
+			(None, EncryptionSetup::Unlock, None) => return Ok(()),
+			(None, EncryptionSetup::New, None) | (None, EncryptionSetup::Existing, None) => {
+				let holds_state = self.store.read("manager").map_err(|e| e.to_string())?.is_some()
+					|| Path::new(&self.ldk_data_dir).join(KEYS_SEED_FNAME).exists();
+				if holds_state && encryption == EncryptionSetup::New {
+					return Err(
+						"the node already has state, start with --encrypt-existing to encrypt it"
+							.to_string(),
+					);
+				}
+				println!("Choose a passphrase to encrypt the node's state with. It can't be recovered if lost.");
+				let (header, cipher) =
+					EncryptionHeader::new(&read_passphrase(passphrase_file, true)?)?;
+				self.store
+					.write(ENCRYPTION_HEADER_KEY, &header.encode())
+					.map_err(|e| format!("failed to write the encryption header: {}", e))?;
+				cipher
+			}
+		};
+		self.cipher = Some(cipher);
+		if encryption == EncryptionSetup::Existing {
+			self.encrypt_existing()
+				.map_err(|e| format!("failed to encrypt the node's state: {}", e))?;
+		}
+		Ok(())
+	}
+
+	/// Encrypts whatever was written before the node's state was encrypted. Values already
+	/// encrypted are left alone, so this can be run again if interrupted.
+	fn encrypt_existing(&self) -> io::Result<()> {
+		let cipher = self.cipher.as_ref().unwrap();
+		let mut values = self.store.read_state_dirs()?;
+		for key in STATE_KEYS {
+			if let Some(value) = self.store.read(key)? {
+				values.push((key.to_string(), value));
+			}
+		}
+		for (key, value) in values {
+			if !Cipher::is_encrypted(&value) {
+				self.store.write(&key, &cipher.encrypt(&key, &value))?;
+			}
+		}
+		let keys_seed_path = Path::new(&self.ldk_data_dir).join(KEYS_SEED_FNAME);
+		match fs::read(&keys_seed_path) {
+			Ok(seed) if !Cipher::is_encrypted(&seed) => {
+				let tmp_path = keys_seed_path.with_extension("tmp");
+				let mut f = fs::File::create(&tmp_path)?;
+				f.write_all(&cipher.encrypt(KEYS_SEED_FNAME, &seed))?;
+				f.sync_all()?;
+				fs::rename(&tmp_path, &keys_seed_path)?;
+				fs::File::open(&self.ldk_data_dir)?.sync_all()
+			}
+			Ok(_) => Ok(()),
+			Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+			Err(e) => Err(e),
+		}
+	}
+
+	/// Mirrors every write from now on to `target`, starting with the node's current state.
+	pub(crate) fn replicate_to(&mut self, target: BackupTarget) -> Result<(), String> {
+		let replicator = Replicator::start(target)?;
+		let mut values = self.store.read_state_dirs().map_err(|e| e.to_string())?;
+		for key in STATE_KEYS {
+			if let Some(value) = self.store.read(key).map_err(|e| e.to_string())? {
+				values.push((key.to_string(), value));
+			}
+		}
+		for (key, value) in values {
+			replicator.enqueue(&key, value);
+		}
+		self.replicator = Some(replicator);
+		Ok(())
+	}
+
+	/// Returns the node's state as stored, to be snapshotted. The manager is read first and the
+	/// monitors last, so they're at least as new as it, with their journaled updates read before
+	/// them so none get lost to a monitor rewrite in between.
+	pub(crate) fn read_stored_state(&self) -> io::Result<Vec<(String, Vec<u8>)>> {
+		let mut values = Vec::new();
+		if let Some(header) = self.store.read(ENCRYPTION_HEADER_KEY)? {
+			values.push((ENCRYPTION_HEADER_KEY.to_string(), header));
+		}
+		for key in STATE_KEYS {
+			if let Some(value) = self.store.read(key)? {
+				values.push((key.to_string(), value));
+			}
+		}
+		for dir in STATE_DIRS.iter().rev() {
+			values.extend(self.store.read_dir(dir)?);
+		}
+		Ok(values)
+	}
+
+	/// Stops the background processor's writes of the scorer, which is then only written through
+	/// `persist_scorer`.
+	pub(crate) fn persist_scorer_on_timer(&mut self) {
+		self.scorer_on_timer = true;
+	}
+
+	pub(crate) fn persist_scorer(&self, scorer: &Scorer) -> io::Result<()> {
+		self.seal_and_write("scorer", scorer)
+	}
+
+	fn seal_and_write<W: Writeable>(&self, key: &str, object: &W) -> io::Result<()> {
+		let value = self.seal(key, object.encode());
+		self.store.write(key, &value)?;
+		if let Some(replicator) = &self.replicator {
+			replicator.enqueue(key, value);
+		}
+		Ok(())
+	}
+
+	pub(crate) fn backup_status(&self) -> Option<BackupStatus> {
+		self.replicator.as_ref().map(|replicator| replicator.status())
+	}
+
+	/// Encrypts `value` to be stored under `key` if the node's state is encrypted.
+	pub(crate) fn seal(&self, key: &str, value: Vec<u8>) -> Vec<u8> {
+		match &self.cipher {
+			Some(cipher) => cipher.encrypt(key, &value),
+			None => value,
+		}
+	}
+
+	/// Decrypts `value` stored under `key` if the node's state is encrypted.
+	pub(crate) fn unseal(&self, key: &str, value: Vec<u8>) -> io::Result<Vec<u8>> {
+		match &self.cipher {
+			Some(cipher) => cipher.decrypt(key, &value),
+			None => Ok(value),
+		}
+	}
+
+	/// Returns what was last persisted under `key`, if anything.
+	pub(crate) fn read(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+		match self.store.read(key)? {
+			Some(value) => self.unseal(key, value).map(Some),
+			None => Ok(None),
+		}
+	}
+
+	/// Returns the (key, value) pairs stored under `<dir>/`.
+	pub(crate) fn read_dir(&self, dir: &str) -> io::Result<Vec<(String, Vec<u8>)>> {
+		self.store
+			.read_dir(dir)?
+			.into_iter()
+			.map(|(key, value)| Ok((key.clone(), self.unseal(&key, value)?)))
+			.collect()
+	}
+
+	pub(crate) fn remove(&self, key: &str) -> io::Result<()> {
+		self.store.remove(key)?;
+		if let Some(replicator) = &self.replicator {
+			replicator.enqueue_removal(key);
+		}
+		Ok(())
+	}
+
+	fn read_channelmonitors(
+		&self, keys_manager: Arc<NodeKeys>,
+	) -> io::Result<Vec<(BlockHash, ChannelMonitor<ChannelKeys>)>> {
+		decode_channelmonitors(self.read_dir(MONITORS_DIR)?, &keys_manager)
+	}
+}
+
+impl KVStorePersister for NodePersister {
+	fn persist<W: Writeable>(&self, key: &str, object: &W) -> io::Result<()> {
+		if key == "scorer" && self.scorer_on_timer {
+			return Ok(());
+		}
+		self.seal_and_write(key, object)
+	}
+}
+
+/// How many updates a channel's journal holds at most before its monitor is rewritten in full.
+const MAX_JOURNALED_UPDATES: usize = 64;
+
+fn monitor_key(funding_txo: OutPoint) -> String {
+	format!("{}/{}_{}", MONITORS_DIR, funding_txo.txid, funding_txo.index)
+}
+
+fn journal_key(funding_txo: OutPoint, update_id: u64) -> String {
+	format!("{}/{}_{}_{}", MONITOR_UPDATES_DIR, funding_txo.txid, funding_txo.index, update_id)
+}
+
+/// Persists `ChannelMonitor`s for the `ChainMonitor`. Rather than rewriting a whole monitor on
+/// every update, each `ChannelMonitorUpdate` is appended to the channel's journal, and the monitor
+/// is only rewritten once the journal is full, or for changes that don't come with an update. The
+/// journal is replayed onto the monitors on startup, so a crash mid-write loses nothing that was
+/// acknowledged.
+pub(crate) struct JournalingPersister {
+	persister: Arc<NodePersister>,
+	/// The updates journaled for each channel since its monitor was last written in full.
+	journaled: Mutex<HashMap<OutPoint, Vec<u64>>>,
+}
+
+impl JournalingPersister {
+	pub(crate) fn new(persister: Arc<NodePersister>) -> Self {
+		Self { persister, journaled: Mutex::new(HashMap::new()) }
+	}
+
+	/// Writes `monitor` in full, then drops the journaled updates it includes.
+	fn write_monitor(
+		&self, funding_txo: OutPoint, monitor: &ChannelMonitor<ChannelKeys>,
+	) -> io::Result<()> {
+		// Read before the monitor is serialized, as it may be updated concurrently, in which case
+		// the newer updates must stay journaled.
+		let latest_update_id = monitor.get_latest_update_id();
+		self.persister.persist(&monitor_key(funding_txo), monitor)?;
+		let folded = match self.journaled.lock().unwrap().get_mut(&funding_txo) {
+			Some(update_ids) => {
+				let (folded, kept) =
+					update_ids.iter().partition::<Vec<u64>, _>(|id| **id <= latest_update_id);
+				*update_ids = kept;
+				folded
+			}
+			None => return Ok(()),
+		};
+		// The monitor is safely written by now, so an update we fail to remove is only skipped
+		// when the journal is replayed on startup, and removed then.
+		for update_id in folded {
+			if let Err(e) = self.persister.remove(&journal_key(funding_txo, update_id)) {
+				println!(
+					"ERROR: failed to remove update {} from the journal of ChannelMonitor {}: {}",
+					update_id,
+					monitor_key(funding_txo),
+					e
+				);
+			}
+		}
+		Ok(())
+	}
+
+	/// Reads the monitors and replays their journaled updates onto them, then writes back those
+	/// that had any, emptying the journal.
+	pub(crate) fn read_channelmonitors<B: Deref, F: Deref + Clone>(
+		&self, keys_manager: Arc<NodeKeys>, broadcaster: &B, fee_estimator: &F,
+		logger: &Arc<FilesystemLogger>,
+	) -> io::Result<Vec<(BlockHash, ChannelMonitor<ChannelKeys>)>>
+	where
+		B::Target: BroadcasterInterface,
+		F::Target: FeeEstimator,
+	{
+		let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+		let monitors = self.persister.read_channelmonitors(keys_manager)?;
+		let mut journal: HashMap<OutPoint, Vec<ChannelMonitorUpdate>> = HashMap::new();
+		for (key, value) in self.persister.read_dir(MONITOR_UPDATES_DIR)? {
+			let (funding_txo, update) = decode_journaled_update(&key, &value)?;
+			journal.entry(funding_txo).or_insert_with(Vec::new).push(update);
+		}
+		for (_, monitor) in monitors.iter() {
+			let funding_txo = monitor.get_funding_txo().0;
+			let mut updates = match journal.remove(&funding_txo) {
+				Some(updates) => updates,
+				None => continue,
+			};
+			updates.sort_by_key(|update| update.update_id);
+			for update in updates.iter() {
+				// Already in the monitor if we crashed before its journal was emptied.
+				if update.update_id <= monitor.get_latest_update_id() {
+					continue;
+				}
+				monitor
+					.update_monitor(update, broadcaster, fee_estimator.clone(), logger)
+					.map_err(|()| {
+						invalid(format!(
+							"failed to replay update {} onto ChannelMonitor {}",
+							update.update_id,
+							monitor_key(funding_txo)
+						))
+					})?;
+			}
+			self.journaled
+				.lock()
+				.unwrap()
+				.insert(funding_txo, updates.iter().map(|update| update.update_id).collect());
+			self.write_monitor(funding_txo, monitor)?;
+		}
+		// Left behind by channels whose monitor is gone.
+		for (funding_txo, updates) in journal {
+			for update in updates {
+				self.persister.remove(&journal_key(funding_txo, update.update_id))?;
+			}
+		}
+		Ok(monitors)
+	}
+}
+
+impl Persist<ChannelKeys> for JournalingPersister {
+	fn persist_new_channel(
+		&self, funding_txo: OutPoint, monitor: &ChannelMonitor<ChannelKeys>,
+		_update_id: MonitorUpdateId,
+	) -> ChannelMonitorUpdateStatus {
+		match self.write_monitor(funding_txo, monitor) {
+			Ok(()) => ChannelMonitorUpdateStatus::Completed,
+			Err(_) => ChannelMonitorUpdateStatus::PermanentFailure,
+		}
+	}
+
+	fn update_persisted_channel(
+		&self, funding_txo: OutPoint, update: Option<&ChannelMonitorUpdate>,
+		monitor: &ChannelMonitor<ChannelKeys>, _update_id: MonitorUpdateId,
+	) -> ChannelMonitorUpdateStatus {
+		let result = match update {
+			// Updates to closed channels all share the same ID, so they can't be told apart in the
+			// journal.
+			Some(update) if update.update_id != CLOSED_CHANNEL_UPDATE_ID => {
+				let mut journaled = self.journaled.lock().unwrap();
+				let update_ids = journaled.entry(funding_txo).or_insert_with(Vec::new);
+				if update_ids.len() < MAX_JOURNALED_UPDATES {
+					let result =
+						self.persister.persist(&journal_key(funding_txo, update.update_id), update);
+					if result.is_ok() {
+						update_ids.push(update.update_id);
+					}
+					result
+				} else {
+					drop(journaled);
+					self.write_monitor(funding_txo, monitor)
+				}
+			}
+			_ => self.write_monitor(funding_txo, monitor),
+		};
+		match result {
+			Ok(()) => ChannelMonitorUpdateStatus::Completed,
+			Err(_) => ChannelMonitorUpdateStatus::PermanentFailure,
+		}
+	}
+}
+
+#[derive(Clone, PartialEq)]
+pub(crate) enum StorageBackend {
+	Filesystem,
+	Sqlite,
+	/// Given the database's connection string.
+	Postgres(String),
+	/// Given the server's base URL and the store ID the node's keys are kept under.
+	Vss {
+		url: String,
+		store_id: String,
+	},
+}
+
+impl StorageBackend {
+	/// The name `--storage` takes.
+	pub(crate) fn name(&self) -> &'static str {
+		match self {
+			StorageBackend::Filesystem => "files",
+			StorageBackend::Sqlite => "sqlite",
+			StorageBackend::Postgres(_) => "postgres",
+			StorageBackend::Vss { .. } => "vss",
+		}
+	}
+}
+
+/// Marks a store whose state was migrated to another backend, naming it, so the node doesn't start
+/// from the stale copy left behind.
+const MIGRATED_TO_KEY: &str = "migrated_to";
+
+/// Copies the node's state from one backend to another, checking each value reads back intact,
+/// then marks the source as migrated. Values are copied as stored, so encrypted state stays
+/// encrypted. A destination the state was migrated away from before is overwritten, so migrations
+/// can be reversed. Returns how many values were copied.
+pub(crate) async fn migrate_storage(
+	from: &StorageBackend, to: &StorageBackend, ldk_data_dir: &str,
+) -> Result<usize, String> {
+	let source = Store::open(from, ldk_data_dir).await?;
+	let dest = Store::open(to, ldk_data_dir).await?;
+	let io_err = |e: io::Error| e.to_string();
+	if let Some(name) = source.read(MIGRATED_TO_KEY).map_err(io_err)? {
+		return Err(format!(
+			"the {} storage was already migrated to {} storage",
+			from.name(),
+			String::from_utf8_lossy(&name)
+		));
+	}
+	if source.read("manager").map_err(io_err)?.is_none() {
+		return Err(format!("the {} storage doesn't hold a node's state", from.name()));
+	}
+	if dest.read(MIGRATED_TO_KEY).map_err(io_err)?.is_none()
+		&& dest.read("manager").map_err(io_err)?.is_some()
+	{
+		return Err(format!("the {} storage already holds a node's state", to.name()));
+	}
+
+	// The encryption header goes first and the manager last, so the destination never holds a
+	// manager without its monitors.
+	let mut values = Vec::new();
+	if let Some(header) = source.read(ENCRYPTION_HEADER_KEY).map_err(io_err)? {
+		values.push((ENCRYPTION_HEADER_KEY.to_string(), header));
+	}
+	values.extend(source.read_state_dirs().map_err(io_err)?);
+	for key in STATE_KEYS.iter().rev() {
+		if let Some(value) = source.read(key).map_err(io_err)? {
+			values.push((key.to_string(), value));
+		}
+	}
+	for (key, value) in values.iter() {
+		dest.write(key, value).map_err(|e| format!("failed to write {}: {}", key, e))?;
+		match dest.read_back(key).map_err(io_err)? {
+			Some(copied) if copied == *value => {}
+			_ => return Err(format!("{} didn't read back intact", key)),
+		}
+	}
+
+	// Drop whatever is left of a state migrated away from the destination before.
+	let mut stale =
+		dest.read_state_dirs().map_err(io_err)?.into_iter().map(|(key, _)| key).collect::<Vec<_>>();
+	stale.extend(STATE_KEYS.iter().map(|key| key.to_string()));
+	stale.push(ENCRYPTION_HEADER_KEY.to_string());
+	for key in stale.iter().filter(|key| !values.iter().any(|(copied, _)| copied == *key)) {
+		dest.remove(key).map_err(|e| format!("failed to remove stale {}: {}", key, e))?;
+	}
+	dest.remove(MIGRATED_TO_KEY).map_err(io_err)?;
+	source.write(MIGRATED_TO_KEY, to.name().as_bytes()).map_err(io_err)?;
+	Ok(values.len())
+}
+
+/// Replaces whatever state `storage` holds with `values`, as returned by
+/// `NodePersister::read_stored_state`. The manager is written last, so the store never holds a
+/// manager without its monitors.
+pub(crate) async fn restore_state(
+	storage: &StorageBackend, ldk_data_dir: &str, mut values: Vec<(String, Vec<u8>)>,
+) -> Result<(), String> {
+	let store = Store::open(storage, ldk_data_dir).await?;
+	let io_err = |e: io::Error| e.to_string();
+	values.sort_by_key(|(key, _)| (key != ENCRYPTION_HEADER_KEY, key == "manager"));
+	let mut stale = store
+		.read_state_dirs()
+		.map_err(io_err)?
+		.into_iter()
+		.map(|(key, _)| key)
+		.collect::<Vec<_>>();
+	stale.extend(STATE_KEYS.iter().map(|key| key.to_string()));
+	stale.push(ENCRYPTION_HEADER_KEY.to_string());
+	// Drop the manager first, so the store doesn't look like it holds a node's state until the
+	// restore is complete.
+	store.remove("manager").map_err(io_err)?;
+	for key in stale.iter().filter(|key| !values.iter().any(|(restored, _)| restored == *key)) {
+		store.remove(key).map_err(|e| format!("failed to remove {}: {}", key, e))?;
+	}
+	store.remove(MIGRATED_TO_KEY).map_err(io_err)?;
+	for (key, value) in values.iter() {
+		store.write(key, value).map_err(|e| format!("failed to write {}: {}", key, e))?;
+	}
+	Ok(())
+}
+
+/// Keeps every key in a single `kv` table.
+pub(crate) struct SqliteStore {
+	conn: Mutex<Connection>,
+}
+
+fn sqlite_err(e: rusqlite::Error) -> io::Error {
+	io::Error::new(io::ErrorKind::Other, e)
+}
+
+impl SqliteStore {
+	fn open(path: &Path) -> Result<Self, String> {
+		let conn = Connection::open(path)
+			.map_err(|e| format!("failed to open {}: {}", path.display(), e))?;
+		// Writes are only acknowledged once they're durable, as LDK requires for monitors.
+		conn.execute_batch(
+			"PRAGMA journal_mode = WAL;
+			PRAGMA synchronous = FULL;
+			CREATE TABLE IF NOT EXISTS kv (key TEXT PRIMARY KEY NOT NULL, value BLOB NOT NULL);",
+		)
+		.map_err(|e| format!("failed to set up {}: {}", path.display(), e))?;
+		Ok(Self { conn: Mutex::new(conn) })
+	}
+
+	fn read(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+		self.conn
+			.lock()
+			.unwrap()
+			.query_row("SELECT value FROM kv WHERE key = ?1", params![key], |row| row.get(0))
+			.optional()
+			.map_err(sqlite_err)
+	}
+
+	fn write(&self, key: &str, value: &[u8]) -> io::Result<()> {
+		self.conn
+			.lock()
+			.unwrap()
+			.execute(
+				"INSERT INTO kv (key, value) VALUES (?1, ?2)
+				ON CONFLICT (key) DO UPDATE SET value = excluded.value",
+				params![key, value],
+			)
+			.map(|_| ())
+			.map_err(sqlite_err)
+	}
+
+	fn remove(&self, key: &str) -> io::Result<()> {
+		self.conn
+			.lock()
+			.unwrap()
+			.execute("DELETE FROM kv WHERE key = ?1", params![key])
+			.map(|_| ())
+			.map_err(sqlite_err)
+	}
+
+	fn read_dir(&self, dir: &str) -> io::Result<Vec<(String, Vec<u8>)>> {
+		let conn = self.conn.lock().unwrap();
+		let mut stmt =
+			conn.prepare("SELECT key, value FROM kv WHERE key LIKE ?1").map_err(sqlite_err)?;
+		let rows = stmt
+			.query_map(params![format!("{}/%", dir)], |row| {
+				Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?))
+			})
+			.map_err(sqlite_err)?
+			.collect::<Result<Vec<_>, _>>()
+			.map_err(sqlite_err);
+		rows
+	}
+}
+
+/// LDK persists synchronously, from both our async tasks and the background processor's thread,
+/// so remote stores block on their async clients without stalling the other tasks of this worker.
+fn block_on<F: Future>(handle: &tokio::runtime::Handle, future: F) -> F::Output {
+	tokio::task::block_in_place(|| handle.block_on(future))
+}
+
+/// How many connections the Postgres pool keeps open at most.
+const POSTGRES_POOL_SIZE: usize = 8;
+
+/// The schema, one migration per version. Applied migrations must never change, new ones are
+/// appended.
+const POSTGRES_MIGRATIONS: &[&str] =
+	&["CREATE TABLE kv (key TEXT PRIMARY KEY NOT NULL, value BYTEA NOT NULL);"];
+
+/// Keeps every key in a `kv` table, talking to the database through a connection pool.
+pub(crate) struct PostgresStore {
+	pool: deadpool_postgres::Pool,
+	handle: tokio::runtime::Handle,
+}
+
+fn postgres_err<E: std::fmt::Display>(e: E) -> io::Error {
+	io::Error::new(io::ErrorKind::Other, e.to_string())
+}
+
+impl PostgresStore {
+	async fn open(url: &str) -> Result<Self, String> {
+		let config = tokio_postgres::Config::from_str(url)
+			.map_err(|e| format!("invalid Postgres connection string: {}", e))?;
+		let manager = deadpool_postgres::Manager::new(config, NoTls);
+		let pool = deadpool_postgres::Pool::builder(manager)
+			.max_size(POSTGRES_POOL_SIZE)
+			.build()
+			.map_err(|e| format!("failed to create Postgres connection pool: {}", e))?;
+		let store = Self { pool, handle: tokio::runtime::Handle::current() };
+		store.migrate().await.map_err(|e| format!("failed to migrate Postgres schema: {}", e))?;
+		Ok(store)
+	}
+
+	/// Brings the schema up to date, recording applied migrations in `schema_migrations`.
+	async fn migrate(&self) -> io::Result<()> {
+		let mut client = self.pool.get().await.map_err(postgres_err)?;
+		client
+			.batch_execute(
+				"CREATE TABLE IF NOT EXISTS schema_migrations (
+					version INTEGER PRIMARY KEY,
+					applied_at TIMESTAMPTZ NOT NULL DEFAULT now()
+				);",
+			)
+			.await
+			.map_err(postgres_err)?;
+		let tx = client.transaction().await.map_err(postgres_err)?;
+		// Keep another node starting against the same database from migrating concurrently.
+		tx.batch_execute("LOCK TABLE schema_migrations IN EXCLUSIVE MODE;")
+			.await
+			.map_err(postgres_err)?;
+		let applied: i32 = tx
+			.query_one("SELECT COALESCE(MAX(version), 0) FROM schema_migrations", &[])
+			.await
+			.map_err(postgres_err)?
+			.get(0);
+		if applied as usize > POSTGRES_MIGRATIONS.len() {
+			return Err(postgres_err(format!(
+				"the database schema is at version {}, newer than this node knows about",
+				applied
+			)));
+		}
+		for (version, migration) in POSTGRES_MIGRATIONS.iter().enumerate().skip(applied as usize) {
+			tx.batch_execute(migration).await.map_err(postgres_err)?;
+			tx.execute(
+				"INSERT INTO schema_migrations (version) VALUES ($1)",
+				&[&(version as i32 + 1)],
+			)
+			.await
+			.map_err(postgres_err)?;
+		}
+		tx.commit().await.map_err(postgres_err)
+	}
+
+	async fn read_async(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+		let client = self.pool.get().await.map_err(postgres_err)?;
+		let row = client
+			.query_opt("SELECT value FROM kv WHERE key = $1", &[&key])
+			.await
+			.map_err(postgres_err)?;
+		Ok(row.map(|row| row.get(0)))
+	}
+
+	fn read(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+		block_on(&self.handle, self.read_async(key))
+	}
+
+	fn write(&self, key: &str, value: &[u8]) -> io::Result<()> {
+		block_on(&self.handle, async {
+			let client = self.pool.get().await.map_err(postgres_err)?;
+			client
+				.execute(
+					"INSERT INTO kv (key, value) VALUES ($1, $2)
+					ON CONFLICT (key) DO UPDATE SET value = EXCLUDED.value",
+					&[&key, &value],
+				)
+				.await
+				.map(|_| ())
+				.map_err(postgres_err)
+		})
+	}
+
+	fn remove(&self, key: &str) -> io::Result<()> {
+		block_on(&self.handle, async {
+			let client = self.pool.get().await.map_err(postgres_err)?;
+			client
+				.execute("DELETE FROM kv WHERE key = $1", &[&key])
+				.await
+				.map(|_| ())
+				.map_err(postgres_err)
+		})
+	}
+
+	fn read_dir(&self, dir: &str) -> io::Result<Vec<(String, Vec<u8>)>> {
+		block_on(&self.handle, async {
+			let client = self.pool.get().await.map_err(postgres_err)?;
+			let rows = client
+				.query("SELECT key, value FROM kv WHERE key LIKE $1", &[&format!("{}/%", dir)])
+				.await
+				.map_err(postgres_err)?;
+			Ok::<Vec<(String, Vec<u8>)>, io::Error>(
+				rows.iter().map(|row| (row.get(0), row.get(1))).collect(),
+			)
+		})
+	}
+}
+
+/// The store ID the node's keys are kept under on the VSS server unless told otherwise.
+pub(crate) const DEFAULT_VSS_STORE_ID: &str = "ldk_sample";
+
+/// How many times a VSS write is attempted before giving up, as a failed monitor write makes LDK
+/// close the channel.
+const VSS_WRITE_ATTEMPTS: u32 = 5;
+
+/// Keeps every key in a remote Versioned Storage Service. Each value carries a version the server
+/// checks on every write, so a write based on a stale version (e.g. by another host running the
+/// same node) is rejected rather than silently overwriting newer state.
+pub(crate) struct VssStore {
+	client: VssClient,
+	store_id: String,
+	handle: tokio::runtime::Handle,
+	/// The values fetched or written so far, with their versions, `None` if the server had none.
+	/// Values are only fetched the first time they're needed.
+	cache: Mutex<HashMap<String, Option<(i64, Vec<u8>)>>>,
+	/// Keeps writes to the same key from racing on its version.
+	write_lock: Mutex<()>,
+}
+
+fn vss_err<E: std::fmt::Display>(e: E) -> io::Error {
+	io::Error::new(io::ErrorKind::Other, format!("VSS request failed: {}", e))
+}
+
+impl VssStore {
+	fn new(url: &str, store_id: &str) -> Self {
+		Self {
+			client: VssClient::new(url),
+			store_id: store_id.to_string(),
+			handle: tokio::runtime::Handle::current(),
+			cache: Mutex::new(HashMap::new()),
+			write_lock: Mutex::new(()),
+		}
+	}
+
+	async fn fetch(&self, key: &str) -> io::Result<Option<(i64, Vec<u8>)>> {
+		if let Some(cached) = self.cache.lock().unwrap().get(key) {
+			return Ok(cached.clone());
+		}
+		let request = GetObjectRequest { store_id: self.store_id.clone(), key: key.to_string() };
+		let value = match self.client.get_object(&request).await {
+			Ok(response) => response.value.map(|kv| (kv.version, kv.value)),
+			Err(VssError::NoSuchKeyError(_)) => None,
+			Err(e) => return Err(vss_err(e)),
+		};
+		self.cache.lock().unwrap().insert(key.to_string(), value.clone());
+		Ok(value)
+	}
+
+	async fn read_async(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+		Ok(self.fetch(key).await?.map(|(_, value)| value))
+	}
+
+	fn read(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+		block_on(&self.handle, self.read_async(key))
+	}
+
+	fn write(&self, key: &str, value: &[u8]) -> io::Result<()> {
+		let _lock = self.write_lock.lock().unwrap();
+		block_on(&self.handle, async {
+			let version = self.fetch(key).await?.map_or(0, |(version, _)| version);
+			let request = PutObjectRequest {
+				store_id: self.store_id.clone(),
+				global_version: None,
+				transaction_items: vec![KeyValue {
+					key: key.to_string(),
+					version,
+					value: value.to_vec(),
+				}],
+				delete_items: Vec::new(),
+			};
+			let mut attempt = 1;
+			loop {
+				match self.client.put_object(&request).await {
+					Ok(_) => break,
+					Err(VssError::ConflictError(e)) => {
+						// Our cached version is stale, someone else wrote since.
+						self.cache.lock().unwrap().remove(key);
+						return Err(vss_err(format!("{} was modified concurrently: {}", key, e)));
+					}
+					Err(e) if attempt < VSS_WRITE_ATTEMPTS => {
+						println!("ERROR: failed to write {} to VSS, retrying: {}", key, e);
+						tokio::time::sleep(Duration::from_secs(1 << attempt)).await;
+						attempt += 1;
+					}
+					Err(e) => return Err(vss_err(e)),
+				}
+			}
+			self.cache.lock().unwrap().insert(key.to_string(), Some((version + 1, value.to_vec())));
+			Ok(())
+		})
+	}
+
+	fn remove(&self, key: &str) -> io::Result<()> {
+		let _lock = self.write_lock.lock().unwrap();
+		block_on(&self.handle, async {
+			let version = match self.fetch(key).await? {
+				Some((version, _)) => version,
+				None => return Ok(()),
+			};
+			let request = DeleteObjectRequest {
+				store_id: self.store_id.clone(),
+				key_value: Some(KeyValue { key: key.to_string(), version, value: Vec::new() }),
+			};
+			self.client.delete_object(&request).await.map_err(vss_err)?;
+			self.cache.lock().unwrap().insert(key.to_string(), None);
+			Ok(())
+		})
+	}
+
+	fn read_dir(&self, dir: &str) -> io::Result<Vec<(String, Vec<u8>)>> {
+		block_on(&self.handle, async {
+			let mut keys = Vec::new();
+			let mut page_token = None;
+			loop {
+				let request = ListKeyVersionsRequest {
+					store_id: self.store_id.clone(),
+					key_prefix: Some(format!("{}/", dir)),
+					page_size: None,
+					page_token: page_token.take(),
+				};
+				let response = self.client.list_key_versions(&request).await.map_err(vss_err)?;
+				let last_page = response.key_versions.is_empty();
+				keys.extend(response.key_versions.into_iter().map(|kv| kv.key));
+				match response.next_page_token {
+					Some(token) if !last_page && !token.is_empty() => page_token = Some(token),
+					_ => break,
+				}
+			}
+			let mut values = Vec::new();
+			for key in keys {
+				if let Some((_, value)) = self.fetch(&key).await? {
+					values.push((key, value));
+				}
+			}
+			Ok(values)
+		})
+	}
+}
+
+/// Decodes the monitors persisted under `monitors/<funding_txid>_<funding_index>`, the keys LDK's
+/// `Persist` implementation uses, given as (key, value) pairs.
+fn decode_channelmonitors(
+	rows: Vec<(String, Vec<u8>)>, keys_manager: &NodeKeys,
+) -> io::Result<Vec<(BlockHash, ChannelMonitor<ChannelKeys>)>> {
+	rows.iter().map(|(key, value)| decode_channelmonitor(key, value, keys_manager)).collect()
+}
+
+/// Decodes the monitor stored under `key`, checking it's the one for the funding outpoint the key
+/// names.
+pub(crate) fn decode_channelmonitor(
+	key: &str, value: &[u8], keys_manager: &NodeKeys,
+) -> io::Result<(BlockHash, ChannelMonitor<ChannelKeys>)> {
+	let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+	let mut name_parts = key[MONITORS_DIR.len() + 1..].splitn(2, '_');
+	let (txid, index) =
+		match (name_parts.next().map(Txid::from_hex), name_parts.next().map(str::parse::<u16>)) {
+			(Some(Ok(txid)), Some(Ok(index))) => (txid, index),
+			_ => return Err(invalid(format!("invalid ChannelMonitor key {}", key))),
+		};
+	let (blockhash, monitor) = <(BlockHash, ChannelMonitor<ChannelKeys>)>::read(
+		&mut io::Cursor::new(value),
+		(keys_manager, keys_manager),
+	)
+	.map_err(|e| invalid(format!("failed to read ChannelMonitor {}: {:?}", key, e)))?;
+	let funding_txo = monitor.get_funding_txo().0;
+	if funding_txo.txid != txid || funding_txo.index != index {
+		return Err(invalid(format!(
+			"ChannelMonitor {} is for {}:{}",
+			key, funding_txo.txid, funding_txo.index
+		)));
+	}
+	Ok((blockhash, monitor))
+}
+
+/// Decodes the update journaled under `key`, along with the funding outpoint of its channel.
+pub(crate) fn decode_journaled_update(
+	key: &str, value: &[u8],
+) -> io::Result<(OutPoint, ChannelMonitorUpdate)> {
+	let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+	let mut name_parts = key[MONITOR_UPDATES_DIR.len() + 1..].splitn(3, '_');
+	let funding_txo =
+		match (name_parts.next().map(Txid::from_hex), name_parts.next().map(str::parse::<u16>)) {
+			(Some(Ok(txid)), Some(Ok(index))) => OutPoint { txid, index },
+			_ => return Err(invalid(format!("invalid ChannelMonitorUpdate key {}", key))),
+		};
+	let update = Readable::read(&mut io::Cursor::new(value))
+		.map_err(|e| invalid(format!("failed to read ChannelMonitorUpdate {}: {:?}", key, e)))?;
+	Ok((funding_txo, update))
+}
+
+#[cfg(test)]
+pub(crate) mod journal_tests {
+	use super::*;
+	use bitcoin::blockdata::block::BlockHeader;
+	use bitcoin::blockdata::constants::genesis_block;
+	use bitcoin::hashes::Hash;
+	use bitcoin::secp256k1::PublicKey;
+	use bitcoin::{Network, PackedLockTime, Transaction, TxIn, TxMerkleNode, TxOut, Witness};
+	use lightning::chain::chaininterface::ConfirmationTarget;
+	use lightning::chain::keysinterface::KeysManager;
+	use lightning::chain::{chainmonitor, BestBlock, Confirm, Filter};
+	use lightning::ln::channelmanager::{self, ChainParameters, ChannelDetails, PaymentId};
+	use lightning::ln::features::ChannelFeatures;
+	use lightning::ln::msgs::{self, ChannelMessageHandler, ErrorAction, LightningError};
+	use lightning::ln::{PaymentHash, PaymentSecret};
+	use lightning::routing::router::{InFlightHtlcs, Route, RouteHop, RouteParameters, Router};
+	use lightning::util::config::UserConfig;
+	use lightning::util::events::{
+		Event, EventsProvider, MessageSendEvent, MessageSendEventsProvider,
+	};
+	use std::time::SystemTime;
+
+	struct TestChain;
+
+	impl BroadcasterInterface for TestChain {
+		fn broadcast_transaction(&self, _tx: &Transaction) {}
+	}
+
+	impl FeeEstimator for TestChain {
+		fn get_est_sat_per_1000_weight(&self, _confirmation_target: ConfirmationTarget) -> u32 {
+			253
+		}
+	}
+
+	/// The test's payment is routed by hand.
+	struct NoRouter;
+
+	impl Router for NoRouter {
+		fn find_route(
+			&self, _payer: &PublicKey, _route_params: &RouteParameters,
+			_first_hops: Option<&[&ChannelDetails]>, _inflight_htlcs: &InFlightHtlcs,
+		) -> Result<Route, LightningError> {
+			Err(LightningError { err: "no routes".to_string(), action: ErrorAction::IgnoreError })
+		}
+	}
+
+	type TestChainMonitor = chainmonitor::ChainMonitor<
+		ChannelKeys,
+		Arc<dyn Filter + Send + Sync>,
+		Arc<TestChain>,
+		Arc<TestChain>,
+		Arc<FilesystemLogger>,
+		Arc<JournalingPersister>,
+	>;
+
+	type TestChannelManager = channelmanager::ChannelManager<
+		Arc<TestChainMonitor>,
+		Arc<TestChain>,
+		Arc<NodeKeys>,
+		Arc<NodeKeys>,
+		Arc<NodeKeys>,
+		Arc<TestChain>,
+		Arc<NoRouter>,
+		Arc<FilesystemLogger>,
+	>;
+
+	pub(crate) struct TestNode {
+		chain_monitor: Arc<TestChainMonitor>,
+		channel_manager: TestChannelManager,
+	}
+
+	pub(crate) fn test_dir(name: &str) -> String {
+		let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap();
+		let dir = std::env::temp_dir().join(format!("ldk-sample-{}-{}", name, now.as_nanos()));
+		fs::create_dir_all(&dir).unwrap();
+		dir.to_str().unwrap().to_string()
+	}
+
+	pub(crate) async fn open_persister(dir: &str) -> Arc<NodePersister> {
+		let persister = NodePersister::open(
+			StorageBackend::Filesystem,
+			dir,
+			EncryptionSetup::Unlock,
+			None,
+			None,
+		)
+		.await
+		.unwrap();
+		Arc::new(persister)
+	}
+
+	pub(crate) fn keys(seed: u8) -> Arc<NodeKeys> {
+		let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap();
+		Arc::new(NodeKeys::Local(KeysManager::new(&[seed; 32], now.as_secs(), now.subsec_nanos())))
+	}
+
+	pub(crate) fn new_node(dir: &str, persister: Arc<NodePersister>, seed: u8) -> TestNode {
+		let chain = Arc::new(TestChain);
+		let logger = Arc::new(FilesystemLogger::new(dir.to_string()));
+		let keys_manager = keys(seed);
+		let chain_monitor: Arc<TestChainMonitor> = Arc::new(chainmonitor::ChainMonitor::new(
+			None,
+			Arc::clone(&chain),
+			Arc::clone(&logger),
+			Arc::clone(&chain),
+			Arc::new(JournalingPersister::new(persister)),
+		));
+		let network = Network::Regtest;
+		let channel_manager = channelmanager::ChannelManager::new(
+			Arc::clone(&chain),
+			Arc::clone(&chain_monitor),
+			Arc::clone(&chain),
+			Arc::new(NoRouter),
+			logger,
+			Arc::clone(&keys_manager),
+			Arc::clone(&keys_manager),
+			keys_manager,
+			UserConfig::default(),
+			ChainParameters { network, best_block: BestBlock::from_genesis(network) },
+		);
+		TestNode { chain_monitor, channel_manager }
+	}
+
+	/// Hands `to` the messages `from` queued for it, returning whether there were any.
+	fn deliver_messages(from: &TestNode, to: &TestNode) -> bool {
+		let from_id = from.channel_manager.get_our_node_id();
+		let to_id = to.channel_manager.get_our_node_id();
+		let msg_events = from.channel_manager.get_and_clear_pending_msg_events();
+		let delivered = !msg_events.is_empty();
+		for event in msg_events {
+			match event {
+				MessageSendEvent::SendOpenChannel { msg, .. } => {
+					let features = from.channel_manager.provided_init_features(&to_id);
+					to.channel_manager.handle_open_channel(&from_id, features, &msg)
+				}
+				MessageSendEvent::SendAcceptChannel { msg, .. } => {
+					let features = from.channel_manager.provided_init_features(&to_id);
+					to.channel_manager.handle_accept_channel(&from_id, features, &msg)
+				}
+				MessageSendEvent::SendFundingCreated { msg, .. } => {
+					to.channel_manager.handle_funding_created(&from_id, &msg)
+				}
+				MessageSendEvent::SendFundingSigned { msg, .. } => {
+					to.channel_manager.handle_funding_signed(&from_id, &msg)
+				}
+				MessageSendEvent::SendChannelReady { msg, .. } => {
+					to.channel_manager.handle_channel_ready(&from_id, &msg)
+				}
+				MessageSendEvent::UpdateHTLCs { updates, .. } => {
+					for msg in updates.update_add_htlcs.iter() {
+						to.channel_manager.handle_update_add_htlc(&from_id, msg);
+					}
+					for msg in updates.update_fail_htlcs.iter() {
+						to.channel_manager.handle_update_fail_htlc(&from_id, msg);
+					}
+					to.channel_manager
+						.handle_commitment_signed(&from_id, &updates.commitment_signed);
+				}
+				MessageSendEvent::SendRevokeAndACK { msg, .. } => {
+					to.channel_manager.handle_revoke_and_ack(&from_id, &msg)
+				}
+				_ => {}
+			}
+		}
+		delivered
+	}
+
+	fn deliver_all_messages(a: &TestNode, b: &TestNode) {
+		while deliver_messages(a, b) | deliver_messages(b, a) {}
+	}
+
+	/// Funds the channel `node` is opening, returning its funding transaction.
+	fn fund_channel(node: &TestNode) -> Transaction {
+		let funding_tx = Mutex::new(None);
+		node.channel_manager.process_pending_events(&|event| {
+			if let Event::FundingGenerationReady {
+				temporary_channel_id,
+				counterparty_node_id,
+				channel_value_satoshis,
+				output_script,
+				..
+			} = event
+			{
+				let tx = Transaction {
+					version: 2,
+					lock_time: PackedLockTime(0),
+					input: vec![TxIn {
+						witness: Witness::from_vec(vec![vec![1]]),
+						..Default::default()
+					}],
+					output: vec![TxOut {
+						value: channel_value_satoshis,
+						script_pubkey: output_script,
+					}],
+				};
+				node.channel_manager
+					.funding_transaction_generated(
+						&temporary_channel_id,
+						&counterparty_node_id,
+						tx.clone(),
+					)
+					.unwrap();
+				*funding_tx.lock().unwrap() = Some(tx);
+			}
+		});
+		funding_tx.into_inner().unwrap().unwrap()
+	}
+
+	/// Confirms `tx` in block 1, then connects blocks up to 6, enough for channels to be ready.
+	fn confirm(node: &TestNode, tx: &Transaction) {
+		let header = |prev_blockhash| BlockHeader {
+			version: 0x20000000,
+			prev_blockhash,
+			merkle_root: TxMerkleNode::all_zeros(),
+			time: 42,
+			bits: 42,
+			nonce: 42,
+		};
+		let block_1 = header(genesis_block(Network::Regtest).block_hash());
+		node.channel_manager.transactions_confirmed(&block_1, &[(0, tx)], 1);
+		node.channel_manager.best_block_updated(&header(block_1.block_hash()), 6);
+	}
+
+	/// Connects `a` to `b` and opens a 100k sat channel from `a`, returning its funding transaction
+	/// once the channel is ready.
+	pub(crate) fn open_channel(a: &TestNode, b: &TestNode) -> Transaction {
+		let a_id = a.channel_manager.get_our_node_id();
+		let b_id = b.channel_manager.get_our_node_id();
+		let init = |node: &TestNode, their_node_id| msgs::Init {
+			features: node.channel_manager.provided_init_features(their_node_id),
+			remote_network_address: None,
+		};
+		a.channel_manager.peer_connected(&b_id, &init(b, &a_id), true).unwrap();
+		b.channel_manager.peer_connected(&a_id, &init(a, &b_id), false).unwrap();
+
+		a.channel_manager.create_channel(b_id, 100_000, 0, 42, None).unwrap();
+		deliver_all_messages(a, b);
+		let funding_tx = fund_channel(a);
+		deliver_all_messages(a, b);
+		confirm(a, &funding_tx);
+		confirm(b, &funding_tx);
+		deliver_all_messages(a, b);
+		funding_tx
+	}
+
+	#[tokio::test]
+	async fn journaled_updates_are_replayed_on_restart() {
+		let a_dir = test_dir("journal-a");
+		let b_dir = test_dir("journal-b");
+		let a = new_node(&a_dir, open_persister(&a_dir).await, 1);
+		let b = new_node(&b_dir, open_persister(&b_dir).await, 2);
+		let funding_tx = open_channel(&a, &b);
+		let b_id = b.channel_manager.get_our_node_id();
+
+		// Adding an HTLC takes A's monitor through a few updates, which get journaled.
+		let channel = a.channel_manager.list_usable_channels().pop().unwrap();
+		let route = Route {
+			paths: vec![vec![RouteHop {
+				pubkey: b_id,
+				node_features: b.channel_manager.provided_node_features(),
+				short_channel_id: channel.short_channel_id.unwrap(),
+				channel_features: ChannelFeatures::empty(),
+				fee_msat: 10_000_000,
+				cltv_expiry_delta: 40,
+			}]],
+			payment_params: None,
+		};
+		a.channel_manager
+			.send_payment(
+				&route,
+				PaymentHash([1; 32]),
+				&Some(PaymentSecret([2; 32])),
+				PaymentId([1; 32]),
+			)
+			.unwrap();
+		deliver_all_messages(&a, &b);
+
+		let funding_txo = OutPoint { txid: funding_tx.txid(), index: 0 };
+		let latest_update_id =
+			a.chain_monitor.get_monitor(funding_txo).unwrap().get_latest_update_id();
+		drop(a);
+
+		let persister = open_persister(&a_dir).await;
+		let keys_manager = keys(1);
+		let journaled = persister.read_dir(MONITOR_UPDATES_DIR).unwrap();
+		assert!(!journaled.is_empty());
+		assert_eq!(journaled.len() as u64, latest_update_id);
+		let (_, written) =
+			persister.read_channelmonitors(Arc::clone(&keys_manager)).unwrap().pop().unwrap();
+		assert!(written.get_latest_update_id() < latest_update_id);
+
+		let chain = Arc::new(TestChain);
+		let logger = Arc::new(FilesystemLogger::new(a_dir.clone()));
+		let (_, monitor) = JournalingPersister::new(Arc::clone(&persister))
+			.read_channelmonitors(Arc::clone(&keys_manager), &chain, &chain, &logger)
+			.unwrap()
+			.pop()
+			.unwrap();
+		assert_eq!(monitor.get_funding_txo().0, funding_txo);
+		assert_eq!(monitor.get_latest_update_id(), latest_update_id);
+
+		// The replayed updates were folded into the monitor written back, emptying the journal.
+		assert!(persister.read_dir(MONITOR_UPDATES_DIR).unwrap().is_empty());
+		let (_, written) = persister.read_channelmonitors(keys_manager).unwrap().pop().unwrap();
+		assert_eq!(written.get_latest_update_id(), latest_update_id);
+
+		fs::remove_dir_all(&a_dir).unwrap();
+		fs::remove_dir_all(&b_dir).unwrap();
+	}
+}
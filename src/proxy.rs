@@ -0,0 +1,146 @@
+use crate::tor;
+use lightning::ln::msgs::NetAddress;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// Proxies, Tor in particular, take a while to build a circuit to the peer.
+const PROXY_CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+const DIRECT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where a peer listens. Hostnames are only resolved when connecting, by the proxy if there's one,
+/// so they don't leak through DNS and `.onion` hosts can be reached through Tor.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum PeerAddr {
+	Ip(SocketAddr),
+	Host { host: String, port: u16 },
+}
+
+impl FromStr for PeerAddr {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, String> {
+		if let Ok(addr) = SocketAddr::from_str(s) {
+			return Ok(PeerAddr::Ip(addr));
+		}
+		let mut parts = s.rsplitn(2, ':');
+		match (parts.next().map(u16::from_str), parts.next()) {
+			(Some(Ok(port)), Some(host)) if !host.is_empty() && !host.contains(':') => {
+				Ok(PeerAddr::Host { host: host.to_string(), port })
+			}
+			_ => Err(format!("{} isn't formatted as host:port", s)),
+		}
+	}
+}
+
+impl PeerAddr {
+	/// Converts an address from a node announcement, if it's one we can connect to.
+	pub(crate) fn from_net_address(address: &NetAddress) -> Option<Self> {
+		match address {
+			NetAddress::IPv4 { addr, port } => Some(PeerAddr::Ip(SocketAddr::from((*addr, *port)))),
+			NetAddress::IPv6 { addr, port } => Some(PeerAddr::Ip(SocketAddr::from((*addr, *port)))),
+			NetAddress::OnionV3 { ed25519_pubkey, checksum, version, port } => {
+				Some(PeerAddr::Host {
+					host: tor::onion_v3_host(ed25519_pubkey, *checksum, *version),
+					port: *port,
+				})
+			}
+			_ => None,
+		}
+	}
+}
+
+impl fmt::Display for PeerAddr {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			PeerAddr::Ip(addr) => write!(f, "{}", addr),
+			PeerAddr::Host { host, port } => write!(f, "{}:{}", host, port),
+		}
+	}
+}
+
+/// Opens a TCP connection to `addr`, through the SOCKS5 proxy at `proxy` if given.
+pub(crate) async fn connect(
+	addr: &PeerAddr, proxy: Option<SocketAddr>,
+) -> io::Result<std::net::TcpStream> {
+	let stream = match (proxy, addr) {
+		(Some(proxy), _) => {
+			tokio::time::timeout(PROXY_CONNECT_TIMEOUT, connect_through_proxy(proxy, addr)).await
+		}
+		(None, PeerAddr::Host { host, .. }) if host.ends_with(".onion") => {
+			return Err(io::Error::new(
+				io::ErrorKind::Other,
+				"onion addresses can only be reached through a proxy",
+			))
+		}
+		(None, PeerAddr::Ip(addr)) => {
+			tokio::time::timeout(DIRECT_CONNECT_TIMEOUT, TcpStream::connect(addr)).await
+		}
+		(None, PeerAddr::Host { host, port }) => {
+			tokio::time::timeout(DIRECT_CONNECT_TIMEOUT, TcpStream::connect((host.as_str(), *port)))
+				.await
+		}
+	};
+	match stream {
+		Ok(stream) => stream?.into_std(),
+		Err(_) => Err(io::Error::new(
+			io::ErrorKind::TimedOut,
+			format!("timed out connecting to {}", addr),
+		)),
+	}
+}
+
+async fn connect_through_proxy(proxy: SocketAddr, addr: &PeerAddr) -> io::Result<TcpStream> {
+	let socks_err = |msg: String| io::Error::new(io::ErrorKind::Other, msg);
+	let mut stream = TcpStream::connect(proxy).await?;
+	// SOCKS version 5, offering no authentication as the only method.
+	stream.write_all(&[5, 1, 0]).await?;
+	let mut method = [0; 2];
+	stream.read_exact(&mut method).await?;
+	if method != [5, 0] {
+		return Err(socks_err("the SOCKS proxy requires authentication".to_string()));
+	}
+	let mut request = vec![5, 1, 0];
+	let port = match addr {
+		PeerAddr::Ip(SocketAddr::V4(addr)) => {
+			request.push(1);
+			request.extend_from_slice(&addr.ip().octets());
+			addr.port()
+		}
+		PeerAddr::Ip(SocketAddr::V6(addr)) => {
+			request.push(4);
+			request.extend_from_slice(&addr.ip().octets());
+			addr.port()
+		}
+		PeerAddr::Host { host, port } => {
+			if host.len() > 255 {
+				return Err(socks_err(format!("{} is too long a hostname", host)));
+			}
+			request.push(3);
+			request.push(host.len() as u8);
+			request.extend_from_slice(host.as_bytes());
+			*port
+		}
+	};
+	request.extend_from_slice(&port.to_be_bytes());
+	stream.write_all(&request).await?;
+	let mut reply = [0; 4];
+	stream.read_exact(&mut reply).await?;
+	if reply[1] != 0 {
+		return Err(socks_err(format!("the SOCKS proxy failed to connect, error {}", reply[1])));
+	}
+	// Skip the address the proxy connected from.
+	let bound_addr_len = match reply[3] {
+		1 => 4,
+		4 => 16,
+		3 => stream.read_u8().await? as usize,
+		_ => return Err(socks_err("unexpected reply from the SOCKS proxy".to_string())),
+	};
+	let mut bound_addr = vec![0; bound_addr_len + 2];
+	stream.read_exact(&mut bound_addr).await?;
+	Ok(stream)
+}
@@ -0,0 +1,409 @@
+use crate::disk::FilesystemLogger;
+use crate::{time_now_secs, NetworkGraph};
+use bitcoin::blockdata::constants::genesis_block;
+use bitcoin::network::constants::Network;
+use lightning::routing::gossip::{ChannelInfo, ChannelUpdateInfo, NodeId};
+use lightning::util::ser::{BigSize, Writeable};
+use lightning_rapid_gossip_sync::RapidGossipSync;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// Rapid Gossip Sync servers publish new snapshots daily, and more often for recent updates.
+const RGS_SYNC_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const RGS_DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The "LDK" magic and version 1 of the snapshot format.
+const SNAPSHOT_PREFIX: [u8; 4] = [76, 68, 75, 1];
+const DAY_SECS: u32 = 24 * 60 * 60;
+/// How far back the deltas we serve go, besides the full snapshot. A client is sent the shortest
+/// delta that covers everything since its last sync.
+const SNAPSHOT_LOOKBACK_DAYS: &[u32] = &[1, 2, 3, 4, 5, 6, 7, 14, 21, 28];
+
+/// Flags of an update in a snapshot, besides the direction and disabled flags of `channel_update`.
+const UPDATE_HAS_CLTV_EXPIRY_DELTA: u8 = 0b0100_0000;
+const UPDATE_HAS_HTLC_MINIMUM_MSAT: u8 = 0b0010_0000;
+const UPDATE_HAS_FEE_BASE_MSAT: u8 = 0b0001_0000;
+const UPDATE_HAS_FEE_PROPORTIONAL_MILLIONTHS: u8 = 0b0000_1000;
+const UPDATE_HAS_HTLC_MAXIMUM_MSAT: u8 = 0b0000_0100;
+
+pub(crate) type GossipSync = RapidGossipSync<Arc<NetworkGraph>, Arc<FilesystemLogger>>;
+
+/// Keeps the network graph up to date from a Rapid Gossip Sync server, unless switched off in
+/// favor of only the gossip our peers send us.
+pub(crate) struct RgsClient {
+	gossip_sync: Arc<GossipSync>,
+	network_graph: Arc<NetworkGraph>,
+	/// Where snapshots are fetched from, as `<url>/<last sync timestamp>`.
+	url: String,
+	enabled: AtomicBool,
+}
+
+impl RgsClient {
+	pub(crate) fn new(network_graph: Arc<NetworkGraph>, url: String) -> Self {
+		RgsClient {
+			gossip_sync: Arc::new(RapidGossipSync::new(Arc::clone(&network_graph))),
+			network_graph,
+			url: url.trim_end_matches('/').to_string(),
+			enabled: AtomicBool::new(true),
+		}
+	}
+
+	/// For the background processor, which only prunes the graph once a snapshot was applied.
+	pub(crate) fn gossip_sync(&self) -> Arc<GossipSync> {
+		Arc::clone(&self.gossip_sync)
+	}
+
+	pub(crate) fn is_enabled(&self) -> bool {
+		self.enabled.load(Ordering::Acquire)
+	}
+
+	pub(crate) fn set_enabled(&self, enabled: bool) {
+		self.enabled.store(enabled, Ordering::Release);
+	}
+
+	/// Applies the updates since our last sync, or a full snapshot on the first one, returning
+	/// the server's timestamp for them.
+	pub(crate) async fn sync(&self) -> Result<u32, String> {
+		let last_sync = self.network_graph.get_last_rapid_gossip_sync_timestamp().unwrap_or(0);
+		let url = format!("{}/{}", self.url, last_sync);
+		let client = reqwest::Client::builder().timeout(RGS_DOWNLOAD_TIMEOUT).build().unwrap();
+		let response = client.get(&url).send().await.map_err(|e| e.to_string())?;
+		if !response.status().is_success() {
+			return Err(format!("{} returned {}", url, response.status()));
+		}
+		let snapshot = response.bytes().await.map_err(|e| e.to_string())?;
+		self.gossip_sync
+			.update_network_graph(&snapshot)
+			.map_err(|e| format!("failed to apply the snapshot from {}: {:?}", url, e))
+	}
+
+	/// Syncs now and every `RGS_SYNC_INTERVAL` after that, while enabled.
+	pub(crate) async fn run(self: Arc<Self>) {
+		let mut interval = tokio::time::interval(RGS_SYNC_INTERVAL);
+		loop {
+			interval.tick().await;
+			if !self.is_enabled() {
+				continue;
+			}
+			if let Err(e) = self.sync().await {
+				println!("ERROR: failed to sync the network graph from the RGS server: {}", e);
+			}
+		}
+	}
+}
+
+/// Serves our network graph to other nodes in the Rapid Gossip Sync format, as a full snapshot and
+/// deltas over a few lookback windows, all regenerated every `RGS_SYNC_INTERVAL`.
+pub(crate) struct RgsServer {
+	network_graph: Arc<NetworkGraph>,
+	network: Network,
+	/// Each snapshot with the timestamp of the oldest changes it holds, 0 for the full one.
+	snapshots: RwLock<Vec<(u32, Vec<u8>)>>,
+}
+
+impl RgsServer {
+	pub(crate) fn new(network_graph: Arc<NetworkGraph>, network: Network) -> Self {
+		RgsServer { network_graph, network, snapshots: RwLock::new(Vec::new()) }
+	}
+
+	/// Regenerates the snapshots now and every `RGS_SYNC_INTERVAL` after that.
+	pub(crate) async fn run(self: Arc<Self>) {
+		let mut interval = tokio::time::interval(RGS_SYNC_INTERVAL);
+		loop {
+			interval.tick().await;
+			let now = time_now_secs() as u32;
+			let mut snapshots = vec![(0, self.serialize(0, now))];
+			for days in SNAPSHOT_LOOKBACK_DAYS {
+				let since = now.saturating_sub(days * DAY_SECS);
+				snapshots.push((since, self.serialize(since, now)));
+			}
+			*self.snapshots.write().unwrap() = snapshots;
+		}
+	}
+
+	/// The snapshot a client that last synced at `last_sync` should apply: the shortest delta
+	/// that goes back far enough, or the full snapshot. `None` until the first ones are ready.
+	pub(crate) fn snapshot(&self, last_sync: u32) -> Option<Vec<u8>> {
+		let snapshots = self.snapshots.read().unwrap();
+		snapshots
+			.iter()
+			.filter(|(since, _)| last_sync != 0 && *since <= last_sync)
+			.max_by_key(|(since, _)| *since)
+			.or_else(|| snapshots.iter().find(|(since, _)| *since == 0))
+			.map(|(_, snapshot)| snapshot.clone())
+	}
+
+	/// Serializes the channels announced and the updates received after `since`, timestamped
+	/// `now`. Every channel with an update is announced again, which clients ignore if they
+	/// already know it, so they never get an update for a channel they don't know.
+	fn serialize(&self, since: u32, now: u32) -> Vec<u8> {
+		let graph = self.network_graph.read_only();
+		let mut announcements: Vec<(u64, &ChannelInfo)> = Vec::new();
+		let mut updates: Vec<(u64, u8, &ChannelUpdateInfo)> = Vec::new();
+		for (short_channel_id, channel) in graph.channels().unordered_iter() {
+			let new_channel = since == 0 || channel.announcement_received_time > since as u64;
+			let directions = [(0, &channel.one_to_two), (1, &channel.two_to_one)];
+			let mut updated = false;
+			for (direction, update) in directions.iter() {
+				if let Some(update) = update {
+					if new_channel || update.last_update > since {
+						updates.push((*short_channel_id, *direction, update));
+						updated = true;
+					}
+				}
+			}
+			if new_channel || updated {
+				announcements.push((*short_channel_id, channel));
+			}
+		}
+		announcements.sort_by_key(|(short_channel_id, _)| *short_channel_id);
+		updates.sort_by_key(|(short_channel_id, direction, _)| (*short_channel_id, *direction));
+
+		let mut node_ids: Vec<NodeId> = Vec::new();
+		let mut node_indices: HashMap<NodeId, u64> = HashMap::new();
+		for (_, channel) in announcements.iter() {
+			for node_id in [channel.node_one, channel.node_two].iter() {
+				node_indices.entry(*node_id).or_insert_with(|| {
+					node_ids.push(*node_id);
+					node_ids.len() as u64 - 1
+				});
+			}
+		}
+
+		let mut out = SNAPSHOT_PREFIX.to_vec();
+		genesis_block(self.network).header.block_hash().write(&mut out).unwrap();
+		now.write(&mut out).unwrap();
+		(node_ids.len() as u32).write(&mut out).unwrap();
+		for node_id in node_ids.iter() {
+			out.extend_from_slice(node_id.as_slice());
+		}
+
+		// Channels are sorted so their IDs can be written as the difference from the previous.
+		(announcements.len() as u32).write(&mut out).unwrap();
+		let mut previous_scid = 0;
+		for (short_channel_id, channel) in announcements.iter() {
+			channel.features.write(&mut out).unwrap();
+			BigSize(short_channel_id - previous_scid).write(&mut out).unwrap();
+			BigSize(node_indices[&channel.node_one]).write(&mut out).unwrap();
+			BigSize(node_indices[&channel.node_two]).write(&mut out).unwrap();
+			previous_scid = *short_channel_id;
+		}
+
+		(updates.len() as u32).write(&mut out).unwrap();
+		if updates.is_empty() {
+			return out;
+		}
+		// Updates only include the fields that differ from the most common values.
+		let infos = || updates.iter().map(|(_, _, update)| update);
+		let default_cltv_expiry_delta = most_common(infos().map(|u| u.cltv_expiry_delta));
+		let default_htlc_minimum_msat = most_common(infos().map(|u| u.htlc_minimum_msat));
+		let default_fee_base_msat = most_common(infos().map(|u| u.fees.base_msat));
+		let default_fee_proportional_millionths =
+			most_common(infos().map(|u| u.fees.proportional_millionths));
+		let default_htlc_maximum_msat = most_common(infos().map(|u| u.htlc_maximum_msat));
+		default_cltv_expiry_delta.write(&mut out).unwrap();
+		default_htlc_minimum_msat.write(&mut out).unwrap();
+		default_fee_base_msat.write(&mut out).unwrap();
+		default_fee_proportional_millionths.write(&mut out).unwrap();
+		default_htlc_maximum_msat.write(&mut out).unwrap();
+
+		let mut previous_scid = 0;
+		for (short_channel_id, direction, update) in updates.iter() {
+			BigSize(short_channel_id - previous_scid).write(&mut out).unwrap();
+			previous_scid = *short_channel_id;
+			let mut flags = *direction;
+			if !update.enabled {
+				flags |= 0b0000_0010;
+			}
+			let mut fields = Vec::new();
+			if update.cltv_expiry_delta != default_cltv_expiry_delta {
+				flags |= UPDATE_HAS_CLTV_EXPIRY_DELTA;
+				update.cltv_expiry_delta.write(&mut fields).unwrap();
+			}
+			if update.htlc_minimum_msat != default_htlc_minimum_msat {
+				flags |= UPDATE_HAS_HTLC_MINIMUM_MSAT;
+				update.htlc_minimum_msat.write(&mut fields).unwrap();
+			}
+			if update.fees.base_msat != default_fee_base_msat {
+				flags |= UPDATE_HAS_FEE_BASE_MSAT;
+				update.fees.base_msat.write(&mut fields).unwrap();
+			}
+			if update.fees.proportional_millionths != default_fee_proportional_millionths {
+				flags |= UPDATE_HAS_FEE_PROPORTIONAL_MILLIONTHS;
+				update.fees.proportional_millionths.write(&mut fields).unwrap();
+			}
+			if update.htlc_maximum_msat != default_htlc_maximum_msat {
+				flags |= UPDATE_HAS_HTLC_MAXIMUM_MSAT;
+				update.htlc_maximum_msat.write(&mut fields).unwrap();
+			}
+			flags.write(&mut out).unwrap();
+			out.extend_from_slice(&fields);
+		}
+		out
+	}
+}
+
+fn most_common<T: Copy + Default + Eq + Hash>(values: impl Iterator<Item = T>) -> T {
+	let mut counts: HashMap<T, usize> = HashMap::new();
+	for value in values {
+		*counts.entry(value).or_insert(0) += 1;
+	}
+	counts.into_iter().max_by_key(|(_, count)| *count).map(|(value, _)| value).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod rgs_tests {
+	use super::*;
+	use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+	use lightning::ln::features::ChannelFeatures;
+	use lightning::ln::msgs::UnsignedChannelUpdate;
+	use lightning::routing::gossip::RoutingFees;
+	use std::time::SystemTime;
+
+	const HOUR_SECS: u32 = 60 * 60;
+
+	fn test_graph() -> Arc<NetworkGraph> {
+		let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap();
+		let dir = std::env::temp_dir().join(format!("ldk-sample-rgs-{}", now.as_nanos()));
+		let logger = Arc::new(FilesystemLogger::new(dir.to_str().unwrap().to_string()));
+		let genesis_hash = genesis_block(Network::Regtest).header.block_hash();
+		Arc::new(NetworkGraph::new(genesis_hash, logger))
+	}
+
+	fn test_pubkeys() -> Vec<PublicKey> {
+		let secp_ctx = Secp256k1::new();
+		let mut pubkeys = (1..=3)
+			.map(|byte| {
+				PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[byte; 32]).unwrap())
+			})
+			.collect::<Vec<_>>();
+		pubkeys.sort_by_key(|pubkey| pubkey.serialize());
+		pubkeys
+	}
+
+	fn update(
+		short_channel_id: u64, direction: u8, timestamp: u32, cltv_expiry_delta: u16,
+		fee_base_msat: u32, htlc_maximum_msat: u64,
+	) -> UnsignedChannelUpdate {
+		UnsignedChannelUpdate {
+			chain_hash: genesis_block(Network::Regtest).header.block_hash(),
+			short_channel_id,
+			timestamp,
+			flags: direction,
+			cltv_expiry_delta,
+			htlc_minimum_msat: 1_000,
+			htlc_maximum_msat,
+			fee_base_msat,
+			fee_proportional_millionths: 100,
+			excess_data: Vec::new(),
+		}
+	}
+
+	/// The fields of an update a snapshot carries.
+	fn update_fields(
+		update: &Option<ChannelUpdateInfo>,
+	) -> Option<(bool, u16, u64, u64, RoutingFees)> {
+		update.as_ref().map(|update| {
+			(
+				update.enabled,
+				update.cltv_expiry_delta,
+				update.htlc_minimum_msat,
+				update.htlc_maximum_msat,
+				update.fees,
+			)
+		})
+	}
+
+	/// Checks `graph` holds exactly the channels `short_channel_ids` of `source`, with the same
+	/// updates.
+	fn assert_channels(graph: &NetworkGraph, source: &NetworkGraph, short_channel_ids: &[u64]) {
+		let graph = graph.read_only();
+		let source = source.read_only();
+		let mut channels = graph.channels().unordered_keys().copied().collect::<Vec<_>>();
+		channels.sort_unstable();
+		assert_eq!(channels, short_channel_ids);
+		for short_channel_id in short_channel_ids {
+			let channel = graph.channels().get(short_channel_id).unwrap();
+			let expected = source.channels().get(short_channel_id).unwrap();
+			assert_eq!(channel.node_one, expected.node_one);
+			assert_eq!(channel.node_two, expected.node_two);
+			assert_eq!(update_fields(&channel.one_to_two), update_fields(&expected.one_to_two));
+			assert_eq!(update_fields(&channel.two_to_one), update_fields(&expected.two_to_one));
+		}
+	}
+
+	#[test]
+	fn test_serialize() {
+		let now = time_now_secs() as u32;
+		let ten_days_ago = now - 10 * DAY_SECS;
+		let pubkeys = test_pubkeys();
+		let source = test_graph();
+		let announce = |short_channel_id, timestamp, node_one: usize, node_two: usize| {
+			source
+				.add_channel_from_partial_announcement(
+					short_channel_id,
+					timestamp as u64,
+					ChannelFeatures::empty(),
+					pubkeys[node_one],
+					pubkeys[node_two],
+				)
+				.unwrap()
+		};
+		// Channel 1 hasn't changed in ten days, with one direction disabled.
+		announce(1, ten_days_ago, 0, 1);
+		source.update_channel_unsigned(&update(1, 0, ten_days_ago, 40, 1_000, 1_000_000)).unwrap();
+		let mut disabled = update(1, 1, ten_days_ago, 40, 1_000, 1_000_000);
+		disabled.flags |= 0b0000_0010;
+		source.update_channel_unsigned(&disabled).unwrap();
+		// Channel 2's fees changed an hour ago.
+		announce(2, ten_days_ago, 1, 2);
+		source.update_channel_unsigned(&update(2, 0, ten_days_ago, 40, 1_000, 1_000_000)).unwrap();
+		source
+			.update_channel_unsigned(&update(2, 0, now - HOUR_SECS, 40, 5_000, 1_000_000))
+			.unwrap();
+		// Channel 3 was announced an hour ago, and has only been updated in one direction.
+		announce(3, now - HOUR_SECS, 0, 2);
+		source.update_channel_unsigned(&update(3, 1, now - HOUR_SECS, 144, 0, 5_000_000)).unwrap();
+		let server = RgsServer::new(Arc::clone(&source), Network::Regtest);
+
+		let graph = test_graph();
+		let gossip_sync = RapidGossipSync::new(Arc::clone(&graph));
+		assert_eq!(gossip_sync.update_network_graph(&server.serialize(0, now)).unwrap(), now);
+		assert_channels(&graph, &source, &[1, 2, 3]);
+
+		// A delta only has what changed since, which is applied on top of what the client has.
+		let delta = server.serialize(now - DAY_SECS, now);
+		let graph = test_graph();
+		let gossip_sync = RapidGossipSync::new(Arc::clone(&graph));
+		assert_eq!(gossip_sync.update_network_graph(&delta).unwrap(), now);
+		assert_channels(&graph, &source, &[2, 3]);
+	}
+
+	#[test]
+	fn test_serialize_empty_graph() {
+		let now = time_now_secs() as u32;
+		let server = RgsServer::new(test_graph(), Network::Regtest);
+		let graph = test_graph();
+		let gossip_sync = RapidGossipSync::new(Arc::clone(&graph));
+		assert_eq!(gossip_sync.update_network_graph(&server.serialize(0, now)).unwrap(), now);
+		assert_eq!(graph.read_only().channels().len(), 0);
+	}
+
+	#[test]
+	fn test_snapshot() {
+		let server = RgsServer::new(test_graph(), Network::Regtest);
+		assert!(server.snapshot(0).is_none());
+		*server.snapshots.write().unwrap() =
+			vec![(0, vec![0]), (700, vec![7]), (900, vec![9]), (950, vec![95])];
+		// New clients get the full snapshot, as do those that synced before any delta.
+		assert_eq!(server.snapshot(0), Some(vec![0]));
+		assert_eq!(server.snapshot(500), Some(vec![0]));
+		// Others get the shortest delta that covers everything since their last sync.
+		assert_eq!(server.snapshot(700), Some(vec![7]));
+		assert_eq!(server.snapshot(920), Some(vec![9]));
+		assert_eq!(server.snapshot(2_000), Some(vec![95]));
+	}
+}
@@ -0,0 +1,352 @@
+use crate::disk::FilesystemLogger;
+use crate::{NetworkGraph, Scorer};
+use bitcoin::hashes::sha256::Hash as Sha256;
+use bitcoin::hashes::Hash;
+use bitcoin::secp256k1::PublicKey;
+use lightning::ln::channelmanager::{ChannelDetails, PaymentId};
+use lightning::ln::msgs::{ErrorAction, LightningError};
+use lightning::ln::PaymentHash;
+use lightning::routing::gossip::NodeId;
+use lightning::routing::router::{
+	find_route, DefaultRouter, InFlightHtlcs, Route, RouteHop, RouteParameters, Router,
+	ScorerAccountingForInFlightHtlcs,
+};
+use lightning::routing::scoring::{ChannelUsage, Score};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Limits on the routes a single payment may take, given to `sendpayment` as flags.
+#[derive(Clone, Default)]
+pub(crate) struct RouteConstraints {
+	/// The most the payment may pay in routing fees, in total.
+	pub(crate) max_fee_msat: Option<u64>,
+	/// The most the payment may pay in routing fees, as a percentage of its amount.
+	pub(crate) max_fee_percent: Option<f64>,
+	/// The most channels any of the payment's paths may go through.
+	pub(crate) max_hops: Option<usize>,
+	/// Nodes none of the payment's paths may go through.
+	pub(crate) avoid_nodes: Vec<PublicKey>,
+	/// The only channels of ours the payment may leave through, or any if empty.
+	pub(crate) first_hop_channels: Vec<[u8; 32]>,
+}
+
+impl RouteConstraints {
+	pub(crate) fn is_empty(&self) -> bool {
+		self.max_fee_msat.is_none()
+			&& self.max_fee_percent.is_none()
+			&& self.max_hops.is_none()
+			&& self.avoid_nodes.is_empty()
+			&& self.first_hop_channels.is_empty()
+	}
+
+	/// The fees a route for `value_msat` of a payment of `payment_amount_msat` may pay. Retries
+	/// only route the part of the payment that failed, so the limit is spread over its parts.
+	fn max_fee_msat(&self, value_msat: u64, payment_amount_msat: u64) -> Option<u64> {
+		let from_sat = self.max_fee_msat.map(|max_fee_msat| {
+			(max_fee_msat as u128 * value_msat as u128 / payment_amount_msat.max(1) as u128) as u64
+		});
+		let from_percent =
+			self.max_fee_percent.map(|percent| (value_msat as f64 * percent / 100.0) as u64);
+		match (from_sat, from_percent) {
+			(Some(a), Some(b)) => Some(a.min(b)),
+			(a, b) => a.or(b),
+		}
+	}
+
+	/// Checks a route found for `value_msat` of a payment of `payment_amount_msat` against the
+	/// limits the router can't take into account while searching.
+	fn check(
+		&self, route: &Route, value_msat: u64, payment_amount_msat: u64,
+	) -> Result<(), String> {
+		if let Some(max_hops) = self.max_hops {
+			if let Some(path) = route.paths.iter().find(|path| path.len() > max_hops) {
+				return Err(format!(
+					"the route found is {} hops long, more than the {} allowed",
+					path.len(),
+					max_hops
+				));
+			}
+		}
+		let mut hops = route.paths.iter().flatten();
+		if let Some(hop) = hops.find(|hop| self.avoid_nodes.contains(&hop.pubkey)) {
+			return Err(format!("the route found goes through {}, which is avoided", hop.pubkey));
+		}
+		if let Some(max_fee_msat) = self.max_fee_msat(value_msat, payment_amount_msat) {
+			let fee_msat = route.get_total_fees();
+			if fee_msat > max_fee_msat {
+				return Err(format!(
+					"the route found pays {} msat in fees, more than the {} msat allowed",
+					fee_msat, max_fee_msat
+				));
+			}
+		}
+		Ok(())
+	}
+}
+
+/// Routes payments like LDK's `DefaultRouter`, except for those given `RouteConstraints`, which
+/// are routed within them every time they're retried.
+pub(crate) struct ConstrainedRouter {
+	router: DefaultRouter<Arc<NetworkGraph>, Arc<FilesystemLogger>, Arc<Mutex<Scorer>>>,
+	network_graph: Arc<NetworkGraph>,
+	logger: Arc<FilesystemLogger>,
+	scorer: Arc<Mutex<Scorer>>,
+	random_seed_bytes: Mutex<[u8; 32]>,
+	/// The constraints of pending payments, with the amount each payment sends.
+	constraints: Mutex<HashMap<PaymentId, (u64, RouteConstraints)>>,
+}
+
+impl ConstrainedRouter {
+	pub(crate) fn new(
+		network_graph: Arc<NetworkGraph>, logger: Arc<FilesystemLogger>,
+		random_seed_bytes: [u8; 32], scorer: Arc<Mutex<Scorer>>,
+	) -> Self {
+		let router = DefaultRouter::new(
+			Arc::clone(&network_graph),
+			Arc::clone(&logger),
+			Sha256::hash(&random_seed_bytes).into_inner(),
+			Arc::clone(&scorer),
+		);
+		ConstrainedRouter {
+			router,
+			network_graph,
+			logger,
+			scorer,
+			random_seed_bytes: Mutex::new(random_seed_bytes),
+			constraints: Mutex::new(HashMap::new()),
+		}
+	}
+
+	/// Has the routes of the payment `payment_id`, sending `amount_msat`, follow `constraints`
+	/// until `remove_constraints` is called for it.
+	pub(crate) fn set_constraints(
+		&self, payment_id: PaymentId, amount_msat: u64, constraints: RouteConstraints,
+	) {
+		if !constraints.is_empty() {
+			self.constraints.lock().unwrap().insert(payment_id, (amount_msat, constraints));
+		}
+	}
+
+	/// Called once a payment succeeded or failed, as it won't be routed again.
+	pub(crate) fn remove_constraints(&self, payment_id: &PaymentId) {
+		self.constraints.lock().unwrap().remove(payment_id);
+	}
+}
+
+impl Router for ConstrainedRouter {
+	fn find_route(
+		&self, payer: &PublicKey, route_params: &RouteParameters,
+		first_hops: Option<&[&ChannelDetails]>, inflight_htlcs: &InFlightHtlcs,
+	) -> Result<Route, LightningError> {
+		self.router.find_route(payer, route_params, first_hops, inflight_htlcs)
+	}
+
+	fn find_route_with_id(
+		&self, payer: &PublicKey, route_params: &RouteParameters,
+		first_hops: Option<&[&ChannelDetails]>, inflight_htlcs: &InFlightHtlcs,
+		payment_hash: PaymentHash, payment_id: PaymentId,
+	) -> Result<Route, LightningError> {
+		let (payment_amount_msat, constraints) =
+			match self.constraints.lock().unwrap().get(&payment_id) {
+				Some((amount_msat, constraints)) => (*amount_msat, constraints.clone()),
+				None => {
+					return self.router.find_route_with_id(
+						payer,
+						route_params,
+						first_hops,
+						inflight_htlcs,
+						payment_hash,
+						payment_id,
+					)
+				}
+			};
+		let first_hops = first_hops.map(|channels| {
+			channels
+				.iter()
+				.copied()
+				.filter(|channel| {
+					constraints.first_hop_channels.is_empty()
+						|| constraints.first_hop_channels.contains(&channel.channel_id)
+				})
+				.collect::<Vec<_>>()
+		});
+		let avoid_nodes: Vec<NodeId> =
+			constraints.avoid_nodes.iter().map(NodeId::from_pubkey).collect();
+		let random_seed_bytes = {
+			let mut seed = self.random_seed_bytes.lock().unwrap();
+			*seed = Sha256::hash(&*seed).into_inner();
+			*seed
+		};
+		let route = {
+			let mut scorer = self.scorer.lock().unwrap();
+			let scorer = AvoidingScorer {
+				scorer: ScorerAccountingForInFlightHtlcs::new(&mut *scorer, inflight_htlcs),
+				avoid_nodes: &avoid_nodes,
+			};
+			find_route(
+				payer,
+				route_params,
+				&self.network_graph,
+				first_hops.as_deref(),
+				&*self.logger,
+				&scorer,
+				&random_seed_bytes,
+			)?
+		};
+		constraints
+			.check(&route, route_params.final_value_msat, payment_amount_msat)
+			.map_err(|err| LightningError { err, action: ErrorAction::IgnoreError })?;
+		Ok(route)
+	}
+}
+
+/// Keeps routes away from the channels of the nodes a payment avoids, scoring the others like
+/// `scorer`. It's only used to find routes: the background processor updates the scorer.
+struct AvoidingScorer<'a, S: Score> {
+	scorer: S,
+	avoid_nodes: &'a [NodeId],
+}
+
+impl<'a, S: Score> Score for AvoidingScorer<'a, S> {
+	fn channel_penalty_msat(
+		&self, short_channel_id: u64, source: &NodeId, target: &NodeId, usage: ChannelUsage,
+	) -> u64 {
+		if self.avoid_nodes.contains(source) || self.avoid_nodes.contains(target) {
+			return u64::max_value();
+		}
+		self.scorer.channel_penalty_msat(short_channel_id, source, target, usage)
+	}
+
+	fn payment_path_failed(&mut self, _path: &[&RouteHop], _short_channel_id: u64) {}
+
+	fn payment_path_successful(&mut self, _path: &[&RouteHop]) {}
+
+	fn probe_failed(&mut self, _path: &[&RouteHop], _short_channel_id: u64) {}
+
+	fn probe_successful(&mut self, _path: &[&RouteHop]) {}
+}
+
+#[cfg(test)]
+mod router_tests {
+	use super::*;
+	use bitcoin::secp256k1::{Secp256k1, SecretKey};
+	use lightning::ln::features::{ChannelFeatures, NodeFeatures};
+	use lightning::routing::gossip::EffectiveCapacity;
+	use lightning::routing::scoring::FixedPenaltyScorer;
+
+	fn test_pubkey(byte: u8) -> PublicKey {
+		PublicKey::from_secret_key(&Secp256k1::new(), &SecretKey::from_slice(&[byte; 32]).unwrap())
+	}
+
+	/// A path through the nodes `bytes` map to, paying each hop but the last `fee_msat`.
+	fn path(bytes: &[u8], fee_msat: u64, value_msat: u64) -> Vec<RouteHop> {
+		bytes
+			.iter()
+			.enumerate()
+			.map(|(i, byte)| RouteHop {
+				pubkey: test_pubkey(*byte),
+				node_features: NodeFeatures::empty(),
+				short_channel_id: i as u64 + 1,
+				channel_features: ChannelFeatures::empty(),
+				fee_msat: if i + 1 == bytes.len() { value_msat } else { fee_msat },
+				cltv_expiry_delta: 40,
+			})
+			.collect()
+	}
+
+	fn route(paths: Vec<Vec<RouteHop>>) -> Route {
+		Route { paths, payment_params: None }
+	}
+
+	#[test]
+	fn test_max_fee_msat() {
+		assert_eq!(RouteConstraints::default().max_fee_msat(100_000, 100_000), None);
+		let constraints = RouteConstraints { max_fee_msat: Some(1_000), ..Default::default() };
+		assert_eq!(constraints.max_fee_msat(100_000, 100_000), Some(1_000));
+		let constraints = RouteConstraints { max_fee_percent: Some(0.5), ..Default::default() };
+		assert_eq!(constraints.max_fee_msat(100_000, 100_000), Some(500));
+
+		// The lower of both caps applies.
+		let constraints = RouteConstraints {
+			max_fee_msat: Some(1_000),
+			max_fee_percent: Some(0.5),
+			..Default::default()
+		};
+		assert_eq!(constraints.max_fee_msat(100_000, 100_000), Some(500));
+		let constraints = RouteConstraints {
+			max_fee_msat: Some(300),
+			max_fee_percent: Some(0.5),
+			..Default::default()
+		};
+		assert_eq!(constraints.max_fee_msat(100_000, 100_000), Some(300));
+	}
+
+	#[test]
+	fn test_max_fee_msat_retry() {
+		// A retry of a quarter of the payment may only pay a quarter of its fees.
+		let constraints = RouteConstraints {
+			max_fee_msat: Some(1_000),
+			max_fee_percent: Some(2.0),
+			..Default::default()
+		};
+		assert_eq!(constraints.max_fee_msat(25_000, 100_000), Some(250));
+		let constraints = RouteConstraints {
+			max_fee_msat: Some(1_000),
+			max_fee_percent: Some(0.5),
+			..Default::default()
+		};
+		assert_eq!(constraints.max_fee_msat(25_000, 100_000), Some(125));
+		// Large amounts don't overflow.
+		let constraints =
+			RouteConstraints { max_fee_msat: Some(u64::max_value()), ..Default::default() };
+		assert_eq!(
+			constraints.max_fee_msat(u64::max_value(), u64::max_value()),
+			Some(u64::max_value())
+		);
+	}
+
+	#[test]
+	fn test_check() {
+		let constraints = RouteConstraints {
+			max_fee_msat: Some(1_000),
+			max_hops: Some(3),
+			avoid_nodes: vec![test_pubkey(9)],
+			..Default::default()
+		};
+		let three_hops = route(vec![path(&[1, 2, 3], 400, 100_000)]);
+		assert!(constraints.check(&three_hops, 100_000, 100_000).is_ok());
+		let split = route(vec![path(&[1, 3], 400, 50_000), path(&[2, 3], 400, 50_000)]);
+		assert!(constraints.check(&split, 100_000, 100_000).is_ok());
+
+		// Too long.
+		let four_hops = route(vec![path(&[1, 3], 0, 50_000), path(&[1, 2, 4, 3], 0, 50_000)]);
+		assert!(constraints.check(&four_hops, 100_000, 100_000).is_err());
+		// Through an avoided node.
+		let avoided = route(vec![path(&[1, 9, 3], 0, 100_000)]);
+		assert!(constraints.check(&avoided, 100_000, 100_000).is_err());
+		// Too expensive, in total or for the part of the payment being retried.
+		let expensive = route(vec![path(&[1, 2, 3], 600, 100_000)]);
+		assert!(constraints.check(&expensive, 100_000, 100_000).is_err());
+		let retry = route(vec![path(&[1, 2, 3], 300, 50_000)]);
+		assert!(constraints.check(&retry, 50_000, 100_000).is_err());
+		assert!(constraints.check(&retry, 100_000, 100_000).is_ok());
+	}
+
+	#[test]
+	fn test_avoiding_scorer() {
+		let avoid_nodes = vec![NodeId::from_pubkey(&test_pubkey(9))];
+		let scorer = AvoidingScorer {
+			scorer: FixedPenaltyScorer::with_penalty(100),
+			avoid_nodes: &avoid_nodes,
+		};
+		let usage = ChannelUsage {
+			amount_msat: 1_000,
+			inflight_htlc_msat: 0,
+			effective_capacity: EffectiveCapacity::Unknown,
+		};
+		let node = |byte| NodeId::from_pubkey(&test_pubkey(byte));
+		assert_eq!(scorer.channel_penalty_msat(1, &node(1), &node(2), usage), 100);
+		assert_eq!(scorer.channel_penalty_msat(1, &node(1), &node(9), usage), u64::max_value());
+		assert_eq!(scorer.channel_penalty_msat(1, &node(9), &node(2), usage), u64::max_value());
+	}
+}
@@ -0,0 +1,379 @@
+use crate::bitcoind_client::BitcoindClient;
+use crate::cli;
+use crate::disk;
+use crate::encryption::Cipher;
+use crate::hex_utils;
+use crate::persist::NodePersister;
+use crate::proxy::PeerAddr;
+use crate::signer::NodeKeys;
+use crate::wallet::{AddressType, OnchainWallet};
+use crate::{ChannelManager, PeerManager};
+use bitcoin::blockdata::script::Script;
+use bitcoin::hash_types::WPubkeyHash;
+use bitcoin::hashes::sha256;
+use bitcoin::hashes::{Hash, HashEngine};
+use bitcoin::secp256k1::{PublicKey, Secp256k1};
+use lightning::chain::chaininterface::{BroadcasterInterface, ConfirmationTarget, FeeEstimator};
+use lightning::chain::keysinterface::{
+	NodeSigner, SpendableOutputDescriptor, StaticPaymentOutputDescriptor,
+};
+use lightning::chain::transaction::OutPoint;
+use lightning::impl_writeable_tlv_based;
+use lightning::util::ser::{Readable, Writeable};
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Binds the backup's encryption to what it is.
+const BACKUP_AAD: &str = "static_channel_backup";
+
+/// How often recovery reconnects to the backup's peers and looks for our outputs on chain.
+const RECOVERY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// What it takes to get a channel's funds back without its `ChannelMonitor`: who to ask to force
+/// close it, and the keys to re-derive our output on their commitment transaction from.
+pub(crate) struct ChannelBackup {
+	counterparty_node_id: PublicKey,
+	peer_addr: Option<String>,
+	channel_id: [u8; 32],
+	funding_txo: OutPoint,
+	channel_value_satoshis: u64,
+	channel_keys_id: [u8; 32],
+}
+
+impl_writeable_tlv_based!(ChannelBackup, {
+	(0, counterparty_node_id, required),
+	(2, peer_addr, option),
+	(4, channel_id, required),
+	(6, funding_txo, required),
+	(8, channel_value_satoshis, required),
+	(10, channel_keys_id, required),
+});
+
+struct StaticChannelBackup {
+	channels: Vec<ChannelBackup>,
+}
+
+impl_writeable_tlv_based!(StaticChannelBackup, {
+	(0, channels, vec_type),
+});
+
+/// The backup is encrypted with a key derived from the keys seed, so the seed alone is enough to
+/// read it back.
+fn backup_cipher(keys_manager: &NodeKeys) -> Cipher {
+	let mut engine = sha256::Hash::engine();
+	engine.input(b"ldk-sample static channel backup");
+	engine.input(&keys_manager.get_inbound_payment_key_material().0);
+	Cipher::from_key(&sha256::Hash::from_engine(engine).into_inner())
+}
+
+/// `ChannelMonitor` doesn't expose the ID its signer was derived from, so we read it out of its
+/// serialization, where it comes after the version prefix, the latest update ID, the commitment
+/// number obscure factor and a few scripts. The holder's revocation basepoint right after it lets
+/// us check we read it right.
+fn channel_keys_id(
+	monitor: &[u8], channel_value_satoshis: u64, keys_manager: &NodeKeys,
+) -> Option<[u8; 32]> {
+	let mut reader = io::Cursor::new(monitor);
+	let mut prefix = [0; 2 + 8 + 6];
+	reader.read_exact(&mut prefix).ok()?;
+	let _destination_script: Script = Readable::read(&mut reader).ok()?;
+	let mut has_revokable_script = [0; 1];
+	reader.read_exact(&mut has_revokable_script).ok()?;
+	if has_revokable_script[0] == 0 {
+		let _script: Script = Readable::read(&mut reader).ok()?;
+		let _key: PublicKey = Readable::read(&mut reader).ok()?;
+		let _script: Script = Readable::read(&mut reader).ok()?;
+	}
+	let _counterparty_payment_script: Script = Readable::read(&mut reader).ok()?;
+	let _shutdown_script: Script = Readable::read(&mut reader).ok()?;
+	let channel_keys_id: [u8; 32] = Readable::read(&mut reader).ok()?;
+	let revocation_basepoint: PublicKey = Readable::read(&mut reader).ok()?;
+	let pubkeys =
+		keys_manager.derive_channel_pubkeys(channel_value_satoshis, channel_keys_id).ok()?;
+	if pubkeys.revocation_basepoint != revocation_basepoint {
+		return None;
+	}
+	Some(channel_keys_id)
+}
+
+/// Writes a backup of our funded channels to `path`, returning how many it holds.
+pub(crate) fn export(
+	path: &Path, channel_manager: &ChannelManager, persister: &NodePersister,
+	keys_manager: &NodeKeys, ldk_data_dir: &str,
+) -> Result<usize, String> {
+	let peer_data_path = format!("{}/channel_peer_data", ldk_data_dir);
+	let peer_addrs = disk::read_channel_peer_data(Path::new(&peer_data_path))
+		.map_err(|e| format!("failed to read channel peer data: {}", e))?;
+	let mut channels = Vec::new();
+	for chan in channel_manager.list_channels() {
+		let funding_txo = match chan.funding_txo {
+			Some(funding_txo) => funding_txo,
+			None => continue,
+		};
+		let key = format!("monitors/{}_{}", funding_txo.txid, funding_txo.index);
+		let monitor = persister
+			.read(&key)
+			.map_err(|e| format!("failed to read {}: {}", key, e))?
+			.ok_or_else(|| format!("{} is missing", key))?;
+		let channel_keys_id = channel_keys_id(&monitor, chan.channel_value_satoshis, keys_manager)
+			.ok_or_else(|| {
+				format!(
+					"failed to find the keys of channel {}",
+					hex_utils::hex_str(&chan.channel_id)
+				)
+			})?;
+		channels.push(ChannelBackup {
+			counterparty_node_id: chan.counterparty.node_id,
+			peer_addr: peer_addrs
+				.get(&chan.counterparty.node_id)
+				.and_then(|addrs| addrs.first())
+				.map(|addr| addr.to_string()),
+			channel_id: chan.channel_id,
+			funding_txo,
+			channel_value_satoshis: chan.channel_value_satoshis,
+			channel_keys_id,
+		});
+	}
+	let num_channels = channels.len();
+	write(path, StaticChannelBackup { channels }, keys_manager)?;
+	Ok(num_channels)
+}
+
+fn write(path: &Path, backup: StaticChannelBackup, keys_manager: &NodeKeys) -> Result<(), String> {
+	let encrypted = backup_cipher(keys_manager).encrypt(BACKUP_AAD, &backup.encode());
+	let tmp_path = path.with_extension("tmp");
+	fs::File::create(&tmp_path)
+		.and_then(|mut f| f.write_all(&encrypted).and_then(|()| f.sync_all()))
+		.and_then(|()| fs::rename(&tmp_path, path))
+		.map_err(|e| format!("failed to write {}: {}", path.display(), e))
+}
+
+/// Reads the backup at `path`, which must have been exported by a node with the same keys seed.
+pub(crate) fn read(path: &Path, keys_manager: &NodeKeys) -> Result<Vec<ChannelBackup>, String> {
+	let encrypted = fs::read(path).map_err(|e| e.to_string())?;
+	let backup = backup_cipher(keys_manager)
+		.decrypt(BACKUP_AAD, &encrypted)
+		.map_err(|_| "it wasn't exported by a node with this keys seed".to_string())?;
+	let backup: StaticChannelBackup =
+		Readable::read(&mut io::Cursor::new(backup)).map_err(|e| format!("{:?}", e))?;
+	Ok(backup.channels)
+}
+
+/// Our output on the counterparty's commitment transaction, which only needs our payment key to
+/// be spent.
+fn to_remote_script(channel: &ChannelBackup, keys_manager: &NodeKeys) -> Result<Script, ()> {
+	let pubkeys = keys_manager
+		.derive_channel_pubkeys(channel.channel_value_satoshis, channel.channel_keys_id)?;
+	Ok(Script::new_v0_p2wpkh(&WPubkeyHash::hash(&pubkeys.payment_point.serialize())))
+}
+
+/// Gets back what we can of the funds in channels we lost the monitors of. Connecting to their
+/// peers makes them reestablish channels we don't know about, which we answer with an error that
+/// makes them force close. Our balance then ends up in an output of their commitment transaction
+/// only we can spend, which we sweep to the wallet. Pending HTLCs are lost.
+pub(crate) async fn recover(
+	channels: Vec<ChannelBackup>, channel_manager: Arc<ChannelManager>,
+	peer_manager: Arc<PeerManager>, bitcoind_client: Arc<BitcoindClient>,
+	keys_manager: Arc<NodeKeys>, wallet: Arc<OnchainWallet>, sweep_address_type: AddressType,
+	proxy: Option<SocketAddr>,
+) {
+	let known_channels =
+		channel_manager.list_channels().iter().map(|chan| chan.channel_id).collect::<HashSet<_>>();
+	let mut pending = channels
+		.into_iter()
+		.filter(|channel| !known_channels.contains(&channel.channel_id))
+		.filter_map(|channel| match to_remote_script(&channel, &keys_manager) {
+			Ok(script) => Some((channel, script)),
+			Err(()) => {
+				println!(
+					"ERROR: recovery: failed to derive the keys of channel {}",
+					hex_utils::hex_str(&channel.channel_id)
+				);
+				None
+			}
+		})
+		.collect::<Vec<_>>();
+	println!("Recovering {} channel(s) from the static channel backup", pending.len());
+	let mut interval = tokio::time::interval(RECOVERY_INTERVAL);
+	while !pending.is_empty() {
+		interval.tick().await;
+		let connected_peers = peer_manager.get_peer_node_ids();
+		for (channel, _) in pending.iter() {
+			let addr = channel.peer_addr.as_deref().and_then(|addr| PeerAddr::from_str(addr).ok());
+			if let Some(addr) = addr {
+				if !connected_peers.iter().any(|(pk, _)| *pk == channel.counterparty_node_id) {
+					let _ = cli::do_connect_peer(
+						channel.counterparty_node_id,
+						addr,
+						Arc::clone(&peer_manager),
+						proxy,
+					)
+					.await;
+				}
+			}
+		}
+
+		// Check which channels were closed before scanning the UTXO set for our outputs, so a
+		// channel closed with nothing for us is only given up on once its closing transaction
+		// confirmed.
+		let mut closed = HashSet::new();
+		for (channel, _) in pending.iter() {
+			match bitcoind_client
+				.is_output_unspent(channel.funding_txo.into_bitcoin_outpoint())
+				.await
+			{
+				Ok(false) => {
+					closed.insert(channel.channel_id);
+				}
+				Ok(true) => {}
+				Err(e) => println!("ERROR: recovery: failed to look up funding output: {}", e),
+			}
+		}
+		let scripts = pending.iter().map(|(_, script)| script.clone()).collect::<Vec<_>>();
+		let outputs = match bitcoind_client.scan_txout_set(&scripts).await {
+			Ok(outputs) => outputs,
+			Err(e) => {
+				println!("ERROR: recovery: failed to scan the UTXO set: {}", e);
+				continue;
+			}
+		};
+
+		let mut recovered = HashSet::new();
+		for (outpoint, output) in outputs {
+			let channel = match pending.iter().find(|(_, script)| *script == output.script_pubkey) {
+				Some((channel, _)) => channel,
+				None => continue,
+			};
+			let descriptor =
+				SpendableOutputDescriptor::StaticPaymentOutput(StaticPaymentOutputDescriptor {
+					outpoint: OutPoint { txid: outpoint.txid, index: outpoint.vout as u16 },
+					output: output.clone(),
+					channel_keys_id: channel.channel_keys_id,
+					channel_value_satoshis: channel.channel_value_satoshis,
+				});
+			let destination_address = match wallet.get_new_address(sweep_address_type).await {
+				Ok(address) => address,
+				Err(e) => {
+					println!("ERROR: recovery: failed to get an address to sweep to: {}", e);
+					continue;
+				}
+			};
+			let tx_feerate =
+				bitcoind_client.get_est_sat_per_1000_weight(ConfirmationTarget::Normal);
+			match keys_manager.spend_spendable_outputs(
+				&[&descriptor],
+				Vec::new(),
+				destination_address.script_pubkey(),
+				tx_feerate,
+				&Secp256k1::new(),
+			) {
+				Ok(tx) => {
+					bitcoind_client.broadcast_transaction(&tx);
+					println!(
+						"Recovered {} sats from channel {} in transaction {}",
+						output.value,
+						hex_utils::hex_str(&channel.channel_id),
+						tx.txid()
+					);
+					recovered.insert(channel.channel_id);
+				}
+				Err(()) => println!(
+					"ERROR: recovery: failed to sweep {} sats from channel {}",
+					output.value,
+					hex_utils::hex_str(&channel.channel_id)
+				),
+			}
+		}
+		pending.retain(|(channel, _)| {
+			if recovered.contains(&channel.channel_id) {
+				return false;
+			}
+			if closed.contains(&channel.channel_id) {
+				println!(
+					"Channel {} was closed with nothing left for us to recover",
+					hex_utils::hex_str(&channel.channel_id)
+				);
+				return false;
+			}
+			true
+		});
+	}
+	println!("Recovery from the static channel backup is complete");
+}
+
+#[cfg(test)]
+mod scb_tests {
+	use super::*;
+	use crate::persist::journal_tests::{keys, new_node, open_channel, open_persister, test_dir};
+	use bitcoin::hash_types::Txid;
+	use lightning::chain::keysinterface::Recipient;
+	use lightning::ln::chan_utils::make_funding_redeemscript;
+	use std::path::PathBuf;
+
+	#[tokio::test]
+	async fn test_channel_keys_id() {
+		let a_dir = test_dir("scb-a");
+		let b_dir = test_dir("scb-b");
+		let a_persister = open_persister(&a_dir).await;
+		let b_persister = open_persister(&b_dir).await;
+		let a = new_node(&a_dir, Arc::clone(&a_persister), 1);
+		let b = new_node(&b_dir, Arc::clone(&b_persister), 2);
+		let funding_tx = open_channel(&a, &b);
+		let key = format!("monitors/{}_{}", funding_tx.txid(), 0);
+		let a_monitor = a_persister.read(&key).unwrap().unwrap();
+		let b_monitor = b_persister.read(&key).unwrap().unwrap();
+
+		// The IDs read out of each side's monitor derive the keys the funding output pays to.
+		let funding_pubkey = |monitor: &[u8], keys_manager: &NodeKeys| {
+			let channel_keys_id = channel_keys_id(monitor, 100_000, keys_manager).unwrap();
+			keys_manager.derive_channel_pubkeys(100_000, channel_keys_id).unwrap().funding_pubkey
+		};
+		let redeem_script = make_funding_redeemscript(
+			&funding_pubkey(&a_monitor, &keys(1)),
+			&funding_pubkey(&b_monitor, &keys(2)),
+		);
+		assert_eq!(funding_tx.output[0].script_pubkey, redeem_script.to_v0_p2wsh());
+
+		// Another node's keys don't derive the revocation basepoint in the monitor, and a truncated
+		// monitor doesn't read.
+		assert!(channel_keys_id(&a_monitor, 100_000, &keys(2)).is_none());
+		assert!(channel_keys_id(&a_monitor[..64], 100_000, &keys(1)).is_none());
+
+		fs::remove_dir_all(&a_dir).unwrap();
+		fs::remove_dir_all(&b_dir).unwrap();
+	}
+
+	#[test]
+	fn test_write_read() {
+		let dir = PathBuf::from(test_dir("scb"));
+		let path = dir.join("channel_backup");
+		let keys_manager = keys(1);
+		let channel = ChannelBackup {
+			counterparty_node_id: keys(2).get_node_id(Recipient::Node).unwrap(),
+			peer_addr: Some("127.0.0.1:9735".to_string()),
+			channel_id: [3; 32],
+			funding_txo: OutPoint { txid: Txid::from_inner([4; 32]), index: 1 },
+			channel_value_satoshis: 100_000,
+			channel_keys_id: [5; 32],
+		};
+		let encoded = channel.encode();
+		write(&path, StaticChannelBackup { channels: vec![channel] }, &keys_manager).unwrap();
+
+		// Only encrypted on disk.
+		assert!(!fs::read(&path).unwrap().windows(encoded.len()).any(|w| w == encoded));
+		let channels = read(&path, &keys(1)).unwrap();
+		assert_eq!(channels.len(), 1);
+		assert_eq!(channels[0].encode(), encoded);
+
+		// A node with another keys seed can't read it.
+		assert!(read(&path, &keys(2)).is_err());
+
+		fs::remove_dir_all(&dir).unwrap();
+	}
+}
@@ -0,0 +1,1402 @@
+#[cfg(unix)]
+use crate::mnemonic::{self, MnemonicSetup};
+#[cfg(unix)]
+use crate::KEYS_SEED_FNAME;
+use bitcoin::bech32::u5;
+use bitcoin::blockdata::script::Script;
+use bitcoin::blockdata::transaction::{Transaction, TxOut};
+use bitcoin::secp256k1::ecdh::SharedSecret;
+use bitcoin::secp256k1::ecdsa::{RecoverableSignature, RecoveryId, Signature};
+use bitcoin::secp256k1::{All, PublicKey, Scalar, Secp256k1, SecretKey};
+use lightning::chain::keysinterface::{
+	ChannelSigner, EcdsaChannelSigner, EntropySource, InMemorySigner, KeyMaterial, KeysManager,
+	NodeSigner, PhantomKeysManager, Recipient, SignerProvider, SpendableOutputDescriptor,
+	WriteableEcdsaChannelSigner,
+};
+use lightning::chain::transaction::OutPoint;
+use lightning::ln::chan_utils::{
+	ChannelPublicKeys, ChannelTransactionParameters, ClosingTransaction, CommitmentTransaction,
+	HTLCOutputInCommitment, HolderCommitmentTransaction,
+};
+use lightning::ln::msgs::{
+	DecodeError, UnsignedChannelAnnouncement, UnsignedChannelUpdate, UnsignedGossipMessage,
+	UnsignedNodeAnnouncement,
+};
+use lightning::ln::script::ShutdownScript;
+use lightning::ln::PaymentPreimage;
+use lightning::util::message_signing;
+use lightning::util::ser::{Readable, Writeable, Writer};
+use lightning::{impl_writeable_tlv_based, impl_writeable_tlv_based_enum};
+use rand::{thread_rng, Rng};
+use std::fs;
+#[cfg(unix)]
+use std::io::Read;
+use std::io::{self, Write};
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+#[cfg(unix)]
+use std::sync::Mutex;
+#[cfg(unix)]
+use std::time::{Duration, Instant, SystemTime};
+#[cfg(unix)]
+use std::{cmp, thread};
+#[cfg(unix)]
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+#[cfg(unix)]
+use tokio::net::UnixListener;
+
+/// How long the node waits for the remote signer to answer a request.
+#[cfg(unix)]
+const SIGNER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Larger frames are refused rather than allocated.
+#[cfg(unix)]
+const MAX_FRAME_LEN: usize = 4 * 1024 * 1024;
+
+/// How long we first wait to retry a request the node can't do without, doubled with every failure
+/// up to `SIGNER_RETRY_MAX_BACKOFF`.
+#[cfg(unix)]
+const SIGNER_RETRY_MIN_BACKOFF: Duration = Duration::from_secs(1);
+#[cfg(unix)]
+const SIGNER_RETRY_MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// How long a request the node can't do without is retried before the node gives up on the signer.
+#[cfg(unix)]
+const SIGNER_RETRY_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// Everything the signer needs to re-derive a channel's keys, so it doesn't keep any state.
+#[derive(Clone)]
+struct ChannelRef {
+	channel_value_satoshis: u64,
+	channel_keys_id: [u8; 32],
+	/// Known once the channel is funded, and needed to sign anything for it.
+	parameters: Option<ChannelTransactionParameters>,
+}
+
+impl_writeable_tlv_based!(ChannelRef, {
+	(0, channel_value_satoshis, required),
+	(2, channel_keys_id, required),
+	(4, parameters, option),
+});
+
+/// What the node asks the remote signer for, one for each signing operation LDK needs.
+enum Request {
+	GetNodeInfo,
+	Ecdh {
+		other_key: PublicKey,
+		tweak: Option<[u8; 32]>,
+	},
+	SignInvoice {
+		hrp_bytes: Vec<u8>,
+		invoice_data: Vec<u8>,
+	},
+	SignGossipMessage {
+		kind: u8,
+		message: Vec<u8>,
+	},
+	SignMessage {
+		message: Vec<u8>,
+	},
+	SpendSpendableOutputs {
+		descriptors: Vec<SpendableOutputDescriptor>,
+		outputs: Vec<TxOut>,
+		change_destination_script: Script,
+		feerate_sat_per_1000_weight: u32,
+	},
+	DeriveChannelPubkeys {
+		channel: ChannelRef,
+	},
+	GetPerCommitmentPoint {
+		channel: ChannelRef,
+		idx: u64,
+	},
+	ReleaseCommitmentSecret {
+		channel: ChannelRef,
+		idx: u64,
+	},
+	SignCounterpartyCommitment {
+		channel: ChannelRef,
+		commitment_tx: CommitmentTransaction,
+		preimages: Vec<PaymentPreimage>,
+	},
+	SignHolderCommitmentAndHtlcs {
+		channel: ChannelRef,
+		commitment_tx: HolderCommitmentTransaction,
+	},
+	SignJusticeRevokedOutput {
+		channel: ChannelRef,
+		justice_tx: Transaction,
+		input: u64,
+		amount: u64,
+		per_commitment_key: SecretKey,
+	},
+	SignJusticeRevokedHtlc {
+		channel: ChannelRef,
+		justice_tx: Transaction,
+		input: u64,
+		amount: u64,
+		per_commitment_key: SecretKey,
+		htlc: HTLCOutputInCommitment,
+	},
+	SignCounterpartyHtlcTransaction {
+		channel: ChannelRef,
+		htlc_tx: Transaction,
+		input: u64,
+		amount: u64,
+		per_commitment_point: PublicKey,
+		htlc: HTLCOutputInCommitment,
+	},
+	SignClosingTransaction {
+		channel: ChannelRef,
+		to_holder_value_sat: u64,
+		to_counterparty_value_sat: u64,
+		to_holder_script: Script,
+		to_counterparty_script: Script,
+		funding_outpoint: OutPoint,
+	},
+	SignHolderAnchorInput {
+		channel: ChannelRef,
+		anchor_tx: Transaction,
+		input: u64,
+	},
+	SignChannelAnnouncement {
+		channel: ChannelRef,
+		message: UnsignedChannelAnnouncement,
+	},
+}
+
+impl_writeable_tlv_based_enum!(Request,
+	(0, GetNodeInfo) => {},
+	(2, Ecdh) => {
+		(0, other_key, required),
+		(2, tweak, option),
+	},
+	(4, SignInvoice) => {
+		(0, hrp_bytes, required),
+		(2, invoice_data, required),
+	},
+	(6, SignGossipMessage) => {
+		(0, kind, required),
+		(2, message, required),
+	},
+	(8, SignMessage) => {
+		(0, message, required),
+	},
+	(10, SpendSpendableOutputs) => {
+		(0, descriptors, vec_type),
+		(2, outputs, vec_type),
+		(4, change_destination_script, required),
+		(6, feerate_sat_per_1000_weight, required),
+	},
+	(12, DeriveChannelPubkeys) => {
+		(0, channel, required),
+	},
+	(14, GetPerCommitmentPoint) => {
+		(0, channel, required),
+		(2, idx, required),
+	},
+	(16, ReleaseCommitmentSecret) => {
+		(0, channel, required),
+		(2, idx, required),
+	},
+	(18, SignCounterpartyCommitment) => {
+		(0, channel, required),
+		(2, commitment_tx, required),
+		(4, preimages, vec_type),
+	},
+	(20, SignHolderCommitmentAndHtlcs) => {
+		(0, channel, required),
+		(2, commitment_tx, required),
+	},
+	(22, SignJusticeRevokedOutput) => {
+		(0, channel, required),
+		(2, justice_tx, required),
+		(4, input, required),
+		(6, amount, required),
+		(8, per_commitment_key, required),
+	},
+	(24, SignJusticeRevokedHtlc) => {
+		(0, channel, required),
+		(2, justice_tx, required),
+		(4, input, required),
+		(6, amount, required),
+		(8, per_commitment_key, required),
+		(10, htlc, required),
+	},
+	(26, SignCounterpartyHtlcTransaction) => {
+		(0, channel, required),
+		(2, htlc_tx, required),
+		(4, input, required),
+		(6, amount, required),
+		(8, per_commitment_point, required),
+		(10, htlc, required),
+	},
+	(28, SignClosingTransaction) => {
+		(0, channel, required),
+		(2, to_holder_value_sat, required),
+		(4, to_counterparty_value_sat, required),
+		(6, to_holder_script, required),
+		(8, to_counterparty_script, required),
+		(10, funding_outpoint, required),
+	},
+	(30, SignHolderAnchorInput) => {
+		(0, channel, required),
+		(2, anchor_tx, required),
+		(4, input, required),
+	},
+	(32, SignChannelAnnouncement) => {
+		(0, channel, required),
+		(2, message, required),
+	};
+);
+
+const GOSSIP_CHANNEL_ANNOUNCEMENT: u8 = 0;
+const GOSSIP_CHANNEL_UPDATE: u8 = 1;
+const GOSSIP_NODE_ANNOUNCEMENT: u8 = 2;
+
+/// What the node learns from the remote signer once, when it connects.
+struct NodeInfo {
+	node_id: PublicKey,
+	/// Only used to derive payment secrets, it can't spend anything.
+	inbound_payment_key_material: [u8; 32],
+	destination_script: Script,
+	shutdown_script: ShutdownScript,
+}
+
+impl_writeable_tlv_based!(NodeInfo, {
+	(0, node_id, required),
+	(2, inbound_payment_key_material, required),
+	(4, destination_script, required),
+	(6, shutdown_script, required),
+});
+
+#[cfg(unix)]
+fn write_frame(stream: &mut UnixStream, bytes: &[u8]) -> io::Result<()> {
+	stream.write_all(&(bytes.len() as u32).to_be_bytes())?;
+	stream.write_all(bytes)?;
+	stream.flush()
+}
+
+#[cfg(unix)]
+fn read_frame(stream: &mut UnixStream) -> io::Result<Vec<u8>> {
+	let mut len = [0; 4];
+	stream.read_exact(&mut len)?;
+	let len = u32::from_be_bytes(len) as usize;
+	if len > MAX_FRAME_LEN {
+		return Err(io::Error::new(io::ErrorKind::InvalidData, "frame too large"));
+	}
+	let mut bytes = vec![0; len];
+	stream.read_exact(&mut bytes)?;
+	Ok(bytes)
+}
+
+/// Connections to the signer daemon. A request takes an idle one or opens a new one, and puts it
+/// back once answered, so requests don't wait on each other while the signer answers.
+#[cfg(unix)]
+struct SignerClient {
+	socket_path: PathBuf,
+	idle: Mutex<Vec<UnixStream>>,
+}
+
+#[cfg(unix)]
+impl SignerClient {
+	fn new(socket_path: PathBuf) -> Self {
+		SignerClient { socket_path, idle: Mutex::new(Vec::new()) }
+	}
+
+	fn connect(&self) -> Result<UnixStream, ()> {
+		let stream = UnixStream::connect(&self.socket_path).map_err(|e| {
+			println!(
+				"ERROR: failed to connect to the remote signer at {}: {}",
+				self.socket_path.display(),
+				e
+			);
+		})?;
+		stream.set_read_timeout(Some(SIGNER_TIMEOUT)).map_err(|_| ())?;
+		Ok(stream)
+	}
+
+	fn call<T: Readable>(&self, request: &Request) -> Result<T, ()> {
+		let request = request.encode();
+		let mut response = None;
+		// Requests don't change the signer's state, so one that failed on a connection the
+		// signer closed, say by restarting, is sent again on a new one.
+		for attempt in 0..2 {
+			let idle = if attempt == 0 { self.idle.lock().unwrap().pop() } else { None };
+			let mut stream = match idle {
+				Some(stream) => stream,
+				None => self.connect()?,
+			};
+			match write_frame(&mut stream, &request).and_then(|()| read_frame(&mut stream)) {
+				Ok(bytes) => {
+					self.idle.lock().unwrap().push(stream);
+					response = Some(bytes);
+					break;
+				}
+				Err(e) => {
+					println!("ERROR: remote signer request failed: {}", e);
+					// The other idle connections likely broke along with it.
+					self.idle.lock().unwrap().clear();
+				}
+			}
+		}
+		match response.as_ref().and_then(|response| response.split_first()) {
+			Some((&0, payload)) => T::read(&mut io::Cursor::new(payload)).map_err(|e| {
+				println!("ERROR: invalid response from the remote signer: {:?}", e);
+			}),
+			Some((_, error)) => {
+				println!("ERROR: the remote signer refused: {}", String::from_utf8_lossy(error));
+				Err(())
+			}
+			None => Err(()),
+		}
+	}
+
+	/// Like `call`, retrying with a growing backoff until `timeout` is up.
+	fn call_with_retries<T: Readable>(
+		&self, request: &Request, timeout: Duration,
+	) -> Result<T, ()> {
+		let started = Instant::now();
+		let mut backoff = SIGNER_RETRY_MIN_BACKOFF;
+		loop {
+			if let Ok(response) = self.call(request) {
+				return Ok(response);
+			}
+			if started.elapsed() + backoff > timeout {
+				return Err(());
+			}
+			println!("ERROR: retrying the remote signer request in {}s", backoff.as_secs());
+			thread::sleep(backoff);
+			backoff = cmp::min(backoff * 2, SIGNER_RETRY_MAX_BACKOFF);
+		}
+	}
+
+	/// Like `call`, for the requests LDK gives us no way to fail: the node can't go on without an
+	/// answer, so we wait a while for the signer to be back, then abort rather than hang.
+	fn call_or_abort<T: Readable>(&self, request: &Request) -> T {
+		match self.call_with_retries(request, SIGNER_RETRY_TIMEOUT) {
+			Ok(response) => response,
+			Err(()) => panic!(
+				"The remote signer didn't answer for {}s, the node can't go on without it",
+				SIGNER_RETRY_TIMEOUT.as_secs()
+			),
+		}
+	}
+}
+
+/// Remote signers are reached over a Unix socket, so there are none to connect to elsewhere.
+#[cfg(not(unix))]
+enum SignerClient {}
+
+#[cfg(not(unix))]
+impl SignerClient {
+	fn call<T: Readable>(&self, _request: &Request) -> Result<T, ()> {
+		match *self {}
+	}
+
+	fn call_or_abort<T: Readable>(&self, _request: &Request) -> T {
+		match *self {}
+	}
+}
+
+/// Node keys held by a signer daemon, reached over a Unix socket.
+pub(crate) struct RemoteSigner {
+	client: Arc<SignerClient>,
+	info: NodeInfo,
+}
+
+impl RemoteSigner {
+	#[cfg(unix)]
+	pub(crate) fn connect(socket_path: PathBuf) -> Result<Self, ()> {
+		let client = Arc::new(SignerClient::new(socket_path));
+		let info = client.call(&Request::GetNodeInfo)?;
+		Ok(RemoteSigner { client, info })
+	}
+
+	#[cfg(not(unix))]
+	pub(crate) fn connect(_socket_path: PathBuf) -> Result<Self, ()> {
+		println!("ERROR: remote signers are reached over a Unix socket, which this platform lacks");
+		Err(())
+	}
+
+	fn channel_signer(
+		&self, channel_value_satoshis: u64, channel_keys_id: [u8; 32], pubkeys: ChannelPublicKeys,
+	) -> RemoteChannelSigner {
+		RemoteChannelSigner {
+			client: Arc::clone(&self.client),
+			channel: ChannelRef { channel_value_satoshis, channel_keys_id, parameters: None },
+			pubkeys,
+		}
+	}
+}
+
+/// A channel's keys held by the signer daemon. Only its public keys are kept here.
+#[derive(Clone)]
+pub(crate) struct RemoteChannelSigner {
+	client: Arc<SignerClient>,
+	channel: ChannelRef,
+	pubkeys: ChannelPublicKeys,
+}
+
+impl RemoteChannelSigner {
+	fn call<T: Readable>(&self, request: impl FnOnce(ChannelRef) -> Request) -> Result<T, ()> {
+		self.client.call(&request(self.channel.clone()))
+	}
+
+	fn call_or_abort<T: Readable>(&self, request: impl FnOnce(ChannelRef) -> Request) -> T {
+		self.client.call_or_abort(&request(self.channel.clone()))
+	}
+}
+
+/// The node's keys, either derived here from the keys seed, or held by a signer daemon so they
+/// never touch the node's host.
+pub(crate) enum NodeKeys {
+	Local(KeysManager),
+	/// Derived from the keys seed too, for a node that's part of a phantom node cluster, whose
+	/// nodes share the phantom node's key and the key of the payments to it.
+	Phantom(PhantomKeysManager),
+	Remote(RemoteSigner),
+}
+
+impl NodeKeys {
+	/// Sweeps outputs our channels left us on chain, see
+	/// [`KeysManager::spend_spendable_outputs`].
+	pub(crate) fn spend_spendable_outputs(
+		&self, descriptors: &[&SpendableOutputDescriptor], outputs: Vec<TxOut>,
+		change_destination_script: Script, feerate_sat_per_1000_weight: u32,
+		secp_ctx: &Secp256k1<All>,
+	) -> Result<Transaction, ()> {
+		match self {
+			NodeKeys::Local(keys_manager) => keys_manager.spend_spendable_outputs(
+				descriptors,
+				outputs,
+				change_destination_script,
+				feerate_sat_per_1000_weight,
+				secp_ctx,
+			),
+			NodeKeys::Phantom(keys_manager) => keys_manager.spend_spendable_outputs(
+				descriptors,
+				outputs,
+				change_destination_script,
+				feerate_sat_per_1000_weight,
+				secp_ctx,
+			),
+			NodeKeys::Remote(signer) => signer.client.call(&Request::SpendSpendableOutputs {
+				descriptors: descriptors.iter().map(|descriptor| (*descriptor).clone()).collect(),
+				outputs,
+				change_destination_script,
+				feerate_sat_per_1000_weight,
+			}),
+		}
+	}
+
+	/// Signs `message` with the node key, as `signmessage` does.
+	pub(crate) fn sign_message(&self, message: &[u8]) -> Result<String, ()> {
+		match self {
+			NodeKeys::Local(keys_manager) => {
+				message_signing::sign(message, &keys_manager.get_node_secret_key()).map_err(|_| ())
+			}
+			NodeKeys::Phantom(keys_manager) => {
+				message_signing::sign(message, &keys_manager.get_node_secret_key()).map_err(|_| ())
+			}
+			NodeKeys::Remote(signer) => {
+				let signature: Vec<u8> =
+					signer.client.call(&Request::SignMessage { message: message.to_vec() })?;
+				String::from_utf8(signature).map_err(|_| ())
+			}
+		}
+	}
+
+	/// The public keys of a channel we may no longer have the monitor of.
+	pub(crate) fn derive_channel_pubkeys(
+		&self, channel_value_satoshis: u64, channel_keys_id: [u8; 32],
+	) -> Result<ChannelPublicKeys, ()> {
+		match self {
+			NodeKeys::Local(keys_manager) => Ok(keys_manager
+				.derive_channel_keys(channel_value_satoshis, &channel_keys_id)
+				.pubkeys()
+				.clone()),
+			NodeKeys::Phantom(keys_manager) => Ok(keys_manager
+				.derive_channel_keys(channel_value_satoshis, &channel_keys_id)
+				.pubkeys()
+				.clone()),
+			NodeKeys::Remote(signer) => signer.client.call(&Request::DeriveChannelPubkeys {
+				channel: ChannelRef { channel_value_satoshis, channel_keys_id, parameters: None },
+			}),
+		}
+	}
+}
+
+impl EntropySource for NodeKeys {
+	fn get_secure_random_bytes(&self) -> [u8; 32] {
+		match self {
+			NodeKeys::Local(keys_manager) => keys_manager.get_secure_random_bytes(),
+			NodeKeys::Phantom(keys_manager) => keys_manager.get_secure_random_bytes(),
+			NodeKeys::Remote(_) => {
+				let mut bytes = [0; 32];
+				thread_rng().fill_bytes(&mut bytes);
+				bytes
+			}
+		}
+	}
+}
+
+impl NodeSigner for NodeKeys {
+	fn get_inbound_payment_key_material(&self) -> KeyMaterial {
+		match self {
+			NodeKeys::Local(keys_manager) => keys_manager.get_inbound_payment_key_material(),
+			NodeKeys::Phantom(keys_manager) => keys_manager.get_inbound_payment_key_material(),
+			NodeKeys::Remote(signer) => KeyMaterial(signer.info.inbound_payment_key_material),
+		}
+	}
+
+	fn get_node_id(&self, recipient: Recipient) -> Result<PublicKey, ()> {
+		match (self, recipient) {
+			(NodeKeys::Local(keys_manager), _) => keys_manager.get_node_id(recipient),
+			(NodeKeys::Phantom(keys_manager), _) => keys_manager.get_node_id(recipient),
+			(NodeKeys::Remote(signer), Recipient::Node) => Ok(signer.info.node_id),
+			(NodeKeys::Remote(_), Recipient::PhantomNode) => Err(()),
+		}
+	}
+
+	fn ecdh(
+		&self, recipient: Recipient, other_key: &PublicKey, tweak: Option<&Scalar>,
+	) -> Result<SharedSecret, ()> {
+		match (self, recipient) {
+			(NodeKeys::Local(keys_manager), _) => keys_manager.ecdh(recipient, other_key, tweak),
+			(NodeKeys::Phantom(keys_manager), _) => keys_manager.ecdh(recipient, other_key, tweak),
+			(NodeKeys::Remote(signer), Recipient::Node) => {
+				let secret: [u8; 32] = signer.client.call(&Request::Ecdh {
+					other_key: *other_key,
+					tweak: tweak.map(|tweak| tweak.to_be_bytes()),
+				})?;
+				Ok(SharedSecret::from_bytes(secret))
+			}
+			(NodeKeys::Remote(_), Recipient::PhantomNode) => Err(()),
+		}
+	}
+
+	fn sign_invoice(
+		&self, hrp_bytes: &[u8], invoice_data: &[u5], recipient: Recipient,
+	) -> Result<RecoverableSignature, ()> {
+		match (self, recipient) {
+			(NodeKeys::Local(keys_manager), _) => {
+				keys_manager.sign_invoice(hrp_bytes, invoice_data, recipient)
+			}
+			(NodeKeys::Phantom(keys_manager), _) => {
+				keys_manager.sign_invoice(hrp_bytes, invoice_data, recipient)
+			}
+			(NodeKeys::Remote(signer), Recipient::Node) => {
+				let signature: Vec<u8> = signer.client.call(&Request::SignInvoice {
+					hrp_bytes: hrp_bytes.to_vec(),
+					invoice_data: invoice_data.iter().map(|u| u.to_u8()).collect(),
+				})?;
+				decode_recoverable_signature(&signature)
+			}
+			(NodeKeys::Remote(_), Recipient::PhantomNode) => Err(()),
+		}
+	}
+
+	fn sign_gossip_message(&self, msg: UnsignedGossipMessage) -> Result<Signature, ()> {
+		match self {
+			NodeKeys::Local(keys_manager) => keys_manager.sign_gossip_message(msg),
+			NodeKeys::Phantom(keys_manager) => keys_manager.sign_gossip_message(msg),
+			NodeKeys::Remote(signer) => {
+				let (kind, message) = match msg {
+					UnsignedGossipMessage::ChannelAnnouncement(msg) => {
+						(GOSSIP_CHANNEL_ANNOUNCEMENT, msg.encode())
+					}
+					UnsignedGossipMessage::ChannelUpdate(msg) => {
+						(GOSSIP_CHANNEL_UPDATE, msg.encode())
+					}
+					UnsignedGossipMessage::NodeAnnouncement(msg) => {
+						(GOSSIP_NODE_ANNOUNCEMENT, msg.encode())
+					}
+				};
+				signer.client.call(&Request::SignGossipMessage { kind, message })
+			}
+		}
+	}
+}
+
+impl SignerProvider for NodeKeys {
+	type Signer = ChannelKeys;
+
+	fn generate_channel_keys_id(
+		&self, inbound: bool, channel_value_satoshis: u64, user_channel_id: u128,
+	) -> [u8; 32] {
+		match self {
+			NodeKeys::Local(keys_manager) => keys_manager.generate_channel_keys_id(
+				inbound,
+				channel_value_satoshis,
+				user_channel_id,
+			),
+			NodeKeys::Phantom(keys_manager) => keys_manager.generate_channel_keys_id(
+				inbound,
+				channel_value_satoshis,
+				user_channel_id,
+			),
+			// Any unique ID will do, the signer derives the channel's keys from it.
+			NodeKeys::Remote(_) => self.get_secure_random_bytes(),
+		}
+	}
+
+	fn derive_channel_signer(
+		&self, channel_value_satoshis: u64, channel_keys_id: [u8; 32],
+	) -> ChannelKeys {
+		match self {
+			NodeKeys::Local(keys_manager) => ChannelKeys::Local(
+				keys_manager.derive_channel_signer(channel_value_satoshis, channel_keys_id),
+			),
+			NodeKeys::Phantom(keys_manager) => ChannelKeys::Local(
+				keys_manager.derive_channel_signer(channel_value_satoshis, channel_keys_id),
+			),
+			NodeKeys::Remote(signer) => {
+				let pubkeys = signer.client.call_or_abort(&Request::DeriveChannelPubkeys {
+					channel: ChannelRef {
+						channel_value_satoshis,
+						channel_keys_id,
+						parameters: None,
+					},
+				});
+				ChannelKeys::Remote(signer.channel_signer(
+					channel_value_satoshis,
+					channel_keys_id,
+					pubkeys,
+				))
+			}
+		}
+	}
+
+	fn read_chan_signer(&self, reader: &[u8]) -> Result<ChannelKeys, DecodeError> {
+		match self {
+			NodeKeys::Local(keys_manager) => {
+				keys_manager.read_chan_signer(reader).map(ChannelKeys::Local)
+			}
+			NodeKeys::Phantom(keys_manager) => {
+				keys_manager.read_chan_signer(reader).map(ChannelKeys::Local)
+			}
+			NodeKeys::Remote(signer) => {
+				let (channel_value_satoshis, channel_keys_id, pubkeys): (u64, [u8; 32], _) =
+					Readable::read(&mut io::Cursor::new(reader))?;
+				Ok(ChannelKeys::Remote(signer.channel_signer(
+					channel_value_satoshis,
+					channel_keys_id,
+					pubkeys,
+				)))
+			}
+		}
+	}
+
+	fn get_destination_script(&self) -> Script {
+		match self {
+			NodeKeys::Local(keys_manager) => keys_manager.get_destination_script(),
+			NodeKeys::Phantom(keys_manager) => keys_manager.get_destination_script(),
+			NodeKeys::Remote(signer) => signer.info.destination_script.clone(),
+		}
+	}
+
+	fn get_shutdown_scriptpubkey(&self) -> ShutdownScript {
+		match self {
+			NodeKeys::Local(keys_manager) => keys_manager.get_shutdown_scriptpubkey(),
+			NodeKeys::Phantom(keys_manager) => keys_manager.get_shutdown_scriptpubkey(),
+			NodeKeys::Remote(signer) => signer.info.shutdown_script.clone(),
+		}
+	}
+}
+
+/// A channel's signer. Local ones serialize as `InMemorySigner`, so existing channels read back
+/// as they were.
+#[derive(Clone)]
+pub(crate) enum ChannelKeys {
+	Local(InMemorySigner),
+	Remote(RemoteChannelSigner),
+}
+
+impl ChannelSigner for ChannelKeys {
+	fn get_per_commitment_point(&self, idx: u64, secp_ctx: &Secp256k1<All>) -> PublicKey {
+		match self {
+			ChannelKeys::Local(signer) => signer.get_per_commitment_point(idx, secp_ctx),
+			ChannelKeys::Remote(signer) => {
+				signer.call_or_abort(|channel| Request::GetPerCommitmentPoint { channel, idx })
+			}
+		}
+	}
+
+	fn release_commitment_secret(&self, idx: u64) -> [u8; 32] {
+		match self {
+			ChannelKeys::Local(signer) => signer.release_commitment_secret(idx),
+			ChannelKeys::Remote(signer) => {
+				signer.call_or_abort(|channel| Request::ReleaseCommitmentSecret { channel, idx })
+			}
+		}
+	}
+
+	fn validate_holder_commitment(
+		&self, holder_tx: &HolderCommitmentTransaction, preimages: Vec<PaymentPreimage>,
+	) -> Result<(), ()> {
+		match self {
+			ChannelKeys::Local(signer) => signer.validate_holder_commitment(holder_tx, preimages),
+			// The signer doesn't enforce any policy, so there's nothing for it to check.
+			ChannelKeys::Remote(_) => Ok(()),
+		}
+	}
+
+	fn pubkeys(&self) -> &ChannelPublicKeys {
+		match self {
+			ChannelKeys::Local(signer) => signer.pubkeys(),
+			ChannelKeys::Remote(signer) => &signer.pubkeys,
+		}
+	}
+
+	fn channel_keys_id(&self) -> [u8; 32] {
+		match self {
+			ChannelKeys::Local(signer) => signer.channel_keys_id(),
+			ChannelKeys::Remote(signer) => signer.channel.channel_keys_id,
+		}
+	}
+
+	fn provide_channel_parameters(&mut self, channel_parameters: &ChannelTransactionParameters) {
+		match self {
+			ChannelKeys::Local(signer) => signer.provide_channel_parameters(channel_parameters),
+			ChannelKeys::Remote(signer) => {
+				signer.channel.parameters = Some(channel_parameters.clone())
+			}
+		}
+	}
+}
+
+impl EcdsaChannelSigner for ChannelKeys {
+	fn sign_counterparty_commitment(
+		&self, commitment_tx: &CommitmentTransaction, preimages: Vec<PaymentPreimage>,
+		secp_ctx: &Secp256k1<All>,
+	) -> Result<(Signature, Vec<Signature>), ()> {
+		match self {
+			ChannelKeys::Local(signer) => {
+				signer.sign_counterparty_commitment(commitment_tx, preimages, secp_ctx)
+			}
+			ChannelKeys::Remote(signer) => {
+				signer.call(|channel| Request::SignCounterpartyCommitment {
+					channel,
+					commitment_tx: commitment_tx.clone(),
+					preimages,
+				})
+			}
+		}
+	}
+
+	fn validate_counterparty_revocation(&self, idx: u64, secret: &SecretKey) -> Result<(), ()> {
+		match self {
+			ChannelKeys::Local(signer) => signer.validate_counterparty_revocation(idx, secret),
+			ChannelKeys::Remote(_) => Ok(()),
+		}
+	}
+
+	fn sign_holder_commitment_and_htlcs(
+		&self, commitment_tx: &HolderCommitmentTransaction, secp_ctx: &Secp256k1<All>,
+	) -> Result<(Signature, Vec<Signature>), ()> {
+		match self {
+			ChannelKeys::Local(signer) => {
+				signer.sign_holder_commitment_and_htlcs(commitment_tx, secp_ctx)
+			}
+			ChannelKeys::Remote(signer) => {
+				signer.call(|channel| Request::SignHolderCommitmentAndHtlcs {
+					channel,
+					commitment_tx: commitment_tx.clone(),
+				})
+			}
+		}
+	}
+
+	fn sign_justice_revoked_output(
+		&self, justice_tx: &Transaction, input: usize, amount: u64, per_commitment_key: &SecretKey,
+		secp_ctx: &Secp256k1<All>,
+	) -> Result<Signature, ()> {
+		match self {
+			ChannelKeys::Local(signer) => signer.sign_justice_revoked_output(
+				justice_tx,
+				input,
+				amount,
+				per_commitment_key,
+				secp_ctx,
+			),
+			ChannelKeys::Remote(signer) => {
+				signer.call(|channel| Request::SignJusticeRevokedOutput {
+					channel,
+					justice_tx: justice_tx.clone(),
+					input: input as u64,
+					amount,
+					per_commitment_key: *per_commitment_key,
+				})
+			}
+		}
+	}
+
+	fn sign_justice_revoked_htlc(
+		&self, justice_tx: &Transaction, input: usize, amount: u64, per_commitment_key: &SecretKey,
+		htlc: &HTLCOutputInCommitment, secp_ctx: &Secp256k1<All>,
+	) -> Result<Signature, ()> {
+		match self {
+			ChannelKeys::Local(signer) => signer.sign_justice_revoked_htlc(
+				justice_tx,
+				input,
+				amount,
+				per_commitment_key,
+				htlc,
+				secp_ctx,
+			),
+			ChannelKeys::Remote(signer) => signer.call(|channel| Request::SignJusticeRevokedHtlc {
+				channel,
+				justice_tx: justice_tx.clone(),
+				input: input as u64,
+				amount,
+				per_commitment_key: *per_commitment_key,
+				htlc: htlc.clone(),
+			}),
+		}
+	}
+
+	fn sign_counterparty_htlc_transaction(
+		&self, htlc_tx: &Transaction, input: usize, amount: u64, per_commitment_point: &PublicKey,
+		htlc: &HTLCOutputInCommitment, secp_ctx: &Secp256k1<All>,
+	) -> Result<Signature, ()> {
+		match self {
+			ChannelKeys::Local(signer) => signer.sign_counterparty_htlc_transaction(
+				htlc_tx,
+				input,
+				amount,
+				per_commitment_point,
+				htlc,
+				secp_ctx,
+			),
+			ChannelKeys::Remote(signer) => {
+				signer.call(|channel| Request::SignCounterpartyHtlcTransaction {
+					channel,
+					htlc_tx: htlc_tx.clone(),
+					input: input as u64,
+					amount,
+					per_commitment_point: *per_commitment_point,
+					htlc: htlc.clone(),
+				})
+			}
+		}
+	}
+
+	fn sign_closing_transaction(
+		&self, closing_tx: &ClosingTransaction, secp_ctx: &Secp256k1<All>,
+	) -> Result<Signature, ()> {
+		match self {
+			ChannelKeys::Local(signer) => signer.sign_closing_transaction(closing_tx, secp_ctx),
+			ChannelKeys::Remote(signer) => {
+				// `ClosingTransaction` isn't serializable, so the signer rebuilds it from its parts.
+				let funding_outpoint =
+					closing_tx.trust().built_transaction().input[0].previous_output;
+				signer.call(|channel| Request::SignClosingTransaction {
+					channel,
+					to_holder_value_sat: closing_tx.to_holder_value_sat(),
+					to_counterparty_value_sat: closing_tx.to_counterparty_value_sat(),
+					to_holder_script: closing_tx.to_holder_script().clone(),
+					to_counterparty_script: closing_tx.to_counterparty_script().clone(),
+					funding_outpoint: OutPoint {
+						txid: funding_outpoint.txid,
+						index: funding_outpoint.vout as u16,
+					},
+				})
+			}
+		}
+	}
+
+	fn sign_holder_anchor_input(
+		&self, anchor_tx: &Transaction, input: usize, secp_ctx: &Secp256k1<All>,
+	) -> Result<Signature, ()> {
+		match self {
+			ChannelKeys::Local(signer) => {
+				signer.sign_holder_anchor_input(anchor_tx, input, secp_ctx)
+			}
+			ChannelKeys::Remote(signer) => signer.call(|channel| Request::SignHolderAnchorInput {
+				channel,
+				anchor_tx: anchor_tx.clone(),
+				input: input as u64,
+			}),
+		}
+	}
+
+	fn sign_channel_announcement_with_funding_key(
+		&self, msg: &UnsignedChannelAnnouncement, secp_ctx: &Secp256k1<All>,
+	) -> Result<Signature, ()> {
+		match self {
+			ChannelKeys::Local(signer) => {
+				signer.sign_channel_announcement_with_funding_key(msg, secp_ctx)
+			}
+			ChannelKeys::Remote(signer) => signer
+				.call(|channel| Request::SignChannelAnnouncement { channel, message: msg.clone() }),
+		}
+	}
+}
+
+impl Writeable for ChannelKeys {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), io::Error> {
+		match self {
+			ChannelKeys::Local(signer) => signer.write(writer),
+			ChannelKeys::Remote(signer) => (
+				signer.channel.channel_value_satoshis,
+				signer.channel.channel_keys_id,
+				signer.pubkeys.clone(),
+			)
+				.write(writer),
+		}
+	}
+}
+
+impl WriteableEcdsaChannelSigner for ChannelKeys {}
+
+fn encode_recoverable_signature(signature: &RecoverableSignature) -> Vec<u8> {
+	let (recovery_id, signature) = signature.serialize_compact();
+	let mut bytes = vec![recovery_id.to_i32() as u8];
+	bytes.extend_from_slice(&signature);
+	bytes
+}
+
+fn decode_recoverable_signature(bytes: &[u8]) -> Result<RecoverableSignature, ()> {
+	let (recovery_id, signature) = bytes.split_first().ok_or(())?;
+	let recovery_id = RecoveryId::from_i32(*recovery_id as i32).map_err(|_| ())?;
+	RecoverableSignature::from_compact(signature, recovery_id).map_err(|_| ())
+}
+
+/// Reads the secret shared by the nodes of a phantom node cluster from `path`. The first node of
+/// the cluster creates it, if `create` is set and there's none yet, and it's then copied to the
+/// others.
+pub(crate) fn read_phantom_secret(path: &Path, create: bool) -> Result<[u8; 32], String> {
+	match fs::read(path) {
+		Ok(secret) if secret.len() == 32 => {
+			let mut key = [0; 32];
+			key.copy_from_slice(&secret);
+			Ok(key)
+		}
+		Ok(_) => Err(format!("{} isn't a phantom secret", path.display())),
+		Err(e) if e.kind() == io::ErrorKind::NotFound && create => {
+			let mut key = [0; 32];
+			thread_rng().fill_bytes(&mut key);
+			let mut options = fs::OpenOptions::new();
+			options.write(true).create_new(true);
+			#[cfg(unix)]
+			options.mode(0o600);
+			options
+				.open(path)
+				.and_then(|mut f| f.write_all(&key).and_then(|()| f.sync_all()))
+				.map_err(|e| format!("failed to write {}: {}", path.display(), e))?;
+			Ok(key)
+		}
+		Err(e) if e.kind() == io::ErrorKind::NotFound => Err(format!(
+			"{} doesn't exist, copy it from another node of the cluster or pass --create-phantom-secret",
+			path.display()
+		)),
+		Err(e) => Err(format!("failed to read {}: {}", path.display(), e)),
+	}
+}
+
+/// Reads the signer's keys seed from `signer_dir`, creating it if there's none yet.
+#[cfg(unix)]
+fn read_or_create_seed(
+	signer_dir: &Path, mnemonic: Option<MnemonicSetup>,
+) -> Result<[u8; 32], String> {
+	let keys_seed_path = signer_dir.join(KEYS_SEED_FNAME);
+	match fs::read(&keys_seed_path) {
+		Ok(_) if mnemonic.is_some() => {
+			Err("the signer already has a keys seed, --mnemonic only sets up new ones".to_string())
+		}
+		Ok(seed) if seed.len() == 32 => {
+			let mut key = [0; 32];
+			key.copy_from_slice(&seed);
+			Ok(key)
+		}
+		Ok(_) => Err(format!("{} isn't a keys seed", keys_seed_path.display())),
+		Err(e) if e.kind() == io::ErrorKind::NotFound => {
+			let key = match mnemonic {
+				Some(setup) => mnemonic::setup(setup)?,
+				None => {
+					let mut key = [0; 32];
+					thread_rng().fill_bytes(&mut key);
+					key
+				}
+			};
+			fs::create_dir_all(signer_dir).map_err(|e| e.to_string())?;
+			fs::OpenOptions::new()
+				.write(true)
+				.create_new(true)
+				.mode(0o600)
+				.open(&keys_seed_path)
+				.and_then(|mut f| f.write_all(&key).and_then(|()| f.sync_all()))
+				.map_err(|e| format!("failed to write {}: {}", keys_seed_path.display(), e))?;
+			Ok(key)
+		}
+		Err(e) => Err(format!("failed to read {}: {}", keys_seed_path.display(), e)),
+	}
+}
+
+/// Re-derives a channel's signer from the request.
+#[cfg(unix)]
+fn channel_signer(keys_manager: &KeysManager, channel: ChannelRef) -> InMemorySigner {
+	let mut signer =
+		keys_manager.derive_channel_keys(channel.channel_value_satoshis, &channel.channel_keys_id);
+	if let Some(parameters) = channel.parameters.as_ref() {
+		signer.provide_channel_parameters(parameters);
+	}
+	signer
+}
+
+/// Like `channel_signer`, for signing, which `InMemorySigner` can't do without the parameters.
+#[cfg(unix)]
+fn funded_channel_signer(
+	keys_manager: &KeysManager, channel: ChannelRef,
+) -> Result<InMemorySigner, String> {
+	if channel.parameters.is_none() {
+		return Err("the channel's parameters are missing".to_string());
+	}
+	Ok(channel_signer(keys_manager, channel))
+}
+
+#[cfg(unix)]
+fn signed<T: Writeable>(what: &str, result: Result<T, ()>) -> Result<Vec<u8>, String> {
+	result.map(|value| value.encode()).map_err(|()| format!("failed to {}", what))
+}
+
+#[cfg(unix)]
+fn handle_request(keys_manager: &KeysManager, request: Request) -> Result<Vec<u8>, String> {
+	let secp_ctx = Secp256k1::new();
+	match request {
+		Request::GetNodeInfo => Ok(NodeInfo {
+			node_id: keys_manager.get_node_id(Recipient::Node).unwrap(),
+			inbound_payment_key_material: keys_manager.get_inbound_payment_key_material().0,
+			destination_script: keys_manager.get_destination_script(),
+			shutdown_script: keys_manager.get_shutdown_scriptpubkey(),
+		}
+		.encode()),
+		Request::Ecdh { other_key, tweak } => {
+			let tweak = tweak
+				.map(Scalar::from_be_bytes)
+				.transpose()
+				.map_err(|_| "invalid ECDH tweak".to_string())?;
+			let secret = keys_manager.ecdh(Recipient::Node, &other_key, tweak.as_ref());
+			signed("compute the shared secret", secret.map(|secret| secret.secret_bytes()))
+		}
+		Request::SignInvoice { hrp_bytes, invoice_data } => {
+			let invoice_data = invoice_data
+				.into_iter()
+				.map(u5::try_from_u8)
+				.collect::<Result<Vec<_>, _>>()
+				.map_err(|_| "invalid invoice data".to_string())?;
+			let signature = keys_manager.sign_invoice(&hrp_bytes, &invoice_data, Recipient::Node);
+			signed("sign the invoice", signature.map(|sig| encode_recoverable_signature(&sig)))
+		}
+		Request::SignGossipMessage { kind, message } => {
+			let mut reader = io::Cursor::new(message);
+			let malformed = |e: DecodeError| format!("malformed gossip message: {:?}", e);
+			let signature = match kind {
+				GOSSIP_CHANNEL_ANNOUNCEMENT => {
+					let msg = UnsignedChannelAnnouncement::read(&mut reader).map_err(malformed)?;
+					keys_manager
+						.sign_gossip_message(UnsignedGossipMessage::ChannelAnnouncement(&msg))
+				}
+				GOSSIP_CHANNEL_UPDATE => {
+					let msg = UnsignedChannelUpdate::read(&mut reader).map_err(malformed)?;
+					keys_manager.sign_gossip_message(UnsignedGossipMessage::ChannelUpdate(&msg))
+				}
+				GOSSIP_NODE_ANNOUNCEMENT => {
+					let msg = UnsignedNodeAnnouncement::read(&mut reader).map_err(malformed)?;
+					keys_manager.sign_gossip_message(UnsignedGossipMessage::NodeAnnouncement(&msg))
+				}
+				_ => return Err(format!("unknown gossip message kind {}", kind)),
+			};
+			signed("sign the gossip message", signature)
+		}
+		Request::SignMessage { message } => {
+			let signature = message_signing::sign(&message, &keys_manager.get_node_secret_key());
+			signed("sign the message", signature.map(String::into_bytes).map_err(|_| ()))
+		}
+		Request::SpendSpendableOutputs {
+			descriptors,
+			outputs,
+			change_destination_script,
+			feerate_sat_per_1000_weight,
+		} => signed(
+			"spend the outputs",
+			keys_manager.spend_spendable_outputs(
+				&descriptors.iter().collect::<Vec<_>>(),
+				outputs,
+				change_destination_script,
+				feerate_sat_per_1000_weight,
+				&secp_ctx,
+			),
+		),
+		Request::DeriveChannelPubkeys { channel } => {
+			Ok(channel_signer(keys_manager, channel).pubkeys().encode())
+		}
+		Request::GetPerCommitmentPoint { channel, idx } => {
+			Ok(channel_signer(keys_manager, channel)
+				.get_per_commitment_point(idx, &secp_ctx)
+				.encode())
+		}
+		Request::ReleaseCommitmentSecret { channel, idx } => {
+			Ok(channel_signer(keys_manager, channel).release_commitment_secret(idx).encode())
+		}
+		Request::SignCounterpartyCommitment { channel, commitment_tx, preimages } => signed(
+			"sign the counterparty's commitment",
+			funded_channel_signer(keys_manager, channel)?.sign_counterparty_commitment(
+				&commitment_tx,
+				preimages,
+				&secp_ctx,
+			),
+		),
+		Request::SignHolderCommitmentAndHtlcs { channel, commitment_tx } => signed(
+			"sign our commitment",
+			funded_channel_signer(keys_manager, channel)?
+				.sign_holder_commitment_and_htlcs(&commitment_tx, &secp_ctx),
+		),
+		Request::SignJusticeRevokedOutput {
+			channel,
+			justice_tx,
+			input,
+			amount,
+			per_commitment_key,
+		} => signed(
+			"sign the justice transaction",
+			funded_channel_signer(keys_manager, channel)?.sign_justice_revoked_output(
+				&justice_tx,
+				input as usize,
+				amount,
+				&per_commitment_key,
+				&secp_ctx,
+			),
+		),
+		Request::SignJusticeRevokedHtlc {
+			channel,
+			justice_tx,
+			input,
+			amount,
+			per_commitment_key,
+			htlc,
+		} => signed(
+			"sign the justice transaction",
+			funded_channel_signer(keys_manager, channel)?.sign_justice_revoked_htlc(
+				&justice_tx,
+				input as usize,
+				amount,
+				&per_commitment_key,
+				&htlc,
+				&secp_ctx,
+			),
+		),
+		Request::SignCounterpartyHtlcTransaction {
+			channel,
+			htlc_tx,
+			input,
+			amount,
+			per_commitment_point,
+			htlc,
+		} => signed(
+			"sign the HTLC transaction",
+			funded_channel_signer(keys_manager, channel)?.sign_counterparty_htlc_transaction(
+				&htlc_tx,
+				input as usize,
+				amount,
+				&per_commitment_point,
+				&htlc,
+				&secp_ctx,
+			),
+		),
+		Request::SignClosingTransaction {
+			channel,
+			to_holder_value_sat,
+			to_counterparty_value_sat,
+			to_holder_script,
+			to_counterparty_script,
+			funding_outpoint,
+		} => {
+			let closing_tx = ClosingTransaction::new(
+				to_holder_value_sat,
+				to_counterparty_value_sat,
+				to_holder_script,
+				to_counterparty_script,
+				funding_outpoint.into_bitcoin_outpoint(),
+			);
+			signed(
+				"sign the closing transaction",
+				funded_channel_signer(keys_manager, channel)?
+					.sign_closing_transaction(&closing_tx, &secp_ctx),
+			)
+		}
+		Request::SignHolderAnchorInput { channel, anchor_tx, input } => signed(
+			"sign the anchor input",
+			funded_channel_signer(keys_manager, channel)?.sign_holder_anchor_input(
+				&anchor_tx,
+				input as usize,
+				&secp_ctx,
+			),
+		),
+		Request::SignChannelAnnouncement { channel, message } => signed(
+			"sign the channel announcement",
+			funded_channel_signer(keys_manager, channel)?
+				.sign_channel_announcement_with_funding_key(&message, &secp_ctx),
+		),
+	}
+}
+
+/// Answers the signing requests the node sends over `stream` until it disconnects.
+#[cfg(unix)]
+async fn serve(mut stream: tokio::net::UnixStream, keys_manager: &KeysManager) {
+	loop {
+		let len = match stream.read_u32().await {
+			Ok(len) if (len as usize) <= MAX_FRAME_LEN => len as usize,
+			// The node disconnected, or isn't speaking our protocol.
+			_ => return,
+		};
+		let mut request = vec![0; len];
+		if stream.read_exact(&mut request).await.is_err() {
+			return;
+		}
+		let response = match Request::read(&mut io::Cursor::new(request)) {
+			Ok(request) => handle_request(keys_manager, request),
+			Err(e) => Err(format!("malformed request: {:?}", e)),
+		};
+		let frame = match response {
+			Ok(payload) => [&[0][..], &payload[..]].concat(),
+			Err(e) => {
+				println!("ERROR: refused a signing request: {}", e);
+				[&[1][..], e.as_bytes()].concat()
+			}
+		};
+		if stream.write_u32(frame.len() as u32).await.is_err()
+			|| stream.write_all(&frame).await.is_err()
+		{
+			return;
+		}
+	}
+}
+
+/// Serves the node's signing requests on `socket_path`, with the keys seed in `signer_dir`. It
+/// signs whatever the node asks for, so the socket must only be reachable by the node.
+#[cfg(unix)]
+pub(crate) async fn run_daemon(
+	signer_dir: &Path, socket_path: &Path, mnemonic: Option<MnemonicSetup>,
+) -> Result<(), String> {
+	let keys_seed = read_or_create_seed(signer_dir, mnemonic)?;
+	let cur = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap();
+	let keys_manager = Arc::new(KeysManager::new(&keys_seed, cur.as_secs(), cur.subsec_nanos()));
+
+	match fs::remove_file(socket_path) {
+		Ok(()) => {}
+		Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+		Err(e) => return Err(format!("failed to remove {}: {}", socket_path.display(), e)),
+	}
+	// The socket is created with only our user's permissions, rather than restricted once it's
+	// already there to be connected to. Nothing else runs yet to create files meanwhile.
+	let umask = unsafe { libc::umask(0o177) };
+	let listener = UnixListener::bind(socket_path);
+	unsafe { libc::umask(umask) };
+	let listener =
+		listener.map_err(|e| format!("failed to listen on {}: {}", socket_path.display(), e))?;
+	println!(
+		"Signing for node {} on {}",
+		keys_manager.get_node_id(Recipient::Node).unwrap(),
+		socket_path.display()
+	);
+
+	loop {
+		let (stream, _) = listener.accept().await.map_err(|e| e.to_string())?;
+		let keys_manager = Arc::clone(&keys_manager);
+		tokio::spawn(async move { serve(stream, &keys_manager).await });
+	}
+}
+
+#[cfg(all(test, unix))]
+mod remote_signer_tests {
+	use super::*;
+
+	fn keys_manager() -> Arc<KeysManager> {
+		let cur = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap();
+		Arc::new(KeysManager::new(&[1; 32], cur.as_secs(), cur.subsec_nanos()))
+	}
+
+	#[tokio::test]
+	async fn test_call_round_trip() {
+		let (client_stream, daemon_stream) = UnixStream::pair().unwrap();
+		daemon_stream.set_nonblocking(true).unwrap();
+		let daemon_stream = tokio::net::UnixStream::from_std(daemon_stream).unwrap();
+		let keys_manager = keys_manager();
+		let daemon_keys_manager = Arc::clone(&keys_manager);
+		tokio::spawn(async move { serve(daemon_stream, &daemon_keys_manager).await });
+
+		// The client only has the connection it's given, as there's no daemon at its path.
+		let client = SignerClient::new(PathBuf::from("/nonexistent/signer.sock"));
+		client.idle.lock().unwrap().push(client_stream);
+		// The client blocks, so it's run off the runtime the daemon is served on.
+		tokio::task::spawn_blocking(move || {
+			let info: NodeInfo = client.call(&Request::GetNodeInfo).unwrap();
+			let node_id = keys_manager.get_node_id(Recipient::Node).unwrap();
+			assert_eq!(info.node_id, node_id);
+			assert_eq!(info.destination_script, keys_manager.get_destination_script());
+
+			let channel = ChannelRef {
+				channel_value_satoshis: 100_000,
+				channel_keys_id: [2; 32],
+				parameters: None,
+			};
+			let pubkeys: ChannelPublicKeys =
+				client.call(&Request::DeriveChannelPubkeys { channel }).unwrap();
+			assert_eq!(&pubkeys, keys_manager.derive_channel_keys(100_000, &[2; 32]).pubkeys());
+
+			let message = b"hello".to_vec();
+			let signature: Vec<u8> =
+				client.call(&Request::SignMessage { message: message.clone() }).unwrap();
+			let signature = String::from_utf8(signature).unwrap();
+			assert!(message_signing::verify(&message, &signature, &node_id));
+
+			// Refused requests fail, but leave the connection usable.
+			let request = Request::SignGossipMessage { kind: 42, message: Vec::new() };
+			assert!(client.call::<Vec<u8>>(&request).is_err());
+			assert_eq!(client.idle.lock().unwrap().len(), 1);
+			let info: NodeInfo = client.call(&Request::GetNodeInfo).unwrap();
+			assert_eq!(info.node_id, node_id);
+		})
+		.await
+		.unwrap();
+	}
+
+	#[test]
+	fn test_call_with_retries_gives_up() {
+		let client = SignerClient::new(PathBuf::from("/nonexistent/signer.sock"));
+		let result = client.call_with_retries::<NodeInfo>(&Request::GetNodeInfo, Duration::ZERO);
+		assert!(result.is_err());
+		assert!(client.idle.lock().unwrap().is_empty());
+	}
+}
+
+#[cfg(test)]
+mod phantom_secret_tests {
+	use super::*;
+
+	fn test_path(name: &str) -> PathBuf {
+		let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap();
+		std::env::temp_dir().join(format!("ldk-sample-{}-{}", name, now.as_nanos()))
+	}
+
+	#[test]
+	fn test_read_phantom_secret() {
+		let path = test_path("phantom-secret");
+		assert!(read_phantom_secret(&path, false).is_err());
+		let secret = read_phantom_secret(&path, true).unwrap();
+		// Other nodes of the cluster read the same secret.
+		assert_eq!(read_phantom_secret(&path, false).unwrap(), secret);
+		assert_eq!(read_phantom_secret(&path, true).unwrap(), secret);
+		fs::remove_file(&path).unwrap();
+
+		fs::write(&path, [1u8; 16]).unwrap();
+		assert!(read_phantom_secret(&path, true).is_err());
+		fs::remove_file(&path).unwrap();
+	}
+}
@@ -0,0 +1,205 @@
+use crate::persist::{self, NodePersister, StorageBackend};
+use crate::{time_now_secs, KEYS_SEED_FNAME};
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The files from the LDK data directory a snapshot holds, besides what's kept in the store.
+const SNAPSHOT_FILES: &[&str] = &[KEYS_SEED_FNAME, "channel_peer_data"];
+
+const DAY_SECS: u64 = 24 * 60 * 60;
+const WEEK_SECS: u64 = 7 * DAY_SECS;
+
+/// Where snapshots of the node's state are taken to, how often, and how many are kept.
+#[derive(Clone, PartialEq)]
+pub(crate) struct SnapshotConfig {
+	pub(crate) dir: PathBuf,
+	pub(crate) interval: Duration,
+	/// The latest snapshot of each of the last `keep_daily` days that have any is kept.
+	pub(crate) keep_daily: usize,
+	/// Likewise for weeks.
+	pub(crate) keep_weekly: usize,
+}
+
+impl Default for SnapshotConfig {
+	fn default() -> Self {
+		SnapshotConfig {
+			dir: PathBuf::new(),
+			interval: Duration::from_secs(DAY_SECS),
+			keep_daily: 7,
+			keep_weekly: 4,
+		}
+	}
+}
+
+/// Takes a snapshot every `config.interval`, starting now, and prunes the ones the retention
+/// policy doesn't keep.
+pub(crate) async fn run(
+	config: SnapshotConfig, persister: Arc<NodePersister>, ldk_data_dir: String,
+) {
+	let mut interval = tokio::time::interval(config.interval);
+	loop {
+		interval.tick().await;
+		if let Err(e) = take(&persister, &ldk_data_dir, &config.dir) {
+			println!("ERROR: failed to take a snapshot: {}", e);
+			continue;
+		}
+		if let Err(e) = prune(&config) {
+			println!("ERROR: failed to prune snapshots in {}: {}", config.dir.display(), e);
+		}
+	}
+}
+
+/// Writes the node's state, as stored, to `<dir>/<timestamp>`, returning the timestamp.
+fn take(persister: &NodePersister, ldk_data_dir: &str, dir: &Path) -> Result<u64, String> {
+	let timestamp = time_now_secs();
+	let snapshot_path = dir.join(timestamp.to_string());
+	if snapshot_path.exists() {
+		return Err(format!("snapshot {} already exists", timestamp));
+	}
+	let mut values = persister
+		.read_stored_state()
+		.map_err(|e| format!("failed to read the node's state: {}", e))?;
+	for name in SNAPSHOT_FILES {
+		match fs::read(Path::new(ldk_data_dir).join(name)) {
+			Ok(value) => values.push((name.to_string(), value)),
+			Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+			Err(e) => return Err(format!("failed to read {}: {}", name, e)),
+		}
+	}
+
+	// Written under a temporary name first, so an interrupted snapshot never looks complete.
+	let tmp_path = dir.join(format!("{}.tmp", timestamp));
+	let write = || -> io::Result<()> {
+		for (key, value) in values.iter() {
+			let path = tmp_path.join(key);
+			fs::create_dir_all(path.parent().unwrap())?;
+			let mut f = fs::File::create(&path)?;
+			f.write_all(value)?;
+			f.sync_all()?;
+		}
+		fs::rename(&tmp_path, &snapshot_path)?;
+		fs::File::open(dir)?.sync_all()
+	};
+	fs::create_dir_all(dir)
+		.and_then(|()| write())
+		.map_err(|e| format!("failed to write snapshot {}: {}", timestamp, e))?;
+	Ok(timestamp)
+}
+
+/// Returns the timestamps of the snapshots in `dir`, newest first.
+fn list(dir: &Path) -> io::Result<Vec<u64>> {
+	let mut timestamps = Vec::new();
+	for entry in fs::read_dir(dir)? {
+		if let Some(Ok(timestamp)) = entry?.file_name().to_str().map(str::parse::<u64>) {
+			timestamps.push(timestamp);
+		}
+	}
+	timestamps.sort_unstable_by(|a, b| b.cmp(a));
+	Ok(timestamps)
+}
+
+/// Deletes every snapshot but the latest one and those the retention policy keeps, along with
+/// whatever interrupted snapshots left behind.
+fn prune(config: &SnapshotConfig) -> io::Result<()> {
+	let timestamps = list(&config.dir)?;
+	let mut keep = timestamps.first().cloned().into_iter().collect::<HashSet<_>>();
+	for &(period_secs, periods_kept) in
+		[(DAY_SECS, config.keep_daily), (WEEK_SECS, config.keep_weekly)].iter()
+	{
+		let mut periods = HashSet::new();
+		for timestamp in timestamps.iter() {
+			let period = timestamp / period_secs;
+			if periods.contains(&period) {
+				continue;
+			}
+			if periods.len() == periods_kept {
+				break;
+			}
+			// Snapshots are listed newest first, so this is the period's latest.
+			periods.insert(period);
+			keep.insert(*timestamp);
+		}
+	}
+	for timestamp in timestamps.iter().filter(|timestamp| !keep.contains(*timestamp)) {
+		fs::remove_dir_all(config.dir.join(timestamp.to_string()))?;
+	}
+	for entry in fs::read_dir(&config.dir)? {
+		let entry = entry?;
+		if entry.file_name().to_string_lossy().ends_with(".tmp") {
+			fs::remove_dir_all(entry.path())?;
+		}
+	}
+	Ok(())
+}
+
+/// Reads every file under `root/dir`, keyed by its path relative to `root`.
+fn read_tree(root: &Path, dir: &Path, values: &mut Vec<(String, Vec<u8>)>) -> io::Result<()> {
+	for entry in fs::read_dir(root.join(dir))? {
+		let entry = entry?;
+		let path = dir.join(entry.file_name());
+		if entry.file_type()?.is_dir() {
+			read_tree(root, &path, values)?;
+		} else {
+			values.push((path.to_string_lossy().into_owned(), fs::read(entry.path())?));
+		}
+	}
+	Ok(())
+}
+
+/// Replaces the node's state in `storage` with the snapshot taken at `timestamp`, returning how
+/// many values it held. The node must be stopped. A different keys seed in the LDK data directory
+/// is set aside rather than overwritten.
+pub(crate) async fn restore(
+	timestamp: u64, snapshot_dir: &Path, storage: &StorageBackend, ldk_data_dir: &str,
+) -> Result<usize, String> {
+	let snapshot_path = snapshot_dir.join(timestamp.to_string());
+	if !snapshot_path.is_dir() {
+		let available = list(snapshot_dir)
+			.unwrap_or_default()
+			.iter()
+			.map(|timestamp| timestamp.to_string())
+			.collect::<Vec<_>>();
+		return Err(format!(
+			"there's no snapshot {} in {}, available ones are: {}",
+			timestamp,
+			snapshot_dir.display(),
+			available.join(", ")
+		));
+	}
+	let mut values = Vec::new();
+	read_tree(&snapshot_path, Path::new(""), &mut values)
+		.map_err(|e| format!("failed to read snapshot {}: {}", timestamp, e))?;
+	let (files, state): (Vec<_>, Vec<_>) =
+		values.into_iter().partition(|(key, _)| SNAPSHOT_FILES.contains(&key.as_str()));
+	let num_values = files.len() + state.len();
+
+	fs::create_dir_all(ldk_data_dir).map_err(|e| e.to_string())?;
+	let keys_seed_path = Path::new(ldk_data_dir).join(KEYS_SEED_FNAME);
+	if let Some((_, seed)) = files.iter().find(|(name, _)| name == KEYS_SEED_FNAME) {
+		match fs::read(&keys_seed_path) {
+			Ok(current) if current != *seed => {
+				let set_aside = keys_seed_path.with_extension(format!("{}.bak", time_now_secs()));
+				fs::rename(&keys_seed_path, &set_aside).map_err(|e| e.to_string())?;
+				println!(
+					"The snapshot's keys seed differs from the current one, which was moved to {}",
+					set_aside.display()
+				);
+			}
+			_ => {}
+		}
+	}
+	persist::restore_state(storage, ldk_data_dir, state).await?;
+	for (name, value) in files {
+		let path = Path::new(ldk_data_dir).join(&name);
+		let tmp_path = path.with_extension("tmp");
+		fs::File::create(&tmp_path)
+			.and_then(|mut f| f.write_all(&value).and_then(|()| f.sync_all()))
+			.and_then(|()| fs::rename(&tmp_path, &path))
+			.map_err(|e| format!("failed to write {}: {}", name, e))?;
+	}
+	Ok(num_values)
+}
@@ -0,0 +1,240 @@
+use crate::hex_utils;
+use bitcoin::hashes::hmac::{Hmac, HmacEngine};
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use lightning::ln::msgs::NetAddress;
+use rand::{thread_rng, Rng};
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::net::SocketAddr;
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+pub(crate) const DEFAULT_TOR_SOCKS_ADDR: &str = "127.0.0.1:9050";
+/// The onion service's private key, as Tor gave it to us, so its address stays the same.
+const ONION_KEY_FNAME: &str = "tor_onion_key";
+const BASE32_ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+#[derive(Clone)]
+pub(crate) struct TorConfig {
+	/// Tor's control port, to create the onion service through.
+	pub(crate) control_addr: SocketAddr,
+	/// For control ports set up with `HashedControlPassword`, rather than cookie authentication.
+	pub(crate) control_password: Option<String>,
+	/// Tor's SOCKS port, which outbound connections go through in Tor-only mode.
+	pub(crate) socks_addr: SocketAddr,
+	/// Only listen on localhost, for the onion service, and connect to peers through Tor.
+	pub(crate) only: bool,
+}
+
+/// A connection to Tor's control port. Tor removes the onion service once it's closed, so it's
+/// kept open for as long as the node runs.
+pub(crate) struct TorControl {
+	stream: BufReader<TcpStream>,
+}
+
+impl TorControl {
+	pub(crate) async fn connect(config: &TorConfig) -> Result<Self, String> {
+		let stream = TcpStream::connect(config.control_addr)
+			.await
+			.map_err(|e| format!("failed to reach Tor's control port: {}", e))?;
+		let mut control = TorControl { stream: BufReader::new(stream) };
+		control.authenticate(config.control_password.as_deref()).await?;
+		Ok(control)
+	}
+
+	/// Sends `command` and returns the lines of Tor's reply, without their status code, if it
+	/// succeeded.
+	async fn command(&mut self, command: &str) -> Result<Vec<String>, String> {
+		let io_err = |e: io::Error| format!("lost the connection to Tor's control port: {}", e);
+		self.stream.write_all(format!("{}\r\n", command).as_bytes()).await.map_err(io_err)?;
+		let mut lines = Vec::new();
+		loop {
+			let mut line = String::new();
+			if self.stream.read_line(&mut line).await.map_err(io_err)? == 0 {
+				return Err("Tor closed the control connection".to_string());
+			}
+			let line = line.trim_end_matches(&['\r', '\n'][..]);
+			if line.len() < 4 {
+				return Err(format!("unexpected reply from Tor: {}", line));
+			}
+			if !line.starts_with("250") {
+				return Err(format!(
+					"Tor refused `{}`: {}",
+					command.split(' ').next().unwrap(),
+					line
+				));
+			}
+			lines.push(line[4..].to_string());
+			if line.as_bytes()[3] == b' ' {
+				return Ok(lines);
+			}
+		}
+	}
+
+	async fn authenticate(&mut self, password: Option<&str>) -> Result<(), String> {
+		if let Some(password) = password {
+			let quoted = password.replace('\\', "\\\\").replace('"', "\\\"");
+			self.command(&format!("AUTHENTICATE \"{}\"", quoted)).await?;
+			return Ok(());
+		}
+		let protocol_info = self.command("PROTOCOLINFO 1").await?;
+		let auth = protocol_info
+			.iter()
+			.find(|line| line.starts_with("AUTH "))
+			.ok_or("Tor didn't say how to authenticate")?;
+		let methods = reply_value(auth, "METHODS").unwrap_or_default();
+		let methods = methods.split(',').collect::<Vec<_>>();
+		if methods.contains(&"NULL") {
+			self.command("AUTHENTICATE").await?;
+			return Ok(());
+		}
+		if !methods.contains(&"SAFECOOKIE") {
+			return Err(
+				"Tor's control port requires a password, pass --tor-control-password".to_string()
+			);
+		}
+		let cookie_path = reply_value(auth, "COOKIEFILE").ok_or("Tor didn't give a cookie file")?;
+		let cookie = fs::read(&cookie_path)
+			.map_err(|e| format!("failed to read Tor's cookie file {}: {}", cookie_path, e))?;
+
+		// SAFECOOKIE has both sides prove they know the cookie without sending it.
+		let mut client_nonce = [0; 32];
+		thread_rng().fill_bytes(&mut client_nonce);
+		let challenge = self
+			.command(&format!("AUTHCHALLENGE SAFECOOKIE {}", hex_utils::hex_str(&client_nonce)))
+			.await?;
+		let challenge = &challenge[0];
+		let server_hash = reply_value(challenge, "SERVERHASH").and_then(|h| hex_utils::to_vec(&h));
+		let server_nonce =
+			reply_value(challenge, "SERVERNONCE").and_then(|n| hex_utils::to_vec(&n));
+		let (server_hash, server_nonce) = match (server_hash, server_nonce) {
+			(Some(hash), Some(nonce)) => (hash, nonce),
+			_ => return Err(format!("unexpected AUTHCHALLENGE reply from Tor: {}", challenge)),
+		};
+		let safecookie_hash = |key: &[u8]| {
+			let mut engine = HmacEngine::<sha256::Hash>::new(key);
+			engine.input(&cookie);
+			engine.input(&client_nonce);
+			engine.input(&server_nonce);
+			Hmac::<sha256::Hash>::from_engine(engine).into_inner()
+		};
+		if safecookie_hash(b"Tor safe cookie authentication server-to-controller hash")[..]
+			!= server_hash[..]
+		{
+			return Err("Tor's control port doesn't know the cookie it gave us".to_string());
+		}
+		let client_hash =
+			safecookie_hash(b"Tor safe cookie authentication controller-to-server hash");
+		self.command(&format!("AUTHENTICATE {}", hex_utils::hex_str(&client_hash))).await?;
+		Ok(())
+	}
+
+	/// Has Tor serve an onion service forwarding `port` to the Lightning listener on localhost,
+	/// under the key kept in `ldk_data_dir` so its address doesn't change across restarts.
+	pub(crate) async fn add_onion_service(
+		&mut self, ldk_data_dir: &str, port: u16,
+	) -> Result<NetAddress, String> {
+		let key_path = Path::new(ldk_data_dir).join(ONION_KEY_FNAME);
+		let key = match fs::read_to_string(&key_path) {
+			Ok(key) => Some(key.trim().to_string()),
+			Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+			Err(e) => return Err(format!("failed to read {}: {}", key_path.display(), e)),
+		};
+		let reply = self
+			.command(&format!(
+				"ADD_ONION {} Port={},127.0.0.1:{}",
+				key.as_deref().unwrap_or("NEW:ED25519-V3"),
+				port,
+				port
+			))
+			.await?;
+		if key.is_none() {
+			let new_key = reply
+				.iter()
+				.find_map(|line| line.strip_prefix("PrivateKey="))
+				.ok_or("Tor didn't return the onion service's key")?;
+			fs::File::create(&key_path)
+				.and_then(|mut f| {
+					#[cfg(unix)]
+					f.set_permissions(fs::Permissions::from_mode(0o600))?;
+					f.write_all(new_key.as_bytes())?;
+					f.sync_all()
+				})
+				.map_err(|e| format!("failed to write {}: {}", key_path.display(), e))?;
+		}
+		let service_id = reply
+			.iter()
+			.find_map(|line| line.strip_prefix("ServiceID="))
+			.ok_or("Tor didn't return the onion service's address")?;
+		onion_v3_address(service_id, port)
+			.ok_or_else(|| format!("Tor returned an invalid onion address: {}", service_id))
+	}
+}
+
+/// Returns the value of `key` in a `KEY=value` or `KEY="value"` reply line.
+fn reply_value(line: &str, key: &str) -> Option<String> {
+	let start = line.find(&format!("{}=", key))? + key.len() + 1;
+	let value = &line[start..];
+	if let Some(quoted) = value.strip_prefix('"') {
+		let mut unquoted = String::new();
+		let mut chars = quoted.chars();
+		while let Some(c) = chars.next() {
+			match c {
+				'"' => return Some(unquoted),
+				'\\' => unquoted.push(chars.next()?),
+				c => unquoted.push(c),
+			}
+		}
+		None
+	} else {
+		Some(value.split(' ').next().unwrap().to_string())
+	}
+}
+
+/// Decodes a v3 onion service ID, the base32 encoding of its public key, checksum and version.
+fn onion_v3_address(service_id: &str, port: u16) -> Option<NetAddress> {
+	if service_id.len() != 56 {
+		return None;
+	}
+	let mut bytes = Vec::with_capacity(35);
+	let (mut buffer, mut bits) = (0u64, 0);
+	for c in service_id.bytes() {
+		buffer = buffer << 5 | BASE32_ALPHABET.iter().position(|a| *a == c)? as u64;
+		bits += 5;
+		if bits >= 8 {
+			bits -= 8;
+			bytes.push((buffer >> bits) as u8);
+		}
+	}
+	let mut ed25519_pubkey = [0; 32];
+	ed25519_pubkey.copy_from_slice(&bytes[..32]);
+	Some(NetAddress::OnionV3 {
+		ed25519_pubkey,
+		checksum: u16::from_be_bytes([bytes[32], bytes[33]]),
+		version: bytes[34],
+		port,
+	})
+}
+
+/// The `<service_id>.onion` host of a v3 onion service.
+pub(crate) fn onion_v3_host(ed25519_pubkey: &[u8; 32], checksum: u16, version: u8) -> String {
+	let mut bytes = ed25519_pubkey.to_vec();
+	bytes.extend_from_slice(&checksum.to_be_bytes());
+	bytes.push(version);
+	let mut host = String::with_capacity(62);
+	let (mut buffer, mut bits) = (0u64, 0);
+	for byte in bytes {
+		buffer = buffer << 8 | byte as u64;
+		bits += 8;
+		while bits >= 5 {
+			bits -= 5;
+			host.push(BASE32_ALPHABET[(buffer >> bits) as usize & 31] as char);
+		}
+	}
+	host.push_str(".onion");
+	host
+}
@@ -0,0 +1,240 @@
+use crate::bitcoind_client::BitcoindClient;
+use crate::disk::{self, FilesystemLogger};
+use crate::persist::{self, NodePersister, MONITORS_DIR, MONITOR_UPDATES_DIR};
+use crate::signer::{ChannelKeys, NodeKeys};
+use crate::{
+	ChainMonitor, ChannelManager, ForwardingHistory, NetworkGraph, ProbeStats, Router, Scorer,
+	WithdrawLink, FORWARDING_HISTORY_FNAME, INBOUND_PAYMENTS_FNAME, OUTBOUND_PAYMENTS_FNAME,
+	PROBE_STATS_FNAME, WITHDRAW_LINKS_FNAME,
+};
+use bitcoin::blockdata::transaction::Transaction;
+use bitcoin::secp256k1::PublicKey;
+use bitcoin::BlockHash;
+use lightning::chain::chaininterface::BroadcasterInterface;
+use lightning::chain::channelmonitor::{Balance, ChannelMonitor, ChannelMonitorUpdate};
+use lightning::chain::keysinterface::EntropySource;
+use lightning::chain::transaction::OutPoint;
+use lightning::ln::channelmanager::{self, ChannelManagerReadArgs};
+use lightning::routing::scoring::{ProbabilisticScorer, ProbabilisticScoringParameters};
+use lightning::util::config::UserConfig;
+use lightning::util::ser::{Readable, ReadableArgs};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Reading back a `ChannelManager` older than its monitors, as the stored one usually is while
+/// the node runs, force closes the channels they're ahead of. This keeps those closes from
+/// reaching the network.
+struct DiscardBroadcaster;
+
+impl BroadcasterInterface for DiscardBroadcaster {
+	fn broadcast_transaction(&self, _tx: &Transaction) {}
+}
+
+type ReadBackChannelManager = channelmanager::ChannelManager<
+	Arc<ChainMonitor>,
+	Arc<DiscardBroadcaster>,
+	Arc<NodeKeys>,
+	Arc<NodeKeys>,
+	Arc<NodeKeys>,
+	Arc<BitcoindClient>,
+	Arc<Router>,
+	Arc<FilesystemLogger>,
+>;
+
+/// What `verifystate` found.
+pub(crate) struct StateReport {
+	pub(crate) monitors: usize,
+	pub(crate) journaled_updates: usize,
+	/// How many channels the stored `ChannelManager` has, if it could be read.
+	pub(crate) manager_channels: Option<usize>,
+	/// Values that don't decode, or don't fit with the rest of the state.
+	pub(crate) errors: Vec<String>,
+	/// Monitors of channels the `ChannelManager` doesn't know about, with how many sats are left
+	/// to claim from them.
+	pub(crate) orphaned_monitors: Vec<(OutPoint, u64)>,
+}
+
+fn claimable_sats(balance: &Balance) -> u64 {
+	match balance {
+		Balance::ClaimableOnChannelClose { claimable_amount_satoshis, .. }
+		| Balance::ClaimableAwaitingConfirmations { claimable_amount_satoshis, .. }
+		| Balance::ContentiousClaimable { claimable_amount_satoshis, .. }
+		| Balance::MaybeTimeoutClaimableHTLC { claimable_amount_satoshis, .. }
+		| Balance::MaybePreimageClaimableHTLC { claimable_amount_satoshis, .. }
+		| Balance::CounterpartyRevokedOutputClaimable { claimable_amount_satoshis, .. } => {
+			*claimable_amount_satoshis
+		}
+	}
+}
+
+/// Decodes everything the node has stored, the way it would on startup, without touching the
+/// running node. The manager is read before the monitors and their journal, so the monitors read
+/// back are at least as new as it.
+pub(crate) fn verify_state(
+	persister: &NodePersister, channel_manager: &ChannelManager, chain_monitor: &Arc<ChainMonitor>,
+	keys_manager: &Arc<NodeKeys>, fee_estimator: &Arc<BitcoindClient>,
+	network_graph: &Arc<NetworkGraph>, scorer: &Arc<Mutex<Scorer>>, logger: &Arc<FilesystemLogger>,
+) -> StateReport {
+	let mut errors = Vec::new();
+	let mut read = |key: &str| match persister.read(key) {
+		Ok(value) => value,
+		Err(e) => {
+			errors.push(format!("failed to read {}: {}", key, e));
+			None
+		}
+	};
+	let manager = read("manager");
+	let network_graph_bytes = read("network_graph");
+	let scorer_bytes = read("scorer");
+	let mut payments = Vec::new();
+	for key in [INBOUND_PAYMENTS_FNAME, OUTBOUND_PAYMENTS_FNAME].iter() {
+		payments.push((*key, read(key)));
+	}
+	let forwarding_history = read(FORWARDING_HISTORY_FNAME);
+	let withdraw_links = read(WITHDRAW_LINKS_FNAME);
+	let probe_stats = read(PROBE_STATS_FNAME);
+	let mut read_dir = |dir: &str| match persister.read_dir(dir) {
+		Ok(values) => values,
+		Err(e) => {
+			errors.push(format!("failed to list {}: {}", dir, e));
+			Vec::new()
+		}
+	};
+	let journal = read_dir(MONITOR_UPDATES_DIR);
+	let monitor_rows = read_dir(MONITORS_DIR);
+
+	let mut monitors: Vec<(BlockHash, ChannelMonitor<ChannelKeys>)> = Vec::new();
+	for (key, value) in monitor_rows.iter() {
+		match persist::decode_channelmonitor(key, value, keys_manager) {
+			Ok(monitor) => monitors.push(monitor),
+			Err(e) => errors.push(e.to_string()),
+		}
+	}
+
+	let journaled_updates = journal.len();
+	let mut updates: HashMap<OutPoint, Vec<ChannelMonitorUpdate>> = HashMap::new();
+	for (key, value) in journal.iter() {
+		match persist::decode_journaled_update(key, value) {
+			Ok((funding_txo, update)) => {
+				updates.entry(funding_txo).or_insert_with(Vec::new).push(update)
+			}
+			Err(e) => errors.push(e.to_string()),
+		}
+	}
+	let broadcaster = Arc::new(DiscardBroadcaster);
+	for (funding_txo, mut updates) in updates {
+		let monitor = match monitors.iter().find(|(_, m)| m.get_funding_txo().0 == funding_txo) {
+			Some((_, monitor)) => monitor,
+			None => {
+				errors.push(format!(
+					"{} update(s) are journaled for {}:{}, which has no ChannelMonitor",
+					updates.len(),
+					funding_txo.txid,
+					funding_txo.index
+				));
+				continue;
+			}
+		};
+		updates.sort_by_key(|update| update.update_id);
+		for update in updates.iter().filter(|u| u.update_id > monitor.get_latest_update_id()) {
+			if monitor.update_monitor(update, &broadcaster, fee_estimator.clone(), logger).is_err()
+			{
+				errors.push(format!(
+					"journaled update {} doesn't apply to the ChannelMonitor of {}:{}",
+					update.update_id, funding_txo.txid, funding_txo.index
+				));
+				break;
+			}
+		}
+	}
+
+	let mut manager_channels = None;
+	if let Some(manager) = manager {
+		let router = Arc::new(Router::new(
+			Arc::clone(network_graph),
+			Arc::clone(logger),
+			keys_manager.get_secure_random_bytes(),
+			Arc::clone(scorer),
+		));
+		let read_args = ChannelManagerReadArgs::new(
+			Arc::clone(keys_manager),
+			Arc::clone(keys_manager),
+			Arc::clone(keys_manager),
+			Arc::clone(fee_estimator),
+			Arc::clone(chain_monitor),
+			Arc::clone(&broadcaster),
+			router,
+			Arc::clone(logger),
+			UserConfig::default(),
+			monitors.iter_mut().map(|(_, monitor)| monitor).collect(),
+		);
+		match <(BlockHash, ReadBackChannelManager)>::read(&mut &manager[..], read_args) {
+			Ok((_, manager)) => manager_channels = Some(manager.list_channels().len()),
+			Err(e) => errors.push(format!("failed to read ChannelManager: {:?}", e)),
+		}
+	} else if !monitors.is_empty() {
+		errors.push("there are ChannelMonitors but no ChannelManager".to_string());
+	}
+
+	// Cross-check against the running manager, as the monitors of its channels must be there for
+	// the node to start again.
+	let channels = channel_manager.list_channels();
+	for funding_txo in channels.iter().filter_map(|chan| chan.funding_txo) {
+		if !monitors.iter().any(|(_, monitor)| monitor.get_funding_txo().0 == funding_txo) {
+			errors.push(format!(
+				"channel with funding outpoint {}:{} has no ChannelMonitor",
+				funding_txo.txid, funding_txo.index
+			));
+		}
+	}
+	let mut orphaned_monitors = Vec::new();
+	for (_, monitor) in monitors.iter() {
+		let funding_txo = monitor.get_funding_txo().0;
+		if !channels.iter().any(|chan| chan.funding_txo == Some(funding_txo)) {
+			let claimable: u64 = monitor.get_claimable_balances().iter().map(claimable_sats).sum();
+			orphaned_monitors.push((funding_txo, claimable));
+		}
+	}
+
+	let mut graph = Arc::clone(network_graph);
+	if let Some(bytes) = network_graph_bytes {
+		match NetworkGraph::read(&mut &bytes[..], Arc::clone(logger)) {
+			Ok(read_graph) => graph = Arc::new(read_graph),
+			Err(e) => errors.push(format!("failed to read network_graph: {:?}", e)),
+		}
+	}
+	if let Some(bytes) = scorer_bytes {
+		let args = (ProbabilisticScoringParameters::default(), graph, Arc::clone(logger));
+		if let Err(e) = ProbabilisticScorer::read(&mut &bytes[..], args) {
+			errors.push(format!("failed to read scorer: {:?}", e));
+		}
+	}
+	for (key, bytes) in payments {
+		if bytes.map_or(false, |bytes| disk::decode_payments(&bytes).is_none()) {
+			errors.push(format!("failed to read {}", key));
+		}
+	}
+	if let Some(bytes) = forwarding_history {
+		if let Err(e) = ForwardingHistory::read(&mut &bytes[..]) {
+			errors.push(format!("failed to read {}: {:?}", FORWARDING_HISTORY_FNAME, e));
+		}
+	}
+	if let Some(bytes) = withdraw_links {
+		if let Err(e) = HashMap::<[u8; 32], WithdrawLink>::read(&mut &bytes[..]) {
+			errors.push(format!("failed to read {}: {:?}", WITHDRAW_LINKS_FNAME, e));
+		}
+	}
+	if let Some(bytes) = probe_stats {
+		if let Err(e) = HashMap::<(u64, PublicKey), ProbeStats>::read(&mut &bytes[..]) {
+			errors.push(format!("failed to read {}: {:?}", PROBE_STATS_FNAME, e));
+		}
+	}
+
+	StateReport {
+		monitors: monitors.len(),
+		journaled_updates,
+		manager_channels,
+		errors,
+		orphaned_monitors,
+	}
+}
@@ -0,0 +1,326 @@
+use crate::bitcoind_client::BitcoindClient;
+use crate::hex_utils;
+use bdk::blockchain::rpc::{Auth, RpcBlockchain, RpcConfig, RpcSyncParams};
+use bdk::blockchain::ConfigurableBlockchain;
+use bdk::database::SqliteDatabase;
+use bdk::descriptor::ExtendedDescriptor;
+use bdk::template::Bip84;
+use bdk::wallet::{wallet_name_from_descriptor, AddressIndex};
+use bdk::{FeeRate, IntoWalletDescriptor, KeychainKind, SignOptions, SyncOptions};
+use bitcoin::blockdata::script::Script;
+use bitcoin::blockdata::transaction::{OutPoint, Transaction};
+use bitcoin::consensus::encode;
+use bitcoin::network::constants::Network;
+use bitcoin::secp256k1::Secp256k1;
+use bitcoin::util::address::Address;
+use bitcoin::util::bip32::ExtendedPrivKey;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How often the wallet picks up new transactions from bitcoind.
+const SYNC_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Where bitcoind is, for the wallet to sync from.
+pub(crate) struct WalletRpcConfig {
+	pub(crate) host: String,
+	pub(crate) port: u16,
+	pub(crate) user: String,
+	pub(crate) password: String,
+	pub(crate) cookie_path: Option<PathBuf>,
+}
+
+/// The wallet's balance, in satoshis.
+pub(crate) struct WalletBalance {
+	pub(crate) confirmed: u64,
+	pub(crate) unconfirmed: u64,
+	pub(crate) immature: u64,
+}
+
+/// The kinds of addresses the wallet receives to.
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum AddressType {
+	P2wpkh,
+	P2tr,
+}
+
+impl FromStr for AddressType {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, String> {
+		match s {
+			"p2wpkh" => Ok(AddressType::P2wpkh),
+			"p2tr" => Ok(AddressType::P2tr),
+			_ => Err(format!("unknown address type {}, expected p2wpkh or p2tr", s)),
+		}
+	}
+}
+
+impl fmt::Display for AddressType {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			AddressType::P2wpkh => write!(f, "p2wpkh"),
+			AddressType::P2tr => write!(f, "p2tr"),
+		}
+	}
+}
+
+/// A BDK wallet for one address type, with the watch-only wallet bitcoind keeps for it.
+struct Keychain {
+	wallet: Mutex<bdk::Wallet<SqliteDatabase>>,
+	blockchain: RpcBlockchain,
+}
+
+impl Keychain {
+	fn new<D: IntoWalletDescriptor, F: Fn(KeychainKind) -> D>(
+		descriptor: F, network: Network, database_path: String, rpc: &WalletRpcConfig,
+		birthday: u64,
+	) -> Result<Self, String> {
+		let wallet_name = wallet_name_from_descriptor(
+			descriptor(KeychainKind::External),
+			Some(descriptor(KeychainKind::Internal)),
+			network,
+			&Secp256k1::new(),
+		)
+		.map_err(|e| e.to_string())?;
+		let wallet = bdk::Wallet::new(
+			descriptor(KeychainKind::External),
+			Some(descriptor(KeychainKind::Internal)),
+			network,
+			SqliteDatabase::new(database_path),
+		)
+		.map_err(|e| e.to_string())?;
+		let auth = match rpc.cookie_path.clone() {
+			Some(file) => Auth::Cookie { file },
+			None => Auth::UserPass { username: rpc.user.clone(), password: rpc.password.clone() },
+		};
+		let blockchain = RpcBlockchain::from_config(&RpcConfig {
+			url: format!("http://{}:{}", rpc.host, rpc.port),
+			auth,
+			network,
+			wallet_name,
+			sync_params: Some(RpcSyncParams { start_time: birthday, ..Default::default() }),
+		})
+		.map_err(|e| format!("failed to reach bitcoind: {}", e))?;
+		Ok(Keychain { wallet: Mutex::new(wallet), blockchain })
+	}
+}
+
+/// A BDK wallet with BIP84 (P2WPKH) and BIP86 (P2TR) descriptors derived from the keys seed, so
+/// the seed alone restores it. bitcoind only watches its addresses, from watch-only wallets BDK
+/// creates.
+pub(crate) struct BdkWallet {
+	segwit: Keychain,
+	taproot: Keychain,
+	/// The inputs of transactions we built that the wallet hasn't seen spent yet, which mustn't be
+	/// spent again unless the transaction is discarded.
+	spent_outpoints: Mutex<HashSet<OutPoint>>,
+}
+
+/// The node's on-chain wallet, which funds channels and receives what closed channels leave us.
+pub(crate) enum OnchainWallet {
+	Bdk(BdkWallet),
+	/// bitcoind's own wallet, for nodes whose keys seed is held by a remote signer.
+	Bitcoind(Arc<BitcoindClient>),
+}
+
+impl BdkWallet {
+	/// Opens the wallet kept in `ldk_data_dir`, creating it if needed. A new wallet only looks
+	/// for its transactions in blocks after `birthday`, a UNIX timestamp.
+	pub(crate) fn new(
+		keys_seed: &[u8; 32], network: Network, ldk_data_dir: &str, rpc: WalletRpcConfig,
+		birthday: u64,
+	) -> Result<Self, String> {
+		let xprv = ExtendedPrivKey::new_master(network, keys_seed).map_err(|e| e.to_string())?;
+
+		// The birthday is only known when the wallet is created, so it's kept next to it.
+		let birthday_path = Path::new(ldk_data_dir).join("wallet_birthday");
+		let birthday = match fs::read_to_string(&birthday_path) {
+			Ok(stored) => stored.trim().parse::<u64>().map_err(|e| e.to_string())?,
+			Err(_) => {
+				fs::write(&birthday_path, birthday.to_string()).map_err(|e| e.to_string())?;
+				birthday
+			}
+		};
+
+		let segwit = Keychain::new(
+			|keychain| Bip84(xprv, keychain),
+			network,
+			format!("{}/wallet.sqlite", ldk_data_dir),
+			&rpc,
+			birthday,
+		)?;
+		// The paths BIP86 specifies, as `Bip84` does for the other keychain.
+		let coin_type = if network == Network::Bitcoin { 0 } else { 1 };
+		let taproot = Keychain::new(
+			|keychain| {
+				let change = if keychain == KeychainKind::Internal { 1 } else { 0 };
+				let descriptor = format!("tr({}/86'/{}'/0'/{}/*)", xprv, coin_type, change);
+				ExtendedDescriptor::parse_descriptor(&Secp256k1::new(), &descriptor)
+					.expect("BIP86 descriptors are valid")
+			},
+			network,
+			format!("{}/wallet_taproot.sqlite", ldk_data_dir),
+			&rpc,
+			birthday,
+		)?;
+		Ok(BdkWallet { segwit, taproot, spent_outpoints: Mutex::new(HashSet::new()) })
+	}
+
+	fn sync(&self) -> Result<(), String> {
+		let mut unspent = HashSet::new();
+		for keychain in [&self.segwit, &self.taproot].iter() {
+			let wallet = keychain.wallet.lock().unwrap();
+			wallet.sync(&keychain.blockchain, SyncOptions::default()).map_err(|e| e.to_string())?;
+			let utxos = wallet.list_unspent().map_err(|e| e.to_string())?;
+			unspent.extend(utxos.iter().map(|utxo| utxo.outpoint));
+		}
+		self.spent_outpoints.lock().unwrap().retain(|outpoint| unspent.contains(outpoint));
+		Ok(())
+	}
+
+	fn keychain(&self, address_type: AddressType) -> &Keychain {
+		match address_type {
+			AddressType::P2wpkh => &self.segwit,
+			AddressType::P2tr => &self.taproot,
+		}
+	}
+
+	/// Funds a transaction from the coins of one keychain, as its BDK wallet only signs those.
+	fn fund_from(
+		&self, keychain: &Keychain, output_script: &Script, amount_sats: u64, fee_rate: FeeRate,
+	) -> Result<Transaction, bdk::Error> {
+		let wallet = keychain.wallet.lock().unwrap();
+		let mut spent_outpoints = self.spent_outpoints.lock().unwrap();
+		let mut builder = wallet.build_tx();
+		builder
+			.add_recipient(output_script.clone(), amount_sats)
+			.fee_rate(fee_rate)
+			.unspendable(spent_outpoints.iter().cloned().collect());
+		let (mut psbt, _) = builder.finish()?;
+		if !wallet.sign(&mut psbt, SignOptions::default())? {
+			return Err(bdk::Error::Generic("the wallet couldn't sign all inputs".to_string()));
+		}
+		let tx = psbt.extract_tx();
+		spent_outpoints.extend(tx.input.iter().map(|input| input.previous_output));
+		Ok(tx)
+	}
+}
+
+impl OnchainWallet {
+	/// Lets the inputs of `tx`, which we built but won't broadcast, be spent again.
+	pub(crate) fn release_inputs(&self, tx: &Transaction) {
+		if let OnchainWallet::Bdk(wallet) = self {
+			let mut spent_outpoints = wallet.spent_outpoints.lock().unwrap();
+			for input in tx.input.iter() {
+				spent_outpoints.remove(&input.previous_output);
+			}
+		}
+	}
+
+	/// Keeps the wallet in sync with the chain, as seen by bitcoind.
+	pub(crate) async fn run_sync(wallet: Arc<OnchainWallet>) {
+		if let OnchainWallet::Bitcoind(_) = *wallet {
+			return;
+		}
+		let mut interval = tokio::time::interval(SYNC_INTERVAL);
+		loop {
+			interval.tick().await;
+			let sync_wallet = Arc::clone(&wallet);
+			let res = tokio::task::spawn_blocking(move || match &*sync_wallet {
+				OnchainWallet::Bdk(wallet) => wallet.sync(),
+				OnchainWallet::Bitcoind(_) => Ok(()),
+			})
+			.await;
+			if let Ok(Err(e)) = res {
+				println!("ERROR: failed to sync the on-chain wallet: {}", e);
+			}
+		}
+	}
+
+	pub(crate) async fn get_new_address(
+		&self, address_type: AddressType,
+	) -> Result<Address, String> {
+		match self {
+			OnchainWallet::Bdk(wallet) => {
+				let wallet = wallet.keychain(address_type).wallet.lock().unwrap();
+				let address = wallet.get_address(AddressIndex::New).map_err(|e| e.to_string())?;
+				Ok(address.address)
+			}
+			OnchainWallet::Bitcoind(bitcoind_client) => {
+				let bitcoind_type = match address_type {
+					AddressType::P2wpkh => "bech32",
+					AddressType::P2tr => "bech32m",
+				};
+				Ok(bitcoind_client.get_new_address(bitcoind_type).await)
+			}
+		}
+	}
+
+	pub(crate) async fn get_balance(&self) -> Result<WalletBalance, String> {
+		match self {
+			OnchainWallet::Bdk(wallet) => {
+				let mut total = WalletBalance { confirmed: 0, unconfirmed: 0, immature: 0 };
+				for keychain in [&wallet.segwit, &wallet.taproot].iter() {
+					let balance =
+						keychain.wallet.lock().unwrap().get_balance().map_err(|e| e.to_string())?;
+					total.confirmed += balance.confirmed;
+					total.unconfirmed += balance.trusted_pending + balance.untrusted_pending;
+					total.immature += balance.immature;
+				}
+				Ok(total)
+			}
+			OnchainWallet::Bitcoind(bitcoind_client) => {
+				let balances = bitcoind_client.get_balances().await.map_err(|e| e.to_string())?;
+				Ok(WalletBalance {
+					confirmed: balances.trusted,
+					unconfirmed: balances.untrusted_pending,
+					immature: balances.immature,
+				})
+			}
+		}
+	}
+
+	/// Builds and signs a transaction paying `amount_sats` to `output_script`, which isn't
+	/// replaceable, as it may fund a channel. It isn't broadcast.
+	pub(crate) async fn create_funding_transaction(
+		&self, output_script: &Script, amount_sats: u64, feerate_sat_per_1000_weight: u32,
+		network: Network,
+	) -> Result<Transaction, String> {
+		match self {
+			OnchainWallet::Bdk(wallet) => {
+				let fee_rate = FeeRate::from_sat_per_vb(feerate_sat_per_1000_weight as f32 / 250.0);
+				// P2TR coins are only spent when the P2WPKH ones don't cover the amount.
+				match wallet.fund_from(&wallet.segwit, output_script, amount_sats, fee_rate) {
+					Err(bdk::Error::InsufficientFunds { .. }) => wallet
+						.fund_from(&wallet.taproot, output_script, amount_sats, fee_rate)
+						.map_err(|e| e.to_string()),
+					res => res.map_err(|e| e.to_string()),
+				}
+			}
+			OnchainWallet::Bitcoind(bitcoind_client) => {
+				let address = Address::from_script(output_script, network)
+					.ok_or_else(|| "the output script has no address".to_string())?;
+				let mut outputs = vec![HashMap::with_capacity(1)];
+				outputs[0].insert(address.to_string(), amount_sats as f64 / 100_000_000.0);
+				let raw_tx = bitcoind_client.create_raw_transaction(outputs).await;
+
+				// Have bitcoind's wallet put the inputs into the transaction such that the output
+				// is satisfied.
+				let funded_tx = bitcoind_client.fund_raw_transaction(raw_tx).await;
+
+				let signed_tx =
+					bitcoind_client.sign_raw_transaction_with_wallet(funded_tx.hex).await;
+				if !signed_tx.complete {
+					return Err("bitcoind's wallet couldn't sign all inputs".to_string());
+				}
+				encode::deserialize(&hex_utils::to_vec(&signed_tx.hex).unwrap())
+					.map_err(|e| e.to_string())
+			}
+		}
+	}
+}